@@ -0,0 +1,129 @@
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509};
+use std::fs;
+use std::io;
+
+/// How many days the generated certificate is valid
+const VALID_DAYS: u32 = 365;
+
+/// Self signed certificate and private key in PEM format for `localhost`.
+/// Browsers still warn about it, it's only meant for local testing.
+pub fn self_signed() -> Result<(Vec<u8>, Vec<u8>), ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", "localhost")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut cert = X509::builder()?;
+    cert.set_version(2)?;
+    let serial = serial.to_asn1_integer()?;
+    cert.set_serial_number(&serial)?;
+    cert.set_subject_name(&name)?;
+    cert.set_issuer_name(&name)?;
+    cert.set_pubkey(&key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(VALID_DAYS)?;
+    cert.set_not_before(&not_before)?;
+    cert.set_not_after(&not_after)?;
+    cert.append_extension(BasicConstraints::new().build()?)?;
+    let alt_names = SubjectAlternativeName::new()
+        .dns("localhost")
+        .ip("127.0.0.1")
+        .ip("::1")
+        .build(&cert.x509v3_context(None, None))?;
+    cert.append_extension(alt_names)?;
+    cert.sign(&key, MessageDigest::sha256())?;
+
+    Ok((cert.build().to_pem()?, key.private_key_to_pem_pkcs8()?))
+}
+
+/// Write a new self signed certificate and its private key to the files.
+/// Existing files are not replaced.
+pub fn generate(certificate_file: &str, private_key_file: &str) -> io::Result<()> {
+    for file in &[certificate_file, private_key_file] {
+        if fs::metadata(file).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", file),
+            ));
+        }
+    }
+
+    let (certificate, private_key) = self_signed().map_err(io::Error::other)?;
+    fs::write(certificate_file, certificate)?;
+    fs::write(private_key_file, private_key)?;
+    Ok(())
+}
+
+/// Check that the certificate in `certificate_file` belongs to the private key in `private_key_file`
+pub fn check_key_pair(certificate_file: &str, private_key_file: &str) -> Result<(), String> {
+    let read = |path: &str| fs::read(path).map_err(|e| format!("Cannot read {}: {}", path, e));
+    let certificate = X509::from_pem(&read(certificate_file)?)
+        .map_err(|e| format!("{} is not a PEM certificate: {}", certificate_file, e))?;
+    let private_key = PKey::private_key_from_pem(&read(private_key_file)?)
+        .map_err(|e| format!("{} is not a PEM private key: {}", private_key_file, e))?;
+
+    let public_key = certificate.public_key().map_err(|e| e.to_string())?;
+    if public_key.public_eq(&private_key) {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} is not the private key of {}",
+            private_key_file, certificate_file
+        ))
+    }
+}
+
+#[cfg(test)]
+mod cert_tests {
+    use super::*;
+    use openssl::ssl::{SslAcceptor, SslMethod};
+
+    #[test]
+    fn certificate_matches_key() {
+        let (certificate, private_key) = self_signed().unwrap();
+        let certificate = X509::from_pem(&certificate).unwrap();
+        let private_key = PKey::private_key_from_pem(&private_key).unwrap();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(&certificate).unwrap();
+        acceptor.set_private_key(&private_key).unwrap();
+        acceptor.check_private_key().unwrap();
+        assert!(certificate.verify(&private_key).unwrap());
+    }
+
+    #[test]
+    fn key_pairs() {
+        assert_eq!(check_key_pair("cert.pem", "private.pem"), Ok(()));
+        assert_eq!(
+            check_key_pair(
+                "test_data/client_auth/client.pem",
+                "test_data/client_auth/client_key.pem"
+            ),
+            Ok(())
+        );
+        assert!(check_key_pair("cert.pem", "test_data/client_auth/client_key.pem").is_err());
+        assert!(check_key_pair("cert.pem", "cert.pem").is_err());
+    }
+
+    #[test]
+    fn existing_files_are_kept() {
+        assert_eq!(
+            generate("cert.pem", "new_private.pem").unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(fs::metadata("new_private.pem").is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use clap::{Args, Parser, Subcommand};
+use std::env;
+
+use crate::config::{Config, ConfigFormat};
+
+/// MPEG-DASH media server
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Used when no subcommand is given
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the files. This is the default
+    Serve(ServeArgs),
+    /// Check the configuration and exit
+    CheckConfig(ConfigArgs),
+    /// Check that DASH manifests follow the standard and exit
+    CheckMpd {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Print a configuration file with the default settings
+    GenConfig {
+        /// json, toml or yaml. The settings are documented in toml and yaml
+        #[arg(long, value_parser = parse_format, default_value = "json")]
+        format: ConfigFormat,
+    },
+    /// Write a self signed certificate for local testing
+    GenCert {
+        #[arg(default_value = "cert.pem")]
+        certificate_file: String,
+        #[arg(default_value = "private.pem")]
+        private_key_file: String,
+    },
+}
+
+#[derive(Args, Clone)]
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Detach from the terminal and run in the background
+    #[arg(long)]
+    pub daemon: bool,
+    /// Write the process id to this file
+    #[arg(long)]
+    pub pid_file: Option<String>,
+    /// Append the output to this file instead of the terminal.
+    /// SIGUSR1 opens it again, e.g. after logrotate
+    #[arg(long)]
+    pub log_file: Option<String>,
+}
+
+/// Where the configuration is read from. The flags override the
+/// environment variables which override the configuration file.
+#[derive(Args, Clone)]
+pub struct ConfigArgs {
+    /// Configuration file [default: config.json]
+    #[arg(short, long)]
+    config: Option<String>,
+    /// Configuration file, same as --config
+    #[arg(conflicts_with = "config", hide = true)]
+    config_path: Option<String>,
+    /// json, toml or yaml. Picked by the file extension by default
+    #[arg(long, value_parser = parse_format)]
+    format: Option<ConfigFormat>,
+    /// Override network.port
+    #[arg(long)]
+    port: Option<String>,
+    /// Override network.address
+    #[arg(long)]
+    address: Option<String>,
+    /// Override network.documentRoot
+    #[arg(long)]
+    root: Option<String>,
+}
+
+fn parse_format(name: &str) -> Result<ConfigFormat, String> {
+    ConfigFormat::from_name(name).ok_or_else(|| format!("unknown format {}", name))
+}
+
+impl ConfigArgs {
+    pub fn config_path(&self) -> &str {
+        self.config
+            .as_deref()
+            .or(self.config_path.as_deref())
+            .unwrap_or("config.json")
+    }
+
+    /// Load the file and apply the MPEG_DASH_* environment variables and the flags on top of it.
+    /// The result is validated.
+    pub fn load(&self) -> Result<Config, String> {
+        let path = self.config_path();
+        let config = match self.format {
+            Some(format) => Config::load_as(path, format)?,
+            None => Config::load(path)?,
+        };
+        let mut config = config.with_env_overrides(env::vars())?;
+
+        if let Some(port) = &self.port {
+            config.network.port = port.clone();
+        }
+        if let Some(address) = &self.address {
+            config.network.address = address.clone();
+        }
+        if let Some(root) = &self.root {
+            config.network.document_root = root.clone();
+        }
+
+        config
+            .validate()
+            .map_err(|errors| format!("Invalid configuration:\n  {}", errors.join("\n  ")))?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Cli {
+        Cli::try_parse_from(args).unwrap()
+    }
+
+    #[test]
+    fn positional_config_path() {
+        let cli = parse(&["mpeg-dash", "test_data/config_full.json"]);
+        assert!(cli.command.is_none());
+        assert_eq!(cli.serve.config.config_path(), "test_data/config_full.json");
+        assert_eq!(
+            parse(&["mpeg-dash"]).serve.config.config_path(),
+            "config.json"
+        );
+        assert!(Cli::try_parse_from(["mpeg-dash", "a.json", "--config", "b.json"]).is_err());
+    }
+
+    #[test]
+    fn flags_override_file() {
+        let cli = parse(&[
+            "mpeg-dash",
+            "serve",
+            "--config",
+            "test_data/config_full.toml",
+            "--port",
+            "8000",
+            "--root",
+            "/srv/media",
+        ]);
+        let config = match cli.command {
+            Some(Command::Serve(args)) => args.config.load().unwrap(),
+            _ => panic!("expected serve"),
+        };
+        assert_eq!(config.network.port, "8000");
+        assert_eq!(config.network.document_root, "/srv/media");
+        assert_eq!(config.network.address, "127.0.0.1");
+    }
+
+    #[test]
+    fn daemon_flags() {
+        let cli = parse(&["mpeg-dash", "--daemon", "--pid-file", "dash.pid", "a.json"]);
+        assert!(cli.serve.daemon);
+        assert_eq!(cli.serve.pid_file.as_deref(), Some("dash.pid"));
+        assert_eq!(cli.serve.config.config_path(), "a.json");
+        // Only for serving
+        assert!(Cli::try_parse_from(["mpeg-dash", "check-config", "--daemon"]).is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        match parse(&["mpeg-dash", "gen-config", "--format", "yaml"]).command {
+            Some(Command::GenConfig { format }) => assert_eq!(format, ConfigFormat::Yaml),
+            _ => panic!("expected gen-config"),
+        }
+        assert!(Cli::try_parse_from(["mpeg-dash", "gen-config", "--format", "ini"]).is_err());
+        match parse(&["mpeg-dash", "check-mpd", "a.mpd", "b.mpd"]).command {
+            Some(Command::CheckMpd { files }) => assert_eq!(files, vec!["a.mpd", "b.mpd"]),
+            _ => panic!("expected check-mpd"),
+        }
+        assert!(Cli::try_parse_from(["mpeg-dash", "check-mpd"]).is_err());
+        // Serve flags can't be mixed with other subcommands
+        assert!(Cli::try_parse_from(["mpeg-dash", "gen-cert", "--port", "1"]).is_err());
+    }
+}
@@ -1,5 +1,11 @@
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::fs;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 /// Default ipv4 address
 fn def_ipv4_addr() -> String {
@@ -16,31 +22,241 @@ fn def_allow_origin() -> String {
     "*".to_string()
 }
 
+/// Default directory the content is served from. The working directory
+fn def_document_root() -> String {
+    ".".to_string()
+}
+
+/// Default preload hints for the init segments
+fn def_preload_init_segments() -> PreloadMode {
+    PreloadMode::Off
+}
+
 /// Default structure for network in Config
 fn def_network() -> Network {
     Network {
         port: def_ipv4_port(),
         address: def_ipv4_addr(),
         allow_origin: def_allow_origin(),
+        preload_init_segments: def_preload_init_segments(),
+        early_hints: false_value(),
+        document_root: def_document_root(),
+        redirect_port: None,
+        listeners: def_listeners(),
+        dual_stack: false_value(),
+        socket_options: def_socket_options(),
+        trusted_proxies: def_trusted_proxies(),
+        proxy_protocol: false_value(),
+    }
+}
+
+/// Default structure for socket options in Network
+fn def_socket_options() -> SocketOptions {
+    SocketOptions {
+        tcp_no_delay: false_value(),
+        send_buffer_size: def_socket_buffer_size(),
+        receive_buffer_size: def_socket_buffer_size(),
+        backlog: def_backlog(),
+    }
+}
+
+/// Default socket buffer size in bytes. 0 (the system default)
+fn def_socket_buffer_size() -> usize {
+    0
+}
+
+/// Default length of the queue of connections waiting to be accepted, same as std uses
+fn def_backlog() -> u32 {
+    128
+}
+
+/// Default structure for logging in Config
+fn def_logging() -> Logging {
+    Logging {
+        level: def_log_level(),
+        format: def_log_format(),
+        access_log: None,
+        missing_segments: false,
     }
 }
 
+/// Default realm of the Basic auth challenge. Restricted
+fn def_basic_auth_realm() -> String {
+    "Restricted".to_string()
+}
+
+/// Default signing algorithm of the JWT tokens. HS256
+fn def_jwt_algorithm() -> JwtAlgorithm {
+    JwtAlgorithm::Hs256
+}
+
+/// Default paths that need a JWT token. None
+fn def_jwt_rules() -> Vec<JwtRule> {
+    vec![]
+}
+
+/// Default level of the server log. Info
+fn def_log_level() -> LogLevel {
+    LogLevel::Info
+}
+
+/// Default format of the server log. Plain text
+fn def_log_format() -> LogFormat {
+    LogFormat::Plain
+}
+
+/// Default address of the admin API. Only reachable from the same host
+fn def_admin_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+/// Default trusted proxies. None
+fn def_trusted_proxies() -> Vec<String> {
+    vec![]
+}
+
+/// Default extra listeners. None
+fn def_listeners() -> Vec<Listener> {
+    vec![]
+}
+
 /// Default ThreadPool size
 fn def_thread_pool_size() -> usize {
     4
 }
 
+/// Default largest ThreadPool size. 0 (same as threadPoolSize)
+fn def_max_thread_pool_size() -> usize {
+    0
+}
+
 /// Default tcp connection timeout in seconds
 fn def_tcp_connection_timeout() -> f64 {
     // PHP / Apache seems to use 30 secs so that's probably a good value
     30.0
 }
 
+/// Default time in seconds for receiving a whole request head
+fn def_header_timeout() -> f64 {
+    20.0
+}
+
+/// Default maximum length of the request target in bytes
+fn def_max_uri_length() -> usize {
+    2048
+}
+
+/// Default maximum size of a request head in bytes
+fn def_max_request_size() -> usize {
+    4096
+}
+
+/// Default maximum length of the request line in bytes
+fn def_max_request_line_length() -> usize {
+    4096
+}
+
+/// Default maximum number of header lines in a request
+fn def_max_headers() -> usize {
+    64
+}
+
+/// Default maximum length of a header line in bytes
+fn def_max_header_length() -> usize {
+    4096
+}
+
+/// Default maximum size of a request body in bytes
+fn def_max_body_size() -> u64 {
+    // Big enough for a few seconds of high bitrate video segments
+    16 * 1024 * 1024
+}
+
+/// Default time in seconds to remember that a file doesn't exist
+fn def_not_found_cache_ttl() -> f64 {
+    // Disabled. Live segments have to show up as soon as they are written
+    0.0
+}
+
+/// Default number of segments to prefetch
+fn def_prefetch_segments() -> usize {
+    0
+}
+
+/// Default time in seconds an idle persistent connection is kept open
+fn def_keep_alive_timeout() -> f64 {
+    // Long enough to cover the few seconds between segment requests
+    5.0
+}
+
+/// Default size of the file cache in bytes
+fn def_file_cache_size() -> u64 {
+    // Disabled
+    0
+}
+
+/// Default size in bytes of the biggest file that is cached
+fn def_file_cache_max_entry_size() -> u64 {
+    // Manifests and init segments, not media segments
+    1024 * 1024
+}
+
+/// Default time in seconds a file is kept in the file cache
+fn def_file_cache_ttl() -> f64 {
+    60.0
+}
+
+/// Default time in seconds open connections can finish their responses when the server stops
+fn def_drain_timeout() -> f64 {
+    30.0
+}
+
+/// Default limit of concurrent connections. 0 (no limit)
+fn def_max_connections() -> usize {
+    0
+}
+
+/// Default limit of connections waiting for a free thread. 0 (no limit)
+fn def_max_queued_connections() -> usize {
+    0
+}
+
+/// Default limit of concurrent connections from one client address. 0 (no limit)
+fn def_max_connections_per_address() -> usize {
+    0
+}
+
+/// Default bandwidth limit of a connection in bytes per second. 0 (no limit)
+fn def_max_bytes_per_second_per_connection() -> u64 {
+    0
+}
+
 /// Default structure for performance in Config
 fn def_performance() -> Performance {
     Performance {
         thread_pool_size: def_thread_pool_size(),
+        max_thread_pool_size: def_max_thread_pool_size(),
         connection_timeout: def_tcp_connection_timeout(),
+        header_timeout: def_header_timeout(),
+        max_uri_length: def_max_uri_length(),
+        max_request_size: def_max_request_size(),
+        max_request_line_length: def_max_request_line_length(),
+        max_headers: def_max_headers(),
+        max_header_length: def_max_header_length(),
+        max_body_size: def_max_body_size(),
+        not_found_cache_ttl: def_not_found_cache_ttl(),
+        prefetch_segments: def_prefetch_segments(),
+        keep_alive_timeout: def_keep_alive_timeout(),
+        compression: false_value(),
+        precompressed: false_value(),
+        file_cache_size: def_file_cache_size(),
+        file_cache_max_entry_size: def_file_cache_max_entry_size(),
+        file_cache_ttl: def_file_cache_ttl(),
+        drain_timeout: def_drain_timeout(),
+        max_bytes_per_second_per_connection: def_max_bytes_per_second_per_connection(),
+        max_connections: def_max_connections(),
+        max_queued_connections: def_max_queued_connections(),
+        max_connections_per_address: def_max_connections_per_address(),
     }
 }
 
@@ -48,6 +264,10 @@ fn true_value() -> bool {
     true
 }
 
+fn false_value() -> bool {
+    false
+}
+
 /// Default path for tls certificate file
 fn def_ssl_cert_path() -> String {
     "cert.pem".to_string()
@@ -58,20 +278,201 @@ fn def_ssl_private_key_path() -> String {
     "private.pem".to_string()
 }
 
+/// Default time in seconds between checks for a renewed certificate
+fn def_certificate_check_interval() -> f64 {
+    60.0
+}
+
 /// Default structure for security in Config
 fn def_security() -> Security {
     Security {
         https: true_value(),
         certificate_file: def_ssl_cert_path(),
         private_key_file: def_ssl_private_key_path(),
+        content_digest: false_value(),
+        certificate_check_interval: def_certificate_check_interval(),
+        client_auth: None,
+        rate_limit: None,
+        allow_cidrs: def_cors_list(),
+        deny_cidrs: def_cors_list(),
+        signed_urls: None,
+        jwt: None,
+        basic_auth: None,
+        hotlink_protection: None,
+        user: None,
+        group: None,
+    }
+}
+
+/// Default caching headers for a class of paths. Nothing is sent
+fn def_cache_headers() -> CacheHeaders {
+    CacheHeaders {
+        cache_control: None,
+        surrogate_control: None,
+        cdn_cache_control: None,
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, PartialOrd)]
+/// Default structure for cacheControl in Config
+fn def_cache_control() -> CacheControl {
+    CacheControl {
+        manifest: def_cache_headers(),
+        segment: def_cache_headers(),
+        immutable_prefix: None,
+    }
+}
+
+/// Default for the CORS lists that are empty by default
+fn def_cors_list() -> Vec<String> {
+    vec![]
+}
+
+/// Default burst of the rate limit. 20 requests
+fn def_rate_limit_burst() -> u32 {
+    20
+}
+
+/// Default methods allowed in CORS preflights. Everything the server supports
+fn def_cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "HEAD".to_string(), "OPTIONS".to_string()]
+}
+
+/// Default time in seconds browsers can cache a CORS preflight
+fn def_cors_max_age() -> u64 {
+    86400
+}
+
+/// Default structure for cors in Config
+fn def_cors() -> Cors {
+    Cors {
+        allowed_origins: def_cors_list(),
+        allowed_methods: def_cors_allowed_methods(),
+        allowed_headers: def_cors_list(),
+        exposed_headers: def_cors_list(),
+        allow_credentials: false_value(),
+        max_age: def_cors_max_age(),
+    }
+}
+
+/// Default content types. Only the built-in ones
+fn def_mime_types() -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+/// Default response header rules. No rules
+fn def_response_headers() -> Vec<HeaderRule> {
+    vec![]
+}
+
+/// Default rewrite rules. No rules
+fn def_rewrites() -> Vec<Rewrite> {
+    vec![]
+}
+
+/// Default name of the generated manifests
+fn def_generated_manifest_name() -> String {
+    "manifest.mpd".to_string()
+}
+
+/// Default minBufferTime of the generated manifests in seconds
+fn def_generated_min_buffer_time() -> f64 {
+    2.0
+}
+
+/// Default url path prefixes of the live streams. None
+fn def_live_prefixes() -> Vec<String> {
+    vec![]
+}
+
+/// Default minimumUpdatePeriod of the live manifests in seconds
+fn def_minimum_update_period() -> f64 {
+    2.0
+}
+
+/// Default timeShiftBufferDepth of the live manifests in seconds
+fn def_time_shift_buffer_depth() -> f64 {
+    60.0
+}
+
+/// Default length of the segments of the WebVTT subtitles in seconds
+fn def_subtitle_segment_duration() -> f64 {
+    // Not split, the players download the whole file
+    0.0
+}
+
+/// Default file name ending of the segments that are being written
+fn def_in_progress_suffix() -> String {
+    ".tmp".to_string()
+}
+
+/// Default seconds a segment being written can go without growing
+fn def_idle_timeout() -> f64 {
+    2.0
+}
+
+/// Default url path prefix of the ingest endpoint
+fn def_ingest_prefix() -> String {
+    "/ingest/".to_string()
+}
+
+/// Default live stream directories that are pruned. None
+fn def_prune_directories() -> Vec<String> {
+    vec![]
+}
+
+/// Default seconds a live media segment is kept
+fn def_prune_after() -> f64 {
+    300.0
+}
+
+/// Default encrypted streams. None
+fn def_content_protection() -> Vec<ProtectedStream> {
+    vec![]
+}
+
+/// Default protection scheme, AES-CTR of the whole samples
+fn def_protection_scheme() -> String {
+    "cenc".to_string()
+}
+
+/// Default DRM systems of an encrypted stream. None
+fn def_drm_systems() -> Vec<DrmSystem> {
+    vec![]
+}
+
+/// Default url path of the ClearKey license server
+fn def_license_path() -> String {
+    "/license".to_string()
+}
+
+/// Default ClearKey keys. None
+fn def_clear_keys() -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+/// Default error pages. No pages
+fn def_error_pages() -> BTreeMap<String, ErrorPage> {
+    BTreeMap::new()
+}
+
+/// Default content type of the error pages. text/html; charset=utf-8
+fn def_error_page_content_type() -> String {
+    "text/html; charset=utf-8".to_string()
+}
+
+/// Default tenants. No tenants
+fn def_tenants() -> Vec<Tenant> {
+    vec![]
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
 #[serde(rename_all = "camelCase")]
 pub struct Network {
-    /// IPv4 address.
-    /// E.g. (0.0.0.0) for all connections, (127.0.0.1) for localhost only.
+    /// IPv4 or IPv6 address. IPv6 addresses can be written with or without brackets.
+    /// E.g. (0.0.0.0) for all IPv4 connections, (::) for all IPv6 connections,
+    /// (127.0.0.1) or (::1) for localhost only.
+    /// The sockets passed by systemd socket activation are used instead of
+    /// address, port, listeners and redirectPort when there are any.
     /// ## Defaults to "0.0.0.0".
     #[serde(default = "def_ipv4_addr")]
     pub address: String,
@@ -84,9 +485,110 @@ pub struct Network {
     /// ## Defaults to "*".
     #[serde(default = "def_allow_origin")]
     pub allow_origin: String,
+    /// Add "Link: <...>; rel=preload" headers for the init segments when
+    /// serving a manifest so clients and CDNs can fetch them early.
+    /// "off", "lowest" (lowest bandwidth of every adaptation set) or "all".
+    /// ## Defaults to "off".
+    #[serde(default = "def_preload_init_segments")]
+    pub preload_init_segments: PreloadMode,
+    /// Send the preload links in a "103 Early Hints" response before the
+    /// manifest itself. Only HTTP/1.1 clients get the early hints.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub early_hints: bool,
+    /// Directory the content is served from. Requests that resolve outside
+    /// of it, with ".." or symlinks, are answered with 404.
+    /// ## Defaults to "." (the working directory).
+    #[serde(default = "def_document_root")]
+    pub document_root: String,
+    /// Port of an extra plain http listener that redirects every request to
+    /// the same url over https with "301 Moved Permanently". E.g. "80" for
+    /// users who type http urls. Only used when https is enabled.
+    /// ## Defaults to none (no redirects).
+    pub redirect_port: Option<String>,
+    /// Extra sockets the content is served from next to address and port.
+    /// E.g. [{"port": "8080", "tls": false}] for an internal health checker.
+    /// ## Defaults to [].
+    #[serde(default = "def_listeners")]
+    pub listeners: Vec<Listener>,
+    /// Listen on both IPv4 and IPv6 with a socket each when the address is
+    /// "0.0.0.0" or "::". Applies to every listener. Other addresses are
+    /// bound as is.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub dual_stack: bool,
+    /// Socket options of the listeners. The sockets passed by systemd keep
+    /// the backlog systemd gave them.
+    #[serde(default = "def_socket_options")]
+    pub socket_options: SocketOptions,
+    /// Addresses or CIDR ranges of the reverse proxies in front of the
+    /// server. The client address of their requests is read from Forwarded
+    /// or X-Forwarded-For for the access log, rate limiting and prefetching.
+    /// E.g. ["10.0.0.0/8"]
+    /// ## Defaults to [] (the headers are ignored).
+    #[serde(default = "def_trusted_proxies")]
+    pub trusted_proxies: Vec<String>,
+    /// Every connection to the listeners starts with a PROXY protocol
+    /// header, version 1 or 2, with the client's address. The address is
+    /// used like the one of the socket, also for security.allowCidrs and
+    /// performance.maxConnectionsPerAddress. Connections without the header
+    /// are closed so the listeners should only be reachable by the proxy.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub proxy_protocol: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Listener {
+    /// ## Defaults to "0.0.0.0".
+    #[serde(default = "def_ipv4_addr")]
+    pub address: String,
+    pub port: String,
+    /// Use TLS on this socket. The certificate files are needed even if
+    /// security.https is false.
+    /// ## Defaults to security.https.
+    pub tls: Option<bool>,
+    /// Replaces network.socketOptions for this listener as a whole.
+    /// ## Defaults to network.socketOptions.
+    pub socket_options: Option<SocketOptions>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, PartialOrd)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketOptions {
+    /// Send small writes right away instead of collecting them into bigger
+    /// packets (TCP_NODELAY). Lowers the latency of manifests and the first
+    /// bytes of segments.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub tcp_no_delay: bool,
+    /// Kernel send buffer of the connections in bytes (SO_SNDBUF). Bigger
+    /// buffers keep fast long distance connections busy.
+    /// ## Defaults to 0 (the system default).
+    #[serde(default = "def_socket_buffer_size")]
+    pub send_buffer_size: usize,
+    /// Kernel receive buffer of the connections in bytes (SO_RCVBUF).
+    /// ## Defaults to 0 (the system default).
+    #[serde(default = "def_socket_buffer_size")]
+    pub receive_buffer_size: usize,
+    /// How many connections can wait to be accepted before the kernel
+    /// starts to drop them. Limited by net.core.somaxconn on Linux.
+    /// ## Defaults to 128.
+    #[serde(default = "def_backlog")]
+    pub backlog: u32,
+}
+
+/// Which representations get a preload hint for their init segment
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum PreloadMode {
+    Off,
+    Lowest,
+    All,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
 #[serde(rename_all = "camelCase")]
 pub struct Performance {
     /// How many threads are handling the connection.
@@ -94,16 +596,138 @@ pub struct Performance {
     /// ## Defaults to 4.
     #[serde(default = "def_thread_pool_size")]
     pub thread_pool_size: usize,
-    /// How long will the server wait for data before closing the connection
+    /// How many threads the pool can grow to when connections are waiting
+    /// for a free thread. Threads over threadPoolSize stop after being idle
+    /// for 30 seconds. Not changed by a configuration reload.
+    /// ## Defaults to 0 (the pool stays at threadPoolSize).
+    #[serde(default = "def_max_thread_pool_size")]
+    pub max_thread_pool_size: usize,
+    /// How long will the server wait for data before closing the connection.
+    /// The wait starts again whenever something arrives.
+    /// ## Defaults to 30.
     #[serde(default = "def_tcp_connection_timeout")]
     pub connection_timeout: f64,
+    /// How many seconds a client has for sending the whole request head,
+    /// no matter how often it sends a byte. Stops slowloris clients from
+    /// holding the workers. Counted from the connection or, on a persistent
+    /// connection, from the first byte of the next request. The body of a
+    /// request answered with an error is read and thrown away for as long.
+    /// ## Defaults to 20.
+    #[serde(default = "def_header_timeout")]
+    pub header_timeout: f64,
+    /// Maximum length of the request target (path + query) in bytes.
+    /// Longer requests are answered with 414 URI Too Long.
+    /// ## Defaults to 2048.
+    #[serde(default = "def_max_uri_length")]
+    pub max_uri_length: usize,
+    /// Maximum size of a request head (request line + headers) in bytes.
+    /// Larger heads are answered with 413 Payload Too Large. Also limits the
+    /// trailers of chunked request bodies, which are answered with 400.
+    /// ## Defaults to 4096.
+    #[serde(default = "def_max_request_size")]
+    pub max_request_size: usize,
+    /// Maximum length of the request line (method + target + version) in bytes.
+    /// Longer lines are answered with 414 URI Too Long.
+    /// ## Defaults to 4096.
+    #[serde(default = "def_max_request_line_length")]
+    pub max_request_line_length: usize,
+    /// Maximum number of header lines in a request. Requests with more are
+    /// answered with 431 Request Header Fields Too Large.
+    /// ## Defaults to 64.
+    #[serde(default = "def_max_headers")]
+    pub max_headers: usize,
+    /// Maximum length of a single header line in bytes. Longer lines are
+    /// answered with 431 Request Header Fields Too Large.
+    /// ## Defaults to 4096.
+    #[serde(default = "def_max_header_length")]
+    pub max_header_length: usize,
+    /// Maximum size of a request body in bytes.
+    /// Larger bodies are answered with 413 Payload Too Large.
+    /// ## Defaults to 16 MiB.
+    #[serde(default = "def_max_body_size")]
+    pub max_body_size: u64,
+    /// How many seconds a missing file is answered with 404 without checking
+    /// the filesystem again. Players retrying segments at the live edge cause
+    /// a lot of these so even a fraction of a second helps.
+    /// ## Defaults to 0 (disabled).
+    #[serde(default = "def_not_found_cache_ttl")]
+    pub not_found_cache_ttl: f64,
+    /// How many segments after the requested one are read ahead from the disk
+    /// once a client is playing segments in order. Smooths out disk latency
+    /// spikes at the live edge. Uses the kernel readahead, only on Linux.
+    /// ## Defaults to 0 (disabled).
+    #[serde(default = "def_prefetch_segments")]
+    pub prefetch_segments: usize,
+    /// How many seconds a connection is kept open waiting for the next request
+    /// after a response. Players fetching a segment every few seconds can then
+    /// reuse the connection instead of doing a new TLS handshake every time.
+    /// ## Defaults to 5. 0 closes the connection after every response.
+    #[serde(default = "def_keep_alive_timeout")]
+    pub keep_alive_timeout: f64,
+    /// Compress manifests and subtitles with brotli or gzip for clients
+    /// that accept it. Media segments are never compressed.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub compression: bool,
+    /// Send "<file>.br" or "<file>.gz" instead of the file when it exists and
+    /// the client accepts the encoding. Saves compressing static content on
+    /// every request. Any file can have precompressed versions.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub precompressed: bool,
+    /// How many bytes of small, often requested files like manifests and init
+    /// segments are kept in memory. The least recently used files are dropped
+    /// when the cache is full. A file is read again once it changes.
+    /// ## Defaults to 0 (disabled).
+    #[serde(default = "def_file_cache_size")]
+    pub file_cache_size: u64,
+    /// Files bigger than this many bytes are always read from the disk.
+    /// ## Defaults to 1 MiB.
+    #[serde(default = "def_file_cache_max_entry_size")]
+    pub file_cache_max_entry_size: u64,
+    /// How many seconds a file is served from the cache before it's read again
+    /// even if it hasn't changed.
+    /// ## Defaults to 60. 0 keeps the file until it changes.
+    #[serde(default = "def_file_cache_ttl")]
+    pub file_cache_ttl: f64,
+    /// How many seconds the open connections can finish their responses
+    /// after SIGINT or SIGTERM before the server exits anyway. Not changed
+    /// by a configuration reload.
+    /// ## Defaults to 30.
+    #[serde(default = "def_drain_timeout")]
+    pub drain_timeout: f64,
+    /// Response bodies are sent at most this many bytes a second on each
+    /// connection. Stops a single client from saturating the uplink and can
+    /// emulate a slow network when testing adaptive bitrate players.
+    /// ## Defaults to 0 (no limit).
+    #[serde(default = "def_max_bytes_per_second_per_connection")]
+    pub max_bytes_per_second_per_connection: u64,
+    /// How many connections are served or queued for the thread pool at once.
+    /// Plain http connections over the limit get "503 Service Unavailable",
+    /// TLS connections are closed right away.
+    /// ## Defaults to 0 (no limit).
+    #[serde(default = "def_max_connections")]
+    pub max_connections: usize,
+    /// How many connections can wait for a free thread in the pool. When
+    /// the queue is full, plain http connections get "503 Service
+    /// Unavailable" right away and TLS connections are closed, instead of
+    /// every client waiting longer and longer.
+    /// ## Defaults to 0 (no limit).
+    #[serde(default = "def_max_queued_connections")]
+    pub max_queued_connections: usize,
+    /// How many connections one client address can have open at once.
+    /// Connections over the limit are turned away before the TLS handshake,
+    /// plain http ones with "429 Too Many Requests".
+    /// ## Defaults to 0 (no limit).
+    #[serde(default = "def_max_connections_per_address")]
+    pub max_connections_per_address: usize,
 }
 
-#[derive(Debug, Deserialize, PartialEq, PartialOrd)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
 #[serde(rename_all = "camelCase")]
 pub struct Security {
-    /// Is https enabled.
-    /// # Currently this is required to be on.
+    /// Is https enabled. Without it the server speaks plain http, e.g. behind
+    /// a proxy that terminates TLS. The certificate files aren't needed then.
     /// ## Defaults to true
     #[serde(default = "true_value")]
     pub https: bool,
@@ -115,9 +739,463 @@ pub struct Security {
     /// ## Defaults to "private.pem"
     #[serde(default = "def_ssl_private_key_path")]
     pub private_key_file: String,
+    /// Send the SHA-256 of the content in "Repr-Digest" and "Digest" headers
+    /// so caches and clients can verify the integrity of the media.
+    /// ## Defaults to false
+    #[serde(default = "false_value")]
+    pub content_digest: bool,
+    /// How often in seconds the certificate and private key files are checked
+    /// for changes. Changed files are loaded for the new connections so a
+    /// renewed certificate is used without a restart.
+    /// ## Defaults to 60. 0 never checks the files again.
+    #[serde(default = "def_certificate_check_interval")]
+    pub certificate_check_interval: f64,
+    /// Ask the clients for a certificate signed by the configured CA.
+    /// Invalid certificates fail the TLS handshake and requests without a
+    /// certificate get "403 Forbidden" unless the path is exempt.
+    /// ## Defaults to none (no client certificates).
+    pub client_auth: Option<ClientAuth>,
+    /// Limit on the requests of each client IP address. Requests over the
+    /// limit are answered with "429 Too Many Requests" and Retry-After.
+    /// ## Defaults to none (no limit).
+    pub rate_limit: Option<RateLimit>,
+    /// Addresses and CIDR ranges that can connect, e.g. the ranges of the CDN
+    /// edges. Other clients are disconnected as soon as they connect.
+    /// ## Defaults to [] (everyone can connect).
+    #[serde(default = "def_cors_list")]
+    pub allow_cidrs: Vec<String>,
+    /// Addresses and CIDR ranges that can't connect even if allowCidrs has them
+    /// ## Defaults to [].
+    #[serde(default = "def_cors_list")]
+    pub deny_cidrs: Vec<String>,
+    /// Only serve urls signed with a shared secret, e.g. per-viewer stream
+    /// urls that expire. Requests without a valid signature get "403 Forbidden".
+    /// ## Defaults to none (no signatures needed).
+    pub signed_urls: Option<SignedUrls>,
+    /// Ask for a JWT in "Authorization: Bearer <token>" on the paths of the
+    /// rules, e.g. for entitlements issued by a separate backend. Missing or
+    /// invalid tokens get "401 Unauthorized" and tokens without the scope of
+    /// the path "403 Forbidden".
+    /// ## Defaults to none (no tokens needed).
+    pub jwt: Option<Jwt>,
+    /// Ask for a user and password from the credentials file on the
+    /// protected paths, e.g. for staging content. Requests without valid
+    /// credentials get "401 Unauthorized".
+    /// ## Defaults to none (no passwords needed).
+    pub basic_auth: Option<BasicAuth>,
+    /// Only serve manifests and segments to pages of the allowed sites, going
+    /// by the Origin or Referer header. Other sites get "403 Forbidden".
+    /// ## Defaults to none (any site can embed the streams).
+    pub hotlink_protection: Option<HotlinkProtection>,
+    /// User the server switches to after binding the ports and reading the
+    /// private key as root. Renewed certificates are read as this user.
+    /// ## Defaults to none (the user that started the server).
+    pub user: Option<String>,
+    /// Group the server switches to with the user.
+    /// ## Defaults to none (the primary group of the user).
+    pub group: Option<String>,
+}
+
+/// Caching headers sent with a class of paths.
+/// Headers that are not set are not sent.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheHeaders {
+    /// Value of the "Cache-Control" header meant for the browsers.
+    /// E.g. "no-cache" for live manifests.
+    pub cache_control: Option<String>,
+    /// Value of the "Surrogate-Control" header. Used by reverse proxies and
+    /// CDNs (Fastly, Akamai, Varnish) instead of Cache-Control.
+    /// E.g. "max-age=31536000" to keep segments in the edge caches.
+    pub surrogate_control: Option<String>,
+    /// Value of the "CDN-Cache-Control" header (RFC 9213). Like
+    /// surrogateControl but for the CDNs that support the newer header.
+    pub cdn_cache_control: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheControl {
+    /// Headers for the DASH manifests (.mpd)
+    #[serde(default = "def_cache_headers")]
+    pub manifest: CacheHeaders,
+    /// Headers for everything else: init and media segments etc.
+    #[serde(default = "def_cache_headers")]
+    pub segment: CacheHeaders,
+    /// Url prefix for fingerprinted paths, e.g. "/vod".
+    /// "/vod/<hash>/movie/stream.mpd" serves "movie/stream.mpd" with
+    /// "Cache-Control: public, max-age=31536000, immutable". The hash is 8 to
+    /// 64 first hex digits of the SHA-256 of the file and other hashes get 404,
+    /// so the url changes when the content does. Generated responses and
+    /// dynamic manifests aren't immutable since they change behind the url.
+    /// ## Defaults to None (disabled).
+    pub immutable_prefix: Option<String>,
+}
+
+impl CacheControl {
+    /// Caching headers for the requested path
+    pub fn for_path(&self, path: &str) -> &CacheHeaders {
+        if path.ends_with(".mpd") {
+            &self.manifest
+        } else {
+            &self.segment
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Cors {
+    /// Origins that are allowed to use the content. The origin of the request
+    /// is sent back in Access-Control-Allow-Origin if it's in the list and
+    /// nothing is sent if it isn't. "*" allows every origin.
+    /// E.g. ["https://player.example.com", "https://admin.example.com"]
+    /// ## Defaults to [] (network.allowOrigin is used).
+    #[serde(default = "def_cors_list")]
+    pub allowed_origins: Vec<String>,
+    /// Methods in Access-Control-Allow-Methods of preflight responses.
+    /// ## Defaults to ["GET", "HEAD", "OPTIONS"].
+    #[serde(default = "def_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Headers in Access-Control-Allow-Headers of preflight responses.
+    /// ## Defaults to [] (whatever the browser asks for is allowed).
+    #[serde(default = "def_cors_list")]
+    pub allowed_headers: Vec<String>,
+    /// Response headers the player can read, e.g. ["Content-Length", "Date"].
+    /// ## Defaults to [].
+    #[serde(default = "def_cors_list")]
+    pub exposed_headers: Vec<String>,
+    /// Send "Access-Control-Allow-Credentials: true" so players can send
+    /// cookies and other credentials. "*" isn't allowed by browsers with
+    /// credentials so the request's origin is sent back instead.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub allow_credentials: bool,
+    /// How many seconds browsers can cache a preflight response.
+    /// ## Defaults to 86400.
+    #[serde(default = "def_cors_max_age")]
+    pub max_age: u64,
+}
+
+/// Overrides for the caching headers of a single title.
+/// Headers that are set replace the global ones, others are kept.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheControlOverrides {
+    pub manifest: Option<CacheHeaders>,
+    pub segment: Option<CacheHeaders>,
+}
+
+/// Per-title settings read from a "<stream>.dash.json" sidecar file.
+/// Every field is optional and the ones that are set replace the global
+/// value for the title.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TitleOverrides {
+    /// Replaces network.allowOrigin
+    pub allow_origin: Option<String>,
+    /// Replaces cacheControl.manifest and/or cacheControl.segment
+    pub cache_control: Option<CacheControlOverrides>,
+    /// Replaces network.preloadInitSegments
+    pub preload_init_segments: Option<PreloadMode>,
+    /// Replaces network.earlyHints
+    pub early_hints: Option<bool>,
+    /// Replaces security.contentDigest
+    pub content_digest: Option<bool>,
+    /// Whether the title needs the signed url of security.signedUrls and the
+    /// bearer token of security.jwt. false lets anyone in and true requires
+    /// them outside of the exempt and protected prefixes too.
+    pub require_token: Option<bool>,
+    /// false leaves the ContentProtection elements of contentProtection out
+    /// of the title's manifests
+    pub content_protection: Option<bool>,
+    /// Representations left out of the title's manifests
+    pub manifest_filter: Option<ManifestFilter>,
+}
+
+/// Limits of the representations listed in a manifest. The ones over
+/// a limit are left out.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct ManifestFilter {
+    /// Highest @bandwidth, in bits per second
+    pub max_bandwidth: Option<u64>,
+    /// Highest @height of the video representations
+    pub max_height: Option<u64>,
+}
+
+/// Headers for the paths matching a glob. "*" matches anything but "/",
+/// "**" matches across directories and "?" one character. Patterns without
+/// a "/" are matched against the file name, others against the whole url path.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct HeaderRule {
+    /// E.g. "*.m4s" or "/live/**/*.mpd"
+    pub pattern: String,
+    /// Header names and values, e.g. {"Timing-Allow-Origin": "*"}
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Rewrite of the url path. Either `prefix` or `pattern` is matched against
+/// the path and the matched part is replaced with `to`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Rewrite {
+    /// Paths starting with the prefix, e.g. "/live/chan1/"
+    pub prefix: Option<String>,
+    /// Regular expression, e.g. "^/v[0-9]+/(.*)$"
+    pub pattern: Option<String>,
+    /// Replacement. The captures of a pattern can be used with $1 or
+    /// ${name}, e.g. "/archive/$1"
+    pub to: String,
+}
+
+/// Either a file or an inline body
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorPage {
+    /// File with the body. It's read for every response so changes show up right away.
+    pub file: Option<String>,
+    /// The body itself. "{status}" is replaced with the status code.
+    pub body: Option<String>,
+    /// ## Defaults to "text/html; charset=utf-8".
+    #[serde(default = "def_error_page_content_type")]
+    pub content_type: String,
+}
+
+/// Tenant with its own content, selected by hostname or url prefix
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Tenant {
+    /// Name used to identify the tenant
+    pub name: String,
+    /// Requests with this Host header belong to the tenant.
+    /// E.g. "acme.example.com"
+    pub host: Option<String>,
+    /// Requests under this url prefix belong to the tenant. The prefix is
+    /// removed before the file is looked up.
+    /// E.g. "/acme" serves "/acme/live/stream.mpd" from "<root>/live/stream.mpd"
+    pub prefix: Option<String>,
+    /// Directory the tenant's content is served from.
+    /// Paths can't escape it with "..".
+    pub root: String,
+    /// Replaces network.allowOrigin for the tenant
+    pub allow_origin: Option<String>,
+    /// Replaces the cors section for the tenant
+    /// ## Defaults to none (the cors section).
+    pub cors: Option<Cors>,
+    /// Certificate sent to the clients that ask for the tenant's host in the
+    /// TLS handshake (SNI). Needs the host and the privateKeyFile too.
+    /// ## Defaults to none (security.certificateFile).
+    pub certificate_file: Option<String>,
+    /// Private key of the tenant's certificate
+    /// ## Defaults to none (security.privateKeyFile).
+    pub private_key_file: Option<String>,
+    /// Limits for the tenant's usage. Requests over the limit get
+    /// "429 Too Many Requests" until the period changes. The usage of the
+    /// period is listed by GET /quotas of the admin API.
+    pub quota: Option<Quota>,
+}
+
+/// How often quota usage starts again from zero. Periods follow UTC calendar days and months
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    /// ## Defaults to daily.
+    #[serde(default = "def_quota_period")]
+    pub period: QuotaPeriod,
+    /// How many requests can be served in a period. No limit if not set
+    pub max_requests: Option<u64>,
+    /// How many bytes of content can be served in a period. No limit if not set.
+    /// The response that goes over the limit is still sent in full.
+    pub max_bytes: Option<u64>,
+}
+
+/// Client certificate authentication, e.g. for the link between the origin and a CDN
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientAuth {
+    /// CA bundle in PEM format the client certificates are verified against
+    pub ca_file: String,
+    /// Url prefixes that can be requested without a client certificate.
+    /// Matched by whole path segments so "/health" doesn't cover "/healthz".
+    /// E.g. ["/health"]
+    /// ## Defaults to [].
+    #[serde(default = "def_cors_list")]
+    pub exempt_prefixes: Vec<String>,
+}
+
+/// Token bucket per client IP address. Protects the origin from players
+/// hammering the manifest.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    /// How many requests a second a client can make on average
+    pub requests_per_second: f64,
+    /// How many requests a client can make at once after being idle.
+    /// ## Defaults to 20.
+    #[serde(default = "def_rate_limit_burst")]
+    pub burst: u32,
+    /// Addresses and CIDR ranges that are never limited, e.g. ["10.0.0.0/8", "::1"]
+    /// ## Defaults to [].
+    #[serde(default = "def_cors_list")]
+    pub exempt: Vec<String>,
+}
+
+/// Urls are signed by adding "expires=<unix time>&token=<signature>" to the
+/// query. The signature is the hex encoded HMAC-SHA256 of "<path>\n<expires>"
+/// where the path is percent-decoded and without the query.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedUrls {
+    /// Secret key of the signatures, shared with whatever hands out the urls
+    pub secret: String,
+    /// Url prefixes that can be requested without a signature.
+    /// Matched by whole path segments so "/health" doesn't cover "/healthz".
+    /// E.g. ["/health"]
+    /// ## Defaults to [].
+    #[serde(default = "def_cors_list")]
+    pub exempt_prefixes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct BasicAuth {
+    /// Name of the protected area shown by the browsers
+    /// ## Defaults to "Restricted".
+    #[serde(default = "def_basic_auth_realm")]
+    pub realm: String,
+    /// htpasswd file with the users. bcrypt, MD5 ("$apr1$") and SHA-1
+    /// ("{SHA}") hashes are supported. The file is read again when it changes.
+    pub credentials_file: String,
+    /// Url prefixes of the protected paths, e.g. ["/staging/"]
+    pub prefixes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct HotlinkProtection {
+    /// Origins of the sites that can embed the streams. "*." matches the
+    /// subdomains of a domain.
+    /// E.g. ["https://player.example.com", "https://*.example.org"]
+    pub allowed_sites: Vec<String>,
+    /// Allow requests without Origin and Referer, e.g. from native apps or
+    /// browsers that don't send the Referer.
+    /// ## Defaults to true.
+    #[serde(default = "true_value")]
+    pub allow_empty: bool,
+}
+
+/// Signature algorithm of the accepted tokens
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum JwtAlgorithm {
+    /// HMAC-SHA256 with the shared secret
+    Hs256,
+    /// RSA PKCS#1 v1.5 with SHA-256, verified with the public key
+    Rs256,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Jwt {
+    /// Either HS256 or RS256. Tokens signed with anything else are rejected.
+    /// ## Defaults to HS256.
+    #[serde(default = "def_jwt_algorithm")]
+    pub algorithm: JwtAlgorithm,
+    /// Secret key of HS256 tokens
+    /// ## Defaults to none.
+    pub secret: Option<String>,
+    /// PEM file with the public key of RS256 tokens
+    /// ## Defaults to none.
+    pub public_key_file: Option<String>,
+    /// Url of the JSON Web Key Set with the public keys of RS256 tokens. The
+    /// keys are fetched again every 10 minutes and when a token has an
+    /// unknown "kid".
+    /// ## Defaults to none.
+    pub jwks_url: Option<String>,
+    /// Required "iss" claim of the tokens
+    /// ## Defaults to none (any issuer).
+    pub issuer: Option<String>,
+    /// Required "aud" claim of the tokens
+    /// ## Defaults to none (any audience).
+    pub audience: Option<String>,
+    /// Paths that need a token. The rule with the longest matching prefix is used.
+    /// ## Defaults to [] (no path needs a token).
+    #[serde(default = "def_jwt_rules")]
+    pub rules: Vec<JwtRule>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct JwtRule {
+    /// Url prefix of the paths, e.g. "/premium/"
+    pub prefix: String,
+    /// Scope the token must have in its space separated "scope" claim
+    /// ## Defaults to none (any valid token).
+    pub scope: Option<String>,
+}
+
+/// Least severe messages written to the server log
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Plain text lines for people or one json object per line for log collectors
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+    Plain,
+    Json,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Logging {
+    /// Messages of the server are written to stderr, or to the --log-file.
+    /// One of off, error, warn, info, debug or trace.
+    /// ## Defaults to info.
+    #[serde(default = "def_log_level")]
+    pub level: LogLevel,
+    /// Either plain or json.
+    /// ## Defaults to plain.
+    #[serde(default = "def_log_format")]
+    pub format: LogFormat,
+    /// File every request is logged to in the Combined Log Format followed by
+    /// the duration in microseconds and the X-Request-Id. Opened again on
+    /// SIGUSR1 for log rotation.
+    /// ## Defaults to none (no access log).
+    pub access_log: Option<String>,
+    /// Check that the SegmentTemplate segments of the served manifests exist
+    /// and log the missing ones. Each version of a manifest is checked once
+    /// and the missing segments are listed by GET /segments/missing of the
+    /// admin API. The urls are looked up from the document root of the
+    /// manifest, the rewrites aren't applied to them.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub missing_segments: bool,
+}
+
+/// Default period of quotas. Daily
+fn def_quota_period() -> QuotaPeriod {
+    QuotaPeriod::Daily
 }
 
-#[derive(Debug, Deserialize, PartialEq, PartialOrd)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
     #[serde(default = "def_network")]
@@ -126,52 +1204,1007 @@ pub struct Config {
     pub performance: Performance,
     #[serde(default = "def_security")]
     pub security: Security,
+    #[serde(default = "def_cache_control")]
+    pub cache_control: CacheControl,
+    #[serde(default = "def_cors")]
+    pub cors: Cors,
+    #[serde(default = "def_logging")]
+    pub logging: Logging,
+    /// Json API for operating the server, on a port of its own.
+    /// ## Defaults to none (no admin API).
+    pub admin: Option<Admin>,
+    /// Content types by lowercase file extension without the dot, e.g. {"m4s": "video/mp4"}.
+    /// They are added to the built-in types and replace them for the same extension.
+    #[serde(default = "def_mime_types")]
+    pub mime_types: BTreeMap<String, String>,
+    /// Extra headers for the files matching the rules. Every matching rule
+    /// is applied in order and later rules replace the headers of earlier
+    /// ones. A Cache-Control, Surrogate-Control or CDN-Cache-Control header
+    /// replaces the one from cacheControl.
+    /// ## Defaults to [].
+    #[serde(default = "def_response_headers")]
+    pub response_headers: Vec<HeaderRule>,
+    /// Rewrites of the url path before the file is looked up. Only the
+    /// first matching rule is applied. The rewritten path is routed to the
+    /// tenants like the original one.
+    /// E.g. [{"prefix": "/live/chan1/", "to": "/channels/1/live/"}]
+    /// ## Defaults to [].
+    #[serde(default = "def_rewrites")]
+    pub rewrites: Vec<Rewrite>,
+    /// Bodies of the error responses by status code. "403", "404" and "500"
+    /// can be set, the others are sent without a body.
+    /// E.g. {"404": {"file": "errors/404.html"}}
+    /// ## Defaults to {} (empty bodies).
+    #[serde(default = "def_error_pages")]
+    pub error_pages: BTreeMap<String, ErrorPage>,
+    /// Manifests made from the segments of a directory when the manifest
+    /// file doesn't exist.
+    /// ## Defaults to none (no generated manifests).
+    pub generated_manifests: Option<GeneratedManifests>,
+    /// Segments that are still being written are sent as they grow.
+    /// ## Defaults to none (only complete segments are served).
+    pub low_latency: Option<LowLatency>,
+    /// Encoders upload the segments and manifests of live streams to the server.
+    /// ## Defaults to none (no uploads).
+    pub ingest: Option<Ingest>,
+    /// The document roots are watched for changes so the caches notice them
+    /// right away. The roots in use when the server starts are watched.
+    /// ## Defaults to none (caches notice the changes on the next request).
+    pub watch: Option<Watch>,
+    /// HLS playlists are made from the DASH manifests, generated ones
+    /// included. A missing "<name>.m3u8" is the master playlist of
+    /// "<name>.mpd" and "<name>.m3u8?representation=<id>" are its media
+    /// playlists. Only the video and audio of the first period are included.
+    /// ## Defaults to false.
+    #[serde(default = "false_value")]
+    pub hls_playlists: bool,
+    /// Encrypted streams. Their manifests, generated ones included, get the
+    /// ContentProtection elements that tell the players how the segments
+    /// are encrypted and which DRM systems have the keys.
+    /// ## Defaults to [].
+    #[serde(default = "def_content_protection")]
+    pub content_protection: Vec<ProtectedStream>,
+    /// License server for the players of the ClearKey encrypted streams.
+    /// ## Defaults to none (no license server).
+    pub clear_key: Option<ClearKey>,
+    /// Tenants are matched by the Host header first and then by the url prefix.
+    /// Requests that don't belong to any tenant are served from the working directory.
+    #[serde(default = "def_tenants")]
+    pub tenants: Vec<Tenant>,
+}
+
+/// Admin API. Every request needs the "Authorization: Bearer <token>" header.
+/// GET /config, GET /stats, GET /quotas, GET /segments/missing, POST /cache/flush,
+/// POST /cache/purge, GET and PUT /log-level and POST /shutdown. Four
+/// connections are served at a time and the others get 503 Service Unavailable.
+/// /cache/purge takes {"paths": [...], "prefixes": [...]} with the url paths
+/// in the document root and the roots of the tenants, after the rewrites.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Admin {
+    /// The API is plain HTTP so it shouldn't be reachable from the internet.
+    /// ## Defaults to "127.0.0.1".
+    #[serde(default = "def_admin_address")]
+    pub address: String,
+    pub port: String,
+    /// Secret the clients of the API have to send
+    pub token: String,
+}
+
+/// Manifests generated from the segments in a directory. Each subdirectory
+/// is a representation with an "init.mp4" and media segments named
+/// "seg-1.m4s", "seg-2.m4s" and so on. The codecs, picture sizes and
+/// segment durations are read from the segments and the representations
+/// are grouped into adaptation sets by content type, codec and language.
+/// E.g. "vod/movie/manifest.mpd" is generated from "vod/movie/720p/init.mp4",
+/// "vod/movie/720p/seg-1.m4s", "vod/movie/audio/init.mp4" and so on.
+/// Subtitle files next to the manifest, like "vod/movie/subtitles.en.vtt"
+/// or "vod/movie/subtitles.fi.ttml", are text adaptation sets of the on
+/// demand manifests with the language from the file name.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratedManifests {
+    /// Requests of a missing file with this name get the generated manifest
+    /// ## Defaults to "manifest.mpd".
+    #[serde(default = "def_generated_manifest_name")]
+    pub file_name: String,
+    /// Seconds of media the client should buffer before playing
+    /// ## Defaults to 2.0.
+    #[serde(default = "def_generated_min_buffer_time")]
+    pub min_buffer_time: f64,
+    /// Url path prefixes of the live streams, e.g. ["/live/"]. Their manifests
+    /// are dynamic and list the segments as they are written. The time 0 of
+    /// the segments is when the init segment was written.
+    /// ## Defaults to [].
+    #[serde(default = "def_live_prefixes")]
+    pub live_prefixes: Vec<String>,
+    /// Seconds between the manifest reloads of the live players
+    /// ## Defaults to 2.0.
+    #[serde(default = "def_minimum_update_period")]
+    pub minimum_update_period: f64,
+    /// Seconds behind the live edge that the live manifests list
+    /// ## Defaults to 60.0.
+    #[serde(default = "def_time_shift_buffer_depth")]
+    pub time_shift_buffer_depth: f64,
+    /// Seconds of the segments that the WebVTT subtitles of the on demand
+    /// manifests are split into, e.g. 10.0. The manifest lists them as
+    /// "<file>.vtt?segment=<number>" and each has the cues shown during it.
+    /// 0 lists the whole file instead.
+    /// ## Defaults to 0 (not split).
+    #[serde(default = "def_subtitle_segment_duration")]
+    pub subtitle_segment_duration: f64,
+}
+
+/// Low latency live streaming. The encoder writes a segment to
+/// "<segment><inProgressSuffix>" and renames it when it's complete. A
+/// request of the segment before that gets the part written so far right
+/// away and the rest as it's written, with "Transfer-Encoding: chunked".
+/// HTTP/1.0 clients and range requests get 404 until the segment is complete.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct LowLatency {
+    /// E.g. "seg-5.m4s.tmp" is the "seg-5.m4s" being written
+    /// ## Defaults to ".tmp".
+    #[serde(default = "def_in_progress_suffix")]
+    pub in_progress_suffix: String,
+    /// Seconds a segment can go without growing before the response is
+    /// aborted, e.g. when the encoder has stopped
+    /// ## Defaults to 2.0.
+    #[serde(default = "def_idle_timeout")]
+    pub idle_timeout: f64,
+}
+
+/// DASH-IF live media ingest (interface 1). Encoders PUT or POST the init
+/// segments, media segments and manifests of a stream to
+/// "<prefix><stream>/<path>" and DELETE them when they're no longer needed.
+/// An upload is written to "<path><lowLatency.inProgressSuffix>" and renamed
+/// when it's complete, so chunked uploads are served as they arrive.
+/// A stream without a manifest gets a generated one when root is under the
+/// document root and its url is in generatedManifests.livePrefixes.
+/// Uploads are limited by performance.maxBodySize.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Ingest {
+    /// ## Defaults to "/ingest/".
+    #[serde(default = "def_ingest_prefix")]
+    pub prefix: String,
+    /// Directory the streams are stored in, e.g. "live" stores the uploads
+    /// to "/ingest/chan1/video/seg-1.m4s" in "live/chan1/video/seg-1.m4s"
+    pub root: String,
+    /// Encoders have to send the "Authorization: Bearer <token>" header
+    pub token: String,
+}
+
+/// Watching the content with inotify. A changed file is dropped from the
+/// file cache and the not found cache, and the generated manifests it's
+/// part of are generated again.
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct Watch {
+    /// Directories of live streams, e.g. ["live"]. When a media segment is
+    /// written under one of them, the segments next to it that are older
+    /// than pruneAfter are deleted.
+    /// ## Defaults to [].
+    #[serde(default = "def_prune_directories")]
+    pub prune_directories: Vec<String>,
+    /// Seconds a media segment is kept after it was written. Has to be at
+    /// least generatedManifests.timeShiftBufferDepth so the live manifests
+    /// don't list deleted segments.
+    /// ## Defaults to 300.0.
+    #[serde(default = "def_prune_after")]
+    pub prune_after: f64,
+}
+
+/// Common encryption (ISO/IEC 23001-7) of the streams under a url prefix.
+/// Every adaptation set of their manifests gets the first entry that
+/// matches its content type, unless it already has ContentProtection.
+/// E.g. {"prefix": "/vod/movie/", "defaultKid": "10000000-1000-1000-1000-100000000001",
+/// "systems": [{"systemId": "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed", "pssh": "AAAAQHBzc2g..."}]}
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtectedStream {
+    /// Url path prefix of the manifests, e.g. "/vod/movie/"
+    pub prefix: String,
+    /// "video", "audio" or "text". Adaptation sets without a contentType
+    /// or mimeType only get the entries without one.
+    /// ## Defaults to none (every adaptation set).
+    pub content_type: Option<String>,
+    /// Key ID the segments are encrypted with, as a UUID
+    pub default_kid: String,
+    /// "cenc", "cbcs", "cens" or "cbc1"
+    /// ## Defaults to "cenc".
+    #[serde(default = "def_protection_scheme")]
+    pub scheme: String,
+    /// DRM systems that have the key
+    /// ## Defaults to [].
+    #[serde(default = "def_drm_systems")]
+    pub systems: Vec<DrmSystem>,
+}
+
+/// A DRM system of an encrypted stream
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct DrmSystem {
+    /// UUID of the system, e.g. "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed" for Widevine
+    pub system_id: String,
+    /// Name of the system for the value attribute, e.g. "Widevine"
+    /// ## Defaults to none.
+    pub value: Option<String>,
+    /// Base64 of the whole pssh box, for the players that don't read it
+    /// from the init segment
+    /// ## Defaults to none.
+    pub pssh: Option<String>,
+    /// Url of the license server for the dashif:laurl element, e.g.
+    /// "https://example.com/license" for the ClearKey license server
+    /// ## Defaults to none.
+    pub license_url: Option<String>,
+}
+
+/// W3C ClearKey license server. A POST of the license request of a player,
+/// {"kids": ["<base64url key ID>", ...], "type": "temporary"}, gets the
+/// keys it asks for. The requests go through the same access checks as the
+/// content. The ClearKey system of contentProtection is
+/// "e2719d58-a985-b3c9-781a-b030af78d30e".
+#[derive(Debug, Deserialize, Serialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearKey {
+    /// Url path of the license server
+    /// ## Defaults to "/license".
+    #[serde(default = "def_license_path")]
+    pub path: String,
+    /// 128 bit keys as hex by key ID as a UUID, e.g.
+    /// {"10000000-1000-1000-1000-100000000001": "3a2a1b68dd2bd9b2eeb25e84c4776668"}
+    /// ## Defaults to {}.
+    #[serde(default = "def_clear_keys")]
+    pub keys: BTreeMap<String, String>,
+    /// File with more keys, a "<key ID>:<key>" line for each. Read again
+    /// when it changes. Lines starting with # are comments.
+    /// ## Defaults to none.
+    pub keys_file: Option<String>,
+}
+
+/// Is `name` a header name that doesn't change how the response is framed
+fn is_settable_header(name: &str) -> bool {
+    let framing = [
+        "content-length",
+        "transfer-encoding",
+        "connection",
+        "content-range",
+    ];
+    let is_token = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    is_token && !framing.contains(&name.to_ascii_lowercase().as_str())
+}
+
+/// Is `value` an IP address or a range like "10.0.0.0/8"
+fn is_ip_range(value: &str) -> bool {
+    let (address, prefix) = match value.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value, None),
+    };
+    let max_prefix = match address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => 32,
+        Ok(IpAddr::V6(_)) => 128,
+        Err(_) => return false,
+    };
+    match prefix {
+        Some(prefix) => prefix
+            .parse::<u8>()
+            .is_ok_and(|prefix| prefix <= max_prefix),
+        None => true,
+    }
+}
+
+/// Is `value` a UUID like "10000000-1000-1000-1000-100000000001"
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    let lengths: Vec<usize> = groups.iter().map(|group| group.len()).collect();
+    lengths == [8, 4, 4, 4, 12]
+        && groups
+            .iter()
+            .all(|group| group.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// Does `seconds` fit in a Duration
+fn is_duration(seconds: f64) -> bool {
+    Duration::try_from_secs_f64(seconds).is_ok()
 }
 
-/// Singleton wrapper for Config
-pub struct GlobalConfig {
-    configuration: Option<Config>,
+/// Is `value` a 128 bit key as hex
+fn is_hex_key(value: &str) -> bool {
+    value.len() == 32 && value.bytes().all(|b| b.is_ascii_hexdigit())
 }
 
-impl GlobalConfig {
-    /// Initialize config.
-    /// This should be called in main.rs since the program depends on this.
-    /// If config isn't initialized. It my cause run time errors.
-    /// # Panics if called twice during the runtime.
-    pub fn init(path: &str) {
-        // Make sure that this is only called once.
-        // Since the reads are unsafe, reinit during runtime might cause issues.
-        assert!(!GlobalConfig::is_init());
+/// Is `value` padded base64 of the standard alphabet
+fn is_base64(value: &str) -> bool {
+    let data = value.trim_end_matches('=');
+    !value.is_empty()
+        && value.len().is_multiple_of(4)
+        && value.len() - data.len() <= 2
+        && data
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+}
+
+/// Format of the configuration file. All of them use the same keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format by the file extension. Unknown extensions are read as json
+    pub fn from_path(path: &str) -> ConfigFormat {
+        let extension = path.rsplit_once('.').map(|(_, extension)| extension);
+        match extension
+            .map(|extension| extension.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    /// Format by its name, e.g. "toml"
+    pub fn from_name(name: &str) -> Option<ConfigFormat> {
+        match &name.to_ascii_lowercase()[..] {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// Read and parse the configuration file. The format is picked by the file extension.
+    pub fn load(path: &str) -> Result<Config, String> {
+        Config::load_as(path, ConfigFormat::from_path(path))
+    }
+
+    /// Read and parse the configuration file in the given format
+    pub fn load_as(path: &str, format: ConfigFormat) -> Result<Config, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read the configuration file: {}", e))?;
+        Config::parse(&data, format)
+    }
 
-        let json_data = fs::read_to_string(path).expect("Cannot read the configuration file");
-        let conf: Config = serde_json::from_str(&json_data[..]).expect("Json formatting error");
-        unsafe {
-            GLOBAL_CONFIG = GlobalConfig {
-                configuration: Some(conf),
+    pub fn parse(data: &str, format: ConfigFormat) -> Result<Config, String> {
+        match format {
+            ConfigFormat::Json => {
+                serde_json::from_str(data).map_err(|e| format!("Json formatting error: {}", e))
+            }
+            ConfigFormat::Toml => {
+                toml::from_str(data).map_err(|e| format!("Toml formatting error: {}", e))
+            }
+            ConfigFormat::Yaml => {
+                serde_yaml::from_str(data).map_err(|e| format!("Yaml formatting error: {}", e))
+            }
+        }
+    }
+
+    /// The config written in `format`
+    pub fn to_string_as(&self, format: ConfigFormat) -> Result<String, String> {
+        match format {
+            ConfigFormat::Json => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::to_string(self).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Does any listener use TLS
+    pub fn uses_tls(&self) -> bool {
+        self.security.https
+            || self
+                .network
+                .listeners
+                .iter()
+                .any(|listener| listener.tls == Some(true))
+    }
+
+    /// Check the values that parse fine but the server can't run with.
+    /// Returns every problem found, not just the first one.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+        let mut check = |valid: bool, error: String| {
+            if !valid {
+                errors.push(error);
             }
         };
+
+        let network = &self.network;
+        check(
+            network.port.parse::<u16>().is_ok(),
+            format!("network.port \"{}\" is not a port number", network.port),
+        );
+        if let Some(port) = &network.redirect_port {
+            check(
+                port.parse::<u16>().is_ok(),
+                format!("network.redirectPort \"{}\" is not a port number", port),
+            );
+        }
+        for listener in &network.listeners {
+            check(
+                listener.port.parse::<u16>().is_ok(),
+                format!(
+                    "network.listeners port \"{}\" is not a port number",
+                    listener.port
+                ),
+            );
+        }
+
+        if let Some(admin) = &self.admin {
+            check(
+                admin.port.parse::<u16>().is_ok(),
+                format!("admin.port \"{}\" is not a port number", admin.port),
+            );
+            check(
+                !admin.token.is_empty(),
+                "admin.token can't be empty".to_string(),
+            );
+        }
+
+        let performance = &self.performance;
+        check(
+            performance.thread_pool_size >= 1,
+            "performance.threadPoolSize has to be at least 1".to_string(),
+        );
+        check(
+            performance.max_thread_pool_size == 0
+                || performance.max_thread_pool_size >= performance.thread_pool_size,
+            "performance.maxThreadPoolSize can't be smaller than threadPoolSize".to_string(),
+        );
+        check(
+            performance.max_request_size >= 1,
+            "performance.maxRequestSize has to be at least 1".to_string(),
+        );
+        check(
+            performance.connection_timeout > 0.0,
+            "performance.connectionTimeout has to be more than 0".to_string(),
+        );
+        check(
+            performance.header_timeout > 0.0,
+            "performance.headerTimeout has to be more than 0".to_string(),
+        );
+        // Durations can't be negative
+        let durations = [
+            (
+                "performance.keepAliveTimeout",
+                performance.keep_alive_timeout,
+            ),
+            (
+                "performance.notFoundCacheTtl",
+                performance.not_found_cache_ttl,
+            ),
+            ("performance.fileCacheTtl", performance.file_cache_ttl),
+            ("performance.drainTimeout", performance.drain_timeout),
+            (
+                "security.certificateCheckInterval",
+                self.security.certificate_check_interval,
+            ),
+        ];
+        for (name, seconds) in durations.iter() {
+            check(
+                seconds.is_finite() && *seconds >= 0.0,
+                format!("{} can't be negative", name),
+            );
+        }
+        // The server would panic converting them when they are used
+        let mut all_durations = vec![
+            (
+                "performance.connectionTimeout",
+                performance.connection_timeout,
+            ),
+            ("performance.headerTimeout", performance.header_timeout),
+        ];
+        all_durations.extend_from_slice(&durations);
+        if let Some(generated) = &self.generated_manifests {
+            all_durations.extend_from_slice(&[
+                (
+                    "generatedManifests.minBufferTime",
+                    generated.min_buffer_time,
+                ),
+                (
+                    "generatedManifests.minimumUpdatePeriod",
+                    generated.minimum_update_period,
+                ),
+                (
+                    "generatedManifests.timeShiftBufferDepth",
+                    generated.time_shift_buffer_depth,
+                ),
+                (
+                    "generatedManifests.subtitleSegmentDuration",
+                    generated.subtitle_segment_duration,
+                ),
+            ]);
+        }
+        if let Some(low_latency) = &self.low_latency {
+            all_durations.push(("lowLatency.idleTimeout", low_latency.idle_timeout));
+        }
+        if let Some(watch) = &self.watch {
+            all_durations.push(("watch.pruneAfter", watch.prune_after));
+        }
+        for (name, seconds) in all_durations {
+            // Negative ones are reported above
+            check(
+                seconds.is_nan() || seconds <= 0.0 || is_duration(seconds),
+                format!("{} is too long", name),
+            );
+        }
+
+        let security = &self.security;
+        if let Some(rate_limit) = &security.rate_limit {
+            check(
+                rate_limit.requests_per_second.is_finite() && rate_limit.requests_per_second > 0.0,
+                "security.rateLimit.requestsPerSecond has to be more than 0".to_string(),
+            );
+            check(
+                rate_limit.burst >= 1,
+                "security.rateLimit.burst has to be at least 1".to_string(),
+            );
+            for range in &rate_limit.exempt {
+                check(
+                    is_ip_range(range),
+                    format!(
+                        "security.rateLimit.exempt \"{}\" is not an address or a CIDR range",
+                        range
+                    ),
+                );
+            }
+        }
+        for range in &network.trusted_proxies {
+            check(
+                is_ip_range(range),
+                format!(
+                    "network.trustedProxies \"{}\" is not an address or a CIDR range",
+                    range
+                ),
+            );
+        }
+        let access_lists = [
+            ("security.allowCidrs", &security.allow_cidrs),
+            ("security.denyCidrs", &security.deny_cidrs),
+        ];
+        for (name, ranges) in access_lists.iter() {
+            for range in ranges.iter() {
+                check(
+                    is_ip_range(range),
+                    format!("{} \"{}\" is not an address or a CIDR range", name, range),
+                );
+            }
+        }
+        if let Some(signed_urls) = &security.signed_urls {
+            check(
+                !signed_urls.secret.is_empty(),
+                "security.signedUrls.secret can't be empty".to_string(),
+            );
+        }
+        if let Some(jwt) = &security.jwt {
+            match jwt.algorithm {
+                JwtAlgorithm::Hs256 => check(
+                    jwt.secret.as_ref().is_some_and(|secret| !secret.is_empty()),
+                    "security.jwt.secret is needed for HS256".to_string(),
+                ),
+                JwtAlgorithm::Rs256 => check(
+                    jwt.public_key_file.is_some() != jwt.jwks_url.is_some(),
+                    "security.jwt needs either publicKeyFile or jwksUrl for RS256".to_string(),
+                ),
+            }
+            if let Some(url) = &jwt.jwks_url {
+                check(
+                    url.starts_with("https://") || url.starts_with("http://"),
+                    format!("security.jwt.jwksUrl \"{}\" is not a http(s) url", url),
+                );
+            }
+            if let Some(path) = &jwt.public_key_file {
+                check(
+                    fs::metadata(path).is_ok(),
+                    format!("security.jwt.publicKeyFile \"{}\" doesn't exist", path),
+                );
+            }
+        }
+        if let Some(basic_auth) = &security.basic_auth {
+            check(
+                !basic_auth.realm.contains('"'),
+                "security.basicAuth.realm can't contain quotes".to_string(),
+            );
+            check(
+                fs::metadata(&basic_auth.credentials_file).is_ok(),
+                format!(
+                    "security.basicAuth.credentialsFile \"{}\" doesn't exist",
+                    basic_auth.credentials_file
+                ),
+            );
+        }
+        if let Some(hotlink) = &security.hotlink_protection {
+            for site in &hotlink.allowed_sites {
+                check(
+                    site.starts_with("https://") || site.starts_with("http://"),
+                    format!(
+                        "security.hotlinkProtection.allowedSites \"{}\" is not a http(s) origin",
+                        site
+                    ),
+                );
+            }
+        }
+        for rule in &self.response_headers {
+            check(
+                !rule.pattern.is_empty(),
+                "responseHeaders pattern can't be empty".to_string(),
+            );
+            for (name, value) in &rule.headers {
+                check(
+                    is_settable_header(name),
+                    format!("responseHeaders can't set \"{}\"", name),
+                );
+                check(
+                    !value.contains(['\r', '\n']),
+                    format!(
+                        "responseHeaders value of \"{}\" can't have line breaks",
+                        name
+                    ),
+                );
+            }
+        }
+        if let Some(generated) = &self.generated_manifests {
+            check(
+                generated.file_name.ends_with(".mpd") && !generated.file_name.contains('/'),
+                format!(
+                    "generatedManifests.fileName \"{}\" is not a .mpd file name",
+                    generated.file_name
+                ),
+            );
+            check(
+                generated.min_buffer_time > 0.0,
+                "generatedManifests.minBufferTime has to be positive".to_string(),
+            );
+            check(
+                generated.minimum_update_period > 0.0,
+                "generatedManifests.minimumUpdatePeriod has to be positive".to_string(),
+            );
+            check(
+                generated.time_shift_buffer_depth > 0.0,
+                "generatedManifests.timeShiftBufferDepth has to be positive".to_string(),
+            );
+            check(
+                generated.subtitle_segment_duration >= 0.0,
+                "generatedManifests.subtitleSegmentDuration can't be negative".to_string(),
+            );
+            for prefix in &generated.live_prefixes {
+                check(
+                    prefix.starts_with('/'),
+                    format!(
+                        "generatedManifests.livePrefixes \"{}\" doesn't start with /",
+                        prefix
+                    ),
+                );
+            }
+        }
+        if let Some(low_latency) = &self.low_latency {
+            let suffix = &low_latency.in_progress_suffix;
+            check(
+                !suffix.is_empty() && !suffix.contains('/'),
+                format!(
+                    "lowLatency.inProgressSuffix \"{}\" is not a file name ending",
+                    suffix
+                ),
+            );
+            check(
+                low_latency.idle_timeout > 0.0,
+                "lowLatency.idleTimeout has to be positive".to_string(),
+            );
+        }
+        if let Some(ingest) = &self.ingest {
+            check(
+                ingest.prefix.starts_with('/') && ingest.prefix.ends_with('/'),
+                format!(
+                    "ingest.prefix \"{}\" doesn't start and end with /",
+                    ingest.prefix
+                ),
+            );
+            check(
+                !ingest.root.is_empty(),
+                "ingest.root can't be empty".to_string(),
+            );
+            check(
+                !ingest.token.is_empty(),
+                "ingest.token can't be empty".to_string(),
+            );
+        }
+        if let Some(watch) = &self.watch {
+            check(
+                watch.prune_after > 0.0,
+                "watch.pruneAfter has to be positive".to_string(),
+            );
+            if let (Some(generated), false) = (
+                &self.generated_manifests,
+                watch.prune_directories.is_empty(),
+            ) {
+                check(
+                    watch.prune_after >= generated.time_shift_buffer_depth,
+                    "watch.pruneAfter is shorter than generatedManifests.timeShiftBufferDepth"
+                        .to_string(),
+                );
+            }
+        }
+        for stream in &self.content_protection {
+            check(
+                stream.prefix.starts_with('/'),
+                format!(
+                    "contentProtection prefix \"{}\" doesn't start with /",
+                    stream.prefix
+                ),
+            );
+            if let Some(content_type) = &stream.content_type {
+                check(
+                    ["video", "audio", "text"].contains(&content_type.as_str()),
+                    format!(
+                        "contentProtection contentType \"{}\" is not video, audio or text",
+                        content_type
+                    ),
+                );
+            }
+            check(
+                is_uuid(&stream.default_kid),
+                format!(
+                    "contentProtection defaultKid \"{}\" is not a UUID",
+                    stream.default_kid
+                ),
+            );
+            check(
+                ["cenc", "cbcs", "cens", "cbc1"].contains(&stream.scheme.as_str()),
+                format!(
+                    "contentProtection scheme \"{}\" is not a protection scheme",
+                    stream.scheme
+                ),
+            );
+            for system in &stream.systems {
+                check(
+                    is_uuid(&system.system_id),
+                    format!(
+                        "contentProtection systemId \"{}\" is not a UUID",
+                        system.system_id
+                    ),
+                );
+                if let Some(license_url) = &system.license_url {
+                    check(
+                        license_url.starts_with("https://") || license_url.starts_with("http://"),
+                        format!(
+                            "contentProtection licenseUrl \"{}\" is not a http(s) url",
+                            license_url
+                        ),
+                    );
+                }
+                if let Some(pssh) = &system.pssh {
+                    check(
+                        is_base64(pssh),
+                        format!(
+                            "contentProtection pssh of {} is not base64",
+                            system.system_id
+                        ),
+                    );
+                }
+            }
+        }
+        if let Some(clear_key) = &self.clear_key {
+            check(
+                clear_key.path.starts_with('/'),
+                format!("clearKey.path \"{}\" doesn't start with /", clear_key.path),
+            );
+            for (kid, key) in &clear_key.keys {
+                check(
+                    is_uuid(kid) && is_hex_key(key),
+                    format!(
+                        "clearKey key of \"{}\" is not a UUID and 32 hex digits",
+                        kid
+                    ),
+                );
+            }
+        }
+        for rewrite in &self.rewrites {
+            check(
+                rewrite.prefix.is_some() != rewrite.pattern.is_some(),
+                format!(
+                    "rewrite to \"{}\" needs either a prefix or a pattern",
+                    rewrite.to
+                ),
+            );
+            if let Some(prefix) = &rewrite.prefix {
+                check(
+                    prefix.starts_with('/'),
+                    format!("rewrite prefix \"{}\" doesn't start with /", prefix),
+                );
+            }
+            if let Some(pattern) = &rewrite.pattern {
+                check(
+                    Regex::new(pattern).is_ok(),
+                    format!(
+                        "rewrite pattern \"{}\" is not a valid regular expression",
+                        pattern
+                    ),
+                );
+            }
+            check(
+                rewrite.to.starts_with('/'),
+                format!("rewrite to \"{}\" doesn't start with /", rewrite.to),
+            );
+        }
+        for tenant in &self.tenants {
+            check(
+                tenant.certificate_file.is_some() == tenant.private_key_file.is_some(),
+                format!(
+                    "tenant \"{}\" needs both certificateFile and privateKeyFile",
+                    tenant.name
+                ),
+            );
+            check(
+                tenant.certificate_file.is_none() || tenant.host.is_some(),
+                format!(
+                    "tenant \"{}\" needs a host for its certificate",
+                    tenant.name
+                ),
+            );
+        }
+        for (status, page) in &self.error_pages {
+            check(
+                ["403", "404", "500"].contains(&status.as_str()),
+                format!("errorPages \"{}\" is not 403, 404 or 500", status),
+            );
+            check(
+                page.file.is_some() != page.body.is_some(),
+                format!("errorPages \"{}\" needs either a file or a body", status),
+            );
+            if let Some(file) = &page.file {
+                check(
+                    fs::metadata(file).is_ok(),
+                    format!("errorPages \"{}\" file \"{}\" doesn't exist", status, file),
+                );
+            }
+            check(
+                !page.content_type.contains(['\r', '\n']),
+                format!(
+                    "errorPages \"{}\" contentType can't have line breaks",
+                    status
+                ),
+            );
+        }
+        if self.uses_tls() {
+            let mut files = vec![
+                ("security.certificateFile", &security.certificate_file),
+                ("security.privateKeyFile", &security.private_key_file),
+            ];
+            if let Some(client_auth) = &security.client_auth {
+                files.push(("security.clientAuth.caFile", &client_auth.ca_file));
+            }
+            for tenant in &self.tenants {
+                if let Some(file) = &tenant.certificate_file {
+                    files.push(("tenants.certificateFile", file));
+                }
+                if let Some(file) = &tenant.private_key_file {
+                    files.push(("tenants.privateKeyFile", file));
+                }
+            }
+            for (name, path) in files {
+                check(
+                    fs::metadata(path).is_ok(),
+                    format!("{} \"{}\" doesn't exist", name, path),
+                );
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
     }
 
-    fn is_init() -> bool {
-        match unsafe { &GLOBAL_CONFIG.configuration.as_ref() } {
-            Some(_) => true,
-            None => false,
+    /// Apply the `MPEG_DASH_<SECTION>_<SETTING>` variables in `vars` on top of
+    /// the config, e.g. MPEG_DASH_NETWORK_PORT=8443 sets network.port.
+    /// Lists are comma separated and objects are written as json.
+    pub fn with_env_overrides(
+        self,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Config, String> {
+        let mut value = serde_json::to_value(&self).map_err(|e| e.to_string())?;
+        for (name, raw) in vars {
+            let path = match name.strip_prefix(ENV_PREFIX) {
+                Some(path) => path,
+                None => continue,
+            };
+            let setting = find_setting(&mut value, path)
+                .ok_or_else(|| format!("Unknown setting in the environment: {}", name))?;
+            *setting = env_value(setting, &raw);
         }
+        serde_json::from_value(value)
+            .map_err(|e| format!("Invalid value in the environment: {}", e))
     }
+}
+
+/// Prefix of the environment variables that override settings
+const ENV_PREFIX: &str = "MPEG_DASH_";
 
-    /// Return the initialized config
-    /// # Panics if config isn't initilized before this
-    pub fn config() -> &'static Config {
-        // as_ref gets the configurations reference so rust doesn't
-        // try to to create a duplication or copy of the configuration
-        unsafe { &GLOBAL_CONFIG.configuration.as_ref().unwrap() }
+/// camelCase key in the SCREAMING_SNAKE_CASE used in variable names
+fn env_name(key: &str) -> String {
+    let mut name = String::new();
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
     }
+    name
 }
 
-/// GLOBAL_CONFIG should be treated as read only after initialization
-static mut GLOBAL_CONFIG: GlobalConfig = GlobalConfig {
-    configuration: None,
-};
+/// Setting in `value` named by `path`, e.g. NETWORK_PORT
+fn find_setting<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    for (key, child) in value.as_object_mut()?.iter_mut() {
+        let name = env_name(key);
+        if path == name {
+            return Some(child);
+        }
+        let rest = path
+            .strip_prefix(&name[..])
+            .and_then(|rest| rest.strip_prefix('_'));
+        if let Some(rest) = rest {
+            if let Some(found) = find_setting(child, rest) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+/// Value of the variable `raw` for a setting that currently is `current`
+fn env_value(current: &Value, raw: &str) -> Value {
+    let string = || Value::String(raw.to_string());
+    match current {
+        Value::String(_) => string(),
+        Value::Array(_) if !raw.trim_start().starts_with('[') => raw
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| Value::String(item.to_string()))
+            .collect(),
+        // Unset optional setting, only objects are parsed so numbers stay strings
+        Value::Null => match serde_json::from_str(raw) {
+            Ok(object @ Value::Object(_)) => object,
+            _ => string(),
+        },
+        _ => serde_json::from_str(raw).unwrap_or_else(|_| string()),
+    }
+}
+
+/// Config shared by the server threads.
+/// Replaced as a whole when the config is reloaded.
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig {
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// Current config. Hold on to it for the whole request so the request
+    /// sees the same config even if it's replaced meanwhile.
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Use `config` from now on. Requests that have already started keep the old one.
+    pub fn replace(&self, config: Config) {
+        *self.current.write().unwrap() = Arc::new(config);
+    }
+}
 
 // Rest of the file is tests
 #[cfg(test)]
@@ -182,83 +2215,530 @@ mod config_tests {
     const INVALID_VALUE: &str = "test_data/config_invalid_value.json";
     const EMPTY_OBJECT: &str = "test_data/config_empty_object.json";
 
-    /// call this in every function to make sure config is set to None
-    /// This avoids the assert!(!GlobalConfig::is_init()); from erroring out druing tests
-    fn test_init_conf() {
-        unsafe {
-            GLOBAL_CONFIG = GlobalConfig {
-                configuration: None,
-            };
+    #[test]
+    fn file_not_found() {
+        assert!(Config::load("this_file_doesnt_exist.json").is_err());
+    }
+
+    #[test]
+    fn invalid_json_file() {
+        assert!(Config::load(INVALID_CONFIG).is_err());
+    }
+
+    #[test]
+    fn invalid_value_in_json() {
+        assert!(Config::load(INVALID_VALUE).is_err());
+    }
+
+    #[test]
+    fn toml_and_yaml() {
+        let json = Config::load(CONFIG_FULL).unwrap();
+        assert_eq!(Config::load("test_data/config_full.toml").unwrap(), json);
+        assert_eq!(Config::load("test_data/config_full.yaml").unwrap(), json);
+        // An explicit format is used regardless of the extension
+        assert!(Config::load_as(CONFIG_FULL, ConfigFormat::Toml).is_err());
+        assert!(Config::load_as(CONFIG_FULL, ConfigFormat::Yaml).is_ok());
+    }
+
+    #[test]
+    fn format_detection() {
+        assert_eq!(ConfigFormat::from_path("config.toml"), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path("/etc/dash.YML"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path("config.yaml"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path("config.json"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path("config"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_name("TOML"), Some(ConfigFormat::Toml));
+        assert_eq!(ConfigFormat::from_name("ini"), None);
+    }
+
+    #[test]
+    fn write_and_parse() {
+        let config = Config::load(CONFIG_FULL).unwrap();
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml].iter() {
+            let data = config.to_string_as(*format).unwrap();
+            assert_eq!(Config::parse(&data, *format).unwrap(), config);
         }
     }
 
+    fn env_overrides(vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        Config::load(EMPTY_OBJECT).unwrap().with_env_overrides(vars)
+    }
+
     #[test]
-    #[should_panic]
-    fn init_file_not_found() {
-        test_init_conf();
-        GlobalConfig::init("this_file_doesnt_exist.json");
+    fn env_overrides_settings() {
+        let config = env_overrides(&[
+            ("MPEG_DASH_NETWORK_PORT", "8443"),
+            ("MPEG_DASH_NETWORK_REDIRECT_PORT", "8080"),
+            ("MPEG_DASH_PERFORMANCE_THREAD_POOL_SIZE", "16"),
+            ("MPEG_DASH_PERFORMANCE_COMPRESSION", "true"),
+            ("MPEG_DASH_SECURITY_HTTPS", "false"),
+            ("MPEG_DASH_CACHE_CONTROL_MANIFEST_CACHE_CONTROL", "no-cache"),
+            (
+                "MPEG_DASH_CORS_ALLOWED_ORIGINS",
+                "https://a.example, https://b.example",
+            ),
+            ("MPEG_DASH_SECURITY_CLIENT_AUTH", r#"{"caFile": "ca.pem"}"#),
+            ("PATH", "/usr/bin"),
+        ])
+        .unwrap();
+        assert_eq!(config.network.port, "8443");
+        assert_eq!(config.network.redirect_port, Some("8080".to_string()));
+        assert_eq!(config.performance.thread_pool_size, 16);
+        assert!(config.performance.compression);
+        assert!(!config.security.https);
+        assert_eq!(
+            config.cache_control.manifest.cache_control,
+            Some("no-cache".to_string())
+        );
+        assert_eq!(
+            config.cors.allowed_origins,
+            vec!["https://a.example", "https://b.example"]
+        );
+        assert_eq!(config.security.client_auth.unwrap().ca_file, "ca.pem");
+        // Everything else keeps its value
+        assert_eq!(config.network.address, def_ipv4_addr());
     }
 
     #[test]
-    #[should_panic]
-    fn invalid_json_file() {
-        test_init_conf();
-        GlobalConfig::init(INVALID_CONFIG);
+    fn invalid_env_overrides() {
+        assert!(env_overrides(&[("MPEG_DASH_NETWORK_PROT", "8443")]).is_err());
+        assert!(env_overrides(&[("MPEG_DASH_PERFORMANCE_THREAD_POOL_SIZE", "many")]).is_err());
     }
 
     #[test]
-    #[should_panic]
-    fn double_init_panic() {
-        test_init_conf();
-        GlobalConfig::init(CONFIG_FULL);
-        GlobalConfig::init(CONFIG_FULL);
+    fn valid_config() {
+        assert_eq!(Config::load(CONFIG_FULL).unwrap().validate(), Ok(()));
+        assert_eq!(
+            Config::load("test_data/unit_test_config.json")
+                .unwrap()
+                .validate(),
+            Ok(())
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn invalid_value_in_json() {
-        test_init_conf();
-        GlobalConfig::init(INVALID_VALUE);
+    fn invalid_values() {
+        let mut config = Config::load(CONFIG_FULL).unwrap();
+        config.network.port = "https".to_string();
+        config.performance.thread_pool_size = 0;
+        config.performance.max_request_size = 0;
+        config.performance.connection_timeout = 0.0;
+        config.performance.header_timeout = -2.0;
+        config.performance.drain_timeout = -1.0;
+        config.admin.as_mut().unwrap().token = String::new();
+        let rate_limit = config.security.rate_limit.as_mut().unwrap();
+        rate_limit.exempt.push("10.0.0.0/33".to_string());
+        rate_limit.exempt.push("localhost".to_string());
+        config.security.deny_cidrs.push("edge".to_string());
+        config.network.trusted_proxies.push("10.0.0.0/".to_string());
+        config.security.basic_auth.as_mut().unwrap().realm = "\"".to_string();
+        config.response_headers[0]
+            .headers
+            .insert("Content-Length".to_string(), "0".to_string());
+        config.error_pages.get_mut("500").unwrap().file = Some("500.html".to_string());
+        config.rewrites[1].pattern = Some("^/v[0-9+/(.*)$".to_string());
+        let generated = config.generated_manifests.as_mut().unwrap();
+        generated.file_name = "index.html".to_string();
+        generated.live_prefixes.push("live".to_string());
+        generated.subtitle_segment_duration = -1.0;
+        config.low_latency.as_mut().unwrap().idle_timeout = 0.0;
+        config.ingest.as_mut().unwrap().prefix = "ingest".to_string();
+        config.watch.as_mut().unwrap().prune_after = 30.0;
+        config.content_protection[0].systems[0].pssh = Some("AAAA=".to_string());
+        let clear_key = config.clear_key.as_mut().unwrap();
+        clear_key.keys.insert("1".to_string(), "2".to_string());
+        config.tenants[0].private_key_file = None;
+        let hotlink = config.security.hotlink_protection.as_mut().unwrap();
+        hotlink.allowed_sites.push("example.com".to_string());
+        config.security.signed_urls.as_mut().unwrap().secret = String::new();
+        config.security.jwt.as_mut().unwrap().jwks_url = Some("ftp://keys".to_string());
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors.len(), 28);
+        assert_eq!(errors[0], "network.port \"https\" is not a port number");
+    }
+
+    #[test]
+    fn too_long_durations() {
+        let mut config = Config::load(CONFIG_FULL).unwrap();
+        config.performance.drain_timeout = 1e300;
+        config.performance.header_timeout = f64::INFINITY;
+        config.watch.as_mut().unwrap().prune_after = 1e20;
+        assert_eq!(
+            config.validate(),
+            Err(vec![
+                "performance.headerTimeout is too long".to_string(),
+                "performance.drainTimeout is too long".to_string(),
+                "watch.pruneAfter is too long".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn thread_pool_sizes() {
+        let mut config = Config::load(CONFIG_FULL).unwrap();
+        config.performance.max_thread_pool_size = 100;
+        assert_eq!(
+            config.validate(),
+            Err(vec![
+                "performance.maxThreadPoolSize can't be smaller than threadPoolSize".to_string()
+            ])
+        );
+        config.performance.max_thread_pool_size = 0;
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn missing_certificate_files() {
+        let mut config = Config::load(CONFIG_FULL).unwrap();
+        assert!(!config.uses_tls());
+        // An extra listener with TLS needs the files too
+        config.network.listeners[0].tls = Some(true);
+        assert!(config.uses_tls());
+        assert_eq!(
+            config.validate(),
+            Err(vec![
+                "security.certificateFile \"cert_test_path.pem\" doesn't exist".to_string(),
+                "security.privateKeyFile \"private_test_path.pem\" doesn't exist".to_string(),
+                "security.clientAuth.caFile \"ca.pem\" doesn't exist".to_string(),
+                "tenants.certificateFile \"acme_cert.pem\" doesn't exist".to_string(),
+                "tenants.privateKeyFile \"acme_key.pem\" doesn't exist".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn replace_shared_config() {
+        let shared = SharedConfig::new(Config::load(EMPTY_OBJECT).unwrap());
+        let old = shared.get();
+        shared.replace(Config::load(CONFIG_FULL).unwrap());
+        // Holders of the old config keep it
+        assert_eq!(old.network.port, def_ipv4_port());
+        assert_eq!(shared.get().network.port, "9443");
+        assert_eq!(shared.clone().get().network.port, "9443");
     }
 
     #[test]
     fn full_config() {
-        test_init_conf();
-        GlobalConfig::init(CONFIG_FULL);
-        let config = GlobalConfig::config();
+        let config = Config::load(CONFIG_FULL).unwrap();
         assert_eq!(
-            *config,
+            config,
             Config {
                 network: Network {
                     address: "127.0.0.1".to_string(),
                     port: "9443".to_string(),
                     allow_origin: "255.255.255.1".to_string(),
+                    preload_init_segments: PreloadMode::Lowest,
+                    early_hints: true,
+                    document_root: "/srv/dash".to_string(),
+                    redirect_port: Some("9080".to_string()),
+                    listeners: vec![Listener {
+                        address: "0.0.0.0".to_string(),
+                        port: "9081".to_string(),
+                        tls: Some(false),
+                        socket_options: Some(SocketOptions {
+                            tcp_no_delay: false,
+                            send_buffer_size: 0,
+                            receive_buffer_size: 0,
+                            backlog: 16,
+                        }),
+                    }],
+                    dual_stack: true,
+                    socket_options: SocketOptions {
+                        tcp_no_delay: true,
+                        send_buffer_size: 262144,
+                        receive_buffer_size: 131072,
+                        backlog: 512,
+                    },
+                    trusted_proxies: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+                    proxy_protocol: true,
                 },
                 security: Security {
                     https: false,
                     private_key_file: "private_test_path.pem".to_string(),
                     certificate_file: "cert_test_path.pem".to_string(),
+                    content_digest: true,
+                    certificate_check_interval: 3600.0,
+                    client_auth: Some(ClientAuth {
+                        ca_file: "ca.pem".to_string(),
+                        exempt_prefixes: vec!["/health".to_string()],
+                    }),
+                    rate_limit: Some(RateLimit {
+                        requests_per_second: 2.5,
+                        burst: 10,
+                        exempt: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+                    }),
+                    allow_cidrs: vec!["203.0.113.0/24".to_string(), "2001:db8::/32".to_string()],
+                    deny_cidrs: vec!["203.0.113.7".to_string()],
+                    signed_urls: Some(SignedUrls {
+                        secret: "url-secret".to_string(),
+                        exempt_prefixes: vec!["/public/".to_string()],
+                    }),
+                    jwt: Some(Jwt {
+                        algorithm: JwtAlgorithm::Rs256,
+                        secret: None,
+                        public_key_file: None,
+                        jwks_url: Some("https://auth.example.com/jwks.json".to_string()),
+                        issuer: Some("https://auth.example.com".to_string()),
+                        audience: Some("dash".to_string()),
+                        rules: vec![
+                            JwtRule {
+                                prefix: "/premium/".to_string(),
+                                scope: Some("premium".to_string()),
+                            },
+                            JwtRule {
+                                prefix: "/live/".to_string(),
+                                scope: None,
+                            },
+                        ],
+                    }),
+                    basic_auth: Some(BasicAuth {
+                        realm: "Staging".to_string(),
+                        credentials_file: "test_data/htpasswd".to_string(),
+                        prefixes: vec!["/staging/".to_string()],
+                    }),
+                    hotlink_protection: Some(HotlinkProtection {
+                        allowed_sites: vec![
+                            "https://player.example.com".to_string(),
+                            "https://*.example.org".to_string(),
+                        ],
+                        allow_empty: false,
+                    }),
+                    user: Some("dash".to_string()),
+                    group: Some("media".to_string()),
                 },
                 performance: Performance {
                     thread_pool_size: 123,
+                    max_thread_pool_size: 456,
                     connection_timeout: 321.4,
+                    header_timeout: 45.5,
+                    max_uri_length: 1000,
+                    max_request_size: 8192,
+                    max_request_line_length: 1100,
+                    max_headers: 32,
+                    max_header_length: 2048,
+                    max_body_size: 2000,
+                    not_found_cache_ttl: 0.5,
+                    prefetch_segments: 3,
+                    keep_alive_timeout: 7.5,
+                    compression: true,
+                    precompressed: true,
+                    file_cache_size: 4096,
+                    file_cache_max_entry_size: 512,
+                    file_cache_ttl: 2.5,
+                    drain_timeout: 12.0,
+                    max_bytes_per_second_per_connection: 500000,
+                    max_connections: 1000,
+                    max_queued_connections: 50,
+                    max_connections_per_address: 16,
+                },
+                cache_control: CacheControl {
+                    manifest: CacheHeaders {
+                        cache_control: Some("no-cache".to_string()),
+                        surrogate_control: Some("max-age=2".to_string()),
+                        cdn_cache_control: None,
+                    },
+                    segment: CacheHeaders {
+                        cache_control: Some("max-age=60".to_string()),
+                        surrogate_control: None,
+                        cdn_cache_control: Some("max-age=86400".to_string()),
+                    },
+                    immutable_prefix: Some("/vod".to_string()),
+                },
+                cors: Cors {
+                    allowed_origins: vec![
+                        "https://player.example.com".to_string(),
+                        "https://admin.example.com".to_string(),
+                    ],
+                    allowed_methods: vec!["GET".to_string(), "OPTIONS".to_string()],
+                    allowed_headers: vec!["Authorization".to_string()],
+                    exposed_headers: vec!["Content-Length".to_string()],
+                    allow_credentials: true,
+                    max_age: 600,
+                },
+                logging: Logging {
+                    level: LogLevel::Warn,
+                    format: LogFormat::Json,
+                    access_log: Some("/var/log/dash/access.log".to_string()),
+                    missing_segments: true,
                 },
+                admin: Some(Admin {
+                    address: "::1".to_string(),
+                    port: "9000".to_string(),
+                    token: "secret".to_string(),
+                }),
+                mime_types: [("m4s", "video/mp4"), ("srt", "application/x-subrip")]
+                    .iter()
+                    .map(|(extension, content_type)| {
+                        (extension.to_string(), content_type.to_string())
+                    })
+                    .collect(),
+                response_headers: vec![
+                    HeaderRule {
+                        pattern: "*.m4s".to_string(),
+                        headers: [("Cache-Control", "max-age=31536000, immutable")]
+                            .iter()
+                            .map(|(name, value)| (name.to_string(), value.to_string()))
+                            .collect(),
+                    },
+                    HeaderRule {
+                        pattern: "/live/**/*.mpd".to_string(),
+                        headers: [("Cache-Control", "no-store"), ("Timing-Allow-Origin", "*")]
+                            .iter()
+                            .map(|(name, value)| (name.to_string(), value.to_string()))
+                            .collect(),
+                    },
+                ],
+                rewrites: vec![
+                    Rewrite {
+                        prefix: Some("/live/chan1/".to_string()),
+                        pattern: None,
+                        to: "/channels/1/live/".to_string(),
+                    },
+                    Rewrite {
+                        prefix: None,
+                        pattern: Some("^/v[0-9]+/(.*)$".to_string()),
+                        to: "/$1".to_string(),
+                    },
+                ],
+                error_pages: vec![
+                    (
+                        "404",
+                        ErrorPage {
+                            file: Some("test_data/errors/404.html".to_string()),
+                            body: None,
+                            content_type: "text/html; charset=utf-8".to_string(),
+                        },
+                    ),
+                    (
+                        "500",
+                        ErrorPage {
+                            file: None,
+                            body: Some("Error {status}".to_string()),
+                            content_type: "text/plain".to_string(),
+                        },
+                    ),
+                ]
+                .into_iter()
+                .map(|(status, page)| (status.to_string(), page))
+                .collect(),
+                generated_manifests: Some(GeneratedManifests {
+                    file_name: "stream.mpd".to_string(),
+                    min_buffer_time: 4.0,
+                    live_prefixes: vec!["/live/".to_string()],
+                    minimum_update_period: 1.0,
+                    time_shift_buffer_depth: def_time_shift_buffer_depth(),
+                    subtitle_segment_duration: 10.0,
+                }),
+                low_latency: Some(LowLatency {
+                    in_progress_suffix: ".part".to_string(),
+                    idle_timeout: def_idle_timeout(),
+                }),
+                ingest: Some(Ingest {
+                    prefix: def_ingest_prefix(),
+                    root: "live".to_string(),
+                    token: "encoder-token".to_string(),
+                }),
+                watch: Some(Watch {
+                    prune_directories: vec!["live".to_string()],
+                    prune_after: def_prune_after(),
+                }),
+                hls_playlists: true,
+                content_protection: vec![
+                    ProtectedStream {
+                        prefix: "/vod/".to_string(),
+                        content_type: Some("video".to_string()),
+                        default_kid: "10000000-1000-1000-1000-100000000001".to_string(),
+                        scheme: "cbcs".to_string(),
+                        systems: vec![
+                            DrmSystem {
+                                system_id: "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed".to_string(),
+                                value: Some("Widevine".to_string()),
+                                pssh: Some("AAAAMnBzc2gAAAAA7e+LqXnWSs6jyCfc1R0h7QAAABISEBAAAAAQABAAEAAQAAAAAAE=".to_string()),
+                                license_url: None,
+                            },
+                            DrmSystem {
+                                system_id: "9a04f079-9840-4286-ab92-e65be0885f95".to_string(),
+                                value: None,
+                                pssh: None,
+                                license_url: Some(
+                                    "https://license.example.com/playready".to_string(),
+                                ),
+                            },
+                        ],
+                    },
+                    ProtectedStream {
+                        prefix: "/vod/".to_string(),
+                        content_type: None,
+                        default_kid: "20000000-2000-2000-2000-200000000002".to_string(),
+                        scheme: def_protection_scheme(),
+                        systems: def_drm_systems(),
+                    },
+                ],
+                clear_key: Some(ClearKey {
+                    path: def_license_path(),
+                    keys: vec![(
+                        "10000000-1000-1000-1000-100000000001".to_string(),
+                        "3a2a1b68dd2bd9b2eeb25e84c4776668".to_string(),
+                    )]
+                    .into_iter()
+                    .collect(),
+                    keys_file: Some("clear_keys".to_string()),
+                }),
+                tenants: vec![Tenant {
+                    name: "acme".to_string(),
+                    host: Some("acme.example.com".to_string()),
+                    prefix: Some("/acme".to_string()),
+                    root: "/srv/acme".to_string(),
+                    allow_origin: Some("https://acme.example.com".to_string()),
+                    cors: Some(Cors {
+                        allowed_origins: vec!["https://acme.example.com".to_string()],
+                        allowed_methods: def_cors_allowed_methods(),
+                        allowed_headers: def_cors_list(),
+                        exposed_headers: vec!["Content-Length".to_string()],
+                        allow_credentials: true,
+                        max_age: def_cors_max_age(),
+                    }),
+                    certificate_file: Some("acme_cert.pem".to_string()),
+                    private_key_file: Some("acme_key.pem".to_string()),
+                    quota: Some(Quota {
+                        period: QuotaPeriod::Monthly,
+                        max_requests: Some(1000),
+                        max_bytes: None,
+                    }),
+                }],
             }
         );
     }
 
     #[test]
     fn empty_object_defaults() {
-        test_init_conf();
-        GlobalConfig::init(EMPTY_OBJECT);
-        let config = GlobalConfig::config();
+        let config = Config::load(EMPTY_OBJECT).unwrap();
         assert!(config == config);
         assert_eq!(
-            *config,
+            config,
             Config {
                 network: def_network(),
                 security: def_security(),
                 performance: def_performance(),
+                cache_control: def_cache_control(),
+                cors: def_cors(),
+                logging: def_logging(),
+                admin: None,
+                mime_types: def_mime_types(),
+                response_headers: def_response_headers(),
+                rewrites: def_rewrites(),
+                error_pages: def_error_pages(),
+                generated_manifests: None,
+                low_latency: None,
+                ingest: None,
+                watch: None,
+                hls_playlists: false,
+                content_protection: def_content_protection(),
+                clear_key: None,
+                tenants: def_tenants(),
             }
         );
     }
@@ -0,0 +1,83 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::process;
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Detach from the terminal and continue in the background. The working
+/// directory is kept since the paths in the config can be relative to it.
+/// Has to be called before any threads are started, only the calling
+/// thread continues in the new process.
+pub fn daemonize() -> io::Result<()> {
+    // The parent returns to the shell and the child can start a new session
+    if check(unsafe { libc::fork() })? > 0 {
+        process::exit(0);
+    }
+    check(unsafe { libc::setsid() })?;
+    // Fork again so the daemon isn't a session leader and can't get a terminal back
+    if check(unsafe { libc::fork() })? > 0 {
+        process::exit(0);
+    }
+    Ok(())
+}
+
+/// Point stdout and stderr to `log_file`, opened for appending, or to
+/// /dev/null without one. Stdin is always /dev/null.
+/// Calling this again reopens the file after logrotate moved it.
+pub fn redirect_output(log_file: Option<&str>) -> io::Result<()> {
+    let output = match log_file {
+        Some(path) => OpenOptions::new().create(true).append(true).open(path)?,
+        None => OpenOptions::new().write(true).open("/dev/null")?,
+    };
+    let input = File::open("/dev/null")?;
+    check(unsafe { libc::dup2(input.as_raw_fd(), libc::STDIN_FILENO) })?;
+    check(unsafe { libc::dup2(output.as_raw_fd(), libc::STDOUT_FILENO) })?;
+    check(unsafe { libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO) })?;
+    Ok(())
+}
+
+/// File with the process id for init scripts
+#[derive(Clone)]
+pub struct PidFile {
+    path: String,
+}
+
+impl PidFile {
+    pub fn create(path: &str) -> io::Result<PidFile> {
+        fs::write(path, format!("{}\n", process::id()))?;
+        Ok(PidFile {
+            path: path.to_string(),
+        })
+    }
+
+    /// Remove the file when the server stops. Not done on drop since the
+    /// server exits with process::exit
+    pub fn remove(&self) {
+        // The file may not be writable anymore after dropping the privileges
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod daemon_tests {
+    use super::*;
+
+    #[test]
+    fn pid_file() {
+        let path = "target/daemon_test.pid";
+        let pid_file = PidFile::create(path).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            format!("{}\n", process::id())
+        );
+        pid_file.remove();
+        assert!(fs::metadata(path).is_err());
+    }
+}
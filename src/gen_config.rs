@@ -0,0 +1,210 @@
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::config::{Config, ConfigFormat};
+
+/// The config structs. The doc comments of their fields are written in the generated file
+const CONFIG_SOURCE: &str = include_str!("config.rs");
+
+struct Field {
+    /// Name in the config file
+    name: String,
+    /// Name of the struct the field holds, if any
+    struct_name: String,
+    doc: Vec<String>,
+}
+
+fn camel_case(snake_case: &str) -> String {
+    let mut words = snake_case.split('_');
+    let mut name = words.next().unwrap_or("").to_string();
+    for word in words {
+        let mut chars = word.chars();
+        if let Some(first) = chars.next() {
+            name.push(first.to_ascii_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
+
+/// Fields of the structs in `source` by the struct name, in declaration order
+fn parse_fields(source: &str) -> HashMap<&str, Vec<Field>> {
+    let mut structs = HashMap::new();
+    let mut current = None;
+    let mut doc = vec![];
+    for line in source.lines().map(str::trim) {
+        if let Some(comment) = line.strip_prefix("///") {
+            let comment = comment.trim();
+            doc.push(comment.strip_prefix("## ").unwrap_or(comment).to_string());
+            continue;
+        }
+        if line.starts_with("#[") {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix("pub struct ")
+            .and_then(|rest| rest.strip_suffix(" {"))
+        {
+            current = Some(structs.entry(name).or_insert_with(Vec::new));
+        } else if line == "}" {
+            current = None;
+        } else if let (Some(fields), Some(field)) = (&mut current, line.strip_prefix("pub ")) {
+            if let Some((name, type_name)) = field.trim_end_matches(',').split_once(": ") {
+                let type_name = type_name
+                    .strip_prefix("Option<")
+                    .and_then(|inner| inner.strip_suffix('>'))
+                    .unwrap_or(type_name);
+                fields.push(Field {
+                    name: camel_case(name),
+                    struct_name: type_name.to_string(),
+                    doc: doc.clone(),
+                });
+            }
+        }
+        doc.clear();
+    }
+    structs
+}
+
+fn write_comments(out: &mut String, doc: &[String], indent: &str) {
+    for line in doc {
+        out.push_str(&format!("{}# {}\n", indent, line));
+    }
+}
+
+/// Values that are not structs are written in the json flow style that yaml accepts
+fn write_yaml(
+    out: &mut String,
+    values: &Map<String, Value>,
+    fields: &[Field],
+    structs: &HashMap<&str, Vec<Field>>,
+    indent: usize,
+) {
+    let spaces = " ".repeat(indent);
+    for field in fields {
+        let value = match values.get(&field.name) {
+            Some(value) => value,
+            None => continue,
+        };
+        write_comments(out, &field.doc, &spaces);
+        match (value, structs.get(&field.struct_name[..])) {
+            (Value::Object(object), Some(struct_fields)) => {
+                out.push_str(&format!("{}{}:\n", spaces, field.name));
+                write_yaml(out, object, struct_fields, structs, indent + 2);
+            }
+            _ => out.push_str(&format!("{}{}: {}\n", spaces, field.name, value)),
+        }
+    }
+}
+
+/// Unset optional settings are written commented out since toml has no null
+fn write_toml(
+    out: &mut String,
+    table: &str,
+    values: &Map<String, Value>,
+    fields: &[Field],
+    structs: &HashMap<&str, Vec<Field>>,
+) {
+    let mut tables = vec![];
+    for field in fields {
+        let value = match values.get(&field.name) {
+            Some(value) => value,
+            None => continue,
+        };
+        if let Value::Object(object) = value {
+            // Tables have to come after the plain values
+            tables.push((field, object));
+            continue;
+        }
+
+        write_comments(out, &field.doc, "");
+        match toml::Value::try_from(value) {
+            Ok(value) => out.push_str(&format!("{} = {}\n", field.name, value)),
+            Err(_) => out.push_str(&format!("# {} =\n", field.name)),
+        }
+    }
+
+    for (field, object) in tables {
+        let name = if table.is_empty() {
+            field.name.clone()
+        } else {
+            format!("{}.{}", table, field.name)
+        };
+        out.push('\n');
+        write_comments(out, &field.doc, "");
+        out.push_str(&format!("[{}]\n", name));
+        let struct_fields = structs
+            .get(&field.struct_name[..])
+            .map(|fields| &fields[..]);
+        match struct_fields {
+            Some(struct_fields) => write_toml(out, &name, object, struct_fields, structs),
+            // Maps like mimeTypes
+            None => {
+                for (key, value) in object {
+                    if let Ok(value) = toml::Value::try_from(value) {
+                        out.push_str(&format!("{} = {}\n", toml::Value::from(key.clone()), value));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Configuration file with every setting at its default value.
+/// Toml and yaml files have the documentation of the settings as comments.
+pub fn default_config(format: ConfigFormat) -> Result<String, String> {
+    let defaults = Config::parse("{}", ConfigFormat::Json)?;
+    if format == ConfigFormat::Json {
+        return defaults.to_string_as(format);
+    }
+
+    let values = match serde_json::to_value(&defaults).map_err(|e| e.to_string())? {
+        Value::Object(values) => values,
+        _ => return Err("The config is not an object".to_string()),
+    };
+    let structs = parse_fields(CONFIG_SOURCE);
+    let fields = &structs["Config"];
+    let mut out = String::new();
+    match format {
+        ConfigFormat::Toml => write_toml(&mut out, "", &values, fields, &structs),
+        _ => write_yaml(&mut out, &values, fields, &structs, 0),
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod gen_config_tests {
+    use super::*;
+
+    #[test]
+    fn fields_with_docs() {
+        let structs = parse_fields(CONFIG_SOURCE);
+        let network = &structs["Network"];
+        assert_eq!(network[0].name, "address");
+        assert_eq!(network[0].doc.last().unwrap(), "Defaults to \"0.0.0.0\".");
+        let security = &structs["Security"];
+        let client_auth = security.iter().find(|f| f.name == "clientAuth").unwrap();
+        assert_eq!(client_auth.struct_name, "ClientAuth");
+    }
+
+    #[test]
+    fn generated_configs_parse() {
+        let defaults = Config::parse("{}", ConfigFormat::Json).unwrap();
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml].iter() {
+            let data = default_config(*format).unwrap();
+            assert_eq!(Config::parse(&data, *format).unwrap(), defaults);
+        }
+    }
+
+    #[test]
+    fn comments() {
+        let toml = default_config(ConfigFormat::Toml).unwrap();
+        assert!(toml.contains("# How many threads are handling the connection.\n"));
+        assert!(toml.contains("\n[security]\n"));
+        assert!(toml.contains("# redirectPort =\n"));
+
+        let yaml = default_config(ConfigFormat::Yaml).unwrap();
+        assert!(yaml.contains("  # Defaults to 4.\n  threadPoolSize: 4\n"));
+    }
+}
@@ -1,33 +1,68 @@
 /// ThreadPool implementation in lib.rs is copied from rust-book
-use std::sync::mpsc;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
+use std::time::{Duration, Instant};
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// How often `ThreadPool::shutdown` checks if the workers are done
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a worker over the minimum size of the pool waits for a job before it stops
+const IDLE_WORKER_TIMEOUT: Duration = Duration::from_secs(30);
+
 enum Message {
     NewJob(Job),
     Terminate,
 }
 
+/// State the workers share with the pool
+struct Shared {
+    receiver: Mutex<mpsc::Receiver<Message>>,
+    /// Jobs waiting for a free worker
+    queued: AtomicUsize,
+    /// Workers running a job
+    busy: AtomicUsize,
+    /// Workers that haven't stopped
+    alive: AtomicUsize,
+    min_size: usize,
+    idle_timeout: Duration,
+}
+
+impl Shared {
+    /// Stop an idle worker unless the pool would go under its minimum size
+    fn retire(&self) -> bool {
+        self.alive
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |alive| {
+                (alive > self.min_size).then_some(alive - 1)
+            })
+            .is_ok()
+    }
+}
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
+    workers: Mutex<Vec<Worker>>,
     sender: mpsc::Sender<Message>,
+    shared: Arc<Shared>,
+    max_size: usize,
+    next_id: AtomicUsize,
+    /// Have the workers been told to terminate
+    terminated: bool,
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers.");
-
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
-        }
+        self.terminate();
 
-        println!("Shutting down all workers.");
+        log::debug!("Shutting down all workers.");
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
+        for worker in self.workers.get_mut().unwrap() {
+            log::trace!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
                 thread.join().unwrap();
@@ -45,19 +80,102 @@ impl ThreadPool {
     ///
     /// The `new` function will panic if the size is zero.
     pub fn new(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        ThreadPool::scaling(size, size)
+    }
+
+    /// Create a ThreadPool that grows with the load.
+    ///
+    /// The pool starts with `min_size` threads and starts more, up to
+    /// `max_size`, while jobs are waiting for a free thread. Threads over
+    /// `min_size` stop after they have been idle for a while.
+    ///
+    /// # Panics
+    ///
+    /// The `scaling` function will panic if `min_size` is zero or `max_size`
+    /// is smaller than `min_size`.
+    pub fn scaling(min_size: usize, max_size: usize) -> ThreadPool {
+        ThreadPool::with_idle_timeout(min_size, max_size, IDLE_WORKER_TIMEOUT)
+    }
+
+    fn with_idle_timeout(min_size: usize, max_size: usize, idle_timeout: Duration) -> ThreadPool {
+        assert!(min_size > 0);
+        assert!(max_size >= min_size);
 
         let (sender, receiver) = mpsc::channel();
 
-        let receiver = Arc::new(Mutex::new(receiver));
+        let shared = Arc::new(Shared {
+            receiver: Mutex::new(receiver),
+            queued: AtomicUsize::new(0),
+            busy: AtomicUsize::new(0),
+            alive: AtomicUsize::new(min_size),
+            min_size,
+            idle_timeout,
+        });
+
+        let mut workers = Vec::with_capacity(min_size);
+
+        for id in 0..min_size {
+            workers.push(Worker::new(id, Arc::clone(&shared)));
+        }
 
-        let mut workers = Vec::with_capacity(size);
+        ThreadPool {
+            workers: Mutex::new(workers),
+            sender,
+            shared,
+            max_size,
+            next_id: AtomicUsize::new(min_size),
+            terminated: false,
+        }
+    }
 
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
+    /// Tell every worker to stop once the jobs queued before are done
+    fn terminate(&mut self) {
+        if self.terminated {
+            return;
         }
+        self.terminated = true;
 
-        ThreadPool { workers, sender }
+        log::debug!("Sending terminate message to all workers.");
+
+        // A worker stopping for being idle at the same time leaves an extra
+        // message in the channel, which doesn't matter
+        for _ in 0..self.shared.alive.load(Ordering::SeqCst) {
+            self.sender.send(Message::Terminate).unwrap();
+        }
+    }
+
+    /// Finish the queued jobs and join the workers. Returns false if some
+    /// workers were still busy after `timeout`. Those are left running on
+    /// their own so dropping the pool doesn't wait for them.
+    pub fn shutdown(&mut self, timeout: Duration) -> bool {
+        self.terminate();
+
+        let workers = self.workers.get_mut().unwrap();
+        let start = Instant::now();
+        loop {
+            for worker in workers.iter_mut() {
+                if worker.thread.as_ref().is_some_and(|t| t.is_finished()) {
+                    log::trace!("Shutting down worker {}", worker.id);
+                    // Jobs can't panic the worker, see Worker::new
+                    let _ = worker.thread.take().map(thread::JoinHandle::join);
+                }
+            }
+            let busy = workers.iter().filter(|w| w.thread.is_some()).count();
+            if busy == 0 {
+                return true;
+            }
+            if start.elapsed() >= timeout {
+                log::warn!(
+                    "{} workers were still busy after the shutdown timeout",
+                    busy
+                );
+                for worker in workers.iter_mut() {
+                    worker.thread.take();
+                }
+                return false;
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
     }
 
     pub fn execute<F>(&self, f: F)
@@ -65,7 +183,59 @@ impl ThreadPool {
         F: FnOnce() + Send + 'static,
     {
         let job = Box::new(f);
+        self.shared.queued.fetch_add(1, Ordering::SeqCst);
         self.sender.send(Message::NewJob(job)).unwrap();
+        self.grow();
+    }
+
+    /// Start another worker if there are more jobs waiting than idle
+    /// workers and the pool isn't at its largest
+    fn grow(&self) {
+        let shared = &self.shared;
+        let queued = shared.queued.load(Ordering::SeqCst);
+        let alive = shared.alive.load(Ordering::SeqCst);
+        let idle = alive.saturating_sub(shared.busy.load(Ordering::SeqCst));
+        if queued <= idle {
+            return;
+        }
+        let max_size = self.max_size;
+        let reserved = shared
+            .alive
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |alive| {
+                (alive < max_size).then_some(alive + 1)
+            });
+        if reserved.is_err() {
+            return;
+        }
+
+        let mut workers = self.workers.lock().unwrap();
+        // Forget the workers that stopped for being idle
+        workers.retain(|w| w.thread.as_ref().is_some_and(|t| !t.is_finished()));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        log::debug!("Starting worker {}, {} jobs waiting.", id, queued);
+        workers.push(Worker::new(id, Arc::clone(shared)));
+    }
+
+    /// Number of jobs waiting for a free worker. The queue itself has no
+    /// limit, callers shed load by checking this before `execute`.
+    pub fn queued(&self) -> usize {
+        self.shared.queued.load(Ordering::SeqCst)
+    }
+
+    /// Number of running workers
+    pub fn size(&self) -> usize {
+        self.shared.alive.load(Ordering::SeqCst)
+    }
+}
+
+/// Message of a panic for the log
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
     }
 }
 
@@ -75,22 +245,46 @@ struct Worker {
 }
 
 impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+    /// Start a worker thread. The caller counts it in `shared.alive`.
+    fn new(id: usize, shared: Arc<Shared>) -> Worker {
+        let thread = thread::spawn(move || {
+            loop {
+                let message = shared
+                    .receiver
+                    .lock()
+                    .unwrap()
+                    .recv_timeout(shared.idle_timeout);
 
-            match message {
-                Message::NewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
+                match message {
+                    Ok(Message::NewJob(job)) => {
+                        log::trace!("Worker {} got a job; executing.", id);
+                        shared.queued.fetch_sub(1, Ordering::SeqCst);
+                        shared.busy.fetch_add(1, Ordering::SeqCst);
 
-                    job();
-                }
-                Message::Terminate => {
-                    println!("Worker {} was told to terminate.", id);
+                        // A panicking job must not take the worker with it or
+                        // the pool would shrink with every misbehaving client
+                        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                            log::error!("Worker {} job panicked: {}", id, panic_message(&*payload));
+                        }
+                        shared.busy.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    Ok(Message::Terminate) => {
+                        log::trace!("Worker {} was told to terminate.", id);
+
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        if shared.retire() {
+                            log::debug!("Worker {} was idle; stopping.", id);
 
-                    break;
+                            return;
+                        }
+                    }
+                    // The pool is gone
+                    Err(RecvTimeoutError::Disconnected) => break,
                 }
             }
+            shared.alive.fetch_sub(1, Ordering::SeqCst);
         });
 
         Worker {
@@ -99,3 +293,117 @@ impl Worker {
         }
     }
 }
+
+#[cfg(test)]
+mod thread_pool_tests {
+    use super::*;
+
+    #[test]
+    fn queued_jobs() {
+        let pool = ThreadPool::new(1);
+        let (started, wait_start) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+        });
+        wait_start.recv().unwrap();
+
+        let (done, wait_done) = mpsc::channel();
+        for _ in 0..2 {
+            let done = done.clone();
+            pool.execute(move || done.send(()).unwrap());
+        }
+        assert_eq!(pool.queued(), 2);
+
+        release.send(()).unwrap();
+        wait_done.recv().unwrap();
+        wait_done.recv().unwrap();
+        assert_eq!(pool.queued(), 0);
+    }
+
+    #[test]
+    fn panicking_job_keeps_the_worker() {
+        let pool = ThreadPool::new(1);
+        pool.execute(|| panic!("misbehaving client"));
+        let (done, wait_done) = mpsc::channel();
+        pool.execute(move || done.send(()).unwrap());
+        wait_done
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+    }
+
+    #[test]
+    fn shutdown_finishes_queued_jobs() {
+        let mut pool = ThreadPool::new(2);
+        let (done, wait_done) = mpsc::channel();
+        for _ in 0..4 {
+            let done = done.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(20));
+                done.send(()).unwrap();
+            });
+        }
+        assert!(pool.shutdown(Duration::from_secs(5)));
+        assert_eq!(wait_done.try_iter().count(), 4);
+    }
+
+    #[test]
+    fn shutdown_timeout() {
+        let mut pool = ThreadPool::new(1);
+        let (release, wait_release) = mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = wait_release.recv();
+        });
+        assert!(!pool.shutdown(Duration::from_millis(20)));
+        // Dropping doesn't wait for the busy worker
+        drop(pool);
+        drop(release);
+    }
+
+    #[test]
+    fn scaling_with_the_load() {
+        let pool = ThreadPool::with_idle_timeout(1, 3, Duration::from_millis(50));
+        let (started, wait_start) = mpsc::channel();
+        let (release, wait_release) = mpsc::channel::<()>();
+        let wait_release = Arc::new(Mutex::new(wait_release));
+        for _ in 0..5 {
+            let started = started.clone();
+            let wait_release = Arc::clone(&wait_release);
+            pool.execute(move || {
+                started.send(()).unwrap();
+                let _ = wait_release.lock().unwrap().recv();
+            });
+        }
+        // Grows to the maximum while jobs are waiting
+        for _ in 0..3 {
+            wait_start.recv().unwrap();
+        }
+        assert_eq!(pool.size(), 3);
+        assert_eq!(pool.queued(), 2);
+
+        drop(release);
+        for _ in 0..2 {
+            wait_start.recv().unwrap();
+        }
+        // And shrinks back to the minimum once idle
+        let start = Instant::now();
+        while pool.size() > 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(pool.size(), 1);
+
+        let (done, wait_done) = mpsc::channel();
+        pool.execute(move || done.send(()).unwrap());
+        wait_done.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn panic_messages() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&"formatted".to_string()), "formatted");
+        assert_eq!(panic_message(&42), "unknown panic");
+    }
+}
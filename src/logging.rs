@@ -0,0 +1,159 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{LogFormat, LogLevel, Logging};
+
+/// Writes the messages of the `log` macros to stderr, which is the log
+/// file when the server runs with --log-file
+struct Logger {
+    json: AtomicBool,
+}
+
+static LOGGER: Logger = Logger {
+    json: AtomicBool::new(false),
+};
+
+fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Off => LevelFilter::Off,
+        LogLevel::Error => LevelFilter::Error,
+        LogLevel::Warn => LevelFilter::Warn,
+        LogLevel::Info => LevelFilter::Info,
+        LogLevel::Debug => LevelFilter::Debug,
+        LogLevel::Trace => LevelFilter::Trace,
+    }
+}
+
+/// UTC time in the RFC 3339 format, e.g. "2000-10-10T13:45:36.042Z"
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, second_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from the days since 1970-01-01, by Howard Hinnant
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+fn format_line(json: bool, time: SystemTime, level: Level, target: &str, message: &str) -> String {
+    if json {
+        let line = serde_json::json!({
+            "time": timestamp(time),
+            "level": level.as_str(),
+            "target": target,
+            "message": message,
+        });
+        format!("{}\n", line)
+    } else {
+        format!("{} {:<5} {}: {}\n", timestamp(time), level, target, message)
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_line(
+            self.json.load(Ordering::Relaxed),
+            SystemTime::now(),
+            record.level(),
+            record.target(),
+            &record.args().to_string(),
+        );
+        // One write per line so the lines of different threads don't mix
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Start logging with the level and format of `settings`
+pub fn init(settings: &Logging) {
+    // Only fails if a logger was already set
+    let _ = log::set_logger(&LOGGER);
+    apply(settings);
+}
+
+/// Switch to the level and format of `settings`, e.g. after a reload
+pub fn apply(settings: &Logging) {
+    LOGGER
+        .json
+        .store(settings.format == LogFormat::Json, Ordering::Relaxed);
+    log::set_max_level(level_filter(settings.level));
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(971_185_536_042);
+        assert_eq!(timestamp(time), "2000-10-10T13:45:36.042Z");
+        // Leap day
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(timestamp(time), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(
+            format_line(
+                false,
+                UNIX_EPOCH,
+                Level::Warn,
+                "mpeg_dash::server",
+                "No \"x\""
+            ),
+            "1970-01-01T00:00:00.000Z WARN  mpeg_dash::server: No \"x\"\n"
+        );
+        let line = format_line(
+            true,
+            UNIX_EPOCH,
+            Level::Warn,
+            "mpeg_dash::server",
+            "No \"x\"",
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "time": "1970-01-01T00:00:00.000Z",
+                "level": "WARN",
+                "target": "mpeg_dash::server",
+                "message": "No \"x\"",
+            })
+        );
+        assert!(line.ends_with("}\n"));
+    }
+}
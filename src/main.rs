@@ -1,18 +1,247 @@
-use std::env;
+use std::fs;
+use std::mem;
+use std::process;
+use std::ptr;
+use std::thread;
 
+mod cert;
+mod cli;
 mod config;
+mod daemon;
+mod gen_config;
+mod logging;
+mod mpd;
+mod privileges;
 mod server;
 
+use clap::Parser;
+use cli::{Cli, Command, ConfigArgs, ServeArgs};
+use config::{Config, ConfigFormat, SharedConfig};
+use daemon::PidFile;
+use log::{error, info};
+
+/// Write a self signed certificate for local testing
+fn gen_cert(certificate_file: &str, private_key_file: &str) {
+    if let Err(e) = cert::generate(certificate_file, private_key_file) {
+        eprintln!("Cannot generate the certificate: {}", e);
+        process::exit(1);
+    }
+
+    println!(
+        "Wrote {} and {}. Use them with this in the configuration file:",
+        certificate_file, private_key_file
+    );
+    let security = serde_json::json!({
+        "security": {
+            "https": true,
+            "certificateFile": certificate_file,
+            "privateKeyFile": private_key_file,
+        }
+    });
+    println!("{}", serde_json::to_string_pretty(&security).unwrap());
+}
+
+/// Print the default configuration
+fn gen_config(format: ConfigFormat) {
+    match gen_config::default_config(format) {
+        Ok(data) => print!("{}", data),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn load_config(args: &ConfigArgs) -> Config {
+    match args.load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", args.config_path(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Block SIGINT, SIGTERM, SIGHUP and SIGUSR1 so they can be waited for in one thread.
+/// Has to be called before any other threads are started since they
+/// inherit the signal mask.
+fn block_stop_signals() -> libc::sigset_t {
+    unsafe {
+        let mut signals: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::sigaddset(&mut signals, libc::SIGHUP);
+        libc::sigaddset(&mut signals, libc::SIGUSR1);
+        libc::pthread_sigmask(libc::SIG_BLOCK, &signals, ptr::null_mut());
+        signals
+    }
+}
+
+/// Reload the config on SIGHUP and reopen the log file on SIGUSR1.
+/// Stop the server on the first SIGINT or SIGTERM and exit right away on the second one
+fn handle_signals(
+    signals: libc::sigset_t,
+    stopper: server::Stopper,
+    config: SharedConfig,
+    args: ServeArgs,
+    pid_file: Option<PidFile>,
+    access_log: Option<server::AccessLog>,
+) {
+    thread::spawn(move || {
+        let mut stopping = false;
+        loop {
+            let mut signal = 0;
+            unsafe { libc::sigwait(&signals, &mut signal) };
+            if signal == libc::SIGHUP {
+                match args.config.load() {
+                    Ok(new_config) => {
+                        logging::apply(&new_config.logging);
+                        config.replace(new_config);
+                        info!("Reloaded {}", args.config.config_path());
+                    }
+                    Err(e) => error!("Keeping the old configuration. {}", e),
+                }
+            } else if signal == libc::SIGUSR1 {
+                if let Some(access_log) = &access_log {
+                    access_log.reopen();
+                }
+                if let Some(log_file) = &args.log_file {
+                    if let Err(e) = daemon::redirect_output(Some(log_file)) {
+                        error!("Cannot reopen {}: {}", log_file, e);
+                    }
+                }
+            } else if stopping {
+                if let Some(pid_file) = &pid_file {
+                    pid_file.remove();
+                }
+                process::exit(1);
+            } else {
+                info!("Stopping the server, waiting for open connections");
+                stopper.stop();
+                stopping = true;
+            }
+        }
+    });
+}
+
+/// Check the things the server needs that the configuration file alone
+/// doesn't tell, like the certificate matching the private key.
+/// Exits with 1 if something is wrong.
+fn check_config(args: &ConfigArgs) {
+    let config = load_config(args);
+    let mut errors = vec![];
+
+    if config.uses_tls() {
+        if let Err(e) = cert::check_key_pair(
+            &config.security.certificate_file,
+            &config.security.private_key_file,
+        ) {
+            errors.push(e);
+        }
+    }
+
+    if let Some(user) = &config.security.user {
+        if let Err(e) = privileges::user_ids(user) {
+            errors.push(e.to_string());
+        }
+    }
+    if let Some(group) = &config.security.group {
+        if let Err(e) = privileges::group_id(group) {
+            errors.push(e.to_string());
+        }
+    }
+
+    let mut roots = vec![&config.network.document_root];
+    roots.extend(config.tenants.iter().map(|tenant| &tenant.root));
+    for root in roots {
+        if let Err(e) = fs::read_dir(root) {
+            errors.push(format!("Cannot read the document root {}: {}", root, e));
+        }
+    }
+
+    if !errors.is_empty() {
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        process::exit(1);
+    }
+    println!("{} is valid", args.config_path());
+}
+
+/// Check the manifests and exit with 1 if any of them is invalid
+fn check_mpd(files: &[String]) {
+    let mut valid = true;
+    for file in files {
+        match mpd::Mpd::load_valid(file) {
+            Ok(_) => println!("{} is valid", file),
+            Err(errors) => {
+                valid = false;
+                for error in errors {
+                    eprintln!("{}: {}", file, error);
+                }
+            }
+        }
+    }
+    if !valid {
+        process::exit(1);
+    }
+}
+
+/// Detach, redirect the output and write the pid file as asked in `args`
+fn start_daemon(args: &ServeArgs) -> Option<PidFile> {
+    let mut result = Ok(());
+    if args.daemon {
+        result = daemon::daemonize();
+    }
+    if args.daemon || args.log_file.is_some() {
+        result = result.and_then(|_| daemon::redirect_output(args.log_file.as_deref()));
+    }
+    let pid_file = result.and_then(|_| args.pid_file.as_deref().map(PidFile::create).transpose());
+    match pid_file {
+        Ok(pid_file) => pid_file,
+        Err(e) => {
+            error!("Cannot start the daemon: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+fn serve(args: ServeArgs) {
+    let config = SharedConfig::new(load_config(&args.config));
+    logging::init(&config.get().logging);
+    // Before any threads are started, see daemonize
+    let pid_file = start_daemon(&args);
+    let signals = block_stop_signals();
+    let mut server = server::DashServer::new(config.clone());
+    let security = &config.get().security;
+    if let Err(e) = privileges::drop_to(security.user.as_deref(), security.group.as_deref()) {
+        error!("Cannot switch the user: {}", e);
+        process::exit(1);
+    }
+    let stopper = server.stopper();
+    let access_log = server.access_log();
+    handle_signals(signals, stopper, config, args, pid_file.clone(), access_log);
+
+    // The worker threads may still be blocked on connections that didn't
+    // finish in time so the process exits without waiting for them
+    let drained = server.start_server();
+    if let Some(pid_file) = pid_file {
+        pid_file.remove();
+    }
+    process::exit(if drained { 0 } else { 1 });
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let conf_path = if args.len() < 2 {
-        "config.json"
-    } else {
-        &args[1][..]
-    };
-
-    // Config needs to be initialized here. See the init function for more information
-    config::GlobalConfig::init(conf_path);
-    let server = server::DashServer::new();
-    server.start_server();
+    let cli = Cli::parse();
+    match cli.command.unwrap_or(Command::Serve(cli.serve)) {
+        Command::Serve(args) => serve(args),
+        Command::CheckConfig(args) => check_config(&args),
+        Command::CheckMpd { files } => check_mpd(&files),
+        Command::GenConfig { format } => gen_config(format),
+        Command::GenCert {
+            certificate_file,
+            private_key_file,
+        } => gen_cert(&certificate_file, &private_key_file),
+    }
 }
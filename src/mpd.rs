@@ -0,0 +1,1126 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Namespace of the 2011 schema that every current manifest uses
+pub const NAMESPACE: &str = "urn:mpeg:dash:schema:mpd:2011";
+
+/// Profile of manifests with SegmentTemplate segments
+pub const PROFILE_LIVE: &str = "urn:mpeg:dash:profile:isoff-live:2011";
+
+fn def_namespace() -> String {
+    NAMESPACE.to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MpdType {
+    /// On demand, everything is available from the start
+    #[default]
+    Static,
+    /// Live, segments become available over time
+    Dynamic,
+}
+
+/// The DASH manifest (ISO/IEC 23009-1), the MPD element. Only the parts of
+/// the schema the server works with are modelled, other elements and
+/// attributes are skipped when parsing.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+#[serde(rename = "MPD")]
+pub struct Mpd {
+    #[serde(rename = "@xmlns", default = "def_namespace")]
+    pub xmlns: String,
+    #[serde(rename = "@id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "@type", default)]
+    pub mpd_type: MpdType,
+    /// Comma separated profile urns, e.g. PROFILE_LIVE
+    #[serde(rename = "@profiles")]
+    pub profiles: String,
+    /// xs:duration, e.g. "PT9M56.458S". See `parse_duration`.
+    #[serde(
+        rename = "@mediaPresentationDuration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub media_presentation_duration: Option<String>,
+    #[serde(rename = "@minBufferTime", skip_serializing_if = "Option::is_none")]
+    pub min_buffer_time: Option<String>,
+    /// xs:dateTime, needed by dynamic manifests
+    #[serde(
+        rename = "@availabilityStartTime",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub availability_start_time: Option<String>,
+    #[serde(rename = "@publishTime", skip_serializing_if = "Option::is_none")]
+    pub publish_time: Option<String>,
+    #[serde(
+        rename = "@minimumUpdatePeriod",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub minimum_update_period: Option<String>,
+    #[serde(
+        rename = "@timeShiftBufferDepth",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub time_shift_buffer_depth: Option<String>,
+    #[serde(
+        rename = "@suggestedPresentationDelay",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub suggested_presentation_delay: Option<String>,
+    #[serde(
+        rename = "@maxSegmentDuration",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_segment_duration: Option<String>,
+    #[serde(rename = "BaseURL", default, skip_serializing_if = "Vec::is_empty")]
+    pub base_urls: Vec<String>,
+    #[serde(rename = "Period", default)]
+    pub periods: Vec<Period>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct Period {
+    #[serde(rename = "@id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// xs:duration from the start of the presentation
+    #[serde(rename = "@start", skip_serializing_if = "Option::is_none")]
+    pub start: Option<String>,
+    #[serde(rename = "@duration", skip_serializing_if = "Option::is_none")]
+    pub duration: Option<String>,
+    #[serde(rename = "BaseURL", default, skip_serializing_if = "Vec::is_empty")]
+    pub base_urls: Vec<String>,
+    #[serde(rename = "SegmentTemplate", skip_serializing_if = "Option::is_none")]
+    pub segment_template: Option<SegmentTemplate>,
+    #[serde(rename = "AdaptationSet", default)]
+    pub adaptation_sets: Vec<AdaptationSet>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct AdaptationSet {
+    #[serde(rename = "@id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+    /// "video", "audio", "text" or "image"
+    #[serde(rename = "@contentType", skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(rename = "@mimeType", skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(rename = "@codecs", skip_serializing_if = "Option::is_none")]
+    pub codecs: Option<String>,
+    /// RFC 5646 language tag, e.g. "en"
+    #[serde(rename = "@lang", skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    #[serde(rename = "@segmentAlignment", skip_serializing_if = "Option::is_none")]
+    pub segment_alignment: Option<bool>,
+    #[serde(rename = "@startWithSAP", skip_serializing_if = "Option::is_none")]
+    pub start_with_sap: Option<u32>,
+    #[serde(rename = "@maxWidth", skip_serializing_if = "Option::is_none")]
+    pub max_width: Option<u32>,
+    #[serde(rename = "@maxHeight", skip_serializing_if = "Option::is_none")]
+    pub max_height: Option<u32>,
+    #[serde(rename = "Role", default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<Descriptor>,
+    #[serde(rename = "SegmentTemplate", skip_serializing_if = "Option::is_none")]
+    pub segment_template: Option<SegmentTemplate>,
+    #[serde(rename = "Representation", default)]
+    pub representations: Vec<Representation>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct Representation {
+    #[serde(rename = "@id")]
+    pub id: String,
+    /// Bits per second
+    #[serde(rename = "@bandwidth")]
+    pub bandwidth: u64,
+    #[serde(rename = "@mimeType", skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(rename = "@codecs", skip_serializing_if = "Option::is_none")]
+    pub codecs: Option<String>,
+    #[serde(rename = "@width", skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(rename = "@height", skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    /// E.g. "24" or "30000/1001"
+    #[serde(rename = "@frameRate", skip_serializing_if = "Option::is_none")]
+    pub frame_rate: Option<String>,
+    #[serde(rename = "@audioSamplingRate", skip_serializing_if = "Option::is_none")]
+    pub audio_sampling_rate: Option<String>,
+    #[serde(rename = "@scanType", skip_serializing_if = "Option::is_none")]
+    pub scan_type: Option<String>,
+    #[serde(
+        rename = "AudioChannelConfiguration",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub audio_channel_configurations: Vec<Descriptor>,
+    #[serde(rename = "BaseURL", default, skip_serializing_if = "Vec::is_empty")]
+    pub base_urls: Vec<String>,
+    #[serde(rename = "SegmentTemplate", skip_serializing_if = "Option::is_none")]
+    pub segment_template: Option<SegmentTemplate>,
+}
+
+/// Scheme and value pair, e.g. a Role or an AudioChannelConfiguration
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct Descriptor {
+    #[serde(rename = "@schemeIdUri")]
+    pub scheme_id_uri: String,
+    #[serde(rename = "@value", skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+}
+
+/// Segment urls from a template. The attributes missing here are taken from
+/// the templates of the parent elements, see `effective_template`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct SegmentTemplate {
+    /// E.g. "$RepresentationID$/seg-$Number$.m4s"
+    #[serde(rename = "@media", skip_serializing_if = "Option::is_none")]
+    pub media: Option<String>,
+    #[serde(rename = "@initialization", skip_serializing_if = "Option::is_none")]
+    pub initialization: Option<String>,
+    /// Units per second of the durations and times
+    #[serde(rename = "@timescale", skip_serializing_if = "Option::is_none")]
+    pub timescale: Option<u64>,
+    /// Duration of every segment. Either this or the timeline is used.
+    #[serde(rename = "@duration", skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(rename = "@startNumber", skip_serializing_if = "Option::is_none")]
+    pub start_number: Option<u64>,
+    #[serde(
+        rename = "@presentationTimeOffset",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub presentation_time_offset: Option<u64>,
+    #[serde(rename = "SegmentTimeline", skip_serializing_if = "Option::is_none")]
+    pub segment_timeline: Option<SegmentTimeline>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct SegmentTimeline {
+    #[serde(rename = "S", default)]
+    pub segments: Vec<TimelineSegment>,
+}
+
+/// The S element, `repeat` + 1 segments of the same duration
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Default)]
+pub struct TimelineSegment {
+    /// Start time. Continues from the previous segment when missing.
+    #[serde(rename = "@t", skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    #[serde(rename = "@d")]
+    pub duration: u64,
+    /// How many times the segment repeats. -1 repeats until the next S,
+    /// the end of the Period or the next manifest update.
+    #[serde(rename = "@r", skip_serializing_if = "Option::is_none")]
+    pub repeat: Option<i64>,
+}
+
+/// Parse the manifest in `xml`
+pub fn parse(xml: &str) -> Result<Mpd, String> {
+    quick_xml::de::from_str(xml).map_err(|e| format!("Invalid manifest: {}", e))
+}
+
+/// Read and parse the manifest file at `path`
+pub fn load(path: &str) -> Result<Mpd, String> {
+    let xml = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+    parse(&xml)
+}
+
+/// Parse a xs:duration like "PT1H2M3.5S" or "P1DT12H". Years and months
+/// don't have a fixed length so they aren't supported.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let rest = value.trim().strip_prefix('P')?;
+    let (days, time) = match rest.split_once('T') {
+        Some((days, time)) => (days, Some(time)),
+        None => (rest, None),
+    };
+    if time == Some("") || (days.is_empty() && time.is_none()) {
+        return None;
+    }
+    let mut seconds = 0.0;
+    if !days.is_empty() {
+        seconds += days.strip_suffix('D')?.parse::<u64>().ok()? as f64 * 86400.0;
+    }
+    let mut number = String::new();
+    for c in time.unwrap_or("").chars() {
+        let unit = match c {
+            '0'..='9' | '.' => {
+                number.push(c);
+                continue;
+            }
+            'H' => 3600.0,
+            'M' => 60.0,
+            'S' => 1.0,
+            _ => return None,
+        };
+        // Only the seconds can have a fraction
+        if number.is_empty() || (unit != 1.0 && number.contains('.')) {
+            return None;
+        }
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    // Too long for a Duration
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// `duration` as a xs:duration, e.g. "PT9M56.458S"
+pub fn format_duration(duration: Duration) -> String {
+    let total = duration.as_secs();
+    let hours = total / 3600;
+    let minutes = total % 3600 / 60;
+    let seconds = (total % 60) as f64 + f64::from(duration.subsec_millis()) / 1000.0;
+    let mut out = "PT".to_string();
+    if hours > 0 {
+        out.push_str(&format!("{}H", hours));
+    }
+    if minutes > 0 {
+        out.push_str(&format!("{}M", minutes));
+    }
+    if seconds > 0.0 || (hours == 0 && minutes == 0) {
+        out.push_str(&format!("{}S", seconds));
+    }
+    out
+}
+
+/// `time` as a UTC xs:dateTime, e.g. "2024-05-01T12:30:00.250Z"
+pub fn format_date_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, second_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date from the days since 1970-01-01, by Howard Hinnant
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Identifiers of a segment url template (ISO 23009-1 5.3.9.4.4). Returns
+/// the names without the width formats, e.g. ["RepresentationID", "Number"],
+/// or None if the template has an unknown identifier or an unmatched "$".
+pub fn template_identifiers(template: &str) -> Option<Vec<&str>> {
+    let parts: Vec<&str> = template.split('$').collect();
+    // Text and identifiers alternate so there's an odd number of parts
+    if parts.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut identifiers = vec![];
+    for part in parts.iter().skip(1).step_by(2) {
+        // "$$" is an escaped "$"
+        if part.is_empty() {
+            continue;
+        }
+        let (name, format) = match part.split_once('%') {
+            Some((name, format)) => (name, Some(format)),
+            None => (*part, None),
+        };
+        let known = [
+            "RepresentationID",
+            "Number",
+            "Bandwidth",
+            "Time",
+            "SubNumber",
+        ];
+        if !known.contains(&name) {
+            return None;
+        }
+        if let Some(format) = format {
+            let width = format.strip_prefix('0').and_then(|f| f.strip_suffix('d'));
+            if name == "RepresentationID" || width.is_none_or(|w| w.parse::<u32>().is_err()) {
+                return None;
+            }
+        }
+        identifiers.push(name);
+    }
+    Some(identifiers)
+}
+
+/// The template of `representation` with the missing attributes taken from
+/// the templates of its adaptation set and period
+pub fn effective_template(
+    period: &Period,
+    adaptation_set: &AdaptationSet,
+    representation: &Representation,
+) -> Option<SegmentTemplate> {
+    let templates: Vec<&SegmentTemplate> = [
+        &representation.segment_template,
+        &adaptation_set.segment_template,
+        &period.segment_template,
+    ]
+    .iter()
+    .filter_map(|template| template.as_ref())
+    .collect();
+    if templates.is_empty() {
+        return None;
+    }
+    Some(SegmentTemplate {
+        media: inherited(&templates, |t| &t.media),
+        initialization: inherited(&templates, |t| &t.initialization),
+        timescale: inherited(&templates, |t| &t.timescale),
+        duration: inherited(&templates, |t| &t.duration),
+        start_number: inherited(&templates, |t| &t.start_number),
+        presentation_time_offset: inherited(&templates, |t| &t.presentation_time_offset),
+        segment_timeline: inherited(&templates, |t| &t.segment_timeline),
+    })
+}
+
+/// The first value set in `templates`, the nearest template first
+fn inherited<T: Clone>(
+    templates: &[&SegmentTemplate],
+    get: impl Fn(&SegmentTemplate) -> &Option<T>,
+) -> Option<T> {
+    templates.iter().find_map(|template| get(template).clone())
+}
+
+/// Segments listed for one representation at most. Stops a timeline with a
+/// huge repeat count from listing billions of urls.
+const MAX_SEGMENTS: usize = 100_000;
+
+/// Values of the template identifiers of one segment
+pub struct TemplateValues<'a> {
+    pub representation_id: &'a str,
+    pub bandwidth: u64,
+    pub number: u64,
+    pub time: u64,
+}
+
+/// `template` with the identifiers replaced with `values`, e.g.
+/// "seg-$Number%03d$.m4s" becomes "seg-007.m4s". None if the template is
+/// invalid or has $SubNumber$.
+pub fn fill_template(template: &str, values: &TemplateValues) -> Option<String> {
+    template_identifiers(template)?;
+    let mut url = String::new();
+    for (i, part) in template.split('$').enumerate() {
+        if i.is_multiple_of(2) {
+            url.push_str(part);
+            continue;
+        }
+        if part.is_empty() {
+            url.push('$');
+            continue;
+        }
+        // The width formats were checked by template_identifiers
+        let (name, width) = match part.split_once('%') {
+            Some((name, format)) => (name, format[1..format.len() - 1].parse().ok()?),
+            None => (part, 0),
+        };
+        let value = match name {
+            "RepresentationID" => values.representation_id.to_string(),
+            "Number" => values.number.to_string(),
+            "Bandwidth" => values.bandwidth.to_string(),
+            "Time" => values.time.to_string(),
+            _ => return None,
+        };
+        url.push_str(&format!("{:0>width$}", value, width = width));
+    }
+    Some(url)
+}
+
+/// `reference` resolved against the url path `base`, e.g. "video/seg-1.m4s"
+/// against "/vod/stream.mpd" is "/vod/video/seg-1.m4s". None for urls with
+/// a scheme since they aren't on this server.
+pub fn join_url(base: &str, reference: &str) -> Option<String> {
+    if reference.contains("://") {
+        return None;
+    }
+    if reference.starts_with('/') {
+        return Some(reference.to_string());
+    }
+    let dir = &base[..base.rfind('/').map_or(0, |i| i + 1)];
+    Some(format!("{}{}", dir, reference))
+}
+
+/// Numbers, start times and durations of the media segments of `template`. The
+/// segments can only be counted to the end of the period when it's known,
+/// so a template without a timeline gives none without it.
+fn template_segments(
+    template: &SegmentTemplate,
+    period_duration: Option<f64>,
+) -> Vec<(u64, u64, u64)> {
+    let timescale = template.timescale.unwrap_or(1);
+    let offset = template.presentation_time_offset.unwrap_or(0);
+    let end = period_duration.map(|duration| offset + (duration * timescale as f64).round() as u64);
+    let mut number = template.start_number.unwrap_or(1);
+    let mut segments = vec![];
+
+    if let Some(timeline) = &template.segment_timeline {
+        let mut time = 0;
+        for (i, segment) in timeline.segments.iter().enumerate() {
+            if segment.duration == 0 {
+                break;
+            }
+            if let Some(start) = segment.time {
+                time = start;
+            }
+            let count = match segment.repeat {
+                Some(repeat) if repeat >= 0 => repeat as u64 + 1,
+                // Repeats until the next S or the end of the period
+                Some(_) => {
+                    let next = timeline.segments.get(i + 1).and_then(|next| next.time);
+                    match next.or(end) {
+                        Some(until) if until > time => (until - time).div_ceil(segment.duration),
+                        _ => 1,
+                    }
+                }
+                None => 1,
+            };
+            for _ in 0..count {
+                if segments.len() == MAX_SEGMENTS {
+                    return segments;
+                }
+                segments.push((number, time, segment.duration));
+                number += 1;
+                time += segment.duration;
+            }
+        }
+    } else if let (Some(duration), Some(end)) = (template.duration, end) {
+        if duration == 0 {
+            return segments;
+        }
+        let count = ((end - offset).div_ceil(duration) as usize).min(MAX_SEGMENTS);
+        for k in 0..count as u64 {
+            segments.push((number + k, offset + k * duration, duration));
+        }
+    }
+    segments
+}
+
+/// Urls of the segments of one representation
+#[derive(Debug, PartialEq, Clone)]
+pub struct RepresentationSegments {
+    pub representation_id: String,
+    pub initialization: Option<String>,
+    /// Number of the first segment in `media`
+    pub start_number: u64,
+    pub media: Vec<String>,
+    /// Seconds of each segment in `media`
+    pub durations: Vec<f64>,
+}
+
+/// Error if `value` is set but isn't a xs:duration
+fn check_duration(value: &Option<String>, context: &str, errors: &mut Vec<String>) {
+    if let Some(value) = value {
+        if parse_duration(value).is_none() {
+            errors.push(format!("{} \"{}\" is not a duration", context, value));
+        }
+    }
+}
+
+/// Problems of a segment template, prefixed with `context`
+fn check_template(template: &SegmentTemplate, context: &str, errors: &mut Vec<String>) {
+    let templates = [
+        ("media", &template.media),
+        ("initialization", &template.initialization),
+    ];
+    for (name, value) in templates.iter() {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        let identifiers = match template_identifiers(value) {
+            Some(identifiers) => identifiers,
+            None => {
+                errors.push(format!(
+                    "{}: invalid {} template \"{}\"",
+                    context, name, value
+                ));
+                continue;
+            }
+        };
+        if identifiers.contains(&"Number") && identifiers.contains(&"Time") {
+            errors.push(format!(
+                "{}: {} template can't have both $Number$ and $Time$",
+                context, name
+            ));
+        }
+    }
+    if template.timescale == Some(0) {
+        errors.push(format!("{}: timescale can't be 0", context));
+    }
+    if template.duration == Some(0) {
+        errors.push(format!("{}: duration can't be 0", context));
+    }
+    let timeline = template.segment_timeline.iter().flat_map(|t| &t.segments);
+    if timeline.clone().any(|segment| segment.duration == 0) {
+        errors.push(format!(
+            "{}: SegmentTimeline has a segment without a duration",
+            context
+        ));
+    }
+    if timeline
+        .clone()
+        .any(|segment| segment.repeat.is_some_and(|r| r < -1))
+    {
+        errors.push(format!(
+            "{}: SegmentTimeline repeat can't be below -1",
+            context
+        ));
+    }
+}
+
+/// Problems of the template of a representation that can only be seen after
+/// the attributes of the parent templates are added
+fn check_effective_template(template: &SegmentTemplate, context: &str, errors: &mut Vec<String>) {
+    let media = template.media.as_deref().unwrap_or("");
+    let has_time = template_identifiers(media).is_some_and(|ids| ids.contains(&"Time"));
+    if has_time && template.segment_timeline.is_none() {
+        errors.push(format!("{}: $Time$ needs a SegmentTimeline", context));
+    }
+    if template.duration.is_some() && template.segment_timeline.is_some() {
+        errors.push(format!(
+            "{}: SegmentTemplate can't have both a duration and a SegmentTimeline",
+            context
+        ));
+    }
+}
+
+impl Mpd {
+    /// Read the manifest from `path` and validate it
+    pub fn load_valid(path: &str) -> Result<Mpd, Vec<String>> {
+        let mpd = load(path).map_err(|e| vec![e])?;
+        mpd.validate()?;
+        Ok(mpd)
+    }
+
+    /// Check the rules of the standard that players depend on. Returns every problem found.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if self.profiles.trim().is_empty() {
+            errors.push("MPD profiles can't be empty".to_string());
+        }
+        if self.min_buffer_time.is_none() {
+            errors.push("MPD needs a minBufferTime".to_string());
+        }
+        let durations = [
+            (
+                "mediaPresentationDuration",
+                &self.media_presentation_duration,
+            ),
+            ("minBufferTime", &self.min_buffer_time),
+            ("minimumUpdatePeriod", &self.minimum_update_period),
+            ("timeShiftBufferDepth", &self.time_shift_buffer_depth),
+            (
+                "suggestedPresentationDelay",
+                &self.suggested_presentation_delay,
+            ),
+            ("maxSegmentDuration", &self.max_segment_duration),
+        ];
+        for (name, value) in durations.iter() {
+            check_duration(value, &format!("MPD {}", name), &mut errors);
+        }
+        match self.mpd_type {
+            MpdType::Static => {
+                let last_duration = self
+                    .periods
+                    .last()
+                    .is_some_and(|period| period.duration.is_some());
+                if self.media_presentation_duration.is_none() && !last_duration {
+                    errors.push(
+                        "static MPD needs a mediaPresentationDuration or a duration on the last Period"
+                            .to_string(),
+                    );
+                }
+            }
+            MpdType::Dynamic => {
+                if self.availability_start_time.is_none() {
+                    errors.push("dynamic MPD needs an availabilityStartTime".to_string());
+                }
+            }
+        }
+        if self.periods.is_empty() {
+            errors.push("MPD needs at least one Period".to_string());
+        }
+
+        let mut period_ids = HashSet::new();
+        for (p, period) in self.periods.iter().enumerate() {
+            let context = format!("Period {}", period.id.as_deref().unwrap_or(&p.to_string()));
+            if let Some(id) = &period.id {
+                if !period_ids.insert(id) {
+                    errors.push(format!("{}: the id is used by another Period", context));
+                }
+            }
+            check_duration(&period.start, &format!("{}: start", context), &mut errors);
+            check_duration(
+                &period.duration,
+                &format!("{}: duration", context),
+                &mut errors,
+            );
+            if let Some(template) = &period.segment_template {
+                check_template(template, &context, &mut errors);
+            }
+
+            let mut representation_ids = HashSet::new();
+            for (a, adaptation_set) in period.adaptation_sets.iter().enumerate() {
+                let context = format!("{} AdaptationSet {}", context, a);
+                if let Some(template) = &adaptation_set.segment_template {
+                    check_template(template, &context, &mut errors);
+                }
+                if adaptation_set.representations.is_empty() {
+                    errors.push(format!("{}: needs at least one Representation", context));
+                }
+                for representation in &adaptation_set.representations {
+                    let context = format!("{} Representation \"{}\"", context, representation.id);
+                    let id = &representation.id;
+                    if id.is_empty() || id.contains(char::is_whitespace) {
+                        errors.push(format!("{}: id can't be empty or have spaces", context));
+                    }
+                    if !representation_ids.insert(id) {
+                        errors.push(format!(
+                            "{}: the id is used by another Representation",
+                            context
+                        ));
+                    }
+                    if representation.bandwidth == 0 {
+                        errors.push(format!("{}: bandwidth can't be 0", context));
+                    }
+                    if adaptation_set.mime_type.is_none() && representation.mime_type.is_none() {
+                        errors.push(format!("{}: needs a mimeType", context));
+                    }
+                    if let Some(template) = &representation.segment_template {
+                        check_template(template, &context, &mut errors);
+                    }
+                    if let Some(template) =
+                        effective_template(period, adaptation_set, representation)
+                    {
+                        check_effective_template(&template, &context, &mut errors);
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Seconds from the start of the period at `index` to its end, if known
+    fn period_duration(&self, index: usize) -> Option<f64> {
+        let period = &self.periods[index];
+        if let Some(duration) = &period.duration {
+            return parse_duration(duration).map(|d| d.as_secs_f64());
+        }
+        let start = match &period.start {
+            Some(start) => parse_duration(start)?.as_secs_f64(),
+            None => 0.0,
+        };
+        let end = match self.periods.get(index + 1) {
+            Some(next) => parse_duration(next.start.as_ref()?)?,
+            None => parse_duration(self.media_presentation_duration.as_ref()?)?,
+        };
+        Some(end.as_secs_f64() - start)
+    }
+
+    /// The segment urls of the representations with a SegmentTemplate,
+    /// relative to the url of the manifest. Representations with BaseURLs
+    /// on other servers are left out.
+    pub fn segment_urls(&self) -> Vec<RepresentationSegments> {
+        let mut found = vec![];
+        let base = |base: &str, urls: &[String]| match urls.first() {
+            Some(url) => join_url(base, url),
+            None => Some(base.to_string()),
+        };
+        let mpd_base = match base("", &self.base_urls) {
+            Some(url) => url,
+            None => return found,
+        };
+        for (p, period) in self.periods.iter().enumerate() {
+            let period_duration = self.period_duration(p);
+            let period_base = match base(&mpd_base, &period.base_urls) {
+                Some(url) => url,
+                None => continue,
+            };
+            for adaptation_set in &period.adaptation_sets {
+                for representation in &adaptation_set.representations {
+                    let template = effective_template(period, adaptation_set, representation);
+                    let base = base(&period_base, &representation.base_urls);
+                    let (template, base) = match (template, base) {
+                        (Some(template), Some(base)) => (template, base),
+                        _ => continue,
+                    };
+                    let url = |template: &str, number: u64, time: u64| {
+                        let values = TemplateValues {
+                            representation_id: &representation.id,
+                            bandwidth: representation.bandwidth,
+                            number,
+                            time,
+                        };
+                        join_url(&base, &fill_template(template, &values)?)
+                    };
+                    let initialization = template
+                        .initialization
+                        .as_ref()
+                        .and_then(|init| url(init, 0, 0));
+                    let timescale = template.timescale.unwrap_or(1).max(1) as f64;
+                    let start_number = template.start_number.unwrap_or(1);
+                    let mut media = vec![];
+                    let mut durations = vec![];
+                    if let Some(media_template) = &template.media {
+                        for (number, time, duration) in
+                            template_segments(&template, period_duration)
+                        {
+                            if let Some(url) = url(media_template, number, time) {
+                                media.push(url);
+                                durations.push(duration as f64 / timescale);
+                            }
+                        }
+                    }
+                    found.push(RepresentationSegments {
+                        representation_id: representation.id.clone(),
+                        initialization,
+                        start_number,
+                        media,
+                        durations,
+                    });
+                }
+            }
+        }
+        found
+    }
+
+    /// The manifest as an indented XML document
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let mut serializer = quick_xml::se::Serializer::new(&mut xml);
+        serializer.indent(' ', 2);
+        // Writing to a String only fails for types serde can't write as XML
+        self.serialize(serializer).unwrap();
+        xml.push('\n');
+        xml
+    }
+}
+
+#[cfg(test)]
+mod mpd_tests {
+    use super::*;
+
+    const DASH_DOCUMENT: &str = "test_data/unit_test_dash_document.mpd";
+
+    #[test]
+    fn parse_bento4_manifest() {
+        let mpd = load(DASH_DOCUMENT).unwrap();
+        assert_eq!(mpd.xmlns, NAMESPACE);
+        assert_eq!(mpd.mpd_type, MpdType::Static);
+        assert_eq!(mpd.profiles, PROFILE_LIVE);
+        assert_eq!(
+            mpd.media_presentation_duration.as_deref(),
+            Some("PT9M56.458S")
+        );
+        assert_eq!(mpd.periods.len(), 1);
+        let sets = &mpd.periods[0].adaptation_sets;
+        assert_eq!(sets.len(), 2);
+        assert_eq!(sets[0].mime_type.as_deref(), Some("video/mp4"));
+        assert_eq!(sets[0].max_width, Some(320));
+        assert_eq!(sets[0].segment_alignment, Some(true));
+        let template = sets[0].segment_template.as_ref().unwrap();
+        assert_eq!(
+            template.media.as_deref(),
+            Some("$RepresentationID$/seg-$Number$.m4s")
+        );
+        assert_eq!(template.duration, Some(20000));
+        assert_eq!(template.timescale, Some(1000));
+        let video = &sets[0].representations[0];
+        assert_eq!(video.id, "video/avc1");
+        assert_eq!(video.bandwidth, 702137);
+        assert_eq!(video.frame_rate.as_deref(), Some("24"));
+        let audio = &sets[1].representations[0];
+        assert_eq!(
+            audio.audio_channel_configurations[0].value.as_deref(),
+            Some("2")
+        );
+        assert_eq!(mpd.validate(), Ok(()));
+        assert!(Mpd::load_valid(DASH_DOCUMENT).is_ok());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mpd = load(DASH_DOCUMENT).unwrap();
+        let xml = mpd.to_xml();
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<MPD "));
+        assert!(xml.contains("xmlns=\"urn:mpeg:dash:schema:mpd:2011\""));
+        assert!(xml.contains("type=\"static\""));
+        assert!(!xml.contains("publishTime"));
+        assert_eq!(parse(&xml).unwrap(), mpd);
+    }
+
+    #[test]
+    fn timelines() {
+        let mpd = parse(
+            r#"<?xml version="1.0"?>
+            <MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic" profiles="urn:mpeg:dash:profile:isoff-live:2011"
+                 availabilityStartTime="2024-01-01T00:00:00Z" minBufferTime="PT2S" minimumUpdatePeriod="PT2S">
+              <Period id="p0" start="PT0S">
+                <SegmentTemplate timescale="90000" initialization="$RepresentationID$/init.mp4"/>
+                <AdaptationSet contentType="video" mimeType="video/mp4">
+                  <SegmentTemplate media="$RepresentationID$/$Time$.m4s">
+                    <SegmentTimeline>
+                      <S t="0" d="180000" r="2"/>
+                      <S d="90000"/>
+                    </SegmentTimeline>
+                  </SegmentTemplate>
+                  <Representation id="v1" bandwidth="1000000" width="1280" height="720"/>
+                </AdaptationSet>
+              </Period>
+            </MPD>"#,
+        )
+        .unwrap();
+        assert_eq!(mpd.mpd_type, MpdType::Dynamic);
+        assert_eq!(mpd.validate(), Ok(()));
+        let period = &mpd.periods[0];
+        let set = &period.adaptation_sets[0];
+        let template = effective_template(period, set, &set.representations[0]).unwrap();
+        assert_eq!(template.timescale, Some(90000));
+        assert_eq!(
+            template.initialization.as_deref(),
+            Some("$RepresentationID$/init.mp4")
+        );
+        assert_eq!(
+            template.media.as_deref(),
+            Some("$RepresentationID$/$Time$.m4s")
+        );
+        let segments = &template.segment_timeline.unwrap().segments;
+        assert_eq!(segments[0].time, Some(0));
+        assert_eq!(segments[0].repeat, Some(2));
+        assert_eq!(segments[1].time, None);
+        assert_eq!(segments[1].duration, 90000);
+        assert_eq!(parse(&mpd.to_xml()).unwrap(), mpd);
+    }
+
+    #[test]
+    fn validation_errors() {
+        let mut mpd = load(DASH_DOCUMENT).unwrap();
+        mpd.min_buffer_time = None;
+        mpd.media_presentation_duration = Some("9 minutes".to_string());
+        let period = &mut mpd.periods[0];
+        period.id = Some("main".to_string());
+        let video = &mut period.adaptation_sets[0];
+        video.segment_template.as_mut().unwrap().media =
+            Some("seg-$Number$-$Time$.m4s".to_string());
+        video.representations[0].bandwidth = 0;
+        let audio = &mut period.adaptation_sets[1];
+        audio.mime_type = None;
+        audio.representations[0].id = "video/avc1".to_string();
+        audio.segment_template.as_mut().unwrap().initialization = Some("$Init$.mp4".to_string());
+        period.adaptation_sets.push(AdaptationSet::default());
+
+        assert_eq!(
+            mpd.validate(),
+            Err(vec![
+                "MPD needs a minBufferTime".to_string(),
+                "MPD mediaPresentationDuration \"9 minutes\" is not a duration".to_string(),
+                "Period main AdaptationSet 0: media template can't have both $Number$ and $Time$"
+                    .to_string(),
+                "Period main AdaptationSet 0 Representation \"video/avc1\": bandwidth can't be 0"
+                    .to_string(),
+                "Period main AdaptationSet 0 Representation \"video/avc1\": $Time$ needs a SegmentTimeline"
+                    .to_string(),
+                "Period main AdaptationSet 1: invalid initialization template \"$Init$.mp4\""
+                    .to_string(),
+                "Period main AdaptationSet 1 Representation \"video/avc1\": the id is used by another Representation"
+                    .to_string(),
+                "Period main AdaptationSet 1 Representation \"video/avc1\": needs a mimeType"
+                    .to_string(),
+                "Period main AdaptationSet 2: needs at least one Representation".to_string(),
+            ])
+        );
+
+        mpd.mpd_type = MpdType::Dynamic;
+        mpd.periods.clear();
+        let errors = mpd.validate().unwrap_err();
+        assert!(errors.contains(&"dynamic MPD needs an availabilityStartTime".to_string()));
+        assert!(errors.contains(&"MPD needs at least one Period".to_string()));
+        assert!(parse("<MPD><Period>").is_err());
+        assert!(parse("<MPD profiles=\"a\"><Period><AdaptationSet><Representation id=\"a\"/></AdaptationSet></Period></MPD>").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(
+            parse_duration("PT9M56.458S"),
+            Some(Duration::from_millis(596458))
+        );
+        assert_eq!(parse_duration("PT20.00S"), Some(Duration::from_secs(20)));
+        assert_eq!(parse_duration("P1DT1H"), Some(Duration::from_secs(90000)));
+        assert_eq!(parse_duration("P2D"), Some(Duration::from_secs(172800)));
+        assert_eq!(parse_duration("PT0S"), Some(Duration::ZERO));
+        for invalid in ["", "P", "PT", "P1Y", "PT1.5M", "PT5", "T5S", "PTS", "P1DT"] {
+            assert_eq!(parse_duration(invalid), None, "{}", invalid);
+        }
+        assert_eq!(parse_duration("PT100000000000000000000S"), None);
+        assert_eq!(parse_duration("P18446744073709551615D"), None);
+        assert_eq!(
+            format_duration(Duration::from_millis(596458)),
+            "PT9M56.458S"
+        );
+        assert_eq!(format_duration(Duration::from_secs(3600)), "PT1H");
+        assert_eq!(format_duration(Duration::from_secs(2)), "PT2S");
+        assert_eq!(format_duration(Duration::ZERO), "PT0S");
+        assert_eq!(
+            parse_duration(&format_duration(Duration::from_millis(3723500))),
+            Some(Duration::from_millis(3723500))
+        );
+    }
+
+    #[test]
+    fn filled_templates() {
+        let values = TemplateValues {
+            representation_id: "video/avc1",
+            bandwidth: 702137,
+            number: 7,
+            time: 140000,
+        };
+        let fill = |template: &str| fill_template(template, &values);
+        assert_eq!(
+            fill("$RepresentationID$/seg-$Number$.m4s").as_deref(),
+            Some("video/avc1/seg-7.m4s")
+        );
+        assert_eq!(
+            fill("$Bandwidth$/$Time$-$Number%05d$$$.m4s").as_deref(),
+            Some("702137/140000-00007$.m4s")
+        );
+        assert_eq!(fill("seg-$SubNumber$.m4s"), None);
+        assert_eq!(fill("seg-$Number.m4s"), None);
+    }
+
+    #[test]
+    fn joined_urls() {
+        assert_eq!(
+            join_url("/vod/stream.mpd", "video/seg-1.m4s").as_deref(),
+            Some("/vod/video/seg-1.m4s")
+        );
+        assert_eq!(join_url("", "video/").as_deref(), Some("video/"));
+        assert_eq!(
+            join_url("video/", "seg-1.m4s").as_deref(),
+            Some("video/seg-1.m4s")
+        );
+        assert_eq!(
+            join_url("/vod/", "/other/a.m4s").as_deref(),
+            Some("/other/a.m4s")
+        );
+        assert_eq!(join_url("/vod/", "https://cdn.example.com/a.m4s"), None);
+    }
+
+    #[test]
+    fn segment_urls_of_duration_templates() {
+        let segments = load(DASH_DOCUMENT).unwrap().segment_urls();
+        assert_eq!(segments.len(), 2);
+        let video = &segments[0];
+        assert_eq!(video.representation_id, "video/avc1");
+        assert_eq!(video.initialization.as_deref(), Some("video/avc1/init.mp4"));
+        // 596.458 seconds of 20 second segments
+        assert_eq!(video.media.len(), 30);
+        assert_eq!(video.media[0], "video/avc1/seg-1.m4s");
+        assert_eq!(video.media[29], "video/avc1/seg-30.m4s");
+        assert_eq!(video.start_number, 1);
+        assert_eq!(video.durations[0], 20.0);
+    }
+
+    #[test]
+    fn segment_urls_of_timelines() {
+        let mut mpd = load(DASH_DOCUMENT).unwrap();
+        mpd.base_urls = vec!["media/".to_string()];
+        let set = &mut mpd.periods[0].adaptation_sets[0];
+        let template = set.segment_template.as_mut().unwrap();
+        template.media = Some("$RepresentationID$/$Time$.m4s".to_string());
+        template.segment_timeline = Some(SegmentTimeline {
+            segments: vec![
+                TimelineSegment {
+                    time: Some(1000),
+                    duration: 2000,
+                    repeat: Some(1),
+                },
+                TimelineSegment {
+                    time: None,
+                    duration: 1000,
+                    repeat: None,
+                },
+                // Repeats to the next S
+                TimelineSegment {
+                    time: Some(8000),
+                    duration: 500,
+                    repeat: Some(-1),
+                },
+                TimelineSegment {
+                    time: Some(9000),
+                    duration: 500,
+                    repeat: None,
+                },
+            ],
+        });
+        set.representations[0].base_urls = vec!["https://cdn.example.com/".to_string()];
+        set.representations.push(Representation {
+            id: "720p".to_string(),
+            bandwidth: 1,
+            ..Representation::default()
+        });
+
+        let segments = mpd.segment_urls();
+        // The representation on another server is left out
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].representation_id, "720p");
+        assert_eq!(
+            segments[0].initialization.as_deref(),
+            Some("media/720p/init.mp4")
+        );
+        assert_eq!(
+            segments[0].media,
+            [
+                "media/720p/1000.m4s",
+                "media/720p/3000.m4s",
+                "media/720p/5000.m4s",
+                "media/720p/8000.m4s",
+                "media/720p/8500.m4s",
+                "media/720p/9000.m4s",
+            ]
+        );
+        assert_eq!(segments[0].durations, [2.0, 2.0, 1.0, 0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn date_times() {
+        assert_eq!(format_date_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_714_566_600_250);
+        assert_eq!(format_date_time(time), "2024-05-01T12:30:00.250Z");
+        // Leap day
+        let time = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(format_date_time(time), "2000-02-29T00:00:00.000Z");
+    }
+
+    #[test]
+    fn templates() {
+        assert_eq!(
+            template_identifiers("$RepresentationID$/seg-$Number%05d$.m4s"),
+            Some(vec!["RepresentationID", "Number"])
+        );
+        assert_eq!(template_identifiers("init.mp4"), Some(vec![]));
+        assert_eq!(template_identifiers("a$$b-$Time$.m4s"), Some(vec!["Time"]));
+        assert_eq!(template_identifiers("seg-$Number.m4s"), None);
+        assert_eq!(template_identifiers("seg-$Index$.m4s"), None);
+        assert_eq!(template_identifiers("seg-$Number%5d$.m4s"), None);
+        assert_eq!(template_identifiers("$RepresentationID%02d$.m4s"), None);
+    }
+}
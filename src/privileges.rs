@@ -0,0 +1,116 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::ptr;
+
+/// Size of the buffer for the strings of a passwd or group entry
+const BUFFER_SIZE: usize = 16 * 1024;
+
+fn not_found(kind: &str, name: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} {} doesn't exist", kind, name),
+    )
+}
+
+/// User id and primary group id of `name`
+pub fn user_ids(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = CString::new(name).map_err(|_| not_found("User", name))?;
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    let error = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(error));
+    }
+    if result.is_null() {
+        return Err(not_found("User", name));
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Group id of `name`
+pub fn group_id(name: &str) -> io::Result<libc::gid_t> {
+    let c_name = CString::new(name).map_err(|_| not_found("Group", name))?;
+    let mut group: libc::group = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; BUFFER_SIZE];
+    let mut result = ptr::null_mut();
+    let error = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if error != 0 {
+        return Err(io::Error::from_raw_os_error(error));
+    }
+    if result.is_null() {
+        return Err(not_found("Group", name));
+    }
+    Ok(group.gr_gid)
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Continue as `user` and `group`. Without a group the user's primary group
+/// is used. Nothing is done if neither is given.
+/// The group has to be changed first since the user can't do it anymore.
+pub fn drop_to(user: Option<&str>, group: Option<&str>) -> io::Result<()> {
+    let user_ids = user.map(user_ids).transpose()?;
+    let gid = match (group, user_ids) {
+        (Some(group), _) => Some(group_id(group)?),
+        (None, Some((_, gid))) => Some(gid),
+        (None, None) => None,
+    };
+
+    if let Some(gid) = gid {
+        // The supplementary groups of root would be kept otherwise
+        check(unsafe { libc::setgroups(1, &gid) })?;
+        check(unsafe { libc::setgid(gid) })?;
+    }
+    if let Some((uid, _)) = user_ids {
+        check(unsafe { libc::setuid(uid) })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod privileges_tests {
+    use super::*;
+
+    #[test]
+    fn lookups() {
+        assert_eq!(user_ids("root").unwrap(), (0, 0));
+        assert_eq!(group_id("root").unwrap(), 0);
+        assert_eq!(
+            user_ids("no-such-user").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(
+            group_id("no-such-group").unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn nothing_to_drop() {
+        assert!(drop_to(None, None).is_ok());
+    }
+}
@@ -0,0 +1,324 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{IpAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::error;
+
+use super::request::Request;
+use super::stream::ClientStream;
+
+enum Message {
+    Entry(String),
+    /// Open the file again after it was rotated
+    Reopen,
+}
+
+/// Access log in the Combined Log Format followed by the duration of the
+/// request in microseconds and the request id. The entries are written by a thread of
+/// their own so a slow disk doesn't hold up the responses.
+#[derive(Clone)]
+pub struct AccessLog {
+    sender: Sender<Message>,
+}
+
+fn open(path: &str) -> io::Result<BufWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(BufWriter::new(file))
+}
+
+/// Write the entries until every AccessLog is dropped. The file is flushed
+/// whenever there are no more entries waiting.
+fn write_entries(path: String, mut file: BufWriter<File>, messages: Receiver<Message>) {
+    while let Ok(mut message) = messages.recv() {
+        loop {
+            match message {
+                Message::Entry(entry) => {
+                    if let Err(e) = file.write_all(entry.as_bytes()) {
+                        error!("Cannot write the access log {}: {}", path, e);
+                    }
+                }
+                Message::Reopen => match open(&path) {
+                    Ok(reopened) => {
+                        let _ = file.flush();
+                        file = reopened;
+                    }
+                    Err(e) => error!("Cannot reopen the access log {}: {}", path, e),
+                },
+            }
+            message = match messages.try_recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+        }
+        let _ = file.flush();
+    }
+}
+
+impl AccessLog {
+    pub fn open(path: &str) -> io::Result<AccessLog> {
+        let file = open(path)?;
+        let (sender, receiver) = mpsc::channel();
+        let path = path.to_string();
+        thread::spawn(move || write_entries(path, file, receiver));
+        Ok(AccessLog { sender })
+    }
+
+    /// Log the response recorded by `recorder` to the request in `head` from `client`
+    pub fn log<S>(&self, head: &[u8], recorder: &Recorder<S>, client: IpAddr, duration: Duration)
+    where
+        S: ClientStream,
+    {
+        let head = String::from_utf8_lossy(head);
+        let request = Request::parse(&head);
+        let header = |name| {
+            request
+                .as_ref()
+                .and_then(|request| request.header(name))
+                .unwrap_or("-")
+        };
+        let entry = format_entry(&Entry {
+            peer: &client.to_string(),
+            time: SystemTime::now(),
+            request_line: head.lines().next().unwrap_or(""),
+            status: recorder.status,
+            body_bytes: recorder.body_bytes,
+            referer: header("Referer"),
+            user_agent: header("User-Agent"),
+            duration,
+            request_id: &recorder.request_id,
+        });
+        // The writer thread only stops when every sender is gone
+        let _ = self.sender.send(Message::Entry(entry));
+    }
+
+    /// Open the file again, e.g. after logrotate moved it
+    #[allow(dead_code)] // Only main reopens the logs, the tests include this module too
+    pub fn reopen(&self) {
+        let _ = self.sender.send(Message::Reopen);
+    }
+}
+
+struct Entry<'a> {
+    peer: &'a str,
+    time: SystemTime,
+    request_line: &'a str,
+    status: u16,
+    body_bytes: u64,
+    referer: &'a str,
+    user_agent: &'a str,
+    duration: Duration,
+    request_id: &'a str,
+}
+
+/// Time in the "10/Oct/2000:13:55:36 +0000" format of the log
+fn log_time(time: SystemTime) -> String {
+    // E.g. "Tue, 10 Oct 2000 13:55:36 GMT"
+    let date = httpdate::fmt_http_date(time);
+    let parts: Vec<&str> = date.split(' ').collect();
+    format!("{}/{}/{}:{} +0000", parts[1], parts[2], parts[3], parts[4])
+}
+
+/// Quotes and backslashes in the request line and headers are escaped like apache does
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn format_entry(entry: &Entry) -> String {
+    let body_bytes = match entry.body_bytes {
+        0 => "-".to_string(),
+        bytes => bytes.to_string(),
+    };
+    format!(
+        "{} - - [{}] \"{}\" {} {} \"{}\" \"{}\" {} \"{}\"\n",
+        entry.peer,
+        log_time(entry.time),
+        escape(entry.request_line),
+        entry.status,
+        body_bytes,
+        escape(entry.referer),
+        escape(entry.user_agent),
+        entry.duration.as_micros(),
+        escape(entry.request_id)
+    )
+}
+
+/// Stream that notes the status and the body size of the response written
+/// to it. The X-Request-Id header is added to every response head.
+pub struct Recorder<'a, S> {
+    stream: &'a mut S,
+    /// Response head written so far
+    head: Vec<u8>,
+    in_body: bool,
+    /// Has X-Request-Id been added to the head that is being written
+    id_written: bool,
+    pub request_id: String,
+    /// When the first byte of the response was sent
+    pub first_byte: Option<Instant>,
+    /// 0 if nothing was sent
+    pub status: u16,
+    pub body_bytes: u64,
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+impl<'a, S: ClientStream> Recorder<'a, S> {
+    pub fn new(stream: &'a mut S, request_id: String) -> Recorder<'a, S> {
+        Recorder {
+            stream,
+            head: vec![],
+            in_body: false,
+            id_written: false,
+            request_id,
+            first_byte: None,
+            status: 0,
+            body_bytes: 0,
+        }
+    }
+
+    /// Bytes of `buf` up to the point where the X-Request-Id header goes
+    /// (end of the status line) or where the head ends, if they are in `buf`.
+    /// Writing a head in these pieces gives every 103 Early Hints and the
+    /// final response the header.
+    fn head_piece(&self, buf: &[u8]) -> Option<usize> {
+        let pattern: &[u8] = if self.id_written {
+            b"\r\n\r\n"
+        } else {
+            b"\r\n"
+        };
+        let mut pending = self.head.clone();
+        pending.extend_from_slice(buf);
+        find(&pending, pattern).map(|position| position + pattern.len() - self.head.len())
+    }
+
+    fn record(&mut self, data: &[u8]) {
+        if self.in_body {
+            self.body_bytes += data.len() as u64;
+            return;
+        }
+
+        self.head.extend_from_slice(data);
+        let end = match find(&self.head, b"\r\n\r\n") {
+            Some(end) => end + 4,
+            None => return,
+        };
+        self.id_written = false;
+        // "HTTP/1.1 200 OK"
+        let status = self
+            .head
+            .get(9..12)
+            .and_then(|status| std::str::from_utf8(status).ok())
+            .and_then(|status| status.parse().ok())
+            .unwrap_or(0);
+        let rest = self.head.split_off(end);
+        self.head.clear();
+        // Informational responses like 103 Early Hints come before the real one
+        if (100..200).contains(&status) {
+            self.record(&rest);
+        } else {
+            self.status = status;
+            self.in_body = true;
+            self.body_bytes = rest.len() as u64;
+        }
+    }
+}
+
+impl<'a, S: ClientStream> Read for Recorder<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<'a, S: ClientStream> Write for Recorder<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let piece = if self.in_body {
+            None
+        } else {
+            self.head_piece(buf)
+        };
+        // The piece ends either at the status line or at the end of the head
+        let status_line = !self.in_body && !self.id_written;
+        let written = self.stream.write(&buf[..piece.unwrap_or(buf.len())])?;
+        if written > 0 && self.first_byte.is_none() {
+            self.first_byte = Some(Instant::now());
+        }
+        self.record(&buf[..written]);
+        if status_line && Some(written) == piece {
+            let header = format!("X-Request-Id: {}\r\n", self.request_id);
+            self.stream.write_all(header.as_bytes())?;
+            self.id_written = true;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<'a, S: ClientStream> ClientStream for Recorder<'a, S> {
+    fn tcp(&self) -> &TcpStream {
+        self.stream.tcp()
+    }
+
+    fn has_client_certificate(&self) -> bool {
+        self.stream.has_client_certificate()
+    }
+}
+
+#[cfg(test)]
+mod access_log_tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn combined_format() {
+        let entry = Entry {
+            peer: "127.0.0.1",
+            time: SystemTime::UNIX_EPOCH + Duration::from_secs(971185536),
+            request_line: "GET /live/\"a\".mpd HTTP/1.1",
+            status: 200,
+            body_bytes: 2326,
+            referer: "-",
+            user_agent: "player/1.0",
+            duration: Duration::from_micros(1500),
+            request_id: "cdn-42",
+        };
+        assert_eq!(
+            format_entry(&entry),
+            "127.0.0.1 - - [10/Oct/2000:13:45:36 +0000] \"GET /live/\\\"a\\\".mpd HTTP/1.1\" 200 2326 \"-\" \"player/1.0\" 1500 \"cdn-42\"\n"
+        );
+    }
+
+    #[test]
+    fn records_status_and_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut client, _) = listener.accept().unwrap();
+        let mut recorder = Recorder::new(&mut stream, "id".to_string());
+        recorder
+            .write_all(b"HTTP/1.1 103 EARLY HINTS\r\nLink: </a>\r\n\r\nHTTP/1.1 404 NOT")
+            .unwrap();
+        assert_eq!(recorder.status, 0);
+        recorder
+            .write_all(b" FOUND\r\nContent-Length: 5\r\n\r\nno")
+            .unwrap();
+        recorder.write_all(b"ne").unwrap();
+        assert_eq!(recorder.status, 404);
+        assert_eq!(recorder.body_bytes, 4);
+
+        drop(stream);
+        let mut sent = String::new();
+        client.read_to_string(&mut sent).unwrap();
+        assert_eq!(
+            sent,
+            "HTTP/1.1 103 EARLY HINTS\r\nX-Request-Id: id\r\nLink: </a>\r\n\r\n\
+             HTTP/1.1 404 NOT FOUND\r\nX-Request-Id: id\r\nContent-Length: 5\r\n\r\nnone"
+        );
+    }
+}
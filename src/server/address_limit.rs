@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Open connections of every client address
+pub struct AddressConnections {
+    counts: Mutex<HashMap<IpAddr, usize>>,
+}
+
+/// Counts a connection for its address until it's dropped
+pub struct AddressGuard {
+    connections: Arc<AddressConnections>,
+    address: IpAddr,
+}
+
+impl Drop for AddressGuard {
+    fn drop(&mut self) {
+        let mut counts = self.connections.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.address) {
+            *count -= 1;
+            // Addresses without connections are forgotten so the map stays small
+            if *count == 0 {
+                counts.remove(&self.address);
+            }
+        }
+    }
+}
+
+impl AddressConnections {
+    pub fn new() -> AddressConnections {
+        AddressConnections {
+            counts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a new connection from `address` unless it already has `limit`
+    /// open connections. Zero `limit` means no limit.
+    pub fn try_open(self: &Arc<Self>, address: IpAddr, limit: usize) -> Option<AddressGuard> {
+        // IPv4 clients of a dual stack socket show up as ::ffff:a.b.c.d
+        let address = address.to_canonical();
+        let mut counts = self.counts.lock().unwrap();
+        let count = counts.entry(address).or_insert(0);
+        if limit > 0 && *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(AddressGuard {
+            connections: self.clone(),
+            address,
+        })
+    }
+
+    /// Open connections of `address`
+    #[cfg(test)]
+    fn count(&self, address: IpAddr) -> usize {
+        let counts = self.counts.lock().unwrap();
+        counts.get(&address).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod address_limit_tests {
+    use super::*;
+
+    #[test]
+    fn limit_per_address() {
+        let connections = Arc::new(AddressConnections::new());
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let first = connections.try_open(client, 2).unwrap();
+        let _second = connections.try_open(client, 2).unwrap();
+        assert!(connections.try_open(client, 2).is_none());
+        // The same client over a dual stack socket
+        assert!(connections
+            .try_open("::ffff:192.0.2.1".parse().unwrap(), 2)
+            .is_none());
+        assert!(connections.try_open(other, 2).is_some());
+
+        drop(first);
+        assert_eq!(connections.count(client), 1);
+        let _third = connections.try_open(client, 2).unwrap();
+    }
+
+    #[test]
+    fn closed_addresses_are_forgotten() {
+        let connections = Arc::new(AddressConnections::new());
+        let client: IpAddr = "2001:db8::1".parse().unwrap();
+        let guards: Vec<_> = (0..3)
+            .map(|_| connections.try_open(client, 0).unwrap())
+            .collect();
+        assert_eq!(connections.count(client), 3);
+        drop(guards);
+        assert!(connections.counts.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,444 @@
+use log::{debug, info, LevelFilter};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::body::Framing;
+use super::quota::Quotas;
+use super::request::Request;
+use super::shutdown::Stopper;
+use super::{peer, read_head, request_body, ServerState};
+use crate::config::Config;
+
+/// The API only takes small json objects
+const MAX_BODY_SIZE: u64 = 4096;
+
+/// Connections served at the same time. Others are turned away before their
+/// request is read so clients without the token can't pile up threads.
+const MAX_CONNECTIONS: usize = 4;
+
+/// Counts an admin connection until it's dropped
+pub struct ConnectionGuard<'a> {
+    open: &'a AtomicUsize,
+}
+
+impl Drop for ConnectionGuard<'_> {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Count a new connection in `open` unless MAX_CONNECTIONS are already served
+pub fn try_open(open: &AtomicUsize) -> Option<ConnectionGuard<'_>> {
+    open.fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+        (count < MAX_CONNECTIONS).then_some(count + 1)
+    })
+    .ok()?;
+    Some(ConnectionGuard { open })
+}
+
+/// Turn away a connection over MAX_CONNECTIONS without reading its request
+pub fn reject(mut stream: TcpStream) {
+    debug!("Too many admin connections, rejected {}", peer(&stream));
+    respond_error(
+        &mut stream,
+        "503 Service Unavailable",
+        "Too many admin connections",
+    );
+}
+
+#[derive(Deserialize)]
+struct LogLevelChange {
+    level: String,
+}
+
+/// Url paths of the files to purge from the caches
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Purge {
+    /// Whole paths, e.g. "/live/chan1/manifest.mpd"
+    #[serde(default)]
+    paths: Vec<String>,
+    /// Path prefixes, e.g. "/live/chan1/" for every file of a stream
+    #[serde(default)]
+    prefixes: Vec<String>,
+}
+
+fn respond(stream: &mut impl Write, status: &str, body: &Value) {
+    let body = format!("{:#}\n", body);
+    let out = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    // The client may be gone already, nothing to do about that
+    let _ = stream.write_all(out.as_bytes());
+}
+
+fn respond_error(stream: &mut impl Write, status: &str, message: &str) {
+    respond(stream, status, &json!({ "error": message }));
+}
+
+/// Compared without returning early so the response time doesn't tell
+/// how much of the token was right
+pub fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_authorized(request: &Request, config: &Config) -> bool {
+    let given = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (&config.admin, given) {
+        (Some(admin), Some(given)) => token_matches(given.trim(), &admin.token),
+        // The admin API was removed from the config in a reload
+        _ => false,
+    }
+}
+
+/// The configuration in use without the tokens and the signing secrets
+fn config_json(config: &Config) -> Value {
+    let mut value = serde_json::to_value(config).unwrap_or(Value::Null);
+    if let Some(admin) = value.get_mut("admin").and_then(Value::as_object_mut) {
+        admin.remove("token");
+    }
+    if let Some(ingest) = value.get_mut("ingest").and_then(Value::as_object_mut) {
+        ingest.remove("token");
+    }
+    if let Some(clear_key) = value.get_mut("clearKey").and_then(Value::as_object_mut) {
+        clear_key.remove("keys");
+    }
+    if let Some(signed_urls) = value
+        .pointer_mut("/security/signedUrls")
+        .and_then(Value::as_object_mut)
+    {
+        signed_urls.remove("secret");
+    }
+    if let Some(jwt) = value
+        .pointer_mut("/security/jwt")
+        .and_then(Value::as_object_mut)
+    {
+        jwt.remove("secret");
+    }
+    value
+}
+
+fn stats_json(state: &ServerState) -> Value {
+    let mut stats = state.stats.to_json();
+    let (entries, bytes) = state.files.usage();
+    stats["activeConnections"] = state.shutdown.active().into();
+    stats["fileCache"] = json!({
+        "entries": entries,
+        "bytes": bytes,
+        "hits": state.files.hits.load(Ordering::Relaxed),
+        "misses": state.files.misses.load(Ordering::Relaxed),
+    });
+    stats
+}
+
+/// Usage of the tenants with a quota in the current period
+fn quotas_json(quotas: &Quotas, config: &Config) -> Value {
+    let mut tenants = serde_json::Map::new();
+    for tenant in &config.tenants {
+        if let Some(quota) = &tenant.quota {
+            let (requests, bytes) = quotas.usage(&tenant.name, quota);
+            tenants.insert(
+                tenant.name.clone(),
+                json!({
+                    "period": quota.period,
+                    "requests": requests,
+                    "maxRequests": quota.max_requests,
+                    "bytes": bytes,
+                    "maxBytes": quota.max_bytes,
+                }),
+            );
+        }
+    }
+    json!({ "tenants": tenants })
+}
+
+fn log_level_json() -> Value {
+    json!({ "level": log::max_level().as_str().to_lowercase() })
+}
+
+/// New log level from the json body of the request. Lasts until the config is reloaded
+fn change_log_level(body: &[u8]) -> Result<Value, String> {
+    let change: LogLevelChange = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let level: LevelFilter = change
+        .level
+        .parse()
+        .map_err(|_| format!("Unknown log level \"{}\"", change.level))?;
+    log::set_max_level(level);
+    info!("Log level changed to {}", change.level);
+    Ok(log_level_json())
+}
+
+/// Cache keys of the url `paths` in the document root and the roots of the
+/// tenants. The keys are the file paths the requests are served from.
+fn cache_keys(config: &Config, paths: &[String]) -> Vec<String> {
+    let roots = std::iter::once(&config.network.document_root)
+        .chain(config.tenants.iter().map(|tenant| &tenant.root));
+    roots
+        .flat_map(|root| {
+            let root = root.trim_end_matches('/');
+            paths.iter().map(move |path| format!("{}{}", root, path))
+        })
+        .collect()
+}
+
+/// Is the cache key `key` the file's own or one of its compressed variants.
+/// The digests of the compressed responses are cached as "<file>:<encoding>".
+fn is_key_of(key: &str, file: &str) -> bool {
+    match key.strip_prefix(file) {
+        Some(rest) => {
+            let rest = rest
+                .strip_prefix(".br")
+                .or_else(|| rest.strip_prefix(".gz"))
+                .unwrap_or(rest);
+            rest.is_empty() || rest.starts_with(':')
+        }
+        None => false,
+    }
+}
+
+/// Drop the cached files, digests and 404s of the paths in the json body so
+/// replaced content is served right away
+fn purge(state: &ServerState, config: &Config, body: &[u8]) -> Result<Value, String> {
+    let purge: Purge = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let urls = purge.paths.iter().chain(&purge.prefixes);
+    if let Some(url) = urls.clone().find(|url| !url.starts_with('/')) {
+        return Err(format!("\"{}\" doesn't start with /", url));
+    }
+    let files = cache_keys(config, &purge.paths);
+    let prefixes = cache_keys(config, &purge.prefixes);
+    let matches = |key: &str| {
+        files.iter().any(|file| is_key_of(key, file))
+            || prefixes.iter().any(|prefix| key.starts_with(&prefix[..]))
+    };
+    state.files.remove_matching(matches);
+    state.not_found.remove_matching(matches);
+    state.digests.remove_matching(matches);
+    for file in &files {
+        state.generator.invalidate(Path::new(file));
+    }
+    // The generated manifests are by directory, a prefix can cut one in half
+    if !prefixes.is_empty() {
+        state.generator.clear();
+    }
+    Ok(json!({ "purged": urls.collect::<Vec<_>>() }))
+}
+
+/// Serve one request on the admin port. The connection is closed after the response.
+pub fn handle(mut stream: TcpStream, state: &ServerState, config: &Config, stopper: &Stopper) {
+    let mut buf = vec![];
+    let header_len = match read_head(&mut stream, config, &mut buf, false) {
+        Some(header_len) => header_len,
+        None => return,
+    };
+    let (head, leftover) = buf.split_at(header_len);
+    let head = String::from_utf8_lossy(head);
+    let request = match Request::parse(&head) {
+        Some(request) => request,
+        None => return respond_error(&mut stream, "400 Bad Request", "Invalid request"),
+    };
+    if !is_authorized(&request, config) {
+        return respond_error(&mut stream, "401 Unauthorized", "Invalid token");
+    }
+
+    let framing = match request.body_framing(MAX_BODY_SIZE) {
+        Ok(framing) => framing,
+        Err(_) => return respond_error(&mut stream, "400 Bad Request", "Invalid body"),
+    };
+    let mut body = vec![];
+    if framing != Framing::None {
+        let read = request_body(&mut stream, leftover, framing, MAX_BODY_SIZE, config)
+            .read_to_end(&mut body);
+        if read.is_err() {
+            return respond_error(&mut stream, "400 Bad Request", "Invalid body");
+        }
+    }
+
+    match (&request.method[..], &request.path[..]) {
+        ("GET", "/config") => respond(&mut stream, "200 OK", &config_json(config)),
+        ("GET", "/stats") => respond(&mut stream, "200 OK", &stats_json(state)),
+        ("GET", "/quotas") => respond(&mut stream, "200 OK", &quotas_json(&state.quotas, config)),
+        ("GET", "/segments/missing") => {
+            respond(&mut stream, "200 OK", &state.missing_segments.to_json())
+        }
+        ("POST", "/cache/flush") => {
+            state.files.clear();
+            state.not_found.clear();
+            state.digests.clear();
+            state.missing_segments.clear();
+            info!("Caches flushed by {}", peer(&stream));
+            respond(&mut stream, "200 OK", &json!({ "flushed": true }));
+        }
+        ("POST", "/cache/purge") => match purge(state, config, &body) {
+            Ok(purged) => {
+                info!("Caches purged by {}", peer(&stream));
+                respond(&mut stream, "200 OK", &purged)
+            }
+            Err(e) => respond_error(&mut stream, "400 Bad Request", &e),
+        },
+        ("GET", "/log-level") => respond(&mut stream, "200 OK", &log_level_json()),
+        ("PUT", "/log-level") => match change_log_level(&body) {
+            Ok(level) => respond(&mut stream, "200 OK", &level),
+            Err(e) => respond_error(&mut stream, "400 Bad Request", &e),
+        },
+        ("POST", "/shutdown") => {
+            info!("Shutdown requested by {}", peer(&stream));
+            respond(&mut stream, "202 Accepted", &json!({ "stopping": true }));
+            stopper.stop();
+        }
+        (_, "/config")
+        | (_, "/stats")
+        | (_, "/quotas")
+        | (_, "/segments/missing")
+        | (_, "/cache/flush")
+        | (_, "/cache/purge")
+        | (_, "/log-level")
+        | (_, "/shutdown") => {
+            respond_error(&mut stream, "405 Method Not Allowed", "Method not allowed")
+        }
+        _ => respond_error(&mut stream, "404 Not Found", "No such endpoint"),
+    }
+}
+
+#[cfg(test)]
+mod admin_tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secreT", "secret"));
+        assert!(!token_matches("secret2", "secret"));
+        assert!(!token_matches("", "secret"));
+    }
+
+    #[test]
+    fn connection_limit() {
+        let open = AtomicUsize::new(0);
+        let guards: Vec<ConnectionGuard> = (0..MAX_CONNECTIONS)
+            .map(|_| try_open(&open).unwrap())
+            .collect();
+        assert!(try_open(&open).is_none());
+        drop(guards);
+        assert_eq!(open.load(Ordering::Acquire), 0);
+        assert!(try_open(&open).is_some());
+    }
+
+    #[test]
+    fn purged_keys() {
+        let config = Config::parse(
+            r#"{"network": {"documentRoot": "/srv/www/"},
+                "tenants": [{"name": "acme", "prefix": "/acme", "root": "acme"}]}"#,
+            crate::config::ConfigFormat::Json,
+        )
+        .unwrap();
+        assert_eq!(
+            cache_keys(&config, &["/live/a.mpd".to_string()]),
+            ["/srv/www/live/a.mpd", "acme/live/a.mpd"]
+        );
+
+        assert!(is_key_of("live/a.mpd", "live/a.mpd"));
+        assert!(is_key_of("live/a.mpd.gz", "live/a.mpd"));
+        assert!(is_key_of("live/a.mpd:br", "live/a.mpd"));
+        assert!(is_key_of("live/a.mpd.br:br", "live/a.mpd"));
+        assert!(!is_key_of("live/a.mpd2", "live/a.mpd"));
+        assert!(!is_key_of("live/a.mp", "live/a.mpd"));
+    }
+
+    #[test]
+    fn quota_usage() {
+        let config = Config::parse(
+            r#"{"tenants": [
+                {"name": "free", "prefix": "/free", "root": ".", "quota": {"maxRequests": 10}},
+                {"name": "paid", "prefix": "/paid", "root": "."}
+            ]}"#,
+            crate::config::ConfigFormat::Json,
+        )
+        .unwrap();
+        let quotas = Quotas::new();
+        let quota = config.tenants[0].quota.as_ref().unwrap();
+        assert!(quotas.try_request("free", quota));
+        quotas.add_bytes("free", 1000);
+        assert_eq!(
+            quotas_json(&quotas, &config),
+            json!({"tenants": {"free": {
+                "period": "daily",
+                "requests": 1,
+                "maxRequests": 10,
+                "bytes": 1000,
+                "maxBytes": null,
+            }}})
+        );
+    }
+
+    #[test]
+    fn token_is_not_dumped() {
+        let mut config = Config::parse("{}", crate::config::ConfigFormat::Json).unwrap();
+        config.admin = Some(crate::config::Admin {
+            address: "127.0.0.1".to_string(),
+            port: "9000".to_string(),
+            token: "secret".to_string(),
+        });
+        config.security.signed_urls = Some(crate::config::SignedUrls {
+            secret: "url-secret".to_string(),
+            exempt_prefixes: vec![],
+        });
+        config.security.jwt = Some(crate::config::Jwt {
+            algorithm: crate::config::JwtAlgorithm::Hs256,
+            secret: Some("jwt-secret".to_string()),
+            public_key_file: None,
+            jwks_url: None,
+            issuer: None,
+            audience: None,
+            rules: vec![],
+        });
+        config.ingest = Some(crate::config::Ingest {
+            prefix: "/ingest/".to_string(),
+            root: "live".to_string(),
+            token: "encoder-token".to_string(),
+        });
+        config.clear_key = Some(crate::config::ClearKey {
+            path: "/license".to_string(),
+            keys: vec![(
+                "10000000-1000-1000-1000-100000000001".to_string(),
+                "3a2a1b68dd2bd9b2eeb25e84c4776668".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+            keys_file: None,
+        });
+        let value = config_json(&config);
+        assert_eq!(
+            value["admin"],
+            json!({"address": "127.0.0.1", "port": "9000"})
+        );
+        assert_eq!(
+            value["security"]["signedUrls"],
+            json!({"exemptPrefixes": []})
+        );
+        assert_eq!(value["security"]["jwt"]["algorithm"], "HS256");
+        assert_eq!(value["security"]["jwt"].get("secret"), None);
+        assert_eq!(
+            value["ingest"],
+            json!({"prefix": "/ingest/", "root": "live"})
+        );
+        assert_eq!(
+            value["clearKey"],
+            json!({"path": "/license", "keysFile": null})
+        );
+        assert_eq!(value["network"]["port"], "443");
+    }
+}
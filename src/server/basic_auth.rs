@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::warn;
+use openssl::base64;
+use openssl::hash::{hash, Hasher, MessageDigest};
+use openssl::memcmp;
+
+use crate::config::BasicAuth;
+
+/// Upper limit for remembered credentials so guessing can't use up all the memory
+const MAX_VERIFIED: usize = 1024;
+
+/// Alphabet of the crypt style hashes
+const CRYPT_CHARS: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Password hashes of the users in a htpasswd file
+fn parse_htpasswd(data: &str) -> HashMap<String, String> {
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(user, hash)| (user.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// `n` crypt characters of the lowest bits of `value`
+fn to64(out: &mut String, mut value: u32, n: usize) {
+    for _ in 0..n {
+        out.push(char::from(CRYPT_CHARS[(value & 0x3f) as usize]));
+        value >>= 6;
+    }
+}
+
+fn md5(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Hasher::new(MessageDigest::md5()).unwrap();
+    for part in parts {
+        hasher.update(part).unwrap();
+    }
+    hasher.finish().unwrap().to_vec()
+}
+
+/// The "$apr1$" MD5 hash of Apache's htpasswd. Only the first 8
+/// characters of the salt are used.
+fn apr1(password: &[u8], salt: &str) -> String {
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+    let alternate = md5(&[password, salt, password]);
+    let mut hasher = Hasher::new(MessageDigest::md5()).unwrap();
+    hasher.update(password).unwrap();
+    hasher.update(b"$apr1$").unwrap();
+    hasher.update(salt).unwrap();
+    for chunk in password.chunks(16) {
+        hasher.update(&alternate[..chunk.len()]).unwrap();
+    }
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            hasher.update(&[0]).unwrap();
+        } else {
+            hasher.update(&password[..1]).unwrap();
+        }
+        length >>= 1;
+    }
+    let mut digest = hasher.finish().unwrap().to_vec();
+    // Slows down guessing
+    for i in 0..1000 {
+        let mut parts: Vec<&[u8]> = vec![];
+        parts.push(if i % 2 == 1 { password } else { &digest });
+        if i % 3 != 0 {
+            parts.push(salt);
+        }
+        if i % 7 != 0 {
+            parts.push(password);
+        }
+        parts.push(if i % 2 == 1 { &digest } else { password });
+        digest = md5(&parts);
+    }
+
+    let mut out = format!("$apr1${}$", String::from_utf8_lossy(salt));
+    for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        let value = u32::from(digest[a]) << 16 | u32::from(digest[b]) << 8 | u32::from(digest[c]);
+        to64(&mut out, value, 4);
+    }
+    to64(&mut out, u32::from(digest[11]), 2);
+    out
+}
+
+/// Constant time comparison so the response time doesn't tell how much was right
+fn hashes_match(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && memcmp::eq(a, b)
+}
+
+/// Check `password` against a htpasswd hash. bcrypt, "$apr1$" and "{SHA}"
+/// hashes are supported.
+fn verify_password(password: &str, stored: &str) -> bool {
+    if stored.starts_with("$2y$") || stored.starts_with("$2b$") || stored.starts_with("$2a$") {
+        bcrypt::verify(password, stored).unwrap_or(false)
+    } else if let Some(rest) = stored.strip_prefix("$apr1$") {
+        let salt = rest.split('$').next().unwrap_or("");
+        hashes_match(
+            apr1(password.as_bytes(), salt).as_bytes(),
+            stored.as_bytes(),
+        )
+    } else if let Some(encoded) = stored.strip_prefix("{SHA}") {
+        let digest = hash(MessageDigest::sha1(), password.as_bytes()).unwrap();
+        hashes_match(base64::encode_block(&digest).as_bytes(), encoded.as_bytes())
+    } else {
+        false
+    }
+}
+
+/// User and password of "Basic <base64 of user:password>"
+fn decode_basic(authorization: &str) -> Option<(String, String)> {
+    let (scheme, encoded) = authorization.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("Basic") {
+        return None;
+    }
+    let decoded = base64::decode_block(encoded.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+struct Loaded {
+    /// Credentials file the users came from and its modification time
+    source: Option<(String, SystemTime)>,
+    users: HashMap<String, String>,
+    /// Authorization headers that have already been verified, so bcrypt
+    /// isn't run for every segment
+    verified: HashSet<String>,
+}
+
+/// Users of the credentials file, read again when the file changes
+pub struct Credentials {
+    loaded: Mutex<Loaded>,
+}
+
+impl Credentials {
+    pub fn new() -> Credentials {
+        Credentials {
+            loaded: Mutex::new(Loaded {
+                source: None,
+                users: HashMap::new(),
+                verified: HashSet::new(),
+            }),
+        }
+    }
+
+    /// Is the `authorization` header of a user in the credentials file
+    fn is_valid(&self, file: &str, authorization: &str) -> bool {
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+        let mut loaded = self.loaded.lock().unwrap();
+        let changed = match (&loaded.source, &modified) {
+            (Some((path, at)), Ok(modified)) => path != file || at != modified,
+            _ => true,
+        };
+        if changed {
+            let users = fs::read_to_string(file).map(|data| parse_htpasswd(&data));
+            loaded.users = users.unwrap_or_else(|e| {
+                warn!("Couldn't read the credentials file {}: {}", file, e);
+                HashMap::new()
+            });
+            loaded.source = modified.ok().map(|at| (file.to_string(), at));
+            loaded.verified.clear();
+        }
+        if loaded.verified.contains(authorization) {
+            return true;
+        }
+        let (password, stored) = match decode_basic(authorization) {
+            Some((user, password)) => match loaded.users.get(&user) {
+                Some(stored) => (password, stored.clone()),
+                None => return false,
+            },
+            None => return false,
+        };
+        // bcrypt is slow on purpose, other requests don't wait for it
+        drop(loaded);
+        if !verify_password(&password, &stored) {
+            return false;
+        }
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.verified.len() >= MAX_VERIFIED {
+            loaded.verified.clear();
+        }
+        loaded.verified.insert(authorization.to_string());
+        true
+    }
+
+    /// Can a request to `path` with the `authorization` header go through
+    pub fn authorize(
+        &self,
+        basic_auth: &BasicAuth,
+        path: &str,
+        authorization: Option<&str>,
+    ) -> bool {
+        let protected = basic_auth
+            .prefixes
+            .iter()
+            .any(|prefix| path.starts_with(&prefix[..]));
+        if !protected {
+            return true;
+        }
+        match authorization {
+            Some(authorization) => self.is_valid(&basic_auth.credentials_file, authorization),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod basic_auth_tests {
+    use super::*;
+    use std::io::Write;
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", base64::encode_block(credentials.as_bytes()))
+    }
+
+    #[test]
+    fn apr1_hashes() {
+        // openssl passwd -apr1 -salt r31.ZSwD secret
+        assert_eq!(
+            apr1(b"secret", "r31.ZSwD"),
+            "$apr1$r31.ZSwD$N62pNb6yeq1a6DRWqTRQu."
+        );
+        assert!(verify_password(
+            "secret",
+            "$apr1$r31.ZSwD$N62pNb6yeq1a6DRWqTRQu."
+        ));
+        assert!(!verify_password(
+            "Secret",
+            "$apr1$r31.ZSwD$N62pNb6yeq1a6DRWqTRQu."
+        ));
+    }
+
+    #[test]
+    fn other_hashes() {
+        // htpasswd -nbs user secret
+        assert!(verify_password(
+            "secret",
+            "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ="
+        ));
+        assert!(!verify_password(
+            "secret2",
+            "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ="
+        ));
+        let bcrypted = bcrypt::hash("secret", 4).unwrap();
+        assert!(verify_password("secret", &bcrypted));
+        assert!(!verify_password("secret2", &bcrypted));
+        // Plain text passwords aren't accepted
+        assert!(!verify_password("secret", "secret"));
+    }
+
+    #[test]
+    fn basic_headers() {
+        assert_eq!(
+            decode_basic(&basic("user:pass:word")),
+            Some(("user".to_string(), "pass:word".to_string()))
+        );
+        assert_eq!(decode_basic("Bearer abc"), None);
+        assert_eq!(decode_basic(&basic("nopassword")), None);
+        assert_eq!(decode_basic("Basic !!!"), None);
+    }
+
+    #[test]
+    fn protected_prefixes() {
+        let mut file = std::env::temp_dir();
+        file.push(format!("mpeg-dash-htpasswd-{}", std::process::id()));
+        fs::File::create(&file)
+            .unwrap()
+            .write_all(b"# staging\nalice:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n")
+            .unwrap();
+        let basic_auth = BasicAuth {
+            realm: "Staging".to_string(),
+            credentials_file: file.to_str().unwrap().to_string(),
+            prefixes: vec!["/staging/".to_string()],
+        };
+        let credentials = Credentials::new();
+        let check = |path: &str, authorization: Option<&str>| {
+            credentials.authorize(&basic_auth, path, authorization)
+        };
+        assert!(check("/live/a.mpd", None));
+        assert!(!check("/staging/a.mpd", None));
+        assert!(check("/staging/a.mpd", Some(&basic("alice:secret"))));
+        // Remembered
+        assert!(check("/staging/a.mpd", Some(&basic("alice:secret"))));
+        assert!(!check("/staging/a.mpd", Some(&basic("alice:wrong"))));
+        assert!(!check("/staging/a.mpd", Some(&basic("bob:secret"))));
+
+        fs::remove_file(&file).unwrap();
+        assert!(!check("/staging/a.mpd", Some(&basic("alice:secret"))));
+    }
+}
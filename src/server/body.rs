@@ -0,0 +1,240 @@
+use std::io::{self, Read};
+
+/// Longest accepted chunk size or trailer line in chunked bodies
+const MAX_CHUNK_LINE: usize = 4096;
+
+/// How the length of the request body is determined
+#[derive(Debug, PartialEq)]
+pub enum Framing {
+    /// Request doesn't have a body
+    None,
+    /// Body length is given with Content-Length
+    Length(u64),
+    /// Transfer-Encoding: chunked
+    Chunked,
+}
+
+/// Why the body of the request cannot be read
+#[derive(Debug, PartialEq)]
+pub enum FramingError {
+    /// Invalid Content-Length or Transfer-Encoding header. Answered with 400
+    Invalid,
+    /// Transfer coding other than chunked. Answered with 501
+    UnsupportedEncoding,
+    /// Content-Length is larger than allowed. Answered with 413
+    TooLarge,
+}
+
+/// Reads the request body from the connection and stops at the end of it,
+/// so the handler can stream the body without knowing how it's framed.
+/// Chunked bodies are decoded and the trailers are discarded.
+pub struct BodyReader<R: Read> {
+    source: R,
+    framing: Framing,
+    /// Bytes left in the body (Length) or in the current chunk (Chunked)
+    remaining: u64,
+    /// Is there chunk data that still needs to be followed by CRLF
+    in_chunk: bool,
+    max_size: u64,
+    /// Limit for the trailer lines after the last chunk
+    max_trailer_size: usize,
+    bytes_read: u64,
+    done: bool,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl<R: Read> BodyReader<R> {
+    /// `source` should start from the first byte after the request head.
+    /// Reading fails with InvalidData if the body is larger than `max_size`
+    /// or the trailers are larger than `max_trailer_size`.
+    pub fn new(
+        source: R,
+        framing: Framing,
+        max_size: u64,
+        max_trailer_size: usize,
+    ) -> BodyReader<R> {
+        let (remaining, done) = match framing {
+            Framing::None => (0, true),
+            Framing::Length(len) => (len, len == 0),
+            Framing::Chunked => (0, false),
+        };
+
+        BodyReader {
+            source,
+            framing,
+            remaining,
+            in_chunk: false,
+            max_size,
+            max_trailer_size,
+            bytes_read: 0,
+            done,
+        }
+    }
+
+    /// The source the body is read from
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    /// Read a single CRLF terminated line. Reads one byte at a time so
+    /// nothing past the end of the body is consumed from the connection.
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = vec![];
+        let mut byte = [0_u8; 1];
+        loop {
+            if self.source.read(&mut byte)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "body ended in the middle of a chunk",
+                ));
+            }
+            line.push(byte[0]);
+            if line.ends_with(b"\r\n") {
+                line.truncate(line.len() - 2);
+                return String::from_utf8(line).map_err(|_| invalid_data("invalid chunk line"));
+            }
+            if line.len() > MAX_CHUNK_LINE {
+                return Err(invalid_data("chunk line too long"));
+            }
+        }
+    }
+
+    /// Read the next chunk size line and the trailers if it was the last chunk
+    fn next_chunk(&mut self) -> io::Result<()> {
+        if self.in_chunk {
+            if !self.read_line()?.is_empty() {
+                return Err(invalid_data("chunk data isn't followed by CRLF"));
+            }
+            self.in_chunk = false;
+        }
+
+        let line = self.read_line()?;
+        // Chunk extensions are allowed after ';' but we don't use any of them
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = u64::from_str_radix(size, 16).map_err(|_| invalid_data("invalid chunk size"))?;
+
+        if size == 0 {
+            // Last chunk is followed by optional trailer fields and an empty line
+            let mut trailer_size = 0;
+            loop {
+                let line = self.read_line()?;
+                if line.is_empty() {
+                    break;
+                }
+                trailer_size += line.len() + 2;
+                if trailer_size > self.max_trailer_size {
+                    return Err(invalid_data("trailers are too large"));
+                }
+            }
+            self.done = true;
+            return Ok(());
+        }
+
+        // bytes_read never passes max_size so this can't overflow like a sum
+        if size > self.max_size - self.bytes_read {
+            return Err(invalid_data("request body is too large"));
+        }
+
+        self.remaining = size;
+        self.in_chunk = true;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            // Length framed bodies are marked done when the last byte is read
+            // so only chunked bodies can get here
+            self.next_chunk()?;
+            if self.done {
+                return Ok(0);
+            }
+        }
+
+        let max_read = buf.len().min(self.remaining as usize);
+        let data_len = self.source.read(&mut buf[..max_read])?;
+        if data_len == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before the end of the body",
+            ));
+        }
+
+        self.remaining -= data_len as u64;
+        self.bytes_read += data_len as u64;
+        if self.remaining == 0 && self.framing != Framing::Chunked {
+            self.done = true;
+        }
+
+        Ok(data_len)
+    }
+}
+
+#[cfg(test)]
+mod body_tests {
+    use super::*;
+
+    fn read_all(data: &[u8], framing: Framing, max_size: u64) -> io::Result<Vec<u8>> {
+        let mut reader = BodyReader::new(data, framing, max_size, 100);
+        let mut body = vec![];
+        reader.read_to_end(&mut body)?;
+        Ok(body)
+    }
+
+    #[test]
+    fn content_length_body() {
+        let body = read_all(b"hello worldGET / HTTP/1.1", Framing::Length(11), 100).unwrap();
+        assert_eq!(body, b"hello world");
+        assert!(read_all(b"hello", Framing::Length(11), 100).is_err());
+        assert!(read_all(b"hello", Framing::None, 100).unwrap().is_empty());
+    }
+
+    #[test]
+    fn chunked_body() {
+        let data = b"5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nTrailer: yes\r\n\r\nGET";
+        let mut reader = BodyReader::new(&data[..], Framing::Chunked, 100, 100);
+        let mut body = vec![];
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"hello world");
+    }
+
+    #[test]
+    fn invalid_chunked_body() {
+        assert!(read_all(b"x\r\nhello\r\n0\r\n\r\n", Framing::Chunked, 100).is_err());
+        assert!(read_all(b"5\r\nhelloX\r\n0\r\n\r\n", Framing::Chunked, 100).is_err());
+        assert!(read_all(b"5\r\nhello\r\n", Framing::Chunked, 100).is_err());
+    }
+
+    #[test]
+    fn chunked_body_too_large() {
+        let data = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let error = read_all(data, Framing::Chunked, 10).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // A size that would wrap around when added to the bytes read so far
+        let data = b"5\r\nhello\r\nfffffffffffffffb\r\n world\r\n0\r\n\r\n";
+        let error = read_all(data, Framing::Chunked, 16).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let data = b"ffffffffffffffff\r\nhello\r\n0\r\n\r\n";
+        let error = read_all(data, Framing::Chunked, 16).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn chunked_trailers_too_large() {
+        let trailer = b"Trailer: yes\r\n".repeat(10);
+        let data = [&b"5\r\nhello\r\n0\r\n"[..], &trailer, b"\r\n"].concat();
+        let error = read_all(&data, Framing::Chunked, 100).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let data = [&b"5\r\nhello\r\n0\r\n"[..], &trailer[..70], b"\r\n"].concat();
+        assert_eq!(read_all(&data, Framing::Chunked, 100).unwrap(), b"hello");
+    }
+}
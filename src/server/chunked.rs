@@ -0,0 +1,67 @@
+use std::io::{self, Write};
+
+/// Writes a response body in the chunked transfer coding (RFC 9112 7.1), for
+/// bodies whose length isn't known when the head is sent. Every write is one
+/// chunk and the body ends with `finish`.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+    /// Body bytes written, without the chunk framing
+    written: u64,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner, written: 0 }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Write the last chunk. Without it the client knows the body is incomplete.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.inner
+            .write_all(format!("{:x}\r\n", buf.len()).as_bytes())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod chunked_tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let mut writer = ChunkedWriter::new(vec![]);
+        writer.write_all(b"Hello").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(&[b'x'; 26]).unwrap();
+        assert_eq!(writer.written(), 31);
+        let body = writer.finish().unwrap();
+        let expected = format!("5\r\nHello\r\n1a\r\n{}\r\n0\r\n\r\n", "x".repeat(26));
+        assert_eq!(String::from_utf8(body).unwrap(), expected);
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::warn;
+use openssl::base64;
+use serde::{Deserialize, Serialize};
+
+use super::jwt::decode_base64url;
+use crate::config::ClearKey;
+
+/// Longest accepted license request. Players ask for a few keys at a time
+pub const MAX_REQUEST_SIZE: u64 = 64 * 1024;
+
+/// A key ID or a key
+type Id = [u8; 16];
+
+/// License request of a player (W3C Encrypted Media Extensions 9.1.3)
+#[derive(Deserialize)]
+struct LicenseRequest {
+    /// Key IDs as unpadded base64url
+    kids: Vec<String>,
+    #[serde(rename = "type", default = "def_session_type")]
+    session_type: String,
+}
+
+fn def_session_type() -> String {
+    "temporary".to_string()
+}
+
+/// A key of the license, a JSON Web Key (RFC 7517)
+#[derive(Serialize)]
+struct Key {
+    kty: &'static str,
+    kid: String,
+    k: String,
+}
+
+#[derive(Serialize)]
+struct License {
+    keys: Vec<Key>,
+    #[serde(rename = "type")]
+    session_type: String,
+}
+
+/// Why a license wasn't given
+#[derive(Debug, PartialEq)]
+pub enum LicenseError {
+    /// The body isn't a license request. Answered with 400
+    Invalid,
+    /// None of the requested keys are known. Answered with 404
+    NoKeys,
+}
+
+/// The 16 bytes of a UUID or 32 hex digits
+fn parse_id(value: &str) -> Option<Id> {
+    let hex: String = value.trim().chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut id = [0u8; 16];
+    for (i, byte) in id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(id)
+}
+
+fn encode_base64url(data: &[u8]) -> String {
+    base64::encode_block(data)
+        .trim_end_matches('=')
+        .replace('+', "-")
+        .replace('/', "_")
+}
+
+/// Keys by key ID of a keys file with "<key ID>:<key>" lines
+fn parse_keys_file(data: &str) -> HashMap<Id, Id> {
+    data.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .filter_map(|(kid, key)| Some((parse_id(kid)?, parse_id(key)?)))
+        .collect()
+}
+
+struct Loaded {
+    /// Keys file the keys came from and its modification time
+    source: Option<(String, SystemTime)>,
+    keys: HashMap<Id, Id>,
+}
+
+/// Keys of the keys file, read again when the file changes
+pub struct ClearKeys {
+    loaded: Mutex<Loaded>,
+}
+
+impl ClearKeys {
+    pub fn new() -> ClearKeys {
+        ClearKeys {
+            loaded: Mutex::new(Loaded {
+                source: None,
+                keys: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Keys of `file`, read again if it has changed
+    fn file_keys(&self, file: &str) -> HashMap<Id, Id> {
+        let modified = fs::metadata(file).and_then(|metadata| metadata.modified());
+        let mut loaded = self.loaded.lock().unwrap();
+        let changed = match (&loaded.source, &modified) {
+            (Some((path, at)), Ok(modified)) => path != file || at != modified,
+            _ => true,
+        };
+        if changed {
+            let keys = fs::read_to_string(file).map(|data| parse_keys_file(&data));
+            loaded.keys = keys.unwrap_or_else(|e| {
+                warn!("Couldn't read the keys file {}: {}", file, e);
+                HashMap::new()
+            });
+            loaded.source = modified.ok().map(|at| (file.to_string(), at));
+        }
+        loaded.keys.clone()
+    }
+
+    /// The license JSON for the license request in `body` with the keys of
+    /// the config and the keys file. Unknown key IDs are left out.
+    pub fn license(&self, clear_key: &ClearKey, body: &[u8]) -> Result<String, LicenseError> {
+        let request: LicenseRequest =
+            serde_json::from_slice(body).map_err(|_| LicenseError::Invalid)?;
+        let kids: Vec<Id> = request
+            .kids
+            .iter()
+            .map(|kid| decode_base64url(kid).and_then(|kid| kid.try_into().ok()))
+            .collect::<Option<Vec<Id>>>()
+            .ok_or(LicenseError::Invalid)?;
+        if kids.is_empty() {
+            return Err(LicenseError::Invalid);
+        }
+
+        let mut keys: HashMap<Id, Id> = match &clear_key.keys_file {
+            Some(file) => self.file_keys(file),
+            None => HashMap::new(),
+        };
+        // The keys of the config replace the ones of the file
+        keys.extend(
+            clear_key
+                .keys
+                .iter()
+                .filter_map(|(kid, key)| Some((parse_id(kid)?, parse_id(key)?))),
+        );
+        let license = License {
+            keys: kids
+                .iter()
+                .filter_map(|kid| {
+                    let key = keys.get(kid)?;
+                    Some(Key {
+                        kty: "oct",
+                        kid: encode_base64url(kid),
+                        k: encode_base64url(key),
+                    })
+                })
+                .collect(),
+            session_type: request.session_type,
+        };
+        if license.keys.is_empty() {
+            return Err(LicenseError::NoKeys);
+        }
+        Ok(serde_json::to_string(&license).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod clear_key_tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    const KID: &str = "10000000-1000-1000-1000-100000000001";
+    const KEY: &str = "3a2a1b68dd2bd9b2eeb25e84c4776668";
+    /// KID as base64url
+    const ENCODED_KID: &str = "EAAAABAAEAAQABAAAAAAAQ";
+
+    fn clear_key(keys_file: Option<String>) -> ClearKey {
+        ClearKey {
+            path: "/license".to_string(),
+            keys: vec![(KID.to_string(), KEY.to_string())]
+                .into_iter()
+                .collect(),
+            keys_file,
+        }
+    }
+
+    fn license(
+        keys: &ClearKeys,
+        clear_key: &ClearKey,
+        request: Value,
+    ) -> Result<Value, LicenseError> {
+        keys.license(clear_key, request.to_string().as_bytes())
+            .map(|license| serde_json::from_str(&license).unwrap())
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(parse_id(KID), parse_id("10000000100010001000100000000001"));
+        assert_eq!(parse_id(KEY).unwrap()[0], 0x3a);
+        assert_eq!(parse_id("3a2a1b68"), None);
+        assert_eq!(parse_id("3a2a1b68dd2bd9b2eeb25e84c477666g"), None);
+        assert_eq!(encode_base64url(&parse_id(KID).unwrap()), ENCODED_KID);
+    }
+
+    #[test]
+    fn keys_of_request() {
+        let keys = ClearKeys::new();
+        let clear_key = clear_key(None);
+        assert_eq!(
+            license(
+                &keys,
+                &clear_key,
+                json!({"kids": [ENCODED_KID, "IAAAACAAIAAgACAAAAAAAg"]})
+            ),
+            Ok(json!({
+                "keys": [{"kty": "oct", "kid": ENCODED_KID, "k": "OiobaN0r2bLusl6ExHdmaA"}],
+                "type": "temporary"
+            }))
+        );
+        let persistent = json!({"kids": [ENCODED_KID], "type": "persistent-license"});
+        assert_eq!(
+            license(&keys, &clear_key, persistent).unwrap()["type"],
+            "persistent-license"
+        );
+
+        let unknown = json!({"kids": ["IAAAACAAIAAgACAAAAAAAg"]});
+        assert_eq!(
+            license(&keys, &clear_key, unknown),
+            Err(LicenseError::NoKeys)
+        );
+        let invalid = [
+            json!({"kids": []}),
+            json!({"kids": ["EAAAABAAEAAQABAAAAAA"]}),
+            json!({"kids": ["EAAAABAAEAAQABAAAAAAAQ=="]}),
+            json!({"keys": [ENCODED_KID]}),
+        ];
+        for request in invalid {
+            assert_eq!(
+                license(&keys, &clear_key, request),
+                Err(LicenseError::Invalid)
+            );
+        }
+        assert_eq!(
+            keys.license(&clear_key, b"kids"),
+            Err(LicenseError::Invalid)
+        );
+    }
+
+    #[test]
+    fn keys_file() {
+        let path = std::env::temp_dir().join(format!("dash-clear-keys-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# Channel 1\n20000000-2000-2000-2000-200000000002:00112233445566778899aabbccddeeff\nbroken\n",
+        )
+        .unwrap();
+        let keys = ClearKeys::new();
+        let clear_key = clear_key(Some(path.to_string_lossy().into_owned()));
+        let request = json!({"kids": [ENCODED_KID, "IAAAACAAIAAgACAAAAAAAg"]});
+        let license = license(&keys, &clear_key, request).unwrap();
+        assert_eq!(license["keys"].as_array().unwrap().len(), 2);
+        assert_eq!(license["keys"][1]["k"], "ABEiM0RVZneImaq7zN3u_w");
+        fs::remove_file(path).unwrap();
+    }
+}
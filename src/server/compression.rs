@@ -0,0 +1,188 @@
+use flate2::write::GzEncoder;
+use std::io::{self, Read, Write};
+
+/// Brotli quality. Higher levels are too slow for manifests compressed on every request
+const BROTLI_QUALITY: u32 = 5;
+/// Brotli window size as a power of two
+const BROTLI_WINDOW: u32 = 22;
+
+/// Content coding of the response body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Name of the coding in Accept-Encoding and Content-Encoding
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+}
+
+/// Path of the precompressed version of the file in `path`, e.g. "stream.mpd.br"
+pub fn variant_path(path: &str, encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Identity => path.to_string(),
+        Encoding::Gzip => format!("{}.gz", path),
+        Encoding::Brotli => format!("{}.br", path),
+    }
+}
+
+/// Is the file text that is worth compressing. Media segments are already compressed
+pub fn is_compressible(path: &str) -> bool {
+    [".mpd", ".m3u8", ".vtt", ".ttml"]
+        .iter()
+        .any(|extension| path.ends_with(extension))
+}
+
+/// Pick the coding the client prefers from its Accept-Encoding header (RFC 9110 12.5.3)
+/// out of the `available` ones. Brotli wins ties since it compresses XML better.
+pub fn negotiate(accept_encoding: Option<&str>, available: &[Encoding]) -> Encoding {
+    let accept_encoding = match accept_encoding {
+        Some(accept_encoding) => accept_encoding,
+        None => return Encoding::Identity,
+    };
+
+    let mut wildcard = None;
+    let mut gzip = None;
+    let mut brotli = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        let quality = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if coding.eq_ignore_ascii_case("br") {
+            brotli = Some(quality);
+        } else if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(quality);
+        } else if coding == "*" {
+            wildcard = Some(quality);
+        }
+    }
+
+    let quality = |encoding, quality: Option<f32>| {
+        if available.contains(&encoding) {
+            quality.or(wildcard).unwrap_or(0.0)
+        } else {
+            0.0
+        }
+    };
+    let brotli = quality(Encoding::Brotli, brotli);
+    let gzip = quality(Encoding::Gzip, gzip);
+    if brotli > 0.0 && brotli >= gzip {
+        Encoding::Brotli
+    } else if gzip > 0.0 {
+        Encoding::Gzip
+    } else {
+        Encoding::Identity
+    }
+}
+
+/// Compress `data` with `encoding`
+pub fn compress(data: &[u8], encoding: Encoding) -> io::Result<Vec<u8>> {
+    compress_to(data, vec![], encoding)
+}
+
+/// Compress everything read from `source` into `writer` as it's read, so
+/// the whole uncompressed or compressed data is never in memory
+pub fn compress_to<R: Read, W: Write>(
+    mut source: R,
+    mut writer: W,
+    encoding: Encoding,
+) -> io::Result<W> {
+    match encoding {
+        Encoding::Identity => {
+            io::copy(&mut source, &mut writer)?;
+            Ok(writer)
+        }
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(writer, flate2::Compression::default());
+            io::copy(&mut source, &mut encoder)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut encoder =
+                brotli::CompressorWriter::new(writer, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            io::copy(&mut source, &mut encoder)?;
+            encoder.flush()?;
+            Ok(encoder.into_inner())
+        }
+    }
+}
+
+/// ETag of the compressed representation. It has to differ from the
+/// uncompressed one since the bytes are different
+pub fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Identity => etag.to_string(),
+        _ => format!("{}-{}\"", etag.trim_end_matches('"'), encoding.name()),
+    }
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    #[test]
+    fn negotiation() {
+        let negotiate = |header| negotiate(header, &[Encoding::Brotli, Encoding::Gzip]);
+        assert_eq!(negotiate(None), Encoding::Identity);
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Encoding::Brotli);
+        assert_eq!(negotiate(Some("gzip, br;q=0.5")), Encoding::Gzip);
+        assert_eq!(negotiate(Some("br;q=0, gzip;q=0")), Encoding::Identity);
+        assert_eq!(negotiate(Some("deflate")), Encoding::Identity);
+        assert_eq!(negotiate(Some("*")), Encoding::Brotli);
+        assert_eq!(negotiate(Some("*;q=0.5, gzip")), Encoding::Gzip);
+    }
+
+    #[test]
+    fn negotiation_of_available_encodings() {
+        let gzip_only = [Encoding::Gzip];
+        assert_eq!(
+            super::negotiate(Some("br, gzip"), &gzip_only),
+            Encoding::Gzip
+        );
+        assert_eq!(super::negotiate(Some("br"), &gzip_only), Encoding::Identity);
+        assert_eq!(super::negotiate(Some("*"), &[]), Encoding::Identity);
+        assert_eq!(
+            variant_path("a/stream.mpd", Encoding::Brotli),
+            "a/stream.mpd.br"
+        );
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        let data = "<MPD></MPD>".repeat(100);
+
+        let gzip = compress(data.as_bytes(), Encoding::Gzip).unwrap();
+        let mut decoded = String::new();
+        GzDecoder::new(&gzip[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        let brotli = compress(data.as_bytes(), Encoding::Brotli).unwrap();
+        let mut decoded = String::new();
+        brotli::Decompressor::new(&brotli[..], 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+        assert!(brotli.len() < data.len() / 10);
+    }
+
+    #[test]
+    fn etag_per_encoding() {
+        assert_eq!(encoded_etag("\"1-2\"", Encoding::Identity), "\"1-2\"");
+        assert_eq!(encoded_etag("\"1-2\"", Encoding::Brotli), "\"1-2-br\"");
+    }
+}
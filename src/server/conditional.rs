@@ -0,0 +1,101 @@
+use httpdate::HttpDate;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::request::Request;
+
+/// Value of the Last-Modified header for a file modified at `modified`
+pub fn last_modified(modified: SystemTime) -> String {
+    httpdate::fmt_http_date(modified)
+}
+
+/// Strong ETag for a file from its modification time and size.
+/// The time has sub-second precision so manifests that are rewritten
+/// many times a second still get a new tag every time.
+pub fn etag(modified: SystemTime, length: u64) -> String {
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("\"{:x}-{:x}\"", since_epoch.as_nanos(), length)
+}
+
+/// Entity tag without the weak prefix, for the weak comparison (RFC 9110 8.8.3.2)
+fn opaque_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Does the client already have the current version of the file (RFC 9110 13.1.2, 13.1.3).
+/// If-None-Match is used when the client sends it, If-Modified-Since otherwise.
+/// HTTP dates only have second precision so the modification time is truncated too.
+pub fn is_not_modified(request: &Request, modified: SystemTime, etag: &str) -> bool {
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        let if_none_match = if_none_match.trim();
+        return if_none_match == "*"
+            || if_none_match
+                .split(',')
+                .any(|tag| opaque_tag(tag.trim()) == opaque_tag(etag));
+    }
+
+    let since = match request
+        .header("If-Modified-Since")
+        .and_then(|date| httpdate::parse_http_date(date).ok())
+    {
+        Some(since) => since,
+        // Invalid dates are ignored
+        None => return false,
+    };
+    HttpDate::from(modified) <= HttpDate::from(since)
+}
+
+#[cfg(test)]
+mod conditional_tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn last_modified_date() {
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        assert_eq!(last_modified(modified), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let modified = UNIX_EPOCH + Duration::from_millis(784_111_777_500);
+        let not_modified = |date: &str| {
+            let head = format!("GET / HTTP/1.1\r\nIf-Modified-Since: {}\r\n\r\n", date);
+            is_not_modified(&Request::parse(&head).unwrap(), modified, "\"1\"")
+        };
+        assert!(not_modified("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert!(not_modified("Sun, 06 Nov 1994 08:50:00 GMT"));
+        assert!(!not_modified("Sun, 06 Nov 1994 08:49:36 GMT"));
+        assert!(!not_modified("yesterday"));
+
+        let head = "GET / HTTP/1.1\r\n\r\n";
+        assert!(!is_not_modified(
+            &Request::parse(head).unwrap(),
+            modified,
+            "\"1\""
+        ));
+    }
+
+    #[test]
+    fn etags() {
+        let modified = UNIX_EPOCH + Duration::from_nanos(1_500_000_000);
+        assert_eq!(etag(modified, 255), "\"59682f00-ff\"");
+    }
+
+    #[test]
+    fn if_none_match() {
+        let modified = UNIX_EPOCH + Duration::from_secs(10);
+        let tag = etag(modified, 100);
+        let not_modified = |value: &str| {
+            let head = format!(
+                "GET / HTTP/1.1\r\nIf-None-Match: {}\r\nIf-Modified-Since: Fri, 01 Jan 2100 00:00:00 GMT\r\n\r\n",
+                value
+            );
+            is_not_modified(&Request::parse(&head).unwrap(), modified, &tag)
+        };
+        assert!(not_modified(&tag));
+        assert!(not_modified(&format!("\"other\", W/{}", tag)));
+        assert!(not_modified("*"));
+        // If-Modified-Since would match but If-None-Match takes precedence
+        assert!(!not_modified("\"other\""));
+    }
+}
@@ -0,0 +1,288 @@
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+
+use crate::config::ProtectedStream;
+
+/// Namespace of the cenc:default_KID attribute and the cenc:pssh element
+const CENC_NAMESPACE: &str = "urn:mpeg:cenc:2013";
+/// Namespace of the dashif:laurl element with the license server url
+const DASHIF_NAMESPACE: &str = "https://dashif.org/CPS";
+/// Scheme of the element that tells the protection scheme and the key ID
+const MP4_PROTECTION_SCHEME: &str = "urn:mpeg:dash:mp4protection:2011";
+
+/// Elements of an AdaptationSet that come before its ContentProtection
+const PRECEDING_ELEMENTS: [&[u8]; 2] = [b"FramePacking", b"AudioChannelConfiguration"];
+
+/// The entries of `streams` for the manifest at the url `path`
+pub fn for_manifest<'a>(streams: &'a [ProtectedStream], path: &str) -> Vec<&'a ProtectedStream> {
+    streams
+        .iter()
+        .filter(|stream| path.starts_with(&stream.prefix[..]))
+        .collect()
+}
+
+/// Value of the attribute `name` in the element
+pub fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.as_ref() == name.as_bytes())
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// "video", "audio" or "text" from the contentType or the mimeType of the set
+fn content_type(set: &BytesStart) -> Option<String> {
+    attribute(set, "contentType").or_else(|| {
+        let mime_type = attribute(set, "mimeType")?;
+        let kind = mime_type.split('/').next()?;
+        Some(kind.to_string()).filter(|kind| ["video", "audio", "text"].contains(&kind.as_str()))
+    })
+}
+
+/// The ContentProtection elements of `stream`, the protection scheme first
+fn elements(stream: &ProtectedStream) -> Vec<String> {
+    let mut elements = vec![format!(
+        "<ContentProtection schemeIdUri=\"{}\" value=\"{}\" cenc:default_KID=\"{}\"/>",
+        MP4_PROTECTION_SCHEME,
+        escape(&stream.scheme),
+        stream.default_kid.to_ascii_lowercase()
+    )];
+    for system in &stream.systems {
+        let mut element = format!(
+            "<ContentProtection schemeIdUri=\"urn:uuid:{}\"",
+            system.system_id.to_ascii_lowercase()
+        );
+        if let Some(value) = &system.value {
+            element.push_str(&format!(" value=\"{}\"", escape(value)));
+        }
+        let mut children = String::new();
+        if let Some(pssh) = &system.pssh {
+            children.push_str(&format!("<cenc:pssh>{}</cenc:pssh>", escape(pssh)));
+        }
+        if let Some(license_url) = &system.license_url {
+            children.push_str(&format!(
+                "<dashif:laurl>{}</dashif:laurl>",
+                escape(license_url)
+            ));
+        }
+        if children.is_empty() {
+            element.push_str("/>");
+        } else {
+            element.push_str(&format!(">{}</ContentProtection>", children));
+        }
+        elements.push(element);
+    }
+    elements
+}
+
+/// `mpd` with the ContentProtection elements of `streams` in its adaptation
+/// sets. A set gets the elements of the first stream for its content type
+/// unless it already has some. Everything else is kept as it was.
+pub fn inject(mpd: &str, streams: &[&ProtectedStream]) -> Result<String, String> {
+    let mut reader = Reader::from_str(mpd);
+    let mut writer = Writer::new(Vec::new());
+    // Depth of the elements that are open
+    let mut depth = 0;
+    // Elements waiting for the first child of the set they go before, with
+    // the depth of the set's children
+    let mut pending: Option<(usize, Vec<String>)> = None;
+    // Whitespace before the last child so the new ones line up with it
+    let mut indent = String::new();
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("Invalid manifest: {}", e))?;
+        if let Some((child_depth, elements)) = &pending {
+            if depth == *child_depth {
+                // None until it's known if the elements go before this event
+                let insert = match &event {
+                    Event::Start(element) | Event::Empty(element) => {
+                        let name = element.local_name();
+                        if PRECEDING_ELEMENTS.contains(&name.as_ref()) {
+                            None
+                        } else {
+                            Some(name.as_ref() != b"ContentProtection")
+                        }
+                    }
+                    Event::End(_) => Some(true),
+                    Event::Text(text) => {
+                        if text.iter().all(u8::is_ascii_whitespace) {
+                            indent = String::from_utf8_lossy(text).into_owned();
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+                if let Some(insert) = insert {
+                    if insert {
+                        for element in elements {
+                            writer.get_mut().extend_from_slice(element.as_bytes());
+                            writer.get_mut().extend_from_slice(indent.as_bytes());
+                        }
+                    }
+                    pending = None;
+                }
+            }
+        }
+
+        let next_depth = match &event {
+            Event::Start(_) => depth + 1,
+            Event::End(_) => depth - 1,
+            _ => depth,
+        };
+        match event {
+            Event::Eof => break,
+            Event::Start(element) if element.local_name().as_ref() == b"MPD" => {
+                let mut element = element.into_owned();
+                if !streams.is_empty() && attribute(&element, "xmlns:cenc").is_none() {
+                    element.push_attribute(("xmlns:cenc", CENC_NAMESPACE));
+                }
+                let has_license_urls = streams
+                    .iter()
+                    .flat_map(|stream| &stream.systems)
+                    .any(|system| system.license_url.is_some());
+                if has_license_urls && attribute(&element, "xmlns:dashif").is_none() {
+                    element.push_attribute(("xmlns:dashif", DASHIF_NAMESPACE));
+                }
+                writer.write_event(Event::Start(element))
+            }
+            Event::Start(element) if element.local_name().as_ref() == b"AdaptationSet" => {
+                let kind = content_type(&element);
+                let stream = streams
+                    .iter()
+                    .find(|stream| stream.content_type.is_none() || stream.content_type == kind);
+                if let Some(stream) = stream {
+                    pending = Some((depth + 1, elements(stream)));
+                    indent.clear();
+                }
+                writer.write_event(Event::Start(element))
+            }
+            event => writer.write_event(event),
+        }
+        .map_err(|e| format!("Cannot write the manifest: {}", e))?;
+        depth = next_depth;
+    }
+    String::from_utf8(writer.into_inner()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod content_protection_tests {
+    use super::*;
+    use crate::config::DrmSystem;
+    use crate::mpd;
+
+    const DASH_DOCUMENT: &str = "test_data/unit_test_dash_document.mpd";
+
+    fn stream(content_type: Option<&str>, default_kid: &str) -> ProtectedStream {
+        ProtectedStream {
+            prefix: "/vod/".to_string(),
+            content_type: content_type.map(|t| t.to_string()),
+            default_kid: default_kid.to_string(),
+            scheme: "cenc".to_string(),
+            systems: vec![DrmSystem {
+                system_id: "EDEF8BA9-79D6-4ACE-A3C8-27DCD51D21ED".to_string(),
+                value: Some("Widevine".to_string()),
+                pssh: Some("AAAAEnBzc2g=".to_string()),
+                license_url: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn streams_of_manifest() {
+        let streams = [
+            stream(None, "10000000-1000-1000-1000-100000000001"),
+            ProtectedStream {
+                prefix: "/live/".to_string(),
+                ..stream(None, "20000000-2000-2000-2000-200000000002")
+            },
+        ];
+        assert_eq!(for_manifest(&streams, "/vod/movie/manifest.mpd").len(), 1);
+        assert_eq!(for_manifest(&streams, "/vod").len(), 0);
+    }
+
+    #[test]
+    fn injected_into_sets() {
+        let xml = std::fs::read_to_string(DASH_DOCUMENT).unwrap();
+        let video = stream(Some("video"), "10000000-1000-1000-1000-100000000001");
+        let audio = ProtectedStream {
+            systems: vec![],
+            ..stream(Some("audio"), "20000000-2000-2000-2000-200000000002")
+        };
+        let protected = inject(&xml, &[&video, &audio]).unwrap();
+        assert!(protected.contains(" xmlns:cenc=\"urn:mpeg:cenc:2013\">\n"));
+        assert!(!protected.contains("xmlns:dashif"));
+        assert!(protected.contains(concat!(
+            "<AdaptationSet maxHeight=\"180\" maxWidth=\"320\" mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">\n",
+            "      <ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" value=\"cenc\" cenc:default_KID=\"10000000-1000-1000-1000-100000000001\"/>\n",
+            "      <ContentProtection schemeIdUri=\"urn:uuid:edef8ba9-79d6-4ace-a3c8-27dcd51d21ed\" value=\"Widevine\"><cenc:pssh>AAAAEnBzc2g=</cenc:pssh></ContentProtection>\n",
+            "      <SegmentTemplate "
+        )));
+        assert!(protected.contains(concat!(
+            "startWithSAP=\"1\">\n",
+            "      <ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" value=\"cenc\" cenc:default_KID=\"20000000-2000-2000-2000-200000000002\"/>\n",
+            "      <SegmentTemplate "
+        )));
+        // Nothing else changes
+        assert!(protected.contains("<!-- Created with Bento4 mp4-dash.py"));
+        assert_eq!(protected.matches("<ContentProtection ").count(), 3);
+        assert_eq!(mpd::parse(&protected), mpd::parse(&xml));
+        assert_eq!(inject(&xml, &[]).unwrap(), xml);
+    }
+
+    #[test]
+    fn existing_protection_is_kept() {
+        let xml = concat!(
+            "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" xmlns:cenc=\"urn:mpeg:cenc:2013\">",
+            "<Period><AdaptationSet contentType=\"audio\">",
+            "<AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"2\"/>",
+            "<Representation id=\"a\" bandwidth=\"1\"/></AdaptationSet>",
+            "<AdaptationSet contentType=\"video\">",
+            "<ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" value=\"cbcs\"/>",
+            "<Representation id=\"v\" bandwidth=\"1\"/></AdaptationSet>",
+            "<AdaptationSet><Representation id=\"t\" bandwidth=\"1\"/></AdaptationSet>",
+            "</Period></MPD>"
+        );
+        let stream = ProtectedStream {
+            systems: vec![],
+            ..stream(Some("audio"), "10000000-1000-1000-1000-100000000001")
+        };
+        let protected = inject(xml, &[&stream]).unwrap();
+        // After the channel configuration and only in the audio set
+        assert!(protected.contains(concat!(
+            "value=\"2\"/><ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" ",
+            "value=\"cenc\" cenc:default_KID=\"10000000-1000-1000-1000-100000000001\"/>",
+            "<Representation id=\"a\""
+        )));
+        assert_eq!(protected.matches("<ContentProtection ").count(), 2);
+        assert_eq!(protected.matches("xmlns:cenc").count(), 1);
+
+        assert!(inject("<MPD><Period></MPD>", &[&stream]).is_err());
+    }
+
+    #[test]
+    fn license_url() {
+        let xml =
+            "<MPD><Period><AdaptationSet contentType=\"video\"></AdaptationSet></Period></MPD>";
+        let stream = ProtectedStream {
+            systems: vec![DrmSystem {
+                system_id: "e2719d58-a985-b3c9-781a-b030af78d30e".to_string(),
+                value: Some("ClearKey1.0".to_string()),
+                pssh: None,
+                license_url: Some("https://example.com/license?a=1&b=2".to_string()),
+            }],
+            ..stream(None, "10000000-1000-1000-1000-100000000001")
+        };
+        let protected = inject(xml, &[&stream]).unwrap();
+        assert!(protected.starts_with(
+            "<MPD xmlns:cenc=\"urn:mpeg:cenc:2013\" xmlns:dashif=\"https://dashif.org/CPS\">"
+        ));
+        assert!(protected.contains(concat!(
+            "<ContentProtection schemeIdUri=\"urn:uuid:e2719d58-a985-b3c9-781a-b030af78d30e\" value=\"ClearKey1.0\">",
+            "<dashif:laurl>https://example.com/license?a=1&amp;b=2</dashif:laurl></ContentProtection>"
+        )));
+    }
+}
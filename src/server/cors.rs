@@ -0,0 +1,144 @@
+use crate::config::Cors;
+
+/// Access-Control-Allow-Origin value for a request from `origin` when the
+/// title allows the `allowed` origins. None if the origin isn't allowed.
+/// The second value tells if the value depends on the request's origin.
+fn allow_origin<'a>(
+    cors: &Cors,
+    allowed: &[&'a str],
+    origin: Option<&'a str>,
+) -> (Option<&'a str>, bool) {
+    if allowed.contains(&"*") {
+        // Browsers don't accept "*" with credentials
+        return match origin {
+            Some(origin) if cors.allow_credentials => (Some(origin), true),
+            _ => (Some("*"), false),
+        };
+    }
+    // A single origin is always sent, the browser does the comparison
+    if allowed.len() == 1 {
+        return (Some(allowed[0]), false);
+    }
+
+    let matching = origin.filter(|origin| {
+        allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+    });
+    (matching, true)
+}
+
+/// CORS header lines for a response to a request from `origin`
+pub fn response_headers(cors: &Cors, allowed: &[&str], origin: Option<&str>) -> String {
+    let mut out = String::new();
+    let (allow_origin, varies) = allow_origin(cors, allowed, origin);
+    if let Some(allow_origin) = allow_origin {
+        out.push_str(&format!(
+            "Access-Control-Allow-Origin: {}\r\n",
+            allow_origin
+        ));
+        if cors.allow_credentials {
+            out.push_str("Access-Control-Allow-Credentials: true\r\n");
+        }
+        if !cors.exposed_headers.is_empty() {
+            out.push_str(&format!(
+                "Access-Control-Expose-Headers: {}\r\n",
+                cors.exposed_headers.join(", ")
+            ));
+        }
+    }
+    // Caches have to keep the responses to different origins apart
+    if varies {
+        out.push_str("Vary: Origin\r\n");
+    }
+    out
+}
+
+/// CORS header lines for a preflight response. `requested_headers` is the
+/// Access-Control-Request-Headers of the preflight.
+pub fn preflight_headers(
+    cors: &Cors,
+    allowed: &[&str],
+    origin: Option<&str>,
+    requested_headers: Option<&str>,
+) -> String {
+    let mut out = response_headers(cors, allowed, origin);
+    out.push_str(&format!(
+        "Access-Control-Allow-Methods: {}\r\n",
+        cors.allowed_methods.join(", ")
+    ));
+    // Players add headers like DRM tokens so by default
+    // whatever the browser asks for is allowed
+    let allowed_headers = if cors.allowed_headers.is_empty() {
+        requested_headers.map(|headers| headers.to_string())
+    } else {
+        Some(cors.allowed_headers.join(", "))
+    };
+    if let Some(allowed_headers) = allowed_headers {
+        out.push_str(&format!(
+            "Access-Control-Allow-Headers: {}\r\n",
+            allowed_headers
+        ));
+    }
+    out.push_str(&format!("Access-Control-Max-Age: {}\r\n", cors.max_age));
+    out
+}
+
+#[cfg(test)]
+mod cors_tests {
+    use super::*;
+
+    fn cors(allow_credentials: bool) -> Cors {
+        Cors {
+            allowed_origins: vec![],
+            allowed_methods: vec!["GET".to_string()],
+            allowed_headers: vec![],
+            exposed_headers: vec![],
+            allow_credentials,
+            max_age: 60,
+        }
+    }
+
+    #[test]
+    fn origin_matching() {
+        let allowed = ["https://a.example.com", "https://b.example.com"];
+        assert_eq!(
+            allow_origin(&cors(false), &allowed, Some("https://b.example.com")),
+            (Some("https://b.example.com"), true)
+        );
+        assert_eq!(
+            allow_origin(&cors(false), &allowed, Some("https://c.example.com")),
+            (None, true)
+        );
+        assert_eq!(
+            allow_origin(&cors(false), &["https://a.example.com"], None),
+            (Some("https://a.example.com"), false)
+        );
+    }
+
+    #[test]
+    fn wildcard_origin() {
+        let origin = Some("https://a.example.com");
+        assert_eq!(
+            allow_origin(&cors(false), &["*"], origin),
+            (Some("*"), false)
+        );
+        assert_eq!(allow_origin(&cors(true), &["*"], origin), (origin, true));
+    }
+
+    #[test]
+    fn preflight() {
+        let mut cors = cors(true);
+        cors.exposed_headers = vec!["Date".to_string()];
+        assert_eq!(
+            preflight_headers(&cors, &["*"], Some("https://a"), Some("x-token")),
+            "Access-Control-Allow-Origin: https://a\r\n\
+             Access-Control-Allow-Credentials: true\r\n\
+             Access-Control-Expose-Headers: Date\r\n\
+             Vary: Origin\r\n\
+             Access-Control-Allow-Methods: GET\r\n\
+             Access-Control-Allow-Headers: x-token\r\n\
+             Access-Control-Max-Age: 60\r\n"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use openssl::base64;
+use openssl::sha;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Upper limit for cached digests. The cache is emptied when it's full
+const MAX_ENTRIES: usize = 10_000;
+/// Files are hashed in pieces of this size so they don't have to fit in memory
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    digest: String,
+}
+
+/// Base64 encoded SHA-256 digests of served files.
+/// A digest is calculated again when the file's size or modification time changes.
+pub struct DigestCache {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl DigestCache {
+    pub fn new() -> DigestCache {
+        DigestCache {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Digest of the file in `path` of `size` bytes. The content is read
+    /// from `data` only when there is no up to date digest in the cache.
+    pub fn digest(
+        &self,
+        path: &str,
+        modified: SystemTime,
+        size: u64,
+        data: impl Read,
+    ) -> io::Result<String> {
+        if let Some(entry) = self.entries.lock().unwrap().get(path) {
+            if entry.modified == modified && entry.size == size {
+                return Ok(entry.digest.clone());
+            }
+        }
+
+        // Hashing is done without holding the lock, big segments take a while
+        let digest = hash(data)?;
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.insert(
+            path.to_string(),
+            Entry {
+                modified,
+                size,
+                digest: digest.clone(),
+            },
+        );
+        Ok(digest)
+    }
+
+    /// Forget the digests of the paths that `matches`
+    pub fn remove_matching(&self, matches: impl Fn(&str) -> bool) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|path, _| !matches(path));
+    }
+
+    /// Forget the digests so they are calculated again on the next request
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Base64 encoded SHA-256 digest of `data`
+pub fn hash(mut data: impl Read) -> io::Result<String> {
+    let mut hasher = sha::Sha256::new();
+    let mut buffer = vec![0u8; HASH_BUFFER_SIZE];
+    loop {
+        let read = data.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(base64::encode_block(&hasher.finish()))
+}
+
+/// Is `fingerprint` the start of the SHA-256 `digest` as hex digits,
+/// like the hash of a fingerprinted path
+pub fn has_fingerprint(digest: &str, fingerprint: &str) -> bool {
+    let hex: String = match base64::decode_block(digest) {
+        Ok(bytes) => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        Err(_) => return false,
+    };
+    !fingerprint.is_empty() && hex.starts_with(&fingerprint.to_ascii_lowercase())
+}
+
+/// Header lines with the digest in both the RFC 9530 "Repr-Digest" format and
+/// the older RFC 3230 "Digest" format that is still used by most clients
+pub fn digest_headers(digest: &str) -> String {
+    format!(
+        "Repr-Digest: sha-256=:{}:\r\nDigest: SHA-256={}\r\n",
+        digest, digest
+    )
+}
+
+#[cfg(test)]
+mod digest_cache_tests {
+    use super::*;
+    use std::time::Duration;
+
+    // echo -n "hello" | openssl dgst -sha256 -binary | base64
+    const HELLO_DIGEST: &str = "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+
+    #[test]
+    fn digest_is_cached_until_file_changes() {
+        let cache = DigestCache::new();
+        let modified = SystemTime::UNIX_EPOCH;
+        let digest = |modified, data: &[u8]| {
+            cache
+                .digest("a.mpd", modified, data.len() as u64, data)
+                .unwrap()
+        };
+        assert_eq!(digest(modified, b"hello"), HELLO_DIGEST);
+        // Same size and modification time, the cached value is used
+        assert_eq!(digest(modified, b"world"), HELLO_DIGEST);
+
+        let modified = modified + Duration::from_secs(1);
+        assert_ne!(digest(modified, b"world"), HELLO_DIGEST);
+    }
+
+    #[test]
+    fn fingerprints() {
+        // echo -n "hello" | sha256sum
+        assert!(has_fingerprint(HELLO_DIGEST, "2cf24dba"));
+        assert!(has_fingerprint(HELLO_DIGEST, "2CF24DBA5FB0A30E"));
+        assert!(!has_fingerprint(HELLO_DIGEST, "2cf24dbb"));
+        assert!(!has_fingerprint(HELLO_DIGEST, ""));
+        assert!(!has_fingerprint("not base64!", "2cf24dba"));
+    }
+
+    #[test]
+    fn digest_header_format() {
+        assert_eq!(
+            digest_headers(HELLO_DIGEST),
+            format!(
+                "Repr-Digest: sha-256=:{}:\r\nDigest: SHA-256={}\r\n",
+                HELLO_DIGEST, HELLO_DIGEST
+            )
+        );
+    }
+}
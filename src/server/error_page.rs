@@ -0,0 +1,100 @@
+use std::fs;
+
+use log::warn;
+
+use crate::config::Config;
+
+/// Response with the configured error page of the `status`, e.g.
+/// "404 NOT FOUND", or an empty body if there is none. HEAD responses get
+/// the headers of the page without the body.
+pub fn response(config: &Config, status: &str, is_head: bool) -> Vec<u8> {
+    let code = status.split(' ').next().unwrap_or("");
+    let page = config.error_pages.get(code).and_then(|page| {
+        let body = match (&page.file, &page.body) {
+            (Some(file), _) => match fs::read(file) {
+                Ok(body) => body,
+                Err(e) => {
+                    // The status still tells what happened
+                    warn!("Couldn't read the error page {}: {}", file, e);
+                    return None;
+                }
+            },
+            (None, Some(body)) => body.replace("{status}", code).into_bytes(),
+            (None, None) => return None,
+        };
+        Some((&page.content_type, body))
+    });
+
+    let mut out = format!("HTTP/1.1 {}\r\n", status).into_bytes();
+    match page {
+        Some((content_type, body)) => {
+            let head = format!(
+                "Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+                content_type,
+                body.len()
+            );
+            out.extend_from_slice(head.as_bytes());
+            if !is_head {
+                out.extend_from_slice(&body);
+            }
+        }
+        None => out.extend_from_slice(b"Content-Length: 0\r\n\r\n"),
+    }
+    out
+}
+
+#[cfg(test)]
+mod error_page_tests {
+    use super::*;
+    use crate::config::{ConfigFormat, ErrorPage};
+
+    fn config() -> Config {
+        let mut config = Config::parse("{}", ConfigFormat::Json).unwrap();
+        config.error_pages.insert(
+            "404".to_string(),
+            ErrorPage {
+                file: None,
+                body: Some("<h1>{status}</h1>".to_string()),
+                content_type: "text/html; charset=utf-8".to_string(),
+            },
+        );
+        config.error_pages.insert(
+            "500".to_string(),
+            ErrorPage {
+                file: Some("test_data/missing.html".to_string()),
+                body: None,
+                content_type: "text/html; charset=utf-8".to_string(),
+            },
+        );
+        config
+    }
+
+    #[test]
+    fn inline_pages() {
+        let config = config();
+        assert_eq!(
+            String::from_utf8(response(&config, "404 NOT FOUND", false)).unwrap(),
+            "HTTP/1.1 404 NOT FOUND\r\nContent-Type: text/html; charset=utf-8\r\n\
+             Content-Length: 12\r\n\r\n<h1>404</h1>"
+        );
+        assert_eq!(
+            String::from_utf8(response(&config, "404 NOT FOUND", true)).unwrap(),
+            "HTTP/1.1 404 NOT FOUND\r\nContent-Type: text/html; charset=utf-8\r\n\
+             Content-Length: 12\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn empty_bodies_without_a_page() {
+        let config = config();
+        assert_eq!(
+            response(&config, "403 FORBIDDEN", false),
+            b"HTTP/1.1 403 FORBIDDEN\r\nContent-Length: 0\r\n\r\n"
+        );
+        // The page can't be read
+        assert_eq!(
+            response(&config, "500 INTERNAL SERVER ERROR", false),
+            b"HTTP/1.1 500 INTERNAL SERVER ERROR\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+struct Entry {
+    modified: SystemTime,
+    size: u64,
+    data: Arc<Vec<u8>>,
+    added: Instant,
+    /// Value of `Entries::clock` when the entry was last used
+    last_used: u64,
+}
+
+struct Entries {
+    map: HashMap<String, Entry>,
+    /// Sum of the sizes of the cached files
+    bytes: u64,
+    /// Incremented on every use so the least recently used entry has the smallest value
+    clock: u64,
+}
+
+/// Contents of small files that are requested often, like manifests and init
+/// segments. A file is read again when its size or modification time changes
+/// or when it has been cached for longer than `ttl`. The least recently used
+/// files are dropped when the cache would grow over `max_bytes`.
+pub struct FileCache {
+    max_bytes: u64,
+    max_entry_size: u64,
+    ttl: Duration,
+    entries: Mutex<Entries>,
+    /// Requests answered from the cache
+    pub hits: AtomicU64,
+    /// Requests that had to read the file from the disk
+    pub misses: AtomicU64,
+}
+
+impl FileCache {
+    /// Zero `max_bytes` disables the cache and zero `ttl` keeps the entries
+    /// until the file changes
+    pub fn new(max_bytes: u64, max_entry_size: u64, ttl: Duration) -> FileCache {
+        FileCache {
+            max_bytes,
+            max_entry_size: max_entry_size.min(max_bytes),
+            ttl,
+            entries: Mutex::new(Entries {
+                map: HashMap::new(),
+                bytes: 0,
+                clock: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Content of the file in `path` of `size` bytes. It's read from `data`
+    /// and cached when there is no up to date copy in the cache.
+    /// None if the file is too big to be cached or it couldn't be read.
+    pub fn get(
+        &self,
+        path: &str,
+        modified: SystemTime,
+        size: u64,
+        data: impl Read,
+    ) -> Option<Arc<Vec<u8>>> {
+        if self.max_bytes == 0 || size > self.max_entry_size {
+            return None;
+        }
+
+        {
+            let mut entries = self.entries.lock().unwrap();
+            entries.clock += 1;
+            let clock = entries.clock;
+            if let Some(entry) = entries.map.get_mut(path) {
+                let expired = !self.ttl.is_zero() && entry.added.elapsed() >= self.ttl;
+                if entry.modified == modified && entry.size == size && !expired {
+                    entry.last_used = clock;
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(entry.data.clone());
+                }
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // Reading is done without holding the lock so other files can be served meanwhile
+        let mut buffer = Vec::with_capacity(size as usize);
+        if data.take(size).read_to_end(&mut buffer).is_err() || buffer.len() as u64 != size {
+            // The file changed while it was read
+            return None;
+        }
+        let data = Arc::new(buffer);
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(old) = entries.map.remove(path) {
+            entries.bytes -= old.size;
+        }
+        while entries.bytes + size > self.max_bytes {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest.and_then(|oldest| entries.map.remove(&oldest)) {
+                Some(evicted) => entries.bytes -= evicted.size,
+                None => break,
+            }
+        }
+        entries.bytes += size;
+        let last_used = entries.clock;
+        entries.map.insert(
+            path.to_string(),
+            Entry {
+                modified,
+                size,
+                data: data.clone(),
+                added: Instant::now(),
+                last_used,
+            },
+        );
+        Some(data)
+    }
+
+    /// Number of cached files and their total size
+    pub fn usage(&self) -> (usize, u64) {
+        let entries = self.entries.lock().unwrap();
+        (entries.map.len(), entries.bytes)
+    }
+
+    /// Drop the file in `path` so it's read from the disk again
+    pub fn remove(&self, path: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(old) = entries.map.remove(path) {
+            entries.bytes -= old.size;
+        }
+    }
+
+    /// Drop the files whose path `matches` so they are read from the disk again
+    pub fn remove_matching(&self, matches: impl Fn(&str) -> bool) {
+        let mut entries = self.entries.lock().unwrap();
+        let mut removed = 0;
+        entries.map.retain(|path, entry| {
+            let keep = !matches(path);
+            if !keep {
+                removed += entry.size;
+            }
+            keep
+        });
+        entries.bytes -= removed;
+    }
+
+    /// Drop every cached file so they are read from the disk again
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.clear();
+        entries.bytes = 0;
+    }
+}
+
+#[cfg(test)]
+mod file_cache_tests {
+    use super::*;
+    use std::thread;
+
+    fn get(cache: &FileCache, path: &str, modified: SystemTime, data: &[u8]) -> Option<Vec<u8>> {
+        cache
+            .get(path, modified, data.len() as u64, data)
+            .map(|data| data.to_vec())
+    }
+
+    #[test]
+    fn cached_until_file_changes() {
+        let cache = FileCache::new(100, 10, Duration::ZERO);
+        let modified = SystemTime::UNIX_EPOCH;
+        assert_eq!(
+            get(&cache, "a.mpd", modified, b"hello"),
+            Some(b"hello".to_vec())
+        );
+        // Same size and modification time, the cached value is used
+        assert_eq!(
+            get(&cache, "a.mpd", modified, b"world"),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(cache.hits.load(Ordering::Relaxed), 1);
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 1);
+
+        let modified = modified + Duration::from_secs(1);
+        assert_eq!(
+            get(&cache, "a.mpd", modified, b"world"),
+            Some(b"world".to_vec())
+        );
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 2);
+
+        cache.remove("a.mpd");
+        assert_eq!(cache.usage(), (0, 0));
+    }
+
+    #[test]
+    fn removed_by_prefix() {
+        let cache = FileCache::new(100, 10, Duration::ZERO);
+        let modified = SystemTime::UNIX_EPOCH;
+        get(&cache, "live/a.mpd", modified, b"aaaa");
+        get(&cache, "live/b.mpd", modified, b"bb");
+        get(&cache, "vod/a.mpd", modified, b"ccc");
+        cache.remove_matching(|path| path.starts_with("live/"));
+        assert_eq!(cache.usage(), (1, 3));
+    }
+
+    #[test]
+    fn entries_expire() {
+        let cache = FileCache::new(100, 10, Duration::from_millis(50));
+        let modified = SystemTime::UNIX_EPOCH;
+        get(&cache, "a.mpd", modified, b"hello");
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            get(&cache, "a.mpd", modified, b"world"),
+            Some(b"world".to_vec())
+        );
+    }
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let cache = FileCache::new(10, 10, Duration::ZERO);
+        let modified = SystemTime::UNIX_EPOCH;
+        get(&cache, "a", modified, b"aaaa");
+        get(&cache, "b", modified, b"bbbb");
+        get(&cache, "a", modified, b"aaaa");
+        // "b" is dropped to make room, "a" was used after it
+        get(&cache, "c", modified, b"cccc");
+        assert_eq!(get(&cache, "a", modified, b"xxxx"), Some(b"aaaa".to_vec()));
+        assert_eq!(get(&cache, "b", modified, b"xxxx"), Some(b"xxxx".to_vec()));
+    }
+
+    #[test]
+    fn clear() {
+        let cache = FileCache::new(100, 10, Duration::ZERO);
+        let modified = SystemTime::UNIX_EPOCH;
+        get(&cache, "a", modified, b"aaaa");
+        get(&cache, "b", modified, b"bb");
+        assert_eq!(cache.usage(), (2, 6));
+        cache.clear();
+        assert_eq!(cache.usage(), (0, 0));
+        assert_eq!(get(&cache, "a", modified, b"xxxx"), Some(b"xxxx".to_vec()));
+    }
+
+    #[test]
+    fn big_files_are_not_cached() {
+        let cache = FileCache::new(100, 4, Duration::ZERO);
+        assert_eq!(get(&cache, "a", SystemTime::UNIX_EPOCH, b"hello"), None);
+        assert_eq!(cache.misses.load(Ordering::Relaxed), 0);
+
+        let disabled = FileCache::new(0, 4, Duration::ZERO);
+        assert_eq!(get(&disabled, "a", SystemTime::UNIX_EPOCH, b"a"), None);
+    }
+
+    #[test]
+    fn short_read_is_not_cached() {
+        let cache = FileCache::new(100, 10, Duration::ZERO);
+        assert_eq!(
+            cache.get("a", SystemTime::UNIX_EPOCH, 8, &b"hello"[..]),
+            None
+        );
+    }
+}
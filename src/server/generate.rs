@@ -0,0 +1,918 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+
+use super::mp4::{self, Track};
+use super::webvtt;
+use crate::config::GeneratedManifests;
+use crate::mpd::{self, AdaptationSet, Descriptor, Mpd, MpdType, Period, Representation};
+use crate::mpd::{SegmentTemplate, SegmentTimeline, TimelineSegment};
+
+/// Init segment of a representation directory
+const INIT_SEGMENT: &str = "init.mp4";
+/// Media segments are "seg-<number>.m4s"
+const SEGMENT_PREFIX: &str = "seg-";
+pub const SEGMENT_EXTENSION: &str = ".m4s";
+
+const AUDIO_CHANNEL_SCHEME: &str = "urn:mpeg:dash:23003:3:audio_channel_configuration:2011";
+const ROLE_SCHEME: &str = "urn:mpeg:dash:role:2011";
+
+/// Subtitle files of the manifest directory by extension and their MIME types
+const SUBTITLE_EXTENSIONS: [(&str, &str); 2] =
+    [(".vtt", "text/vtt"), (".ttml", "application/ttml+xml")];
+
+/// How a manifest is made, from `config::GeneratedManifests`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    pub min_buffer_time: f64,
+    /// None for on demand manifests
+    pub live: Option<Live>,
+    /// Seconds of the WebVTT segments, 0 if the files aren't split
+    pub subtitle_segment_duration: f64,
+}
+
+/// Settings of a dynamic manifest
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Live {
+    pub minimum_update_period: f64,
+    pub time_shift_buffer_depth: f64,
+}
+
+impl Settings {
+    /// Settings of the manifest at the url `path`
+    pub fn new(config: &GeneratedManifests, path: &str) -> Settings {
+        let is_live = config
+            .live_prefixes
+            .iter()
+            .any(|prefix| path.starts_with(&prefix[..]));
+        Settings {
+            min_buffer_time: config.min_buffer_time,
+            live: Some(Live {
+                minimum_update_period: config.minimum_update_period,
+                time_shift_buffer_depth: config.time_shift_buffer_depth,
+            })
+            .filter(|_| is_live),
+            subtitle_segment_duration: config.subtitle_segment_duration,
+        }
+    }
+}
+
+/// A generated manifest and the time of the newest change it's made from
+pub struct Generated {
+    pub data: Arc<Vec<u8>>,
+    pub modified: SystemTime,
+}
+
+/// Manifest of a directory. Generated again when the directory or one of
+/// the representation directories changes.
+struct CachedManifest {
+    /// Modification times of the directory and its subdirectories
+    modified: Vec<SystemTime>,
+    settings: Settings,
+    manifest: Arc<Generated>,
+}
+
+/// Makes manifests from the representation directories in a directory,
+/// see `config::GeneratedManifests`. A segment that is replaced without
+/// changing its directory isn't noticed unless the content is watched. Manifests with a segment that
+/// couldn't be read, like one that is still being written, aren't cached.
+pub struct Generator {
+    dirs: Mutex<HashMap<PathBuf, CachedManifest>>,
+}
+
+/// What a representation directory has
+struct ScannedRepresentation {
+    id: String,
+    track: Track,
+    start_number: u64,
+    /// Decode time of the first segment
+    start_time: u64,
+    /// In the units of the track timescale
+    durations: Vec<u64>,
+    /// Peak bits per second of the segments
+    bandwidth: u64,
+    /// When the init segment was written, the time 0 of a live stream
+    init_modified: SystemTime,
+    /// When the last listed segment was written
+    last_modified: SystemTime,
+    /// False if the segments stopped at one that couldn't be read
+    complete: bool,
+}
+
+/// A subtitle file next to the manifest
+struct ScannedSubtitle {
+    /// The file name without the extension
+    id: String,
+    file_name: String,
+    mime_type: &'static str,
+    /// From a "<name>.<language>.vtt" file name
+    language: Option<String>,
+    length: u64,
+    /// Seconds to the end of the last cue of a WebVTT file that is split
+    /// into segments. None if the file isn't split.
+    cue_end: Option<f64>,
+}
+
+/// Subdirectories of `dir` by name
+fn subdirectories(dir: &Path) -> Vec<(String, PathBuf)> {
+    let mut dirs: Vec<(String, PathBuf)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter(|entry| entry.file_type().map(|t| t.is_dir()).unwrap_or(false))
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                )
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    dirs.sort();
+    dirs
+}
+
+/// Numbers of the media segments in `dir`, sorted
+fn segment_numbers(dir: &Path) -> Vec<u64> {
+    let mut numbers: Vec<u64> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                name.strip_prefix(SEGMENT_PREFIX)?
+                    .strip_suffix(SEGMENT_EXTENSION)?
+                    .parse()
+                    .ok()
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    numbers.sort_unstable();
+    numbers
+}
+
+/// Is `tag` like a RFC 5646 language tag, e.g. "en" or "pt-BR"
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or("");
+    (2..=3).contains(&primary.len())
+        && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.bytes().all(|b| b.is_ascii_alphanumeric())
+        })
+}
+
+/// Read the subtitle file `name` in `path`. None if it isn't one.
+/// WebVTT files are split into `segment_duration` long segments unless it's 0.
+fn scan_subtitle(name: &str, path: &Path, segment_duration: f64) -> Option<ScannedSubtitle> {
+    let (id, mime_type) = SUBTITLE_EXTENSIONS
+        .iter()
+        .find_map(|(extension, mime_type)| Some((name.strip_suffix(extension)?, *mime_type)))?;
+    // The file name is used in the urls of the manifest as it is
+    let is_url_safe = !name.contains(|c: char| c.is_whitespace() || "$?#%".contains(c));
+    if id.is_empty() || !is_url_safe {
+        return None;
+    }
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let language = id
+        .rsplit_once('.')
+        .map(|(_, tag)| tag)
+        .filter(|tag| is_language_tag(tag))
+        .map(str::to_string);
+    let cue_end = if segment_duration > 0.0 && mime_type == "text/vtt" {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|data| webvtt::duration(&data))
+    } else {
+        None
+    };
+    Some(ScannedSubtitle {
+        id: id.to_string(),
+        file_name: name.to_string(),
+        mime_type,
+        language,
+        length: metadata.len(),
+        cue_end,
+    })
+}
+
+/// The subtitle files in `dir` by name
+fn scan_subtitles(dir: &Path, segment_duration: f64) -> Vec<ScannedSubtitle> {
+    let mut subtitles: Vec<ScannedSubtitle> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                scan_subtitle(&name, &entry.path(), segment_duration)
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    subtitles.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    subtitles
+}
+
+/// Read the init segment and the media segments of a representation.
+/// None if the directory isn't one.
+fn scan_representation(id: &str, dir: &Path) -> Option<ScannedRepresentation> {
+    let init_path = dir.join(INIT_SEGMENT);
+    let init = fs::read(&init_path).ok()?;
+    let init_modified = fs::metadata(&init_path).and_then(|m| m.modified()).ok()?;
+    let track = match mp4::parse_init(&init) {
+        Some(track) if track.timescale > 0 => track,
+        _ => {
+            warn!(
+                "Ignoring {:?}, {} isn't a MP4 init segment",
+                dir, INIT_SEGMENT
+            );
+            return None;
+        }
+    };
+
+    let numbers = segment_numbers(dir);
+    let start_number = *numbers.first()?;
+    let mut start_time = 0;
+    let mut durations = vec![];
+    let mut bandwidth = 0;
+    let mut last_modified = init_modified;
+    let mut complete = true;
+    for (i, number) in numbers.iter().enumerate() {
+        // The segment numbers of a template can't have gaps
+        if *number != start_number + i as u64 {
+            warn!(
+                "Segment {} is missing from {:?}",
+                start_number + i as u64,
+                dir
+            );
+            break;
+        }
+        let path = dir.join(format!("{}{}{}", SEGMENT_PREFIX, number, SEGMENT_EXTENSION));
+        let read = File::open(&path).and_then(|mut file| {
+            let metadata = file.metadata()?;
+            Ok((metadata, mp4::read_fragments(&mut file)?))
+        });
+        let (metadata, fragments) = match read {
+            Ok(read) => read,
+            Err(e) => {
+                warn!("Cannot read {:?}: {}", path, e);
+                complete = false;
+                break;
+            }
+        };
+        let duration = match mp4::segment_duration(&fragments, &track) {
+            Some(duration) if duration > 0 => duration,
+            _ => {
+                warn!("{:?} isn't a MP4 media segment", path);
+                complete = false;
+                break;
+            }
+        };
+        if i == 0 {
+            start_time = mp4::decode_time(&fragments).unwrap_or(0);
+        }
+        if let Ok(modified) = metadata.modified() {
+            last_modified = last_modified.max(modified);
+        }
+        let bits_per_second = metadata.len() * 8 * track.timescale as u64 / duration;
+        bandwidth = bandwidth.max(bits_per_second);
+        durations.push(duration);
+    }
+    if durations.is_empty() {
+        return None;
+    }
+
+    Some(ScannedRepresentation {
+        id: id.to_string(),
+        track,
+        start_number,
+        start_time,
+        durations,
+        bandwidth,
+        init_modified,
+        last_modified,
+        complete,
+    })
+}
+
+/// Leave out the segments that ended more than `depth` seconds before the
+/// end of the last one
+fn trim_to_depth(representation: &mut ScannedRepresentation, depth: f64) {
+    let timescale = representation.track.timescale as f64;
+    let mut behind: u64 = representation.durations.iter().sum();
+    let mut trimmed = 0;
+    for duration in &representation.durations {
+        behind -= duration;
+        if behind as f64 / timescale <= depth {
+            break;
+        }
+        trimmed += 1;
+    }
+    let skipped: u64 = representation.durations.drain(..trimmed).sum();
+    representation.start_number += trimmed as u64;
+    representation.start_time += skipped;
+}
+
+/// Timeline of the segment durations, repeating the equal ones
+fn timeline(start_time: u64, durations: &[u64]) -> SegmentTimeline {
+    let mut segments: Vec<TimelineSegment> = vec![];
+    for duration in durations {
+        match segments.last_mut() {
+            Some(last) if last.duration == *duration => {
+                last.repeat = Some(last.repeat.unwrap_or(0) + 1);
+            }
+            _ => segments.push(TimelineSegment {
+                time: if segments.is_empty() {
+                    Some(start_time)
+                } else {
+                    None
+                },
+                duration: *duration,
+                repeat: None,
+            }),
+        }
+    }
+    SegmentTimeline { segments }
+}
+
+/// Timescale, numbering and timeline of a representation's segments. On
+/// demand presentations start from the first segment, live ones from the
+/// decode time 0.
+fn segment_timing(representation: &ScannedRepresentation, live: bool) -> SegmentTemplate {
+    SegmentTemplate {
+        timescale: Some(representation.track.timescale as u64),
+        start_number: Some(representation.start_number),
+        presentation_time_offset: Some(representation.start_time).filter(|time| *time > 0 && !live),
+        segment_timeline: Some(timeline(
+            representation.start_time,
+            &representation.durations,
+        )),
+        ..SegmentTemplate::default()
+    }
+}
+
+/// DASH content type and MIME type of a MP4 handler
+fn content_type(handler: &str) -> Option<(&'static str, &'static str)> {
+    match handler {
+        "vide" => Some(("video", "video/mp4")),
+        "soun" => Some(("audio", "audio/mp4")),
+        "text" | "subt" | "sbtl" => Some(("text", "application/mp4")),
+        _ => None,
+    }
+}
+
+fn representation(scanned: &ScannedRepresentation) -> Representation {
+    let track = &scanned.track;
+    Representation {
+        id: scanned.id.clone(),
+        bandwidth: scanned.bandwidth,
+        codecs: Some(track.codecs.clone()),
+        width: track.width,
+        height: track.height,
+        audio_sampling_rate: track.sample_rate.map(|rate| rate.to_string()),
+        audio_channel_configurations: track
+            .channels
+            .map(|channels| Descriptor {
+                scheme_id_uri: AUDIO_CHANNEL_SCHEME.to_string(),
+                value: Some(channels.to_string()),
+            })
+            .into_iter()
+            .collect(),
+        ..Representation::default()
+    }
+}
+
+/// Adaptation set of representations with the same content type
+fn adaptation_set(id: u32, scanned: &[&ScannedRepresentation], live: bool) -> AdaptationSet {
+    let track = &scanned[0].track;
+    let (content, mime_type) = content_type(&track.handler).unwrap();
+    // Aligned segments can share the timeline
+    let timing = segment_timing(scanned[0], live);
+    let aligned = scanned.iter().all(|r| segment_timing(r, live) == timing);
+    let mut template = if aligned {
+        timing
+    } else {
+        SegmentTemplate::default()
+    };
+    template.media = Some(format!(
+        "$RepresentationID$/{}$Number${}",
+        SEGMENT_PREFIX, SEGMENT_EXTENSION
+    ));
+    template.initialization = Some(format!("$RepresentationID$/{}", INIT_SEGMENT));
+
+    let mut representations: Vec<Representation> = vec![];
+    for scanned in scanned {
+        let mut representation = representation(scanned);
+        if !aligned {
+            representation.segment_template = Some(segment_timing(scanned, live));
+        }
+        representations.push(representation);
+    }
+
+    AdaptationSet {
+        id: Some(id),
+        content_type: Some(content.to_string()),
+        mime_type: Some(mime_type.to_string()),
+        lang: track.language.clone(),
+        segment_alignment: Some(aligned),
+        start_with_sap: Some(1),
+        max_width: scanned.iter().filter_map(|r| r.track.width).max(),
+        max_height: scanned.iter().filter_map(|r| r.track.height).max(),
+        segment_template: Some(template),
+        representations,
+        ..AdaptationSet::default()
+    }
+}
+
+/// Adaptation set of a subtitle file. Split WebVTT files have a segment per
+/// `segment_duration` seconds up to the last cue, the rest are downloaded
+/// whole. `duration` is the length of the presentation in seconds.
+fn subtitle_set(
+    id: u32,
+    subtitle: &ScannedSubtitle,
+    duration: f64,
+    segment_duration: f64,
+) -> AdaptationSet {
+    let mut representation = Representation {
+        id: subtitle.id.clone(),
+        // Spread over the presentation
+        bandwidth: (subtitle.length as f64 * 8.0 / duration.max(1.0))
+            .ceil()
+            .max(1.0) as u64,
+        ..Representation::default()
+    };
+    match subtitle.cue_end {
+        Some(cue_end) => {
+            let count = webvtt::segment_count(cue_end, segment_duration) as usize;
+            let milliseconds = ((segment_duration * 1000.0).round() as u64).max(1);
+            representation.segment_template = Some(SegmentTemplate {
+                media: Some(format!(
+                    "{}?{}=$Number$",
+                    subtitle.file_name,
+                    webvtt::SEGMENT_PARAM
+                )),
+                timescale: Some(1000),
+                start_number: Some(1),
+                segment_timeline: Some(timeline(0, &vec![milliseconds; count])),
+                ..SegmentTemplate::default()
+            });
+        }
+        None => representation.base_urls.push(subtitle.file_name.clone()),
+    }
+
+    AdaptationSet {
+        id: Some(id),
+        content_type: Some("text".to_string()),
+        mime_type: Some(subtitle.mime_type.to_string()),
+        lang: subtitle.language.clone(),
+        roles: vec![Descriptor {
+            scheme_id_uri: ROLE_SCHEME.to_string(),
+            value: Some("subtitle".to_string()),
+        }],
+        representations: vec![representation],
+        ..AdaptationSet::default()
+    }
+}
+
+/// The representations in the subdirectories of `dir`
+fn scan(dir: &Path) -> Vec<ScannedRepresentation> {
+    subdirectories(dir)
+        .iter()
+        // The directory name is the representation id in the segment urls
+        .filter(|(id, _)| !id.contains(|c: char| c.is_whitespace() || c == '$'))
+        .filter_map(|(id, path)| scan_representation(id, path))
+        .filter(|r| content_type(&r.track.handler).is_some())
+        .collect()
+}
+
+/// Manifest of the scanned representations and the subtitles of on demand
+/// presentations. None if there aren't any representations.
+fn build_manifest(
+    mut scanned: Vec<ScannedRepresentation>,
+    subtitles: &[ScannedSubtitle],
+    settings: &Settings,
+) -> Option<Mpd> {
+    if scanned.is_empty() {
+        return None;
+    }
+    if let Some(live) = &settings.live {
+        for representation in &mut scanned {
+            trim_to_depth(representation, live.time_shift_buffer_depth);
+        }
+    }
+    scanned.sort_by_key(|r| r.bandwidth);
+
+    // Sets by content type, codec and language, video first
+    let mut groups: Vec<Vec<&ScannedRepresentation>> = vec![];
+    for representation in &scanned {
+        let key = |r: &ScannedRepresentation| {
+            let codec = r.track.codecs.split('.').next().unwrap_or("").to_string();
+            (r.track.handler.clone(), codec, r.track.language.clone())
+        };
+        match groups.iter_mut().find(|g| key(g[0]) == key(representation)) {
+            Some(group) => group.push(representation),
+            None => groups.push(vec![representation]),
+        }
+    }
+    let order = ["vide", "soun"];
+    groups.sort_by_key(|g| {
+        order
+            .iter()
+            .position(|h| *h == g[0].track.handler)
+            .unwrap_or(order.len())
+    });
+
+    let duration = scanned
+        .iter()
+        .map(|r| r.durations.iter().sum::<u64>() as f64 / r.track.timescale as f64)
+        .fold(0.0, f64::max);
+    let max_segment_duration = scanned
+        .iter()
+        .flat_map(|r| {
+            r.durations
+                .iter()
+                .map(move |d| *d as f64 / r.track.timescale as f64)
+        })
+        .fold(0.0, f64::max);
+
+    let mut adaptation_sets: Vec<AdaptationSet> = groups
+        .iter()
+        .enumerate()
+        .map(|(id, group)| adaptation_set(id as u32, group, settings.live.is_some()))
+        .collect();
+    // Subtitles aren't listed in live manifests since their cues don't follow
+    // the live edge. Representation ids are unique in the period
+    if settings.live.is_none() {
+        let subtitles = subtitles
+            .iter()
+            .filter(|subtitle| scanned.iter().all(|r| r.id != subtitle.id));
+        for subtitle in subtitles {
+            let id = adaptation_sets.len() as u32;
+            let segment_duration = settings.subtitle_segment_duration;
+            adaptation_sets.push(subtitle_set(id, subtitle, duration, segment_duration));
+        }
+    }
+
+    let seconds = |seconds: f64| Some(mpd::format_duration(Duration::from_secs_f64(seconds)));
+    let mut mpd = Mpd {
+        xmlns: mpd::NAMESPACE.to_string(),
+        id: None,
+        mpd_type: MpdType::Static,
+        profiles: mpd::PROFILE_LIVE.to_string(),
+        media_presentation_duration: seconds(duration),
+        min_buffer_time: seconds(settings.min_buffer_time),
+        availability_start_time: None,
+        publish_time: None,
+        minimum_update_period: None,
+        time_shift_buffer_depth: None,
+        suggested_presentation_delay: None,
+        max_segment_duration: seconds(max_segment_duration),
+        base_urls: vec![],
+        periods: vec![Period {
+            id: Some("0".to_string()),
+            start: Some("PT0S".to_string()),
+            adaptation_sets,
+            ..Period::default()
+        }],
+    };
+
+    // The segments of a live stream become available as they are written
+    if let Some(live) = &settings.live {
+        let started = scanned.iter().map(|r| r.init_modified).min().unwrap();
+        let published = scanned.iter().map(|r| r.last_modified).max().unwrap();
+        mpd.mpd_type = MpdType::Dynamic;
+        mpd.media_presentation_duration = None;
+        mpd.availability_start_time = Some(mpd::format_date_time(started));
+        mpd.publish_time = Some(mpd::format_date_time(published));
+        mpd.minimum_update_period = seconds(live.minimum_update_period);
+        mpd.time_shift_buffer_depth = seconds(live.time_shift_buffer_depth);
+    }
+    Some(mpd)
+}
+
+/// Modification times of `dir`, its subdirectories and its subtitle files
+fn modification_times(dir: &Path) -> Option<Vec<SystemTime>> {
+    let mut times = vec![fs::metadata(dir).and_then(|m| m.modified()).ok()?];
+    for (_, path) in subdirectories(dir) {
+        times.push(fs::metadata(path).and_then(|m| m.modified()).ok()?);
+    }
+    let mut subtitles: Vec<(String, SystemTime)> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_subtitle = SUBTITLE_EXTENSIONS
+                .iter()
+                .any(|(extension, _)| name.ends_with(extension));
+            let modified = entry.metadata().and_then(|m| m.modified());
+            Some((name, modified.ok()?)).filter(|_| is_subtitle)
+        })
+        .collect();
+    subtitles.sort();
+    times.extend(subtitles.into_iter().map(|(_, modified)| modified));
+    Some(times)
+}
+
+impl Generator {
+    pub fn new() -> Generator {
+        Generator {
+            dirs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The manifest of `dir`, from the cache if nothing has changed. None if
+    /// the directory doesn't have any representations.
+    pub fn manifest(&self, dir: &Path, settings: &Settings) -> Option<Arc<Generated>> {
+        let modified = modification_times(dir)?;
+        if let Some(cached) = self.dirs.lock().unwrap().get(dir) {
+            if cached.modified == modified && cached.settings == *settings {
+                return Some(cached.manifest.clone());
+            }
+        }
+
+        let scanned = scan(dir);
+        let complete = scanned.iter().all(|r| r.complete);
+        let subtitles = scan_subtitles(dir, settings.subtitle_segment_duration);
+        let mpd = build_manifest(scanned, &subtitles, settings)?;
+        if let Err(errors) = mpd.validate() {
+            warn!(
+                "Generated manifest of {:?} is invalid: {}",
+                dir,
+                errors.join(", ")
+            );
+        }
+        let manifest = Arc::new(Generated {
+            data: Arc::new(mpd.to_xml().into_bytes()),
+            modified: *modified.iter().max().unwrap(),
+        });
+        if complete {
+            self.dirs.lock().unwrap().insert(
+                dir.to_path_buf(),
+                CachedManifest {
+                    modified,
+                    settings: *settings,
+                    manifest: manifest.clone(),
+                },
+            );
+        }
+        Some(manifest)
+    }
+
+    /// Generate the manifests that `path` is part of again on the next request
+    pub fn invalidate(&self, path: &Path) {
+        self.dirs
+            .lock()
+            .unwrap()
+            .retain(|dir, _| !path.starts_with(dir));
+    }
+
+    pub fn clear(&self) {
+        self.dirs.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod generate_tests {
+    use super::*;
+
+    const VOD: &str = "test_data/generated/vod";
+
+    fn generate(dir: &Path, settings: &Settings) -> Option<Mpd> {
+        let subtitles = scan_subtitles(dir, settings.subtitle_segment_duration);
+        build_manifest(scan(dir), &subtitles, settings)
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            min_buffer_time: 2.0,
+            live: None,
+            subtitle_segment_duration: 0.0,
+        }
+    }
+
+    #[test]
+    fn manifest_from_segments() {
+        let mpd = generate(Path::new(VOD), &settings()).unwrap();
+        assert_eq!(mpd.validate(), Ok(()));
+        assert_eq!(mpd.media_presentation_duration.as_deref(), Some("PT5.013S"));
+        assert_eq!(mpd.min_buffer_time.as_deref(), Some("PT2S"));
+        assert_eq!(mpd.max_segment_duration.as_deref(), Some("PT2.005S"));
+
+        let sets = &mpd.periods[0].adaptation_sets;
+        // The subtitle files follow the video and audio
+        assert_eq!(sets.len(), 4);
+        let video = &sets[0];
+        assert_eq!(video.content_type.as_deref(), Some("video"));
+        assert_eq!(video.segment_alignment, Some(true));
+        assert_eq!((video.max_width, video.max_height), (Some(1280), Some(720)));
+        let ids: Vec<&str> = video.representations.iter().map(|r| &r.id[..]).collect();
+        assert_eq!(ids, ["video-360", "video-720"]);
+        // The first segment of 1404 bytes is the biggest per second
+        assert_eq!(video.representations[0].bandwidth, 5616);
+        assert_eq!(
+            video.representations[1].codecs.as_deref(),
+            Some("avc1.64001F")
+        );
+        let template = video.segment_template.as_ref().unwrap();
+        assert_eq!(
+            template.media.as_deref(),
+            Some("$RepresentationID$/seg-$Number$.m4s")
+        );
+        assert_eq!(template.timescale, Some(90000));
+        assert_eq!(template.start_number, Some(1));
+        assert_eq!(
+            template.segment_timeline,
+            Some(SegmentTimeline {
+                segments: vec![
+                    TimelineSegment {
+                        time: Some(0),
+                        duration: 180000,
+                        repeat: Some(1),
+                    },
+                    TimelineSegment {
+                        time: None,
+                        duration: 90000,
+                        repeat: None,
+                    },
+                ],
+            })
+        );
+
+        let audio = &sets[1];
+        assert_eq!(audio.lang.as_deref(), Some("eng"));
+        let representation = &audio.representations[0];
+        assert_eq!(representation.codecs.as_deref(), Some("mp4a.40.2"));
+        assert_eq!(representation.audio_sampling_rate.as_deref(), Some("48000"));
+        assert_eq!(
+            representation.audio_channel_configurations[0]
+                .value
+                .as_deref(),
+            Some("2")
+        );
+    }
+
+    #[test]
+    fn subtitle_sets() {
+        let mpd = generate(Path::new(VOD), &settings()).unwrap();
+        let sets = &mpd.periods[0].adaptation_sets;
+        let subtitles = &sets[2];
+        assert_eq!(subtitles.id, Some(2));
+        assert_eq!(subtitles.content_type.as_deref(), Some("text"));
+        assert_eq!(subtitles.mime_type.as_deref(), Some("text/vtt"));
+        assert_eq!(subtitles.lang.as_deref(), Some("en"));
+        assert_eq!(subtitles.roles[0].value.as_deref(), Some("subtitle"));
+        let representation = &subtitles.representations[0];
+        assert_eq!(representation.id, "subtitles.en");
+        assert_eq!(representation.base_urls, ["subtitles.en.vtt"]);
+        assert_eq!(representation.segment_template, None);
+        assert_eq!(sets[3].mime_type.as_deref(), Some("application/ttml+xml"));
+        assert_eq!(sets[3].lang.as_deref(), Some("fi"));
+
+        // The last cue ends at 5 seconds
+        let mut split = settings();
+        split.subtitle_segment_duration = 2.0;
+        let mpd = generate(Path::new(VOD), &split).unwrap();
+        assert_eq!(mpd.validate(), Ok(()));
+        let sets = &mpd.periods[0].adaptation_sets;
+        let representation = &sets[2].representations[0];
+        assert!(representation.base_urls.is_empty());
+        let template = representation.segment_template.as_ref().unwrap();
+        assert_eq!(
+            template.media.as_deref(),
+            Some("subtitles.en.vtt?segment=$Number$")
+        );
+        assert_eq!(template.timescale, Some(1000));
+        assert_eq!(
+            template.segment_timeline.as_ref().unwrap().segments,
+            vec![TimelineSegment {
+                time: Some(0),
+                duration: 2000,
+                repeat: Some(2),
+            }]
+        );
+        // TTML files aren't split
+        assert_eq!(sets[3].representations[0].base_urls, ["subtitles.fi.ttml"]);
+
+        assert!(is_language_tag("pt-BR"));
+        assert!(!is_language_tag("forced"));
+        assert!(scan_subtitle("movie.vtt", Path::new(VOD), 0.0).is_none());
+        assert!(scan_subtitle("a b.vtt", Path::new(VOD), 0.0).is_none());
+        assert!(scan_subtitle("subtitles.en.srt", Path::new(VOD), 0.0).is_none());
+    }
+
+    #[test]
+    fn live_settings() {
+        let config = GeneratedManifests {
+            file_name: "manifest.mpd".to_string(),
+            min_buffer_time: 2.0,
+            live_prefixes: vec!["/live/".to_string()],
+            minimum_update_period: 1.0,
+            time_shift_buffer_depth: 30.0,
+            subtitle_segment_duration: 0.0,
+        };
+        let live = Settings::new(&config, "/live/channel/manifest.mpd").live;
+        assert_eq!(
+            live,
+            Some(Live {
+                minimum_update_period: 1.0,
+                time_shift_buffer_depth: 30.0,
+            })
+        );
+        assert_eq!(Settings::new(&config, "/vod/manifest.mpd").live, None);
+    }
+
+    #[test]
+    fn live_manifest() {
+        let settings = Settings {
+            min_buffer_time: 2.0,
+            live: Some(Live {
+                minimum_update_period: 1.0,
+                time_shift_buffer_depth: 2.5,
+            }),
+            subtitle_segment_duration: 0.0,
+        };
+        let mpd = generate(Path::new(VOD), &settings).unwrap();
+        assert_eq!(mpd.validate(), Ok(()));
+        assert_eq!(mpd.mpd_type, MpdType::Dynamic);
+        assert_eq!(mpd.media_presentation_duration, None);
+        assert_eq!(mpd.minimum_update_period.as_deref(), Some("PT1S"));
+        assert_eq!(mpd.time_shift_buffer_depth.as_deref(), Some("PT2.5S"));
+        // The stream started when the first init segment was written
+        let started = subdirectories(Path::new(VOD))
+            .iter()
+            .map(|(_, dir)| {
+                fs::metadata(dir.join(INIT_SEGMENT))
+                    .unwrap()
+                    .modified()
+                    .unwrap()
+            })
+            .min()
+            .unwrap();
+        assert_eq!(
+            mpd.availability_start_time,
+            Some(mpd::format_date_time(started))
+        );
+        assert!(mpd.publish_time >= mpd.availability_start_time);
+
+        // Only the segments within the time shift buffer are listed
+        for set in &mpd.periods[0].adaptation_sets {
+            let template = set.segment_template.as_ref().unwrap();
+            assert_eq!(template.start_number, Some(2));
+            assert_eq!(template.presentation_time_offset, None);
+        }
+        let video = mpd.periods[0].adaptation_sets[0]
+            .segment_template
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            video.segment_timeline.as_ref().unwrap().segments,
+            vec![
+                TimelineSegment {
+                    time: Some(180000),
+                    duration: 180000,
+                    repeat: None,
+                },
+                TimelineSegment {
+                    time: None,
+                    duration: 90000,
+                    repeat: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn cached_manifest() {
+        let generator = Generator::new();
+        let first = generator.manifest(Path::new(VOD), &settings()).unwrap();
+        let second = generator.manifest(Path::new(VOD), &settings()).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        let xml = String::from_utf8_lossy(&first.data);
+        assert!(mpd::parse(&xml).is_ok());
+
+        let mut longer = settings();
+        longer.min_buffer_time = 4.0;
+        let third = generator.manifest(Path::new(VOD), &longer).unwrap();
+        assert!(String::from_utf8_lossy(&third.data).contains("minBufferTime=\"PT4S\""));
+
+        // A segment of another directory doesn't matter
+        generator.invalidate(Path::new("test_data/generated/other/seg-1.m4s"));
+        let fourth = generator.manifest(Path::new(VOD), &longer).unwrap();
+        assert!(Arc::ptr_eq(&third, &fourth));
+        generator.invalidate(&Path::new(VOD).join("video-360/seg-1.m4s"));
+        let fifth = generator.manifest(Path::new(VOD), &longer).unwrap();
+        assert!(!Arc::ptr_eq(&fourth, &fifth));
+    }
+
+    #[test]
+    fn directories_without_segments() {
+        assert!(generate(Path::new("test_data/sidecar"), &settings()).is_none());
+        assert!(generate(Path::new("test_data/missing"), &settings()).is_none());
+        assert!(Generator::new()
+            .manifest(Path::new("test_data/missing"), &settings())
+            .is_none());
+    }
+}
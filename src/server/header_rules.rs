@@ -0,0 +1,111 @@
+use crate::config::HeaderRule;
+
+/// Does `text` match the glob `pattern`. "*" doesn't match "/", "**" does
+fn glob_matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_matches(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let segment = text.iter().position(|&b| b == b'/').unwrap_or(text.len());
+            (0..=segment).any(|i| glob_matches(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => !text.is_empty() && text[0] != b'/' && glob_matches(rest, &text[1..]),
+        [first, rest @ ..] => text.first() == Some(first) && glob_matches(rest, &text[1..]),
+    }
+}
+
+/// Does the rule's pattern match the url `path`. Patterns without a "/" are
+/// matched against the file name only.
+fn rule_matches(pattern: &str, path: &str) -> bool {
+    let text = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    glob_matches(pattern.as_bytes(), text.as_bytes())
+}
+
+/// Headers of the rules matching `path`. Later rules replace the headers
+/// with the same name.
+pub fn headers_for<'a>(rules: &'a [HeaderRule], path: &str) -> Vec<(&'a str, &'a str)> {
+    let mut headers: Vec<(&str, &str)> = vec![];
+    for rule in rules
+        .iter()
+        .filter(|rule| rule_matches(&rule.pattern, path))
+    {
+        for (name, value) in &rule.headers {
+            headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+            headers.push((name, value));
+        }
+    }
+    headers
+}
+
+/// Header lines of `headers`
+pub fn header_lines(headers: &[(&str, &str)]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect()
+}
+
+/// Is `name` one of the `headers`, which replace the server's own
+pub fn is_replaced(headers: &[(&str, &str)], name: &str) -> bool {
+    headers
+        .iter()
+        .any(|(replaced, _)| replaced.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod header_rules_tests {
+    use super::*;
+
+    fn rule(pattern: &str, headers: &[(&str, &str)]) -> HeaderRule {
+        HeaderRule {
+            pattern: pattern.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(rule_matches("*.m4s", "/live/v1/seg-1.m4s"));
+        assert!(!rule_matches("*.m4s", "/live/v1/seg-1.mp4"));
+        assert!(rule_matches("seg-?.m4s", "/seg-1.m4s"));
+        assert!(!rule_matches("seg-?.m4s", "/seg-10.m4s"));
+        assert!(rule_matches("/live/*/*.mpd", "/live/a/stream.mpd"));
+        assert!(!rule_matches("/live/*.mpd", "/live/a/stream.mpd"));
+        assert!(rule_matches("/live/**/*.mpd", "/live/a/b/stream.mpd"));
+        assert!(rule_matches("/live/**", "/live/a/b/stream.mpd"));
+        assert!(!rule_matches("/live/**", "/vod/a.mpd"));
+        assert!(rule_matches("*", "/anything"));
+    }
+
+    #[test]
+    fn later_rules_win() {
+        let rules = vec![
+            rule(
+                "*",
+                &[
+                    ("Timing-Allow-Origin", "*"),
+                    ("Cache-Control", "max-age=60"),
+                ],
+            ),
+            rule("*.mpd", &[("cache-control", "no-store")]),
+        ];
+        assert_eq!(
+            headers_for(&rules, "/live/stream.mpd"),
+            vec![("Timing-Allow-Origin", "*"), ("cache-control", "no-store")]
+        );
+        let headers = headers_for(&rules, "/live/seg.m4s");
+        assert_eq!(
+            header_lines(&headers),
+            "Cache-Control: max-age=60\r\nTiming-Allow-Origin: *\r\n"
+        );
+        assert!(is_replaced(&headers, "cache-control"));
+        assert!(!is_replaced(&headers, "Surrogate-Control"));
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt::Write;
+
+use crate::mpd::{AdaptationSet, Mpd, MpdType, Representation};
+
+/// HLS playlists are named like the DASH manifest they're made from
+pub const PLAYLIST_EXTENSION: &str = ".m3u8";
+/// Query parameter of the media playlist urls naming the representation
+pub const REPRESENTATION_PARAM: &str = "representation";
+
+/// Fragmented MP4 segments need version 7 (RFC 8216bis 4.3.2.5)
+const VERSION: u32 = 7;
+/// GROUP-ID of the audio renditions
+const AUDIO_GROUP: &str = "audio";
+
+/// A representation of the first period with its adaptation set
+struct Variant<'a> {
+    set: &'a AdaptationSet,
+    representation: &'a Representation,
+}
+
+impl<'a> Variant<'a> {
+    /// "video", "audio" or "text" from the content type or the MIME type
+    fn kind(&self) -> Option<&'a str> {
+        let mime_type = self
+            .representation
+            .mime_type
+            .as_ref()
+            .or(self.set.mime_type.as_ref());
+        match (&self.set.content_type, mime_type) {
+            (Some(content_type), _) => Some(content_type),
+            (None, Some(mime_type)) => mime_type.split('/').next(),
+            (None, None) => None,
+        }
+    }
+
+    fn codecs(&self) -> Option<&'a str> {
+        self.representation
+            .codecs
+            .as_deref()
+            .or(self.set.codecs.as_deref())
+    }
+}
+
+/// Characters other than the unreserved ones of RFC 3986 as "%XX" escapes
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => write!(encoded, "%{:02X}", byte).unwrap(),
+        }
+    }
+    encoded
+}
+
+/// Quoted attribute values can't have quotes or line breaks
+fn quoted(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .filter(|c| !matches!(c, '"' | '\r' | '\n'))
+        .collect();
+    format!("\"{}\"", value)
+}
+
+/// Url of the media playlist of a representation, relative to the master playlist
+fn media_playlist_url(playlist_name: &str, representation_id: &str) -> String {
+    format!(
+        "{}?{}={}",
+        percent_encode(playlist_name),
+        REPRESENTATION_PARAM,
+        percent_encode(representation_id)
+    )
+}
+
+/// Master playlist (multivariant playlist) of the video and audio of the
+/// manifest's first period. Each video representation is a variant and the
+/// audio representations are its alternative renditions. The media playlists
+/// are "<playlist_name>?representation=<id>". None if there's nothing to play.
+pub fn master_playlist(mpd: &Mpd, playlist_name: &str) -> Option<String> {
+    let period = mpd.periods.first()?;
+    let variants: Vec<Variant> = period
+        .adaptation_sets
+        .iter()
+        .flat_map(|set| {
+            set.representations
+                .iter()
+                .map(move |representation| Variant {
+                    set,
+                    representation,
+                })
+        })
+        .collect();
+    let of_kind = |kind| {
+        variants
+            .iter()
+            .filter(move |variant| variant.kind() == Some(kind))
+            .collect::<Vec<&Variant>>()
+    };
+    let video = of_kind("video");
+    let audio = of_kind("audio");
+    if video.is_empty() && audio.is_empty() {
+        return None;
+    }
+
+    let mut playlist = format!("#EXTM3U\n#EXT-X-VERSION:{}\n", VERSION);
+    playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+    // Audio only streams have the audio representations as the variants
+    if video.is_empty() {
+        for variant in &audio {
+            let representation = variant.representation;
+            write!(
+                playlist,
+                "#EXT-X-STREAM-INF:BANDWIDTH={}",
+                representation.bandwidth
+            )
+            .unwrap();
+            if let Some(codecs) = variant.codecs() {
+                write!(playlist, ",CODECS={}", quoted(codecs)).unwrap();
+            }
+            playlist.push('\n');
+            playlist.push_str(&media_playlist_url(playlist_name, &representation.id));
+            playlist.push('\n');
+        }
+        return Some(playlist);
+    }
+
+    for (i, variant) in audio.iter().enumerate() {
+        let representation = variant.representation;
+        let name = variant.set.lang.as_deref().unwrap_or(&representation.id);
+        write!(
+            playlist,
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID={},NAME={}",
+            quoted(AUDIO_GROUP),
+            quoted(&format!("{} ({})", name, representation.id))
+        )
+        .unwrap();
+        if let Some(lang) = &variant.set.lang {
+            write!(playlist, ",LANGUAGE={}", quoted(lang)).unwrap();
+        }
+        let default = if i == 0 { "YES" } else { "NO" };
+        writeln!(
+            playlist,
+            ",DEFAULT={},AUTOSELECT=YES,URI={}",
+            default,
+            quoted(&media_playlist_url(playlist_name, &representation.id))
+        )
+        .unwrap();
+    }
+
+    // The variant's bandwidth and codecs include the audio played with it
+    let audio_bandwidth = audio
+        .iter()
+        .map(|variant| variant.representation.bandwidth)
+        .max()
+        .unwrap_or(0);
+    let mut audio_codecs: Vec<&str> = vec![];
+    for codecs in audio.iter().filter_map(|variant| variant.codecs()) {
+        if !audio_codecs.contains(&codecs) {
+            audio_codecs.push(codecs);
+        }
+    }
+    for variant in &video {
+        let representation = variant.representation;
+        write!(
+            playlist,
+            "#EXT-X-STREAM-INF:BANDWIDTH={}",
+            representation.bandwidth + audio_bandwidth
+        )
+        .unwrap();
+        let codecs: Vec<&str> = variant
+            .codecs()
+            .into_iter()
+            .chain(audio_codecs.iter().copied())
+            .collect();
+        if !codecs.is_empty() {
+            write!(playlist, ",CODECS={}", quoted(&codecs.join(","))).unwrap();
+        }
+        if let (Some(width), Some(height)) = (representation.width, representation.height) {
+            write!(playlist, ",RESOLUTION={}x{}", width, height).unwrap();
+        }
+        if !audio.is_empty() {
+            write!(playlist, ",AUDIO={}", quoted(AUDIO_GROUP)).unwrap();
+        }
+        playlist.push('\n');
+        playlist.push_str(&media_playlist_url(playlist_name, &representation.id));
+        playlist.push('\n');
+    }
+    Some(playlist)
+}
+
+/// Media playlist of the segments of the representation `representation_id`.
+/// Playlists of dynamic manifests are live playlists without an end.
+/// None if the representation doesn't have segments.
+pub fn media_playlist(mpd: &Mpd, representation_id: &str) -> Option<String> {
+    let segments = mpd
+        .segment_urls()
+        .into_iter()
+        .find(|segments| segments.representation_id == representation_id)?;
+    if segments.media.is_empty() {
+        return None;
+    }
+    // Every EXTINF rounded to the nearest second has to fit in the target duration
+    let target_duration = segments
+        .durations
+        .iter()
+        .map(|duration| duration.round() as u64)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let mut playlist = format!("#EXTM3U\n#EXT-X-VERSION:{}\n", VERSION);
+    writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target_duration).unwrap();
+    writeln!(playlist, "#EXT-X-MEDIA-SEQUENCE:{}", segments.start_number).unwrap();
+    if mpd.mpd_type == MpdType::Static {
+        playlist.push_str("#EXT-X-PLAYLIST-TYPE:VOD\n");
+    }
+    playlist.push_str("#EXT-X-INDEPENDENT-SEGMENTS\n");
+    if let Some(initialization) = &segments.initialization {
+        writeln!(playlist, "#EXT-X-MAP:URI={}", quoted(initialization)).unwrap();
+    }
+    for (url, duration) in segments.media.iter().zip(&segments.durations) {
+        writeln!(playlist, "#EXTINF:{:.3},\n{}", duration, url).unwrap();
+    }
+    if mpd.mpd_type == MpdType::Static {
+        playlist.push_str("#EXT-X-ENDLIST\n");
+    }
+    Some(playlist)
+}
+
+#[cfg(test)]
+mod hls_tests {
+    use super::*;
+    use crate::mpd;
+
+    const MANIFEST: &str = r#"<MPD type="static" profiles="urn:mpeg:dash:profile:isoff-live:2011"
+        mediaPresentationDuration="PT5S" minBufferTime="PT2S">
+      <Period>
+        <AdaptationSet contentType="video" mimeType="video/mp4">
+          <SegmentTemplate timescale="90000" startNumber="1"
+              media="$RepresentationID$/seg-$Number$.m4s" initialization="$RepresentationID$/init.mp4">
+            <SegmentTimeline><S t="0" d="180000" r="1"/><S d="90000"/></SegmentTimeline>
+          </SegmentTemplate>
+          <Representation id="video-360" bandwidth="5000" codecs="avc1.64001F" width="640" height="360"/>
+          <Representation id="video 720" bandwidth="9000" codecs="avc1.64001F" width="1280" height="720"/>
+        </AdaptationSet>
+        <AdaptationSet contentType="audio" mimeType="audio/mp4" lang="eng">
+          <SegmentTemplate timescale="48000" duration="96000"
+              media="$RepresentationID$/seg-$Number$.m4s" initialization="$RepresentationID$/init.mp4"/>
+          <Representation id="audio-en" bandwidth="1000" codecs="mp4a.40.2"/>
+        </AdaptationSet>
+      </Period>
+    </MPD>"#;
+
+    #[test]
+    fn master() {
+        let mpd = mpd::parse(MANIFEST).unwrap();
+        let playlist = master_playlist(&mpd, "manifest.m3u8").unwrap();
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"eng (audio-en)\",LANGUAGE=\"eng\",\
+             DEFAULT=YES,AUTOSELECT=YES,URI=\"manifest.m3u8?representation=audio-en\"\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=6000,CODECS=\"avc1.64001F,mp4a.40.2\",\
+             RESOLUTION=640x360,AUDIO=\"audio\"\n\
+             manifest.m3u8?representation=video-360\n\
+             #EXT-X-STREAM-INF:BANDWIDTH=10000,CODECS=\"avc1.64001F,mp4a.40.2\",\
+             RESOLUTION=1280x720,AUDIO=\"audio\"\n\
+             manifest.m3u8?representation=video%20720\n"
+        );
+
+        let mut audio_only = mpd.clone();
+        audio_only.periods[0].adaptation_sets.remove(0);
+        let playlist = master_playlist(&audio_only, "manifest.m3u8").unwrap();
+        assert!(playlist.ends_with(
+            "#EXT-X-STREAM-INF:BANDWIDTH=1000,CODECS=\"mp4a.40.2\"\n\
+             manifest.m3u8?representation=audio-en\n"
+        ));
+        audio_only.periods[0].adaptation_sets.clear();
+        assert_eq!(master_playlist(&audio_only, "manifest.m3u8"), None);
+    }
+
+    #[test]
+    fn media() {
+        let mut mpd = mpd::parse(MANIFEST).unwrap();
+        let playlist = media_playlist(&mpd, "video-360").unwrap();
+        assert_eq!(
+            playlist,
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:2\n#EXT-X-MEDIA-SEQUENCE:1\n\
+             #EXT-X-PLAYLIST-TYPE:VOD\n#EXT-X-INDEPENDENT-SEGMENTS\n\
+             #EXT-X-MAP:URI=\"video-360/init.mp4\"\n\
+             #EXTINF:2.000,\nvideo-360/seg-1.m4s\n\
+             #EXTINF:2.000,\nvideo-360/seg-2.m4s\n\
+             #EXTINF:1.000,\nvideo-360/seg-3.m4s\n\
+             #EXT-X-ENDLIST\n"
+        );
+        // Segments of a fixed duration until the end of the period
+        let playlist = media_playlist(&mpd, "audio-en").unwrap();
+        assert_eq!(playlist.matches("#EXTINF:2.000,").count(), 3);
+        assert_eq!(media_playlist(&mpd, "missing"), None);
+
+        // Live playlists don't end
+        mpd.mpd_type = MpdType::Dynamic;
+        let playlist = media_playlist(&mpd, "video-360").unwrap();
+        assert!(!playlist.contains("#EXT-X-ENDLIST"));
+        assert!(!playlist.contains("#EXT-X-PLAYLIST-TYPE"));
+    }
+}
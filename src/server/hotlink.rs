@@ -0,0 +1,115 @@
+use crate::config::HotlinkProtection;
+
+/// "scheme://host[:port]" of a Referer url
+fn referer_origin(referer: &str) -> &str {
+    let host_start = referer.find("://").map_or(0, |i| i + 3);
+    match referer[host_start..].find(['/', '?', '#']) {
+        Some(end) => &referer[..host_start + end],
+        None => referer,
+    }
+}
+
+/// Does the `origin` match a site like "https://player.example.com" or
+/// "https://*.example.com", which matches the subdomains of example.com
+fn matches(site: &str, origin: &str) -> bool {
+    if let Some((scheme, domain)) = site.split_once("://*.") {
+        let suffix = format!(".{}", domain.to_ascii_lowercase());
+        match origin.split_once("://") {
+            Some((origin_scheme, host)) => {
+                origin_scheme.eq_ignore_ascii_case(scheme)
+                    && host.len() > suffix.len()
+                    && host.to_ascii_lowercase().ends_with(&suffix)
+            }
+            None => false,
+        }
+    } else {
+        site.eq_ignore_ascii_case(origin)
+    }
+}
+
+/// Can a page from the site in the Origin or Referer header embed the
+/// streams. Origin is used when the browser sends both.
+pub fn is_allowed(
+    hotlink: &HotlinkProtection,
+    origin: Option<&str>,
+    referer: Option<&str>,
+) -> bool {
+    let origin = match (origin, referer) {
+        (Some(origin), _) => origin,
+        (None, Some(referer)) => referer_origin(referer),
+        (None, None) => return hotlink.allow_empty,
+    };
+    hotlink
+        .allowed_sites
+        .iter()
+        .any(|site| matches(site, origin))
+}
+
+#[cfg(test)]
+mod hotlink_tests {
+    use super::*;
+
+    fn protection(allow_empty: bool) -> HotlinkProtection {
+        HotlinkProtection {
+            allowed_sites: vec![
+                "https://player.example.com".to_string(),
+                "https://*.example.org".to_string(),
+            ],
+            allow_empty,
+        }
+    }
+
+    #[test]
+    fn referer_origins() {
+        assert_eq!(
+            referer_origin("https://player.example.com/watch?v=1"),
+            "https://player.example.com"
+        );
+        assert_eq!(
+            referer_origin("http://localhost:3000"),
+            "http://localhost:3000"
+        );
+        assert_eq!(
+            referer_origin("https://example.com?embed"),
+            "https://example.com"
+        );
+    }
+
+    #[test]
+    fn sites() {
+        assert!(matches(
+            "https://player.example.com",
+            "https://Player.example.com"
+        ));
+        assert!(!matches(
+            "https://player.example.com",
+            "http://player.example.com"
+        ));
+        assert!(matches("https://*.example.org", "https://tv.example.org"));
+        assert!(matches("https://*.example.org", "https://a.b.example.org"));
+        assert!(!matches("https://*.example.org", "https://example.org"));
+        assert!(!matches("https://*.example.org", "https://badexample.org"));
+        assert!(!matches("https://*.example.org", "http://tv.example.org"));
+    }
+
+    #[test]
+    fn allowed_requests() {
+        let hotlink = protection(true);
+        assert!(is_allowed(&hotlink, None, None));
+        assert!(!is_allowed(&protection(false), None, None));
+        assert!(is_allowed(
+            &hotlink,
+            None,
+            Some("https://player.example.com/live")
+        ));
+        assert!(!is_allowed(&hotlink, None, Some("https://evil.example/")));
+        assert!(is_allowed(&hotlink, Some("https://tv.example.org"), None));
+        // Origin is the one the browser can't leave out
+        assert!(!is_allowed(
+            &hotlink,
+            Some("https://evil.example"),
+            Some("https://player.example.com/")
+        ));
+        assert!(!is_allowed(&hotlink, Some("null"), None));
+    }
+}
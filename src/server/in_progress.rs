@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::chunked::ChunkedWriter;
+
+/// How often a segment being written is checked for more data
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Largest piece read from the segment at a time
+const READ_SIZE: usize = 64 * 1024;
+
+/// Send the segment in `file` as it's being written in `partial_path`,
+/// each new piece as a chunk right away. The segment is complete once it
+/// has been renamed to `final_path`. Gives up if it doesn't grow in
+/// `idle_timeout` or is removed. Returns the number of body bytes written,
+/// as an error if the transfer was aborted.
+pub fn write_segment<W: Write>(
+    writer: &mut W,
+    mut file: File,
+    partial_path: &Path,
+    final_path: &Path,
+    idle_timeout: Duration,
+    is_gone: impl Fn(&W) -> bool,
+) -> Result<u64, u64> {
+    let mut chunked = ChunkedWriter::new(writer);
+    let mut buffer = vec![0u8; READ_SIZE];
+    let mut written = 0;
+    let mut grown = Instant::now();
+    let mut complete = false;
+    loop {
+        let read = file.read(&mut buffer).map_err(|_| written)?;
+        if read > 0 {
+            if is_gone(chunked.get_ref()) {
+                return Err(written);
+            }
+            // The player gets each CMAF chunk as soon as it's written
+            let sent = chunked
+                .write_all(&buffer[..read])
+                .and_then(|_| chunked.flush());
+            if sent.is_err() {
+                return Err(written);
+            }
+            written += read as u64;
+            grown = Instant::now();
+            continue;
+        }
+        if complete {
+            return match chunked.finish() {
+                Ok(_) => Ok(written),
+                Err(_) => Err(written),
+            };
+        }
+        // Whatever was written before the rename is read on the next round
+        if !partial_path.exists() {
+            if !final_path.exists() {
+                return Err(written);
+            }
+            complete = true;
+            continue;
+        }
+        if grown.elapsed() > idle_timeout {
+            return Err(written);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod in_progress_tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+
+    fn paths(name: &str) -> (String, String) {
+        let dir = std::env::temp_dir().join(format!("dash-in-progress-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let final_path = dir.join(name).to_string_lossy().into_owned();
+        (format!("{}.tmp", final_path), final_path)
+    }
+
+    #[test]
+    fn follows_until_renamed() {
+        let (partial, complete) = paths("seg-1.m4s");
+        fs::write(&partial, b"moof1").unwrap();
+        let writer_path = partial.clone();
+        let (renamed_from, renamed_to) = (partial.clone(), complete.clone());
+        let encoder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            let mut file = OpenOptions::new().append(true).open(writer_path).unwrap();
+            file.write_all(b"mdat1").unwrap();
+            thread::sleep(Duration::from_millis(100));
+            file.write_all(b"moof2").unwrap();
+            fs::rename(renamed_from, renamed_to).unwrap();
+        });
+
+        let mut out = vec![];
+        let file = File::open(&partial).unwrap();
+        let timeout = Duration::from_secs(2);
+        let result = write_segment(
+            &mut out,
+            file,
+            Path::new(&partial),
+            Path::new(&complete),
+            timeout,
+            |_| false,
+        );
+        encoder.join().unwrap();
+        assert_eq!(result, Ok(15));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "5\r\nmoof1\r\n5\r\nmdat1\r\n5\r\nmoof2\r\n0\r\n\r\n"
+        );
+        fs::remove_file(complete).unwrap();
+    }
+
+    #[test]
+    fn stalled_segment_aborts() {
+        let (partial, complete) = paths("seg-2.m4s");
+        fs::write(&partial, b"moof1").unwrap();
+        let mut out = vec![];
+        let file = File::open(&partial).unwrap();
+        let timeout = Duration::from_millis(100);
+        let result = write_segment(
+            &mut out,
+            file,
+            Path::new(&partial),
+            Path::new(&complete),
+            timeout,
+            |_| false,
+        );
+        assert_eq!(result, Err(5));
+        // No last chunk so the client knows it's incomplete
+        assert_eq!(String::from_utf8(out).unwrap(), "5\r\nmoof1\r\n");
+
+        // Removed without the complete segment
+        let file = File::open(&partial).unwrap();
+        fs::remove_file(&partial).unwrap();
+        let result = write_segment(
+            &mut vec![],
+            file,
+            Path::new(&partial),
+            Path::new(&complete),
+            timeout,
+            |_| false,
+        );
+        assert_eq!(result, Err(5));
+    }
+}
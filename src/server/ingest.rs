@@ -0,0 +1,165 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::admin::token_matches;
+use super::sidecar::SIDECAR_EXTENSION;
+use crate::config::{Config, Ingest};
+
+/// Methods in the Allow header of the ingest paths
+pub const ALLOWED_METHODS: &str = "PUT, POST, DELETE";
+
+/// Ending of the uploads being received when lowLatency isn't configured
+const DEFAULT_IN_PROGRESS_SUFFIX: &str = ".tmp";
+
+/// Why an upload wasn't stored
+#[derive(Debug)]
+pub enum StoreError {
+    /// The request body couldn't be read. Answered with 400
+    Upload,
+    /// The file couldn't be written. Answered with 500
+    Disk(io::Error),
+}
+
+/// Ending of the files that are still being uploaded
+pub fn in_progress_suffix(config: &Config) -> &str {
+    match &config.low_latency {
+        Some(low_latency) => &low_latency.in_progress_suffix,
+        None => DEFAULT_IN_PROGRESS_SUFFIX,
+    }
+}
+
+/// Where the upload to the url `path` is stored. None if the path isn't
+/// under the ingest prefix or doesn't name a file of a stream.
+pub fn target_path(ingest: &Ingest, path: &str, in_progress_suffix: &str) -> Option<PathBuf> {
+    let relative = path.strip_prefix(&ingest.prefix[..])?;
+    // "<stream>/<file>" at least
+    let valid = relative.contains('/')
+        && !relative
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        && !relative.ends_with(in_progress_suffix)
+        && !relative.ends_with(SIDECAR_EXTENSION);
+    if valid {
+        Some(Path::new(&ingest.root).join(relative))
+    } else {
+        None
+    }
+}
+
+/// Does the Authorization header have the token of the encoders
+pub fn is_authorized(ingest: &Ingest, authorization: Option<&str>) -> bool {
+    match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+        Some(given) => token_matches(given.trim(), &ingest.token),
+        None => false,
+    }
+}
+
+/// Write the upload in `body` to `target` through "<target><in_progress_suffix>"
+/// so the file never has a partial upload under its own name. Every piece is
+/// written as it arrives so the low latency segments can be served from the
+/// partial file. Returns whether the file was created instead of replaced.
+pub fn store(
+    mut body: impl Read,
+    target: &Path,
+    in_progress_suffix: &str,
+) -> Result<bool, StoreError> {
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(StoreError::Disk)?;
+    }
+    let created = !target.exists();
+    let mut partial_path = target.as_os_str().to_owned();
+    partial_path.push(in_progress_suffix);
+    let partial_path = PathBuf::from(partial_path);
+
+    let result = File::create(&partial_path)
+        .map_err(StoreError::Disk)
+        .and_then(|mut file| {
+            let mut buffer = vec![0u8; 64 * 1024];
+            loop {
+                let read = match body.read(&mut buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(read) => read,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(_) => return Err(StoreError::Upload),
+                };
+                file.write_all(&buffer[..read]).map_err(StoreError::Disk)?;
+            }
+        })
+        .and_then(|_| fs::rename(&partial_path, target).map_err(StoreError::Disk));
+    if result.is_err() {
+        // The segment is incomplete, players shouldn't wait for the rest
+        let _ = fs::remove_file(&partial_path);
+    }
+    result.map(|_| created)
+}
+
+#[cfg(test)]
+mod ingest_tests {
+    use super::*;
+
+    fn ingest() -> Ingest {
+        Ingest {
+            prefix: "/ingest/".to_string(),
+            root: "live".to_string(),
+            token: "encoder-token".to_string(),
+        }
+    }
+
+    #[test]
+    fn target_paths() {
+        let target = |path| target_path(&ingest(), path, ".tmp");
+        assert_eq!(
+            target("/ingest/chan1/video/seg-1.m4s"),
+            Some(PathBuf::from("live/chan1/video/seg-1.m4s"))
+        );
+        assert_eq!(
+            target("/ingest/chan1/manifest.mpd"),
+            Some(PathBuf::from("live/chan1/manifest.mpd"))
+        );
+        assert_eq!(target("/live/chan1/manifest.mpd"), None);
+        assert_eq!(target("/ingest/manifest.mpd"), None);
+        assert_eq!(target("/ingest/chan1/"), None);
+        assert_eq!(target("/ingest/chan1//seg-1.m4s"), None);
+        assert_eq!(target("/ingest/chan1/../../etc/passwd"), None);
+        assert_eq!(target("/ingest/chan1/video/seg-1.m4s.tmp"), None);
+        assert_eq!(target("/ingest/chan1/manifest.dash.json"), None);
+    }
+
+    #[test]
+    fn authorization() {
+        let ingest = ingest();
+        assert!(is_authorized(&ingest, Some("Bearer encoder-token")));
+        assert!(!is_authorized(&ingest, Some("Bearer encoder")));
+        assert!(!is_authorized(&ingest, Some("Basic encoder-token")));
+        assert!(!is_authorized(&ingest, None));
+    }
+
+    #[test]
+    fn stored_through_partial_file() {
+        let dir = std::env::temp_dir().join(format!("dash-ingest-{}", std::process::id()));
+        let target = dir.join("chan1/video/seg-1.m4s");
+        assert!(store(&b"moof"[..], &target, ".tmp").unwrap());
+        assert_eq!(fs::read(&target).unwrap(), b"moof");
+        assert!(!store(&b"moofmdat"[..], &target, ".tmp").unwrap());
+        assert_eq!(fs::read(&target).unwrap(), b"moofmdat");
+
+        // A broken upload leaves the earlier version
+        let broken = (&b"mo"[..]).chain(BrokenUpload);
+        assert!(matches!(
+            store(broken, &target, ".tmp"),
+            Err(StoreError::Upload)
+        ));
+        assert_eq!(fs::read(&target).unwrap(), b"moofmdat");
+        assert!(!dir.join("chan1/video/seg-1.m4s.tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    struct BrokenUpload;
+
+    impl Read for BrokenUpload {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::ConnectionReset.into())
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::net::IpAddr;
+
+/// Does `address` belong to `range`, e.g. "10.0.0.0/8" or a single address like "::1"
+pub fn in_range(address: IpAddr, range: &str) -> bool {
+    let (network, prefix) = match range.split_once('/') {
+        Some((network, prefix)) => (network, prefix.parse::<u32>().ok()),
+        None => (range, None),
+    };
+    match (address, network.parse::<IpAddr>()) {
+        (IpAddr::V4(address), Ok(IpAddr::V4(network))) => {
+            let prefix = prefix.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), Ok(IpAddr::V6(network))) => {
+            let prefix = prefix.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Can a client at `address` connect. Denied ranges win over allowed ones
+/// and an empty `allow` list allows everyone.
+pub fn is_allowed(address: IpAddr, allow: &[String], deny: &[String]) -> bool {
+    // IPv4 clients of a dual stack socket show up as ::ffff:a.b.c.d
+    let address = address.to_canonical();
+    let allowed = allow.is_empty() || allow.iter().any(|range| in_range(address, range));
+    allowed && !deny.iter().any(|range| in_range(address, range))
+}
+
+#[cfg(test)]
+mod ip_filter_tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(in_range(ip("10.1.2.3"), "10.0.0.0/8"));
+        assert!(!in_range(ip("11.1.2.3"), "10.0.0.0/8"));
+        assert!(in_range(ip("192.168.1.1"), "192.168.1.1"));
+        assert!(in_range(ip("1.2.3.4"), "0.0.0.0/0"));
+        assert!(in_range(ip("2001:db8::5"), "2001:db8::/32"));
+        assert!(!in_range(ip("2001:db9::5"), "2001:db8::/32"));
+        assert!(!in_range(ip("10.0.0.1"), "::/0"));
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let list = |ranges: &[&str]| ranges.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert!(is_allowed(ip("192.0.2.1"), &[], &[]));
+
+        let allow = list(&["203.0.113.0/24", "2001:db8::/32"]);
+        let deny = list(&["203.0.113.7"]);
+        assert!(is_allowed(ip("203.0.113.1"), &allow, &deny));
+        assert!(is_allowed(ip("::ffff:203.0.113.1"), &allow, &deny));
+        assert!(is_allowed(ip("2001:db8::1"), &allow, &deny));
+        assert!(!is_allowed(ip("203.0.113.7"), &allow, &deny));
+        assert!(!is_allowed(ip("192.0.2.1"), &allow, &deny));
+        assert!(!is_allowed(ip("203.0.113.7"), &[], &deny));
+        assert!(is_allowed(ip("203.0.113.8"), &[], &deny));
+    }
+}
@@ -0,0 +1,203 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use openssl::bn::BigNum;
+use openssl::pkey::{PKey, Public};
+use openssl::rsa::Rsa;
+use openssl::ssl::{SslConnector, SslMethod};
+use serde_json::Value;
+
+use super::jwt::decode_base64url;
+
+/// Limit on connecting to and reading from the key server
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Key sets are a few kilobytes, anything much larger is not a key set
+const MAX_RESPONSE_SIZE: u64 = 1024 * 1024;
+
+/// Public key of the set with its "kid"
+pub type Key = (Option<String>, PKey<Public>);
+
+fn invalid(error: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.into())
+}
+
+/// Split "https://host:port/path" into the parts needed for the request
+fn parse_url(url: &str) -> io::Result<(bool, &str, u16, &str)> {
+    let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("http://") {
+        (false, rest)
+    } else {
+        return Err(invalid(format!("Not a http(s) url: {}", url)));
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    // The port is after the brackets of an IPv6 address
+    let (host, port) = match authority.rfind(':') {
+        Some(colon) if !authority[colon..].contains(']') => {
+            let port = authority[colon + 1..]
+                .parse()
+                .map_err(|_| invalid(format!("Invalid port in {}", url)))?;
+            (&authority[..colon], port)
+        }
+        _ => (authority, if tls { 443 } else { 80 }),
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(invalid(format!("No host in {}", url)));
+    }
+    Ok((tls, host, port, path))
+}
+
+fn connect(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_error = invalid(format!("{} has no addresses", host));
+    for address in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => {
+                stream.set_read_timeout(Some(TIMEOUT))?;
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+/// Body of a GET to `url`. HTTP/1.0 so the body is never chunked.
+fn get(url: &str) -> io::Result<Vec<u8>> {
+    let (tls, host, port, path) = parse_url(url)?;
+    let stream = connect(host, port)?;
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+        path, host
+    );
+    let mut response = vec![];
+    if tls {
+        let connector = SslConnector::builder(SslMethod::tls())?.build();
+        let mut stream = connector
+            .connect(host, stream)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        stream.write_all(request.as_bytes())?;
+        stream.take(MAX_RESPONSE_SIZE).read_to_end(&mut response)?;
+    } else {
+        let mut stream = stream;
+        stream.write_all(request.as_bytes())?;
+        stream.take(MAX_RESPONSE_SIZE).read_to_end(&mut response)?;
+    }
+
+    let head_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("No response head"))?;
+    let status_line = String::from_utf8_lossy(&response[..head_end])
+        .lines()
+        .next()
+        .unwrap_or("")
+        .to_string();
+    if status_line.split(' ').nth(1) != Some("200") {
+        return Err(invalid(format!("Unexpected response: {}", status_line)));
+    }
+    Ok(response.split_off(head_end + 4))
+}
+
+/// RSA signing keys of the key set. Other keys are left out.
+fn parse(json: &[u8]) -> io::Result<Vec<Key>> {
+    let set: Value = serde_json::from_slice(json).map_err(|e| invalid(e.to_string()))?;
+    let keys = set["keys"]
+        .as_array()
+        .ok_or_else(|| invalid("No keys in the key set"))?;
+    let component = |key: &Value, name: &str| {
+        key[name]
+            .as_str()
+            .and_then(decode_base64url)
+            .and_then(|bytes| BigNum::from_slice(&bytes).ok())
+    };
+    Ok(keys
+        .iter()
+        .filter(|key| key["kty"] == "RSA" && key["use"] != "enc")
+        .filter_map(|key| {
+            let rsa = Rsa::from_public_components(component(key, "n")?, component(key, "e")?);
+            let public = PKey::from_rsa(rsa.ok()?).ok()?;
+            Some((key["kid"].as_str().map(str::to_string), public))
+        })
+        .collect())
+}
+
+/// Fetch the keys of the JSON Web Key Set at `url`
+pub fn fetch(url: &str) -> io::Result<Vec<Key>> {
+    let keys = parse(&get(url)?)?;
+    if keys.is_empty() {
+        return Err(invalid(format!("No RSA keys in {}", url)));
+    }
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod jwks_tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            parse_url("https://auth.example.com/keys.json").unwrap(),
+            (true, "auth.example.com", 443, "/keys.json")
+        );
+        assert_eq!(
+            parse_url("http://127.0.0.1:8000").unwrap(),
+            (false, "127.0.0.1", 8000, "/")
+        );
+        assert_eq!(
+            parse_url("http://[::1]:8000/jwks").unwrap(),
+            (false, "::1", 8000, "/jwks")
+        );
+        assert_eq!(
+            parse_url("https://[::1]/").unwrap(),
+            (true, "::1", 443, "/")
+        );
+        assert!(parse_url("ftp://example.com/").is_err());
+        assert!(parse_url("http://example.com:http/").is_err());
+        assert!(parse_url("https:///keys").is_err());
+    }
+
+    #[test]
+    fn fetched_keys() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let n = openssl::base64::encode_block(&rsa.n().to_vec());
+        let e = openssl::base64::encode_block(&rsa.e().to_vec());
+        let url_safe = |b64: String| {
+            b64.trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_")
+        };
+        let body = serde_json::json!({"keys": [
+            {"kty": "EC", "kid": "ec", "crv": "P-256"},
+            {"kty": "RSA", "kid": "enc", "use": "enc", "n": url_safe(n.clone()), "e": url_safe(e.clone())},
+            {"kty": "RSA", "kid": "sig", "n": url_safe(n), "e": url_safe(e)},
+        ]})
+        .to_string();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let len = stream.read(&mut request).unwrap();
+            assert!(request[..len].starts_with(b"GET /jwks.json HTTP/1.0\r\n"));
+            let response = format!("HTTP/1.0 200 OK\r\n\r\n{}", body);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        let keys = fetch(&url).unwrap();
+        server.join().unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].0.as_deref(), Some("sig"));
+        assert_eq!(keys[0].1.rsa().unwrap().n(), rsa.n());
+    }
+}
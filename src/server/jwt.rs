@@ -0,0 +1,536 @@
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use openssl::base64;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::{PKey, Public};
+use openssl::sign::{Signer, Verifier};
+use serde_json::Value;
+
+use super::jwks::{self, Key};
+use crate::config::{Jwt, JwtAlgorithm, JwtRule};
+
+/// Key sets are fetched again after this even if all the keys are known
+const JWKS_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Shortest time between fetches for unknown keys so tokens with made up
+/// "kid"s can't flood the key server
+const JWKS_MIN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Why a request was not authorized
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+    /// No bearer token in the request
+    Missing,
+    /// The token is malformed, forged, expired or for someone else
+    Invalid(&'static str),
+    /// A valid token without the scope of the path
+    Scope,
+}
+
+/// Decode the unpadded url-safe base64 of the tokens and key sets
+pub fn decode_base64url(encoded: &str) -> Option<Vec<u8>> {
+    // A single leftover character can't hold a whole byte
+    if encoded.contains(['+', '/', '=']) || encoded.len() % 4 == 1 {
+        return None;
+    }
+    let mut standard = encoded.replace('-', "+").replace('_', "/");
+    while !standard.len().is_multiple_of(4) {
+        standard.push('=');
+    }
+    base64::decode_block(&standard).ok()
+}
+
+/// Public keys of RS256 tokens, loaded from the key file or the key set url
+/// of the config in use
+pub struct JwtKeys {
+    loaded: Mutex<LoadedKeys>,
+    /// Held while the keys are read so only one request fetches the key set
+    /// and the others don't wait for it behind `loaded`
+    loading: Mutex<()>,
+}
+
+struct LoadedKeys {
+    /// File or url the keys came from
+    source: String,
+    keys: Vec<Key>,
+    /// When the keys were last loaded, or tried to be loaded
+    at: Option<Instant>,
+}
+
+/// Read the keys from the key set url or the key file at `source`
+fn read_keys(jwt: &Jwt, source: &str) -> std::io::Result<Vec<Key>> {
+    match &jwt.jwks_url {
+        Some(url) => jwks::fetch(url),
+        None => fs::read(source).and_then(|pem| {
+            PKey::public_key_from_pem(&pem)
+                .map(|key| vec![(None, key)])
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        }),
+    }
+}
+
+impl LoadedKeys {
+    fn store(&mut self, result: std::io::Result<Vec<Key>>, source: &str, now: Instant) {
+        match result {
+            Ok(keys) => self.keys = keys,
+            // Keys from an earlier fetch are still better than none
+            Err(e) => {
+                warn!("Couldn't load the JWT keys from {}: {}", source, e);
+                if self.source != source {
+                    self.keys.clear();
+                }
+            }
+        }
+        self.source = source.to_string();
+        self.at = Some(now);
+    }
+
+    fn matching(&self, kid: Option<&str>) -> Vec<PKey<Public>> {
+        self.keys
+            .iter()
+            // Tokens without a kid are tried with every key
+            .filter(|(key_id, _)| kid.is_none() || key_id.as_deref() == kid)
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+}
+
+impl JwtKeys {
+    pub fn new() -> JwtKeys {
+        JwtKeys {
+            loaded: Mutex::new(LoadedKeys {
+                source: String::new(),
+                keys: vec![],
+                at: None,
+            }),
+            loading: Mutex::new(()),
+        }
+    }
+
+    /// Keys that may have signed a token with the `kid`. The keys are read
+    /// without holding `loaded`. While one request reads them the others
+    /// use the keys they have, and wait only if there are none.
+    fn keys(&self, jwt: &Jwt, kid: Option<&str>) -> Vec<PKey<Public>> {
+        let source = match (&jwt.jwks_url, &jwt.public_key_file) {
+            (Some(url), _) => url,
+            (None, Some(file)) => file,
+            (None, None) => return vec![],
+        };
+        let now = Instant::now();
+        let (keys, reload) = {
+            let loaded = self.loaded.lock().unwrap();
+            let current = loaded.source == *source && loaded.at.is_some();
+            let age = loaded.at.map(|at| now.duration_since(at));
+            let expired = jwt.jwks_url.is_some() && age.is_some_and(|age| age >= JWKS_MAX_AGE);
+            let keys = if current {
+                loaded.matching(kid)
+            } else {
+                vec![]
+            };
+            // Rotated keys show up in the key set before the tokens signed with them
+            let may_refetch = age.is_none_or(|age| age >= JWKS_MIN_INTERVAL);
+            let unknown = keys.is_empty() && jwt.jwks_url.is_some() && may_refetch;
+            (keys, !current || expired || unknown)
+        };
+        if !reload {
+            return keys;
+        }
+
+        let _loading = if keys.is_empty() {
+            let loading = self.loading.lock().unwrap();
+            // Loaded by the request that was waited for
+            let loaded = self.loaded.lock().unwrap();
+            if loaded.source == *source && loaded.at.is_some_and(|at| at >= now) {
+                return loaded.matching(kid);
+            }
+            loading
+        } else {
+            match self.loading.try_lock() {
+                Ok(loading) => loading,
+                Err(_) => return keys,
+            }
+        };
+        let result = read_keys(jwt, source);
+        let mut loaded = self.loaded.lock().unwrap();
+        loaded.store(result, source, Instant::now());
+        loaded.matching(kid)
+    }
+}
+
+/// Rule with the longest prefix of `path`
+fn rule_for<'a>(rules: &'a [JwtRule], path: &str) -> Option<&'a JwtRule> {
+    rules
+        .iter()
+        .filter(|rule| path.starts_with(&rule.prefix[..]))
+        .max_by_key(|rule| rule.prefix.len())
+}
+
+fn decode_json(encoded: &str) -> Result<Value, Denied> {
+    decode_base64url(encoded)
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .filter(Value::is_object)
+        .ok_or(Denied::Invalid("malformed token"))
+}
+
+fn verify_signature(
+    jwt: &Jwt,
+    keys: &JwtKeys,
+    header: &Value,
+    signed: &str,
+    signature: &[u8],
+) -> Result<(), Denied> {
+    let expected_alg = match jwt.algorithm {
+        JwtAlgorithm::Hs256 => "HS256",
+        JwtAlgorithm::Rs256 => "RS256",
+    };
+    // Never let the token choose the algorithm, e.g. "none"
+    if header["alg"] != expected_alg {
+        return Err(Denied::Invalid("unexpected algorithm"));
+    }
+    let valid = match jwt.algorithm {
+        JwtAlgorithm::Hs256 => {
+            let secret = jwt.secret.as_deref().unwrap_or("");
+            let key = PKey::hmac(secret.as_bytes()).unwrap();
+            let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+            signer.update(signed.as_bytes()).unwrap();
+            let expected = signer.sign_to_vec().unwrap();
+            // Constant time so the response time doesn't tell how much was right
+            expected.len() == signature.len() && memcmp::eq(&expected, signature)
+        }
+        JwtAlgorithm::Rs256 => keys.keys(jwt, header["kid"].as_str()).iter().any(|key| {
+            Verifier::new(MessageDigest::sha256(), key)
+                .and_then(|mut verifier| {
+                    verifier.update(signed.as_bytes())?;
+                    verifier.verify(signature)
+                })
+                .unwrap_or(false)
+        }),
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(Denied::Invalid("bad signature"))
+    }
+}
+
+/// Check the registered claims that are present, and the issuer and
+/// audience if the config asks for them
+fn verify_claims(jwt: &Jwt, claims: &Value, now: f64) -> Result<(), Denied> {
+    if claims["exp"].as_f64().is_some_and(|exp| exp <= now) {
+        return Err(Denied::Invalid("expired"));
+    }
+    if claims["nbf"].as_f64().is_some_and(|nbf| nbf > now) {
+        return Err(Denied::Invalid("not valid yet"));
+    }
+    if let Some(issuer) = &jwt.issuer {
+        if claims["iss"] != issuer[..] {
+            return Err(Denied::Invalid("wrong issuer"));
+        }
+    }
+    if let Some(audience) = &jwt.audience {
+        // The audience is either one string or a list of them
+        let matches = match &claims["aud"] {
+            Value::Array(audiences) => audiences.iter().any(|aud| aud == &audience[..]),
+            aud => aud == &audience[..],
+        };
+        if !matches {
+            return Err(Denied::Invalid("wrong audience"));
+        }
+    }
+    Ok(())
+}
+
+fn has_scope(claims: &Value, scope: &str) -> bool {
+    match &claims["scope"] {
+        Value::String(scopes) => scopes.split(' ').any(|s| s == scope),
+        Value::Array(scopes) => scopes.iter().any(|s| s == scope),
+        _ => false,
+    }
+}
+
+/// Claims of a valid `token`
+fn verify_token(jwt: &Jwt, keys: &JwtKeys, token: &str, now: f64) -> Result<Value, Denied> {
+    let mut parts = token.split('.');
+    let (header, payload, signature) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(header), Some(payload), Some(signature)) if parts.next().is_none() => {
+            (header, payload, signature)
+        }
+        _ => return Err(Denied::Invalid("malformed token")),
+    };
+    let signed = &token[..header.len() + 1 + payload.len()];
+    let signature = decode_base64url(signature).ok_or(Denied::Invalid("malformed token"))?;
+    verify_signature(jwt, keys, &decode_json(header)?, signed, &signature)?;
+    let claims = decode_json(payload)?;
+    verify_claims(jwt, &claims, now)?;
+    Ok(claims)
+}
+
+fn authorize_at(
+    jwt: &Jwt,
+    keys: &JwtKeys,
+    path: &str,
+    authorization: Option<&str>,
+    required: Option<bool>,
+    now: f64,
+) -> Result<(), Denied> {
+    let rule = rule_for(&jwt.rules, path);
+    let scope = match (required, rule) {
+        (Some(false), _) => return Ok(()),
+        (_, Some(rule)) => rule.scope.as_ref(),
+        (Some(true), None) => None,
+        (None, None) => return Ok(()),
+    };
+    let token = match authorization.and_then(|value| value.split_once(' ')) {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => token.trim(),
+        _ => return Err(Denied::Missing),
+    };
+    let claims = verify_token(jwt, keys, token, now)?;
+    match scope {
+        Some(scope) if !has_scope(&claims, scope) => Err(Denied::Scope),
+        _ => Ok(()),
+    }
+}
+
+/// Check the bearer token in the `authorization` header if a rule covers the `path`.
+/// `required` is the title's own requirement: false lets anyone in and true
+/// requires a valid token even if no rule covers the path.
+pub fn authorize(
+    jwt: &Jwt,
+    keys: &JwtKeys,
+    path: &str,
+    authorization: Option<&str>,
+    required: Option<bool>,
+) -> Result<(), Denied> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs_f64())
+        .unwrap_or(0.0);
+    authorize_at(jwt, keys, path, authorization, required, now)
+}
+
+#[cfg(test)]
+mod jwt_tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn encode(bytes: &[u8]) -> String {
+        base64::encode_block(bytes)
+            .trim_end_matches('=')
+            .replace('+', "-")
+            .replace('/', "_")
+    }
+
+    fn token(header: Value, claims: Value, sign: impl Fn(&[u8]) -> Vec<u8>) -> String {
+        let signed = format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims.to_string().as_bytes())
+        );
+        format!("{}.{}", signed, encode(&sign(signed.as_bytes())))
+    }
+
+    fn hs256(secret: &str) -> impl Fn(&[u8]) -> Vec<u8> + '_ {
+        move |data| {
+            let key = PKey::hmac(secret.as_bytes()).unwrap();
+            let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+            signer.update(data).unwrap();
+            signer.sign_to_vec().unwrap()
+        }
+    }
+
+    fn config(algorithm: JwtAlgorithm) -> Jwt {
+        Jwt {
+            algorithm,
+            secret: Some("secret".to_string()),
+            public_key_file: None,
+            jwks_url: None,
+            issuer: Some("auth".to_string()),
+            audience: Some("dash".to_string()),
+            rules: vec![
+                JwtRule {
+                    prefix: "/live/".to_string(),
+                    scope: None,
+                },
+                JwtRule {
+                    prefix: "/live/premium/".to_string(),
+                    scope: Some("premium".to_string()),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn base64url() {
+        assert_eq!(decode_base64url("aGk_-w").unwrap(), b"hi?\xfb");
+        assert_eq!(decode_base64url("").unwrap(), b"");
+        assert_eq!(decode_base64url("aGk="), None);
+        assert_eq!(decode_base64url("aGk/+w"), None);
+        assert_eq!(decode_base64url("a"), None);
+    }
+
+    #[test]
+    fn hs256_tokens() {
+        let jwt = config(JwtAlgorithm::Hs256);
+        let keys = JwtKeys::new();
+        let header = json!({"alg": "HS256", "typ": "JWT"});
+        let claims =
+            json!({"iss": "auth", "aud": ["cdn", "dash"], "exp": 2000, "scope": "hd premium"});
+        let valid = format!(
+            "Bearer {}",
+            token(header.clone(), claims.clone(), hs256("secret"))
+        );
+        let check = |path: &str, authorization: Option<&str>, now: f64| {
+            authorize_at(&jwt, &keys, path, authorization, None, now)
+        };
+
+        assert_eq!(check("/vod/a.mp4", None, 1000.0), Ok(()));
+        assert_eq!(check("/live/a.mp4", None, 1000.0), Err(Denied::Missing));
+        assert_eq!(
+            check("/live/a.mp4", Some("Basic dXNlcjpwdw=="), 1000.0),
+            Err(Denied::Missing)
+        );
+        assert_eq!(check("/live/a.mp4", Some(&valid), 1000.0), Ok(()));
+        assert_eq!(check("/live/premium/a.mp4", Some(&valid), 1000.0), Ok(()));
+        assert_eq!(
+            check("/live/a.mp4", Some(&valid), 2000.0),
+            Err(Denied::Invalid("expired"))
+        );
+
+        let forged = format!("Bearer {}", token(header.clone(), claims, hs256("guess")));
+        assert_eq!(
+            check("/live/a.mp4", Some(&forged), 1000.0),
+            Err(Denied::Invalid("bad signature"))
+        );
+        let unsigned = token(json!({"alg": "none"}), json!({}), |_| vec![]);
+        assert_eq!(
+            check("/live/a.mp4", Some(&format!("Bearer {}", unsigned)), 1000.0),
+            Err(Denied::Invalid("unexpected algorithm"))
+        );
+        assert_eq!(
+            check("/live/a.mp4", Some("Bearer a.b"), 1000.0),
+            Err(Denied::Invalid("malformed token"))
+        );
+
+        let basic = json!({"iss": "auth", "aud": "dash", "scope": "hd"});
+        let basic = format!("Bearer {}", token(header.clone(), basic, hs256("secret")));
+        assert_eq!(check("/live/a.mp4", Some(&basic), 1000.0), Ok(()));
+        assert_eq!(
+            check("/live/premium/a.mp4", Some(&basic), 1000.0),
+            Err(Denied::Scope)
+        );
+
+        let other = json!({"iss": "auth", "aud": "cdn"});
+        let other = format!("Bearer {}", token(header, other, hs256("secret")));
+        assert_eq!(
+            check("/live/a.mp4", Some(&other), 1000.0),
+            Err(Denied::Invalid("wrong audience"))
+        );
+    }
+
+    #[test]
+    fn title_requirement() {
+        let jwt = config(JwtAlgorithm::Hs256);
+        let keys = JwtKeys::new();
+        let claims = json!({"iss": "auth", "aud": "dash"});
+        let valid = format!(
+            "Bearer {}",
+            token(json!({"alg": "HS256"}), claims, hs256("secret"))
+        );
+        let check = |path: &str, authorization: Option<&str>, required: Option<bool>| {
+            authorize_at(&jwt, &keys, path, authorization, required, 1000.0)
+        };
+
+        assert_eq!(check("/live/a.mp4", None, Some(false)), Ok(()));
+        assert_eq!(check("/vod/a.mp4", None, Some(true)), Err(Denied::Missing));
+        assert_eq!(check("/vod/a.mp4", Some(&valid), Some(true)), Ok(()));
+        // The scope of the rule is still needed
+        assert_eq!(
+            check("/live/premium/a.mp4", Some(&valid), Some(true)),
+            Err(Denied::Scope)
+        );
+    }
+
+    #[test]
+    fn stale_keys_while_loading() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let key = PKey::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+        let mut jwt = config(JwtAlgorithm::Rs256);
+        jwt.jwks_url = Some("http://127.0.0.1:9/jwks.json".to_string());
+        let keys = JwtKeys::new();
+        {
+            let mut loaded = keys.loaded.lock().unwrap();
+            loaded.source = jwt.jwks_url.clone().unwrap();
+            loaded.keys = vec![(Some("old".to_string()), key)];
+            loaded.at = Instant::now().checked_sub(JWKS_MAX_AGE);
+        }
+
+        // Another request is loading the keys so the old ones are used
+        let loading = keys.loading.lock().unwrap();
+        assert_eq!(keys.keys(&jwt, Some("old")).len(), 1);
+        let at = keys.loaded.lock().unwrap().at.unwrap();
+        assert!(at.elapsed() >= JWKS_MAX_AGE);
+        drop(loading);
+
+        // The failed fetch keeps the old keys but isn't tried again right away
+        assert_eq!(keys.keys(&jwt, Some("old")).len(), 1);
+        let at = keys.loaded.lock().unwrap().at.unwrap();
+        assert!(at.elapsed() < JWKS_MIN_INTERVAL);
+    }
+
+    #[test]
+    fn rs256_tokens() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let private = PKey::from_rsa(rsa.clone()).unwrap();
+        let mut key_file = std::env::temp_dir();
+        key_file.push(format!("mpeg-dash-jwt-{}.pem", std::process::id()));
+        fs::File::create(&key_file)
+            .unwrap()
+            .write_all(&rsa.public_key_to_pem().unwrap())
+            .unwrap();
+
+        let mut jwt = config(JwtAlgorithm::Rs256);
+        jwt.public_key_file = Some(key_file.to_str().unwrap().to_string());
+        let keys = JwtKeys::new();
+        let rs256 = |data: &[u8]| {
+            let mut signer = Signer::new(MessageDigest::sha256(), &private).unwrap();
+            signer.update(data).unwrap();
+            signer.sign_to_vec().unwrap()
+        };
+        let claims = json!({"iss": "auth", "aud": "dash"});
+        let valid = token(json!({"alg": "RS256"}), claims.clone(), rs256);
+        let check = |jwt: &Jwt, token: &str| {
+            authorize_at(
+                jwt,
+                &keys,
+                "/live/a.mp4",
+                Some(&format!("Bearer {}", token)),
+                None,
+                0.0,
+            )
+        };
+        assert_eq!(check(&jwt, &valid), Ok(()));
+
+        // A token signed with the public key as an HMAC secret
+        let public_pem = rsa.public_key_to_pem().unwrap();
+        let confused = token(
+            json!({"alg": "HS256"}),
+            claims,
+            hs256(std::str::from_utf8(&public_pem).unwrap()),
+        );
+        assert_eq!(
+            check(&jwt, &confused),
+            Err(Denied::Invalid("unexpected algorithm"))
+        );
+
+        // Keys of the old file aren't used once the config points elsewhere
+        jwt.public_key_file = Some("missing.pem".to_string());
+        assert_eq!(check(&jwt, &valid), Err(Denied::Invalid("bad signature")));
+        fs::remove_file(key_file).unwrap();
+    }
+}
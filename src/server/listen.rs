@@ -0,0 +1,213 @@
+use socket2::{Domain, SockRef, Socket, Type};
+use std::convert::TryFrom;
+use std::env;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::process;
+
+use crate::config::SocketOptions;
+
+/// First file descriptor passed by systemd, after stdin, stdout and stderr
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Socket passed by systemd. `name` is the FileDescriptorName of the socket unit
+pub struct Inherited {
+    pub listener: TcpListener,
+    pub name: Option<String>,
+}
+
+/// "address:port" with IPv6 literals in brackets, e.g. "[::1]:443"
+pub fn socket_address(address: &str, port: &str) -> String {
+    if address.contains(':') && !address.starts_with('[') {
+        format!("[{}]:{}", address, port)
+    } else {
+        format!("{}:{}", address, port)
+    }
+}
+
+/// Set the socket buffers of a listener. Accepted connections inherit them,
+/// the receive buffer has to be set before the handshake to have an effect.
+/// TCP_NODELAY is set on every connection when it's accepted.
+pub fn set_buffer_sizes(socket: SockRef, options: &SocketOptions) -> io::Result<()> {
+    if options.send_buffer_size > 0 {
+        socket.set_send_buffer_size(options.send_buffer_size)?;
+    }
+    if options.receive_buffer_size > 0 {
+        socket.set_recv_buffer_size(options.receive_buffer_size)?;
+    }
+    Ok(())
+}
+
+fn bind_socket(
+    address: SocketAddr,
+    only_v6: bool,
+    options: &SocketOptions,
+) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    socket.set_reuse_address(true)?;
+    set_buffer_sizes(SockRef::from(&socket), options)?;
+    socket.bind(&address.into())?;
+    socket.listen(i32::try_from(options.backlog).unwrap_or(i32::MAX))?;
+    Ok(socket.into())
+}
+
+/// Listen on `address` and `port`. With `dual_stack` a wildcard address
+/// ("0.0.0.0" or "::") is bound for both IPv4 and IPv6 with a socket each.
+/// Other addresses are bound as is.
+pub fn bind(
+    address: &str,
+    port: &str,
+    dual_stack: bool,
+    options: &SocketOptions,
+) -> io::Result<Vec<TcpListener>> {
+    let addresses: Vec<SocketAddr> = socket_address(address, port).to_socket_addrs()?.collect();
+    let first = *addresses.first().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            format!("{} has no addresses", address),
+        )
+    })?;
+
+    let dual_stack = dual_stack && first.ip().is_unspecified();
+    let listener = bind_socket(first, dual_stack, options)?;
+    if !dual_stack {
+        return Ok(vec![listener]);
+    }
+
+    // Same port for both even if it was picked by the system
+    let port = listener.local_addr()?.port();
+    let other = match first {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), port),
+        SocketAddr::V6(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+    };
+    let other = bind_socket(other, true, options)?;
+    Ok(vec![listener, other])
+}
+
+/// File descriptors and their names from the LISTEN_* variables like
+/// sd_listen_fds does. Empty if the sockets were meant for another process.
+fn listen_fds(
+    pid: Option<&str>,
+    fds: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+) -> Vec<(RawFd, Option<String>)> {
+    if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(own_pid) {
+        return vec![];
+    }
+    let count = fds.and_then(|fds| fds.parse::<RawFd>().ok()).unwrap_or(0);
+    let mut names = names.unwrap_or("").split(':');
+    (LISTEN_FDS_START..LISTEN_FDS_START + count.max(0))
+        .map(|fd| {
+            let name = names.next().filter(|name| !name.is_empty());
+            (fd, name.map(str::to_string))
+        })
+        .collect()
+}
+
+/// Listening sockets passed by systemd socket activation. systemd can then
+/// bind privileged ports and keep them open while the server restarts.
+pub fn systemd_sockets() -> Vec<Inherited> {
+    let var = |name| env::var(name).ok();
+    let fds = listen_fds(
+        var("LISTEN_PID").as_deref(),
+        var("LISTEN_FDS").as_deref(),
+        var("LISTEN_FDNAMES").as_deref(),
+        process::id(),
+    );
+    fds.into_iter()
+        .map(|(fd, name)| {
+            // Not passed on to child processes
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+            Inherited {
+                listener: unsafe { TcpListener::from_raw_fd(fd) },
+                name,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod listen_tests {
+    use super::*;
+    use std::net::TcpStream;
+
+    fn options() -> SocketOptions {
+        SocketOptions {
+            tcp_no_delay: false,
+            send_buffer_size: 0,
+            receive_buffer_size: 0,
+            backlog: 128,
+        }
+    }
+
+    #[test]
+    fn ipv6_addresses() {
+        assert_eq!(socket_address("0.0.0.0", "443"), "0.0.0.0:443");
+        assert_eq!(socket_address("::", "443"), "[::]:443");
+        assert_eq!(socket_address("[::1]", "443"), "[::1]:443");
+        assert_eq!(socket_address("localhost", "80"), "localhost:80");
+    }
+
+    #[test]
+    fn systemd_variables() {
+        assert_eq!(
+            listen_fds(Some("42"), Some("2"), Some("https:redirect"), 42),
+            vec![
+                (3, Some("https".to_string())),
+                (4, Some("redirect".to_string()))
+            ]
+        );
+        assert_eq!(
+            listen_fds(Some("42"), Some("2"), None, 42),
+            vec![(3, None), (4, None)]
+        );
+        // Meant for another process
+        assert_eq!(listen_fds(Some("41"), Some("2"), None, 42), vec![]);
+        assert_eq!(listen_fds(None, None, None, 42), vec![]);
+    }
+
+    #[test]
+    fn single_stack() {
+        let listeners = bind("::1", "0", true, &options()).unwrap();
+        // Not a wildcard address so only that one is bound
+        assert_eq!(listeners.len(), 1);
+        let port = listeners[0].local_addr().unwrap().port();
+        assert!(TcpStream::connect(("::1", port)).is_ok());
+    }
+
+    #[test]
+    fn dual_stack() {
+        let listeners = bind("0.0.0.0", "0", true, &options()).unwrap();
+        assert_eq!(listeners.len(), 2);
+        let v4 = listeners[0].local_addr().unwrap();
+        let v6 = listeners[1].local_addr().unwrap();
+        assert!(v4.is_ipv4());
+        assert!(v6.is_ipv6());
+        assert_eq!(v4.port(), v6.port());
+        assert!(TcpStream::connect(("127.0.0.1", v4.port())).is_ok());
+        assert!(TcpStream::connect(("::1", v6.port())).is_ok());
+    }
+
+    #[test]
+    fn buffer_sizes() {
+        let options = SocketOptions {
+            send_buffer_size: 300_000,
+            receive_buffer_size: 200_000,
+            backlog: 16,
+            ..options()
+        };
+        let listeners = bind("127.0.0.1", "0", false, &options).unwrap();
+        let port = listeners[0].local_addr().unwrap().port();
+        let _client = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let (stream, _) = listeners[0].accept().unwrap();
+        // Linux doubles the size for its own bookkeeping
+        let socket = SockRef::from(&stream);
+        assert!(socket.send_buffer_size().unwrap() >= 300_000);
+        assert!(socket.recv_buffer_size().unwrap() >= 200_000);
+    }
+}
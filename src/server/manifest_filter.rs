@@ -0,0 +1,149 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, Writer};
+
+use super::content_protection::attribute;
+use crate::config::ManifestFilter;
+
+/// Numeric value of the attribute `name` in the element
+fn number(element: &BytesStart, name: &str) -> Option<u64> {
+    attribute(element, name)?.parse().ok()
+}
+
+/// Whether the representation is over a limit of the `filter`.
+/// `set_height` is the height of the adaptation set it's in.
+fn is_filtered(
+    representation: &BytesStart,
+    set_height: Option<u64>,
+    filter: &ManifestFilter,
+) -> bool {
+    let bandwidth = number(representation, "bandwidth");
+    let height = number(representation, "height").or(set_height);
+    let over = |value: Option<u64>, limit: Option<u64>| match (value, limit) {
+        (Some(value), Some(limit)) => value > limit,
+        _ => false,
+    };
+    over(bandwidth, filter.max_bandwidth) || over(height, filter.max_height)
+}
+
+/// `mpd` without the representations over the limits of `filter`. Everything
+/// else is written back as it was.
+pub fn apply(mpd: &str, filter: &ManifestFilter) -> Result<String, String> {
+    let mut reader = Reader::from_str(mpd);
+    let mut writer = Writer::new(Vec::new());
+    // Depth of the elements that are open
+    let mut depth = 0;
+    // Depth of the representation whose events are left out
+    let mut skipped: Option<usize> = None;
+    let mut set_height = None;
+    // Whitespace is written with the next element so it goes away with a
+    // representation that is left out
+    let mut whitespace: Vec<u8> = vec![];
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| format!("Invalid manifest: {}", e))?;
+        let next_depth = match &event {
+            Event::Start(_) => depth + 1,
+            Event::End(_) => depth - 1,
+            _ => depth,
+        };
+        if let (Some(skipped_depth), false) = (skipped, event == Event::Eof) {
+            if next_depth == skipped_depth {
+                skipped = None;
+            }
+            depth = next_depth;
+            continue;
+        }
+
+        let left_out = match &event {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name().as_ref() == b"Representation" =>
+            {
+                is_filtered(element, set_height, filter)
+            }
+            _ => false,
+        };
+        match &event {
+            Event::Start(element) if element.local_name().as_ref() == b"AdaptationSet" => {
+                set_height = number(element, "height");
+            }
+            Event::Text(text) if text.iter().all(u8::is_ascii_whitespace) => {
+                whitespace.extend_from_slice(text);
+                depth = next_depth;
+                continue;
+            }
+            _ => (),
+        }
+
+        if left_out {
+            if let Event::Start(_) = event {
+                skipped = Some(depth);
+            }
+        } else {
+            writer.get_mut().append(&mut whitespace);
+            match event {
+                Event::Eof => break,
+                event => writer
+                    .write_event(event)
+                    .map_err(|e| format!("Cannot write the manifest: {}", e))?,
+            }
+        }
+        whitespace.clear();
+        depth = next_depth;
+    }
+    String::from_utf8(writer.into_inner()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod manifest_filter_tests {
+    use super::*;
+    use crate::mpd;
+
+    const DASH_DOCUMENT: &str = "test_data/unit_test_dash_document.mpd";
+
+    const LADDER: &str = "<MPD>\n  <Period>\n    \
+        <AdaptationSet mimeType=\"video/mp4\" height=\"720\">\n      \
+        <Representation id=\"low\" bandwidth=\"500000\" height=\"360\"/>\n      \
+        <Representation id=\"high\" bandwidth=\"3000000\">\n        \
+        <BaseURL>high/</BaseURL>\n      </Representation>\n    </AdaptationSet>\n  \
+        </Period>\n</MPD>\n";
+
+    fn filter(max_bandwidth: Option<u64>, max_height: Option<u64>) -> ManifestFilter {
+        ManifestFilter {
+            max_bandwidth,
+            max_height,
+        }
+    }
+
+    #[test]
+    fn representations_over_limits() {
+        let low_only = "<MPD>\n  <Period>\n    \
+            <AdaptationSet mimeType=\"video/mp4\" height=\"720\">\n      \
+            <Representation id=\"low\" bandwidth=\"500000\" height=\"360\"/>\n    \
+            </AdaptationSet>\n  </Period>\n</MPD>\n";
+        assert_eq!(
+            apply(LADDER, &filter(Some(1000000), None)).unwrap(),
+            low_only
+        );
+        // The height of the set applies to the representations without their own
+        assert_eq!(apply(LADDER, &filter(None, Some(480))).unwrap(), low_only);
+        assert_eq!(apply(LADDER, &filter(None, Some(720))).unwrap(), LADDER);
+        assert_eq!(apply(LADDER, &filter(None, None)).unwrap(), LADDER);
+    }
+
+    #[test]
+    fn filtered_document() {
+        let document = std::fs::read_to_string(DASH_DOCUMENT).unwrap();
+        let filtered = apply(&document, &filter(Some(200000), None)).unwrap();
+        let manifest = mpd::parse(&filtered).unwrap();
+        let representations: Vec<&str> = manifest.periods[0]
+            .adaptation_sets
+            .iter()
+            .flat_map(|set| &set.representations)
+            .map(|representation| &representation.id[..])
+            .collect();
+        assert_eq!(representations, ["audio/und/mp4a"]);
+        assert!(apply("<MPD><Period></MPD>", &filter(None, None)).is_err());
+    }
+}
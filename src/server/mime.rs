@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+/// Content type of files without a known extension
+const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// Content types of the files used in DASH and HLS streaming
+const BUILT_IN_TYPES: &[(&str, &str)] = &[
+    ("mpd", "application/dash+xml"),
+    ("m4s", "video/iso.segment"),
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("m4a", "audio/mp4"),
+    ("cmfv", "video/mp4"),
+    ("cmfa", "audio/mp4"),
+    ("webm", "video/webm"),
+    ("ts", "video/mp2t"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("vtt", "text/vtt"),
+    ("ttml", "application/ttml+xml"),
+    ("xml", "application/xml"),
+    ("json", "application/json"),
+];
+
+/// Content type of the file in `path` by its extension. The configured
+/// `types` are checked first so they can add and replace types.
+pub fn content_type<'a>(types: &'a BTreeMap<String, String>, path: &str) -> &'a str {
+    let name = &path[path.rfind('/').map(|pos| pos + 1).unwrap_or(0)..];
+    let extension = match name.rfind('.') {
+        Some(pos) => name[pos + 1..].to_ascii_lowercase(),
+        None => return DEFAULT_TYPE,
+    };
+
+    if let Some(content_type) = types.get(&extension) {
+        return content_type;
+    }
+    BUILT_IN_TYPES
+        .iter()
+        .find(|(built_in, _)| *built_in == extension)
+        .map(|(_, content_type)| *content_type)
+        .unwrap_or(DEFAULT_TYPE)
+}
+
+#[cfg(test)]
+mod mime_tests {
+    use super::*;
+
+    #[test]
+    fn built_in_types() {
+        let types = BTreeMap::new();
+        assert_eq!(content_type(&types, "a/stream.mpd"), "application/dash+xml");
+        assert_eq!(content_type(&types, "a/seg-1.M4S"), "video/iso.segment");
+        assert_eq!(
+            content_type(&types, "a.b/segment"),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            content_type(&types, "a/file.unknown"),
+            "application/octet-stream"
+        );
+    }
+
+    #[test]
+    fn configured_types() {
+        let mut types = BTreeMap::new();
+        types.insert("m4s".to_string(), "video/mp4".to_string());
+        types.insert("srt".to_string(), "application/x-subrip".to_string());
+        assert_eq!(content_type(&types, "seg-1.m4s"), "video/mp4");
+        assert_eq!(content_type(&types, "sub.srt"), "application/x-subrip");
+        assert_eq!(content_type(&types, "init.mp4"), "video/mp4");
+    }
+}
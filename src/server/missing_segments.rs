@@ -0,0 +1,178 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::mpd;
+
+/// Missing urls kept per manifest. The rest are only counted.
+const MAX_LISTED: usize = 100;
+/// Missing urls written to the log per manifest
+const MAX_LOGGED: usize = 5;
+
+/// Result of checking one version of a manifest
+struct Checked {
+    modified: SystemTime,
+    missing: usize,
+    /// The first MAX_LISTED missing urls
+    listed: Vec<String>,
+}
+
+/// Finds the segments of the served manifests that don't exist. A missing
+/// segment in the middle of a stream stalls the players without any error.
+pub struct MissingSegments {
+    manifests: Mutex<HashMap<String, Checked>>,
+}
+
+/// Segment urls of the manifest in `xml` that have no file under `root`.
+/// The urls are relative to `relative_path`, the path of the manifest.
+fn find_missing(root: &str, relative_path: &str, xml: &str) -> Result<Vec<String>, String> {
+    let manifest = mpd::parse(xml)?;
+    let mut missing = vec![];
+    for representation in manifest.segment_urls() {
+        let urls = representation
+            .initialization
+            .iter()
+            .chain(representation.media.iter());
+        for url in urls {
+            let path = match mpd::join_url(relative_path, url) {
+                Some(path) => path,
+                None => continue,
+            };
+            // The query picks a part of the file, like a WebVTT segment
+            let file = path.split('?').next().unwrap_or("");
+            if !Path::new(root).join(file.trim_start_matches('/')).is_file() {
+                missing.push(path);
+            }
+        }
+    }
+    Ok(missing)
+}
+
+impl MissingSegments {
+    pub fn new() -> MissingSegments {
+        MissingSegments {
+            manifests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check the segments of the manifest `xml` in `relative_path` under
+    /// `root`, unless this version has been checked already
+    pub fn check(&self, root: &str, relative_path: &str, modified: SystemTime, xml: &str) {
+        let key = format!("{}/{}", root.trim_end_matches('/'), relative_path);
+        if let Some(checked) = self.manifests.lock().unwrap().get(&key) {
+            if checked.modified == modified {
+                return;
+            }
+        }
+
+        let missing = match find_missing(root, relative_path, xml) {
+            Ok(missing) => missing,
+            Err(e) => {
+                warn!("Cannot check the segments of {}: {}", key, e);
+                vec![]
+            }
+        };
+        if !missing.is_empty() {
+            let shown: Vec<&str> = missing.iter().take(MAX_LOGGED).map(|u| &u[..]).collect();
+            warn!(
+                "{} segments of {} are missing: {}{}",
+                missing.len(),
+                key,
+                shown.join(", "),
+                if missing.len() > MAX_LOGGED {
+                    ", ..."
+                } else {
+                    ""
+                }
+            );
+        }
+        self.manifests.lock().unwrap().insert(
+            key,
+            Checked {
+                modified,
+                missing: missing.len(),
+                listed: missing.into_iter().take(MAX_LISTED).collect(),
+            },
+        );
+    }
+
+    /// The manifests with missing segments, for the admin API
+    pub fn to_json(&self) -> Value {
+        let manifests = self.manifests.lock().unwrap();
+        let listed: BTreeMap<&String, Value> = manifests
+            .iter()
+            .filter(|(_, checked)| checked.missing > 0)
+            .map(|(path, checked)| {
+                let value = json!({ "missing": checked.missing, "urls": checked.listed });
+                (path, value)
+            })
+            .collect();
+        json!({ "manifests": listed })
+    }
+
+    pub fn clear(&self) {
+        self.manifests.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod missing_segments_tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const MANIFEST: &str = "generated/vod/manifest.mpd";
+
+    fn manifest() -> String {
+        r#"<MPD profiles="urn:mpeg:dash:profile:isoff-live:2011" minBufferTime="PT2S"
+            mediaPresentationDuration="PT6S">
+          <Period>
+            <AdaptationSet mimeType="video/mp4">
+              <SegmentTemplate timescale="1" duration="2" media="$RepresentationID$/seg-$Number$.m4s"
+                  initialization="$RepresentationID$/init.mp4"/>
+              <Representation id="video-360" bandwidth="1"/>
+              <Representation id="video-1080" bandwidth="1"/>
+            </AdaptationSet>
+          </Period>
+        </MPD>"#
+            .to_string()
+    }
+
+    #[test]
+    fn missing_urls() {
+        let missing = find_missing("test_data", MANIFEST, &manifest()).unwrap();
+        assert_eq!(
+            missing,
+            [
+                "generated/vod/video-1080/init.mp4",
+                "generated/vod/video-1080/seg-1.m4s",
+                "generated/vod/video-1080/seg-2.m4s",
+                "generated/vod/video-1080/seg-3.m4s",
+            ]
+        );
+        assert!(find_missing("test_data", MANIFEST, "<MPD").is_err());
+    }
+
+    #[test]
+    fn checked_once_per_version() {
+        let checker = MissingSegments::new();
+        let xml = manifest();
+        checker.check("test_data/", MANIFEST, UNIX_EPOCH, &xml);
+        let json = checker.to_json();
+        let checked = &json["manifests"]["test_data/generated/vod/manifest.mpd"];
+        assert_eq!(checked["missing"], 4);
+        assert_eq!(checked["urls"][0], "generated/vod/video-1080/init.mp4");
+
+        // The same version isn't parsed again
+        checker.check("test_data", MANIFEST, UNIX_EPOCH, "<MPD");
+        assert_eq!(checker.to_json(), json);
+
+        let fixed = xml.replace("video-1080", "video-720");
+        let modified = UNIX_EPOCH + Duration::from_secs(1);
+        checker.check("test_data", MANIFEST, modified, &fixed);
+        assert_eq!(checker.to_json(), json!({ "manifests": {} }));
+    }
+}
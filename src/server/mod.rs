@@ -1,221 +1,2193 @@
 use openssl::ssl;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslStream};
-use std::fs;
-use std::io::{Write};
-use std::net::{TcpListener, TcpStream};
-use std::sync::Arc;
-use std::time::Duration;
-
-use crate::config;
+use socket2::SockRef;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::config::{self, Config, ManifestFilter, SharedConfig};
+use crate::mpd;
+use log::{debug, error, info, warn};
 use mpeg_dash::ThreadPool;
 
-const MAX_REQUEST_SIZE: usize = 4096;
+mod access_log;
+mod address_limit;
+mod admin;
+mod basic_auth;
+mod body;
+mod chunked;
+mod clear_key;
+mod compression;
+mod conditional;
+mod content_protection;
+mod cors;
+mod digest_cache;
+mod error_page;
+mod file_cache;
+mod generate;
+mod header_rules;
+mod hls;
+mod hotlink;
+mod in_progress;
+mod ingest;
+mod ip_filter;
+mod jwks;
+mod jwt;
+mod listen;
+mod manifest_filter;
+mod mime;
+mod missing_segments;
+mod mp4;
+mod not_found_cache;
+mod poll;
+mod prefetch;
+mod preload;
+mod proxy;
+mod quota;
+mod range;
+mod rate_limit;
+mod redirect;
+mod request;
+mod request_id;
+mod rewrite;
+mod root;
+mod shutdown;
+mod sidecar;
+mod signed_url;
+mod stats;
+mod stream;
+mod tenant;
+mod tls;
+mod transfer;
+mod watch;
+mod webvtt;
 
-/// Is the last 4 bytes the end of the http header
-/// TODO: may not be usable if support for POST requests are added
-fn is_end_of_header(buffer: &[u8]) -> bool {
-    if buffer.len() < 4 {
-        return false;
-    }
+pub use access_log::AccessLog;
+use access_log::Recorder;
+use address_limit::AddressConnections;
+use basic_auth::Credentials;
+use body::{BodyReader, Framing, FramingError};
+use chunked::ChunkedWriter;
+use clear_key::{ClearKeys, LicenseError};
+use compression::Encoding;
+use digest_cache::DigestCache;
+use file_cache::FileCache;
+use generate::{Generated, Generator};
+use jwt::{Denied, JwtKeys};
+use missing_segments::MissingSegments;
+use not_found_cache::NotFoundCache;
+use prefetch::Prefetcher;
+use quota::Quotas;
+use range::RangeRequest;
+use rate_limit::RateLimiter;
+use request::{Expectation, Request};
+use rewrite::Rewriter;
+use shutdown::Shutdown;
+pub use shutdown::Stopper;
+use sidecar::{Sidecars, TitleSettings};
+use stats::{ContentKind, Stats};
+use stream::ClientStream;
+use tls::{SniCertificate, TlsAcceptor};
+use watch::{Change, Watcher};
 
-    // HTTP standard defines http header end as "\r\n\r\n"
-    let end = &['\r' as u8, '\n' as u8, '\r' as u8, '\n' as u8];
-    let mut temp_buf = &buffer[..];
-    while !temp_buf.is_empty() {
-        if temp_buf.ends_with(end) {
-            return true;
-        }
-        temp_buf = &temp_buf[..temp_buf.len() - 1];
-    }
+/// Bytes read from the client at a time while reading the request head
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// How long the response to a connection over a limit can take to write
+const REJECT_WRITE_TIMEOUT: Duration = Duration::from_millis(100);
 
-    false
-    // This could be used if we could make sure that the buffer doesn't contain any body data
-    // The method above is actually only really slower IF there is body data
-    // buffer[buffer.len() - 4..buffer.len()] == end
+/// Find the end of the http header.
+/// Returns the length of the header including the empty line at the end.
+fn end_of_header(buffer: &[u8]) -> Option<usize> {
+    // HTTP standard defines http header end as "\r\n\r\n"
+    let end = b"\r\n\r\n";
+    buffer
+        .windows(end.len())
+        .position(|window| window == end)
+        .map(|pos| pos + end.len())
 }
 
 /// Check if the error happend in I/O (false) or in ssl/tsl stack (true)
-fn is_ssl_error(error: ssl::Error) -> bool {
-    // Result returns ssl::Error as Result Err and io::Error as Ok
-    error.into_io_error().is_err()
+fn is_tls_error(error: &io::Error) -> bool {
+    // SslStream wraps the errors that aren't I/O errors in io::Error
+    error
+        .get_ref()
+        .map(|inner| inner.is::<ssl::Error>())
+        .unwrap_or(false)
+}
+
+/// Address of the client for the log messages
+fn peer(stream: &impl ClientStream) -> String {
+    stream
+        .tcp()
+        .peer_addr()
+        .map(|peer| peer.to_string())
+        .unwrap_or_else(|_| "-".to_string())
 }
 
-/// 404 File not found
-fn response_404(mut stream: SslStream<TcpStream>) {
+/// 400 Bad Request
+fn response_400(stream: &mut impl Write) {
     stream
-        .write("HTTP/1.1 404 NOT FOUND\r\n\r\n".as_bytes())
+        .write_all("HTTP/1.1 400 BAD REQUEST\r\n\r\n".as_bytes())
         .unwrap();
 }
 
+/// 401 Unauthorized with the WWW-Authenticate `challenge`
+fn response_401(stream: &mut impl Write, challenge: &str) {
+    let out = format!(
+        "HTTP/1.1 401 UNAUTHORIZED\r\nWWW-Authenticate: {}\r\nContent-Length: 0\r\n\r\n",
+        challenge
+    );
+    stream.write_all(out.as_bytes()).unwrap();
+}
+
+/// 403 Forbidden with the configured error page
+fn response_403(stream: &mut impl Write, config: &Config, is_head: bool) {
+    let out = error_page::response(config, "403 FORBIDDEN", is_head);
+    stream.write_all(&out).unwrap();
+}
+
+/// 404 File not found with the configured error page
+fn response_404(stream: &mut impl Write, config: &Config, is_head: bool) {
+    let out = error_page::response(config, "404 NOT FOUND", is_head);
+    stream.write_all(&out).unwrap();
+}
+
 /// 408 Request Timeout
-fn response_408(mut stream: SslStream<TcpStream>) {
+fn response_408(stream: &mut impl Write) {
     stream
-        .write("HTTP/1.1 408 REQUEST TIMEOUT\r\n\r\n".as_bytes())
+        .write_all("HTTP/1.1 408 REQUEST TIMEOUT\r\n\r\n".as_bytes())
         .unwrap();
 }
 
 /// 413 Payload Too Large
-fn response_413(mut stream: SslStream<TcpStream>) {
+fn response_413(stream: &mut impl Write) {
+    stream
+        .write_all("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n\r\n".as_bytes())
+        .unwrap();
+}
+
+/// 414 URI Too Long
+fn response_414(stream: &mut impl Write) {
     stream
-        .write("HTTP/1.1 413 PAYLOAD TOO LARGE\r\n\r\n".as_bytes())
+        .write_all("HTTP/1.1 414 URI TOO LONG\r\n\r\n".as_bytes())
         .unwrap();
 }
 
-fn handle_client(mut stream: SslStream<TcpStream>) {
-    let config = config::GlobalConfig::config();
+/// 417 Expectation Failed
+fn response_417(stream: &mut impl Write) {
+    stream
+        .write_all("HTTP/1.1 417 EXPECTATION FAILED\r\n\r\n".as_bytes())
+        .unwrap();
+}
 
-    // SslStream doesn't have a timeout so we need to set it to the underlying TcpStream
+/// 429 Too Many Requests
+fn response_429(stream: &mut impl Write) {
     stream
-        .get_ref()
-        .set_read_timeout(Some(Duration::from_secs_f64(
-            config.performance.connection_timeout,
-        )))
+        .write_all("HTTP/1.1 429 TOO MANY REQUESTS\r\n\r\n".as_bytes())
         .unwrap();
+}
+
+/// 429 Too Many Requests from the rate limit. `wait` is the time until the
+/// client can make another request
+fn response_429_retry(stream: &mut impl Write, wait: Duration) {
+    let seconds = wait.as_secs_f64().ceil().max(1.0);
+    let out = format!(
+        "HTTP/1.1 429 TOO MANY REQUESTS\r\nRetry-After: {}\r\n\r\n",
+        seconds
+    );
+    stream.write_all(out.as_bytes()).unwrap();
+}
+
+/// 431 Request Header Fields Too Large
+fn response_431(stream: &mut impl Write) {
+    stream
+        .write_all("HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE\r\n\r\n".as_bytes())
+        .unwrap();
+}
+
+/// 500 Internal Server Error with the configured error page
+fn response_500(stream: &mut impl Write, config: &Config, is_head: bool) {
+    let out = error_page::response(config, "500 INTERNAL SERVER ERROR", is_head);
+    stream.write_all(&out).unwrap();
+}
+
+/// 501 Not Implemented
+fn response_501(stream: &mut impl Write) {
+    stream
+        .write_all("HTTP/1.1 501 NOT IMPLEMENTED\r\n\r\n".as_bytes())
+        .unwrap();
+}
+
+/// 505 HTTP Version Not Supported
+fn response_505(stream: &mut impl Write) {
+    stream
+        .write_all("HTTP/1.1 505 HTTP VERSION NOT SUPPORTED\r\n\r\n".as_bytes())
+        .unwrap();
+}
+
+/// Reader for the request body. `leftover` is the part of the body that
+/// was already read from the stream with the request head. The trailers
+/// of chunked bodies have the same limit as the head.
+fn request_body<'a, S: Read>(
+    stream: &'a mut S,
+    leftover: &'a [u8],
+    framing: Framing,
+    max_size: u64,
+    config: &Config,
+) -> BodyReader<io::Chain<&'a [u8], &'a mut S>> {
+    let max_trailer_size = config.performance.max_request_size;
+    BodyReader::new(leftover.chain(stream), framing, max_size, max_trailer_size)
+}
+
+/// Read and throw away the request body after an error response.
+/// Closing the connection with unread data makes the kernel reset the
+/// connection which can make the client lose the response we just sent.
+/// Gives up after performance.headerTimeout so a slow body can't hold the worker.
+fn discard_body(
+    stream: &mut impl ClientStream,
+    leftover: &[u8],
+    framing: Framing,
+    config: &Config,
+) {
+    let deadline = Instant::now() + Duration::from_secs_f64(config.performance.header_timeout);
+    let connection_timeout = Duration::from_secs_f64(config.performance.connection_timeout);
+    let max_size = config.performance.max_body_size;
+    let mut body = request_body(stream, leftover, framing, max_size, config);
+    let mut buf = [0_u8; READ_BUFFER_SIZE];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            debug!("Stopped reading a request body after an error response");
+            return;
+        }
+        // Only fails if the client is already gone
+        let tcp = body.get_mut().get_mut().1.tcp();
+        let _ = tcp.set_read_timeout(Some(connection_timeout.min(left)));
+        // The response has already been sent so errors don't matter here
+        match body.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => (),
+        }
+    }
+}
+
+/// Methods in the Allow header
+const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Cache-Control for fingerprinted paths. Content behind the url never changes
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Header lines for the configured caching headers that the `custom`
+/// headers of the path don't replace
+fn cache_headers(
+    headers: &config::CacheHeaders,
+    immutable: bool,
+    custom: &[(&str, &str)],
+) -> String {
+    let mut out = String::new();
+    let cache_control = if immutable {
+        Some(IMMUTABLE_CACHE_CONTROL.to_string())
+    } else {
+        headers.cache_control.clone()
+    };
+    let values = [
+        ("Cache-Control", &cache_control),
+        ("Surrogate-Control", &headers.surrogate_control),
+        ("CDN-Cache-Control", &headers.cdn_cache_control),
+    ];
+    for (name, value) in values.iter() {
+        if let Some(value) = value {
+            if !header_rules::is_replaced(custom, name) {
+                out.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+    }
+    out
+}
+
+/// Connection header line telling the client what happens to the connection.
+/// Only needed when it differs from the default of the HTTP version
+fn connection_header(keep_alive: bool, request: &Request) -> &'static str {
+    if !keep_alive {
+        "Connection: close\r\n"
+    } else if request.version == "HTTP/1.0" {
+        "Connection: keep-alive\r\n"
+    } else {
+        ""
+    }
+}
+
+/// Open the regular file in `path`
+fn open_file(path: &str) -> Option<(File, fs::Metadata)> {
+    let file = File::open(path).ok()?;
+    match file.metadata() {
+        Ok(metadata) if metadata.is_file() => Some((file, metadata)),
+        _ => None,
+    }
+}
+
+/// The manifest generated for `file_path` when it doesn't exist.
+/// `url_path` tells if it's a live stream.
+fn generated_manifest(
+    state: &ServerState,
+    config: &Config,
+    root: &str,
+    file_path: &str,
+    url_path: &str,
+) -> Option<Arc<Generated>> {
+    let settings = config.generated_manifests.as_ref()?;
+    let path = Path::new(file_path);
+    let dir = path.parent().unwrap_or_else(|| Path::new(root));
+    let is_generated = path.file_name() == Some(settings.file_name.as_ref())
+        && !path.exists()
+        && root::is_inside(root, &dir.to_string_lossy());
+    if !is_generated {
+        return None;
+    }
+    let settings = generate::Settings::new(settings, url_path);
+    state.generator.manifest(dir, &settings)
+}
+
+/// The XML and the modification time of the manifest, `generated` or the
+/// file at `file_path`
+fn read_manifest(
+    root: &str,
+    file_path: &str,
+    generated: Option<&Generated>,
+) -> Option<(String, SystemTime)> {
+    if let Some(manifest) = generated {
+        let xml = String::from_utf8_lossy(&manifest.data).into_owned();
+        return Some((xml, manifest.modified));
+    }
+    if !root::is_inside(root, file_path) {
+        return None;
+    }
+    let (mut file, metadata) = open_file(file_path)?;
+    let mut xml = String::new();
+    file.read_to_string(&mut xml).ok()?;
+    Some((xml, metadata.modified().ok()?))
+}
+
+/// The manifest at `file_path`, `generated` or the file, with the
+/// ContentProtection elements of the encrypted streams that `url_path`
+/// belongs to. None if the manifest is served as it is.
+fn protected_manifest(
+    config: &Config,
+    root: &str,
+    file_path: &str,
+    url_path: &str,
+    generated: Option<Arc<Generated>>,
+) -> Option<Arc<Generated>> {
+    let streams = content_protection::for_manifest(&config.content_protection, url_path);
+    if streams.is_empty() || !file_path.ends_with(".mpd") {
+        return generated;
+    }
+    let (xml, modified) = match read_manifest(root, file_path, generated.as_deref()) {
+        Some(manifest) => manifest,
+        None => return generated,
+    };
+    match content_protection::inject(&xml, &streams) {
+        Ok(xml) => Some(Arc::new(Generated {
+            data: Arc::new(xml.into_bytes()),
+            modified,
+        })),
+        Err(e) => {
+            warn!("Cannot add ContentProtection to {}: {}", file_path, e);
+            generated
+        }
+    }
+}
+
+/// The manifest at `file_path`, `generated` or the file, without the
+/// representations over the limits of `filter`. None if the manifest is
+/// served as it is.
+fn filtered_manifest(
+    root: &str,
+    file_path: &str,
+    filter: &ManifestFilter,
+    generated: Option<Arc<Generated>>,
+) -> Option<Arc<Generated>> {
+    if !file_path.ends_with(".mpd") {
+        return generated;
+    }
+    let (xml, modified) = match read_manifest(root, file_path, generated.as_deref()) {
+        Some(manifest) => manifest,
+        None => return generated,
+    };
+    match manifest_filter::apply(&xml, filter) {
+        Ok(xml) => Some(Arc::new(Generated {
+            data: Arc::new(xml.into_bytes()),
+            modified,
+        })),
+        Err(e) => {
+            warn!("Cannot filter the representations of {}: {}", file_path, e);
+            generated
+        }
+    }
+}
+
+/// The HLS playlist at `file_path` made from the DASH manifest with the same
+/// name, without the representations over the limits of `filter`. The
+/// representation query parameter picks a media playlist.
+fn hls_playlist(
+    state: &ServerState,
+    config: &Config,
+    root: &str,
+    file_path: &str,
+    filter: Option<&ManifestFilter>,
+    request: &Request,
+) -> Option<Arc<Generated>> {
+    let name = file_path.strip_suffix(hls::PLAYLIST_EXTENSION)?;
+    let manifest_path = format!("{}.mpd", name);
+    let mut generated = generated_manifest(state, config, root, &manifest_path, &request.path);
+    if let Some(filter) = filter {
+        generated = filtered_manifest(root, &manifest_path, filter, generated);
+    }
+    let (xml, modified) = read_manifest(root, &manifest_path, generated.as_deref())?;
+    let manifest = match mpd::parse(&xml) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            warn!("Cannot make HLS playlists of {}: {}", manifest_path, e);
+            return None;
+        }
+    };
+    let playlist = match request.query_param(hls::REPRESENTATION_PARAM) {
+        Some(id) => hls::media_playlist(&manifest, id)?,
+        None => {
+            let playlist_name = Path::new(file_path).file_name()?.to_string_lossy();
+            hls::master_playlist(&manifest, &playlist_name)?
+        }
+    };
+    Some(Arc::new(Generated {
+        data: Arc::new(playlist.into_bytes()),
+        modified,
+    }))
+}
+
+/// Segment `number` of the WebVTT file at `file_path` split into
+/// `segment_duration` long segments. None if there's no such segment.
+fn subtitle_segment(
+    root: &str,
+    file_path: &str,
+    number: &str,
+    segment_duration: f64,
+) -> Option<Arc<Generated>> {
+    if !root::is_inside(root, file_path) {
+        return None;
+    }
+    let (mut file, metadata) = open_file(file_path)?;
+    let mut data = String::new();
+    file.read_to_string(&mut data).ok()?;
+    let segment = webvtt::segment(&data, number.parse().ok()?, segment_duration)?;
+    Some(Arc::new(Generated {
+        data: Arc::new(segment.into_bytes()),
+        modified: metadata.modified().ok()?,
+    }))
+}
+
+/// State shared by all the connections
+struct ServerState {
+    not_found: NotFoundCache,
+    digests: DigestCache,
+    files: FileCache,
+    prefetcher: Prefetcher,
+    sidecars: Sidecars,
+    quotas: Quotas,
+    shutdown: Arc<Shutdown>,
+    access_log: Option<AccessLog>,
+    stats: Stats,
+    rate_limiter: RateLimiter,
+    jwt_keys: JwtKeys,
+    credentials: Credentials,
+    clear_keys: ClearKeys,
+    rewriter: Rewriter,
+    generator: Generator,
+    missing_segments: MissingSegments,
+    addresses: Arc<AddressConnections>,
+}
+
+/// What happens to the connection after a response
+#[derive(Clone, Copy, PartialEq)]
+enum Connection {
+    /// Wait for the next request
+    KeepAlive,
+    Close,
+}
+
+/// What a socket in the accept loop is for. The numbers are indexes to the
+/// listeners of the kind
+#[derive(Clone, Copy)]
+enum Source {
+    Stop,
+    Client(usize),
+    Redirect(usize),
+    Admin(usize),
+    Watch,
+}
+
+/// Accept a connection on a listener the accept loop found ready. None if
+/// there was nothing to accept after all.
+fn accept(listener: &TcpListener) -> Option<TcpStream> {
+    match listener.accept() {
+        // Some systems give the accepted socket the O_NONBLOCK of the listener
+        Ok((stream, _)) => match stream.set_nonblocking(false) {
+            Ok(()) => Some(stream),
+            Err(e) => {
+                debug!("Connection closed before it was served: {}", e);
+                None
+            }
+        },
+        // Another wake up for a connection that was already accepted or aborted
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+        Err(e) => {
+            error!("Cannot accept a connection: {}", e);
+            None
+        }
+    }
+}
+
+/// Which connection limit turned a client away
+#[derive(Clone, Copy, PartialEq)]
+enum Rejection {
+    /// performance.maxConnections
+    Server,
+    /// performance.maxConnectionsPerAddress
+    Address,
+    /// performance.maxQueuedConnections
+    Queue,
+}
+
+/// Read the next request head to the end of `buf`. Returns the length of the head or
+/// None if the connection should be closed. Errors are answered here.
+/// `idle` is true when waiting for the next request on a persistent connection.
+/// The whole head has to arrive within performance.headerTimeout.
+fn read_head(
+    stream: &mut impl ClientStream,
+    config: &Config,
+    buf: &mut Vec<u8>,
+    idle: bool,
+) -> Option<usize> {
+    // A pipelined request may already be in the buffer
+    if let Some(header_len) = end_of_header(&buf[..]) {
+        return Some(header_len);
+    }
+
+    let header_timeout = Duration::from_secs_f64(config.performance.header_timeout);
+    // Waiting for the next request on a persistent connection only has the keep-alive timeout
+    let mut deadline = if idle && buf.is_empty() {
+        None
+    } else {
+        Some(Instant::now() + header_timeout)
+    };
 
     // TODO: is there more optimal way of reading?
-    let mut buf = vec![];
     loop {
+        let mut timeout = Duration::from_secs_f64(if idle && buf.is_empty() {
+            config.performance.keep_alive_timeout
+        } else {
+            config.performance.connection_timeout
+        });
+        // The read timeout starts again after every read so a client sending
+        // a byte at a time would never run out of it
+        if let Some(deadline) = deadline {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                debug!("{} didn't send the request head in time", peer(stream));
+                response_408(stream);
+                return None;
+            }
+            timeout = timeout.min(left);
+        }
+        // SslStream doesn't have a timeout so we need to set it to the underlying TcpStream
+        stream.tcp().set_read_timeout(Some(timeout)).unwrap();
+
         // TODO: why this doesn't work with vec![]?
         //       with ./test_client.py this recieves data_len == 0 with vec![]
         //let mut buf2 = vec![];
-        let mut temp_buf = [0 as u8; MAX_REQUEST_SIZE];
-        match stream.ssl_read(&mut temp_buf) {
+        let mut temp_buf = [0_u8; READ_BUFFER_SIZE];
+        match stream.read(&mut temp_buf) {
             Ok(data_len) => {
                 buf.extend_from_slice(&temp_buf[..data_len]);
+                if deadline.is_none() && !buf.is_empty() {
+                    deadline = Some(Instant::now() + header_timeout);
+                }
+
+                // Check the target before the size of the whole request so
+                // long URIs are reported as such instead of 413
+                let target_len = request::target_length(&buf[..]).unwrap_or(0);
+                if target_len > config.performance.max_uri_length {
+                    response_414(stream);
+                    return None;
+                }
+                let sizes = request::head_sizes(&buf[..]);
+                if sizes.request_line > config.performance.max_request_line_length {
+                    response_414(stream);
+                    return None;
+                }
+                if sizes.headers > config.performance.max_headers
+                    || sizes.longest_header > config.performance.max_header_length
+                {
+                    response_431(stream);
+                    return None;
+                }
 
                 if data_len == 0 {
+                    // Client closed a persistent connection between requests
+                    if idle && buf.is_empty() {
+                        return None;
+                    }
                     // Not completely sure if this even ever happens
-                    break;
-                } else if is_end_of_header(&buf[..]) {
-                    break;
-                } else if buf.len() >= MAX_REQUEST_SIZE {
+                    return Some(buf.len());
+                } else if let Some(header_len) = end_of_header(&buf[..]) {
+                    return Some(header_len);
+                } else if buf.len() >= config.performance.max_request_size {
                     response_413(stream);
-                    return;
+                    return None;
                 }
             }
             Err(error) => {
+                // Idle persistent connection timed out or was closed. Nothing to answer
+                if idle && buf.is_empty() {
+                    return None;
+                }
                 // If ssl_error happens, the connection is not usable so we
                 // can just ignore it but we can still handle the io errors
                 // TODO: figure out how to test the self signed cert error
-                // TODO: log ssl errors
-                if !is_ssl_error(error) {
+                if is_tls_error(&error) {
+                    debug!("TLS error from {}: {}", peer(stream), error);
+                } else {
                     // TODO: what other errors there might be?
+                    debug!(
+                        "Reading the request from {} failed: {}",
+                        peer(stream),
+                        error
+                    );
                     response_408(stream);
                 }
-                return;
+                return None;
             }
         }
     }
+}
 
+/// Serve the requests of a connection from `address`, which is the proxy's
+/// when network.trustedProxies forward the requests, until the client or an
+/// error closes it. The whole connection is served with the config that was
+/// current when it was accepted.
+fn handle_client(
+    mut stream: impl ClientStream,
+    address: IpAddr,
+    state: &ServerState,
+    config: &Config,
+) {
+    let mut buf = vec![];
+    let mut idle = false;
+    while let Some(header_len) = read_head(&mut stream, config, &mut buf, idle) {
+        // Anything after the header belongs to the request body
+        let (head, leftover) = buf.split_at(header_len);
+        let started = Instant::now();
+        let head_text = String::from_utf8_lossy(head);
+        let request = Request::parse(&head_text);
+        let request_id = request_id::request_id(request.as_ref());
+        let client = match &request {
+            Some(request) => {
+                proxy::client_address(address, request, &config.network.trusted_proxies)
+            }
+            None => address,
+        };
+        let mut recorder = Recorder::new(&mut stream, request_id);
+        let connection = handle_request(&mut recorder, state, config, client, head, leftover);
+        if recorder.status >= 400 {
+            let request_line = head_text.lines().next().unwrap_or("");
+            let message = format!(
+                "{} to {} from {} ({})",
+                recorder.status, request_line, client, recorder.request_id
+            );
+            if recorder.status >= 500 {
+                error!("{}", message);
+            } else {
+                debug!("{}", message);
+            }
+        }
+        let kind = ContentKind::of(request.as_ref().map_or("", |request| &request.path[..]));
+        let timing = recorder
+            .first_byte
+            .map(|first_byte| (first_byte - started, first_byte.elapsed()));
+        state
+            .stats
+            .record(kind, recorder.status, recorder.body_bytes, timing);
+        if let Some(access_log) = &state.access_log {
+            access_log.log(head, &recorder, client, started.elapsed());
+        }
+        if connection == Connection::Close {
+            return;
+        }
+        // Persistent connections only have requests without a body
+        // so the rest of the buffer is the start of the next request
+        buf.drain(..header_len);
+        idle = true;
+    }
+}
+
+/// Answer a request to the redirect listener with a redirect to the same url over https
+fn handle_redirect(mut stream: TcpStream, config: &Config) {
+    let mut buf = vec![];
+    let header_len = match read_head(&mut stream, config, &mut buf, false) {
+        Some(header_len) => header_len,
+        None => return,
+    };
+
+    let request_head = String::from_utf8_lossy(&buf[..header_len]);
+    let location = Request::parse(&request_head)
+        .and_then(|request| redirect::https_location(&request, &config.network.port));
+    match location {
+        Some(location) => {
+            let out = format!(
+                "HTTP/1.1 301 MOVED PERMANENTLY\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                location
+            );
+            let _ = stream.write_all(out.as_bytes());
+        }
+        None => response_400(&mut stream),
+    }
+}
+
+/// Answer one request from `client`. `leftover` is the data read after the request head
+fn handle_request<S: ClientStream>(
+    stream: &mut S,
+    state: &ServerState,
+    config: &Config,
+    client: IpAddr,
+    head: &[u8],
+    leftover: &[u8],
+) -> Connection {
     // TODO: is lossy a good (fast) option?
-    let request_full = String::from_utf8_lossy(&buf);
+    let request_head = String::from_utf8_lossy(head);
 
-    // TODO: check all the lines
-    // TODO: handle ERr
-    let first_line = request_full.lines().next().unwrap();
-    let mut request_parts = first_line.split_whitespace();
+    let request = match Request::parse(&request_head) {
+        Some(request) => request,
+        None => {
+            response_400(stream);
+            return Connection::Close;
+        }
+    };
 
-    // Only gets are currenlty supported
-    if request_parts.next().unwrap() != "GET" {
-        stream
-            .write("HTTP/1.1 405 Method Not Allowed\r\n\r\n".as_bytes())
-            .unwrap();
-        return;
+    if !request.is_supported_version() {
+        response_505(stream);
+        return Connection::Close;
+    }
+    // HTTP/1.1 requests have to have exactly one Host header (RFC 9112 3.2)
+    if request.version != "HTTP/1.0" && request.header_count("Host") != 1 {
+        response_400(stream);
+        return Connection::Close;
+    }
+
+    // Expectations are checked before anything else so the client gets a
+    // final response before it starts sending a body that will be rejected.
+    // "100-continue" doesn't need an interim response since the request body
+    // is only read from the uploads and the final response is otherwise sent
+    // right away.
+    let expectation = request.expectation();
+    if expectation == Expectation::Unsupported {
+        response_417(stream);
+        return Connection::Close;
+    }
+
+    let framing = match request.body_framing(config.performance.max_body_size) {
+        Ok(framing) => framing,
+        Err(FramingError::Invalid) => {
+            response_400(stream);
+            return Connection::Close;
+        }
+        Err(FramingError::UnsupportedEncoding) => {
+            response_501(stream);
+            return Connection::Close;
+        }
+        Err(FramingError::TooLarge) => {
+            response_413(stream);
+            return Connection::Close;
+        }
+    };
+
+    // Bodies aren't read on success so a request with one can't be followed by another
+    let keep_alive = config.performance.keep_alive_timeout > 0.0
+        && framing == Framing::None
+        && request.keep_alive()
+        && !state.shutdown.is_requested();
+    let connection = if keep_alive {
+        Connection::KeepAlive
+    } else {
+        Connection::Close
+    };
+
+    // Encoders upload the live streams. Their requests are answered after the
+    // whole body is read and the connection is closed like after any body
+    if let Some(ingest) = &config.ingest {
+        let suffix = ingest::in_progress_suffix(config);
+        if let Some(target) = ingest::target_path(ingest, &request.path, suffix) {
+            let is_upload = request.method == "PUT" || request.method == "POST";
+            let authorized = ingest::is_authorized(ingest, request.header("Authorization"));
+            if !authorized || (!is_upload && request.method != "DELETE") {
+                if authorized {
+                    let out = format!(
+                        "HTTP/1.1 405 Method Not Allowed\r\nAllow: {}\r\n\r\n",
+                        ingest::ALLOWED_METHODS
+                    );
+                    stream.write_all(out.as_bytes()).unwrap();
+                } else {
+                    response_401(stream, "Bearer");
+                }
+                if expectation != Expectation::Continue {
+                    discard_body(stream, leftover, framing, config);
+                }
+                return Connection::Close;
+            }
+
+            let status = if is_upload {
+                if expectation == Expectation::Continue
+                    && stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").is_err()
+                {
+                    return Connection::Close;
+                }
+                let max_size = config.performance.max_body_size;
+                let body = request_body(stream, leftover, framing, max_size, config);
+                let stored = ingest::store(body, &target, suffix);
+                // The new segment may have been missing a moment ago
+                let is_target = |path: &str| root::same_path(Path::new(path), &target);
+                state.not_found.remove_matching(is_target);
+                state.files.remove_matching(is_target);
+                match stored {
+                    Ok(true) => "201 CREATED",
+                    Ok(false) => "204 NO CONTENT",
+                    Err(ingest::StoreError::Upload) => {
+                        response_400(stream);
+                        return Connection::Close;
+                    }
+                    Err(ingest::StoreError::Disk(e)) => {
+                        error!("Failed to store {}: {}", target.display(), e);
+                        response_500(stream, config, false);
+                        return Connection::Close;
+                    }
+                }
+            } else {
+                discard_body(stream, leftover, framing, config);
+                match fs::remove_file(&target) {
+                    Ok(_) => "204 NO CONTENT",
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => "404 NOT FOUND",
+                    Err(e) => {
+                        error!("Failed to remove {}: {}", target.display(), e);
+                        response_500(stream, config, false);
+                        return Connection::Close;
+                    }
+                }
+            };
+            // 204 can't have a body, not even an empty one
+            let length = if status == "204 NO CONTENT" {
+                ""
+            } else {
+                "Content-Length: 0\r\n"
+            };
+            let out = format!("HTTP/1.1 {}\r\n{}Connection: close\r\n\r\n", status, length);
+            let _ = stream.write_all(out.as_bytes());
+            return Connection::Close;
+        }
+    }
+
+    // Only gets are currenlty supported. HEAD is a GET without the body
+    // and OPTIONS is only used for CORS preflights. The ClearKey license
+    // requests are posted
+    let is_head = request.method == "HEAD";
+    let is_options = request.method == "OPTIONS";
+    let is_license = request.method == "POST"
+        && config
+            .clear_key
+            .as_ref()
+            .is_some_and(|clear_key| request.path == clear_key.path);
+    if request.method != "GET" && !is_head && !is_options && !is_license {
+        let out = format!(
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: {}\r\n\r\n",
+            ALLOWED_METHODS
+        );
+        stream.write_all(out.as_bytes()).unwrap();
+        // Client waiting for 100 Continue won't send the body at all
+        if expectation != Expectation::Continue {
+            discard_body(stream, leftover, framing, config);
+        }
+        return Connection::Close;
+    }
+
+    // Limited before anything is read from the disk
+    if let Some(limit) = &config.security.rate_limit {
+        if let Err(wait) = state.rate_limiter.try_request(client, limit) {
+            response_429_retry(stream, wait);
+            return Connection::Close;
+        }
+    }
+
+    if let Some(client_auth) = &config.security.client_auth {
+        let exempt = client_auth
+            .exempt_prefixes
+            .iter()
+            .any(|prefix| root::has_prefix(&request.path, prefix));
+        if !exempt && !stream.has_client_certificate() {
+            response_403(stream, config, is_head);
+            return connection;
+        }
+    }
+
+    let mut path = &request.path[..];
+    let mut fingerprint = None;
+    if let Some(prefix) = &config.cache_control.immutable_prefix {
+        if let Some((hash, stripped)) = request::strip_fingerprint(path, prefix) {
+            path = stripped;
+            fingerprint = Some(hash);
+        }
+    }
+
+    let rewritten = state.rewriter.apply(&config.rewrites, path);
+    if let Some(rewritten) = &rewritten {
+        debug!("Rewrote {} to {}", path, rewritten);
+        path = rewritten;
+    }
+
+    let tenant = match tenant::route(&config.tenants, request.header("Host"), path) {
+        Some((tenant, tenant_path)) => {
+            path = tenant_path;
+            Some(tenant)
+        }
+        None => None,
+    };
+
+    // A title's sidecar can let anyone in or require a token outside the
+    // protected prefixes
+    let needs_token = config.security.signed_urls.is_some() || config.security.jwt.is_some();
+    let require_token = if needs_token && path.len() > 1 && !root::has_parent_segments(&path[1..]) {
+        let root = tenant.map_or(&config.network.document_root[..], |tenant| &tenant.root[..]);
+        state
+            .sidecars
+            .find(root, &path[1..])
+            .and_then(|overrides| overrides.require_token)
+    } else {
+        None
+    };
+
+    // Preflights don't carry the query of the actual request
+    if let Some(signed_urls) = &config.security.signed_urls {
+        let required = require_token.unwrap_or_else(|| {
+            !signed_urls
+                .exempt_prefixes
+                .iter()
+                .any(|prefix| root::has_prefix(&request.path, prefix))
+        });
+        if required && !is_options {
+            if let Err(denied) = signed_url::verify(
+                &signed_urls.secret,
+                &request.path,
+                request.query_param("token"),
+                request.query_param("expires"),
+            ) {
+                debug!("Signed url of {} denied: {:?}", request.path, denied);
+                response_403(stream, config, is_head);
+                return connection;
+            }
+        }
+    }
+
+    if let Some(jwt) = &config.security.jwt {
+        if !is_options {
+            let authorization = request.header("Authorization");
+            match jwt::authorize(
+                jwt,
+                &state.jwt_keys,
+                &request.path,
+                authorization,
+                require_token,
+            ) {
+                Ok(()) => (),
+                Err(Denied::Missing) => {
+                    response_401(stream, "Bearer");
+                    return connection;
+                }
+                Err(Denied::Invalid(reason)) => {
+                    debug!("Bearer token for {} denied: {}", request.path, reason);
+                    response_401(stream, "Bearer error=\"invalid_token\"");
+                    return connection;
+                }
+                Err(Denied::Scope) => {
+                    response_403(stream, config, is_head);
+                    return connection;
+                }
+            }
+        }
+    }
+
+    if let Some(basic_auth) = &config.security.basic_auth {
+        let authorization = request.header("Authorization");
+        if !is_options
+            && !state
+                .credentials
+                .authorize(basic_auth, &request.path, authorization)
+        {
+            let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", basic_auth.realm);
+            response_401(stream, &challenge);
+            return connection;
+        }
+    }
+
+    // Other files, like the player's own assets, can be linked from anywhere
+    if let Some(hotlink) = &config.security.hotlink_protection {
+        let is_media = ContentKind::of(&request.path) != ContentKind::Other;
+        let origin = request.header("Origin");
+        let referer = request.header("Referer");
+        if is_media && !is_options && !hotlink::is_allowed(hotlink, origin, referer) {
+            debug!(
+                "Hotlinked {} denied, Origin {:?}, Referer {:?}",
+                request.path, origin, referer
+            );
+            response_403(stream, config, is_head);
+            return connection;
+        }
+    }
+
+    // Players get the keys of the ClearKey encrypted streams. The body is
+    // read so the connection is closed like after any body
+    if let (Some(clear_key), true) = (&config.clear_key, is_license) {
+        if expectation == Expectation::Continue
+            && stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").is_err()
+        {
+            return Connection::Close;
+        }
+        let max_size = config
+            .performance
+            .max_body_size
+            .min(clear_key::MAX_REQUEST_SIZE);
+        let mut body = vec![];
+        let read = request_body(stream, leftover, framing, max_size, config).read_to_end(&mut body);
+        let license = match read {
+            Ok(_) => state.clear_keys.license(clear_key, &body),
+            Err(_) => Err(LicenseError::Invalid),
+        };
+        let license = match license {
+            Ok(license) => license,
+            Err(LicenseError::Invalid) => {
+                response_400(stream);
+                return Connection::Close;
+            }
+            Err(LicenseError::NoKeys) => {
+                response_404(stream, config, false);
+                return Connection::Close;
+            }
+        };
+        let settings = TitleSettings::new(config, None, None, &request.path[1..]);
+        let mut out = format!(
+            "HTTP/1.1 200 OK\r\nContent-type: application/json\r\nContent-Length: {}\r\n",
+            license.len()
+        );
+        out.push_str("Cache-Control: no-store\r\n");
+        out.push_str(&cors::response_headers(
+            settings.cors,
+            &settings.allowed_origins,
+            request.header("Origin"),
+        ));
+        out.push_str("Connection: close\r\n\r\n");
+        out.push_str(&license);
+        let _ = stream.write_all(out.as_bytes());
+        return Connection::Close;
     }
 
-    let path = request_parts.next().unwrap();
     // Currently the root path doesn't contain anything
     if path.len() <= 1 {
-        response_404(stream);
-        return;
+        response_404(stream, config, is_head);
+        return connection;
     }
 
     let relative_path = &path[1..path.len()];
-    let file_data = match fs::read(relative_path) {
-        Ok(data) => data,
-        Err(_) => {
-            response_404(stream);
-            return;
+    // Sidecars are server configuration, not content
+    if relative_path.ends_with(sidecar::SIDECAR_EXTENSION) {
+        response_404(stream, config, is_head);
+        return connection;
+    }
+
+    // Nothing outside of the document root is served
+    if root::has_parent_segments(relative_path) {
+        response_404(stream, config, is_head);
+        return connection;
+    }
+
+    if let Some(tenant) = tenant {
+        if let Some(quota) = &tenant.quota {
+            if !state.quotas.try_request(&tenant.name, quota) {
+                response_429(stream);
+                return Connection::Close;
+            }
+        }
+    }
+
+    let root = match tenant {
+        Some(tenant) => &tenant.root[..],
+        None => &config.network.document_root[..],
+    };
+    let file_path = format!("{}/{}", root.trim_end_matches('/'), relative_path);
+    let overrides = state.sidecars.find(root, relative_path);
+    let settings = TitleSettings::new(config, tenant, overrides.as_deref(), relative_path);
+
+    // Preflight is answered for any path, the actual request finds out if the file exists
+    if is_options {
+        let mut out = format!("HTTP/1.1 204 NO CONTENT\r\nAllow: {}\r\n", ALLOWED_METHODS);
+        out.push_str(&cors::preflight_headers(
+            settings.cors,
+            &settings.allowed_origins,
+            request.header("Origin"),
+            request.header("Access-Control-Request-Headers"),
+        ));
+        out.push_str(connection_header(keep_alive, &request));
+        out.push_str("\r\n");
+        if stream.write_all(out.as_bytes()).is_err() {
+            return Connection::Close;
+        }
+        return connection;
+    }
+
+    // A missing manifest is generated from the segments in its directory
+    // and a missing HLS playlist from the manifest with the same name.
+    // Manifests of encrypted streams get their ContentProtection elements
+    // and the title's filter leaves out representations
+    let mut generated = generated_manifest(state, config, root, &file_path, &request.path);
+    if settings.content_protection {
+        generated = protected_manifest(config, root, &file_path, &request.path, generated);
+    }
+    if let Some(filter) = settings.manifest_filter {
+        generated = filtered_manifest(root, &file_path, filter, generated);
+    }
+    let is_playlist = config.hls_playlists && relative_path.ends_with(hls::PLAYLIST_EXTENSION);
+    if is_playlist && !Path::new(&file_path).exists() {
+        let filter = settings.manifest_filter;
+        generated = hls_playlist(state, config, root, &file_path, filter, &request);
+    }
+    // The generated manifests can list a WebVTT file as segments of its cues
+    let segment_duration = config
+        .generated_manifests
+        .as_ref()
+        .map_or(0.0, |generated| generated.subtitle_segment_duration);
+    let segment_number = request
+        .query_param(webvtt::SEGMENT_PARAM)
+        .filter(|_| segment_duration > 0.0 && relative_path.ends_with(".vtt"));
+    if let Some(number) = segment_number {
+        generated = subtitle_segment(root, &file_path, number, segment_duration);
+        if generated.is_none() {
+            response_404(stream, config, is_head);
+            return connection;
+        }
+    }
+
+    // A segment that is still being written is sent as it grows. The complete
+    // segment may be in the not found cache from before it was started
+    if let (Some(low_latency), None) = (&config.low_latency, &generated) {
+        let partial_path = format!("{}{}", file_path, low_latency.in_progress_suffix);
+        // The content of a fingerprinted url has to be complete to be checked
+        let in_progress = request.version != "HTTP/1.0"
+            && request.header("Range").is_none()
+            && fingerprint.is_none()
+            && !Path::new(&file_path).exists()
+            && root::is_inside(root, &partial_path);
+        if in_progress {
+            if let Some((file, _)) = open_file(&partial_path) {
+                let mut out = format!(
+                    "HTTP/1.1 200 OK\r\nContent-type: {}\r\nTransfer-Encoding: chunked\r\n",
+                    mime::content_type(&config.mime_types, relative_path)
+                );
+                out.push_str(&cors::response_headers(
+                    settings.cors,
+                    &settings.allowed_origins,
+                    request.header("Origin"),
+                ));
+                out.push_str(connection_header(keep_alive, &request));
+                let custom_headers =
+                    header_rules::headers_for(&config.response_headers, &request.path);
+                // A segment that is still growing is never immutable
+                out.push_str(&cache_headers(
+                    &settings.cache_headers,
+                    false,
+                    &custom_headers,
+                ));
+                out.push_str(&header_rules::header_lines(&custom_headers));
+                out.push_str("\r\n");
+                if stream.write_all(out.as_bytes()).is_err() {
+                    return Connection::Close;
+                }
+                if is_head {
+                    let _ = stream.flush();
+                    return connection;
+                }
+                let written = in_progress::write_segment(
+                    stream,
+                    file,
+                    Path::new(&partial_path),
+                    Path::new(&file_path),
+                    Duration::from_secs_f64(low_latency.idle_timeout),
+                    |stream: &S| transfer::peer_gone(stream.tcp()),
+                );
+                if let Some(tenant) = tenant {
+                    let bytes = match written {
+                        Ok(bytes) | Err(bytes) => bytes,
+                    };
+                    state.quotas.add_bytes(&tenant.name, bytes);
+                }
+                if let Err(bytes) = written {
+                    info!(
+                        "{} of {} was sent before it stopped growing or the client went away",
+                        bytes, partial_path
+                    );
+                    return Connection::Close;
+                }
+                state.not_found.remove(&file_path);
+                return connection;
+            }
+        }
+    }
+
+    if generated.is_none() && state.not_found.is_missing(&file_path) {
+        response_404(stream, config, is_head);
+        return connection;
+    }
+
+    // Symlinks can still point outside of the root
+    if generated.is_none() && !root::is_inside(root, &file_path) {
+        state.not_found.insert(&file_path);
+        response_404(stream, config, is_head);
+        return connection;
+    }
+
+    // Generated manifests are only in memory, everything else is read from the file
+    let (mut file, mut file_length, mut modified) = match (&generated, open_file(&file_path)) {
+        (Some(manifest), _) => (None, manifest.data.len() as u64, Some(manifest.modified)),
+        (None, Some((file, metadata))) => (Some(file), metadata.len(), metadata.modified().ok()),
+        (None, None) => {
+            state.not_found.insert(&file_path);
+            response_404(stream, config, is_head);
+            return connection;
+        }
+    };
+
+    // A fingerprint has to be the start of the SHA-256 of the content. The
+    // response is cached forever unless it's generated or a dynamic manifest,
+    // which change behind the same url
+    let mut immutable = false;
+    if let Some(fingerprint) = fingerprint {
+        let digest = match (&generated, &mut file, modified) {
+            (Some(manifest), _, _) => digest_cache::hash(&manifest.data[..]),
+            (None, Some(file), Some(modified)) => {
+                let digest = state
+                    .digests
+                    .digest(&file_path, modified, file_length, &*file);
+                file.seek(SeekFrom::Start(0)).and(digest)
+            }
+            _ => Err(io::ErrorKind::NotFound.into()),
+        };
+        let matches =
+            digest.is_ok_and(|digest| digest_cache::has_fingerprint(&digest, fingerprint));
+        if !matches {
+            response_404(stream, config, is_head);
+            return connection;
+        }
+        immutable = match &mut file {
+            Some(_) if generated.is_some() => false,
+            Some(file) if relative_path.ends_with(".mpd") => {
+                let mut xml = String::new();
+                let is_static = file.read_to_string(&mut xml).is_ok()
+                    && mpd::parse(&xml).is_ok_and(|mpd| mpd.mpd_type == mpd::MpdType::Static);
+                file.seek(SeekFrom::Start(0)).is_ok() && is_static
+            }
+            Some(_) => true,
+            None => false,
+        };
+    }
+
+    // A precompressed "<file>.br" or "<file>.gz" is sent instead of the file when the
+    // client accepts it. Ranges of the compressed data aren't supported
+    let mut served_path = file_path.clone();
+    let mut precompressed = Encoding::Identity;
+    let mut has_variants = false;
+    if config.performance.precompressed && generated.is_none() {
+        let available: Vec<Encoding> = [Encoding::Brotli, Encoding::Gzip]
+            .iter()
+            .copied()
+            .filter(|encoding| {
+                root::is_inside(root, &compression::variant_path(&file_path, *encoding))
+            })
+            .collect();
+        has_variants = !available.is_empty();
+        let encoding = compression::negotiate(request.header("Accept-Encoding"), &available);
+        if encoding != Encoding::Identity && request.header("Range").is_none() {
+            let variant_path = compression::variant_path(&file_path, encoding);
+            if let Some((variant, metadata)) = open_file(&variant_path) {
+                file = Some(variant);
+                file_length = metadata.len();
+                modified = metadata.modified().ok();
+                served_path = variant_path;
+                precompressed = encoding;
+            }
+        }
+    }
+
+    // Without the modification time a changed file can't be noticed
+    let cached = match (&generated, modified, &file) {
+        (Some(manifest), _, _) => Some(manifest.data.clone()),
+        (None, Some(modified), Some(file)) => {
+            state.files.get(&served_path, modified, file_length, file)
         }
+        _ => None,
     };
 
-    let file_type = if relative_path.ends_with(".mpd") {
-        "application/dash+xml"
+    let file_type = mime::content_type(&config.mime_types, relative_path);
+
+    let compressible =
+        config.performance.compression && compression::is_compressible(relative_path);
+    // Ranges of the compressed data aren't supported so ranges get the file as it is
+    let encoding = if precompressed != Encoding::Identity {
+        precompressed
+    } else if compressible && request.header("Range").is_none() {
+        let available = [Encoding::Brotli, Encoding::Gzip];
+        compression::negotiate(request.header("Accept-Encoding"), &available)
     } else {
-        "application/octet-stream"
+        Encoding::Identity
+    };
+    let compress = encoding != Encoding::Identity && precompressed == Encoding::Identity;
+    let cors_headers = cors::response_headers(
+        settings.cors,
+        &settings.allowed_origins,
+        request.header("Origin"),
+    );
+    let custom_headers = header_rules::headers_for(&config.response_headers, &request.path);
+    let custom_header_lines = header_rules::header_lines(&custom_headers);
+
+    // Caches have to store the compressed and uncompressed versions separately
+    let vary = if compressible || has_variants {
+        "Vary: Accept-Encoding\r\n"
+    } else {
+        ""
+    };
+
+    if let Some(modified) = modified {
+        let etag = compression::encoded_etag(&conditional::etag(modified, file_length), encoding);
+        if conditional::is_not_modified(&request, modified, &etag) {
+            let mut out = format!(
+                "HTTP/1.1 304 NOT MODIFIED\r\nLast-Modified: {}\r\nETag: {}\r\n",
+                conditional::last_modified(modified),
+                etag
+            );
+            out.push_str(&cors_headers);
+            out.push_str(vary);
+            out.push_str(connection_header(keep_alive, &request));
+            out.push_str(&cache_headers(
+                &settings.cache_headers,
+                immutable,
+                &custom_headers,
+            ));
+            out.push_str(&custom_header_lines);
+            out.push_str("\r\n");
+            if stream.write_all(out.as_bytes()).is_err() {
+                return Connection::Close;
+            }
+            return connection;
+        }
+    }
+
+    let range = range::parse_range(request.header("Range"), file_length);
+    let (start, length) = match range {
+        RangeRequest::Full => (0, file_length),
+        RangeRequest::Partial { start, end } => (start, end - start + 1),
+        RangeRequest::Unsatisfiable => {
+            let out = format!(
+                "HTTP/1.1 416 RANGE NOT SATISFIABLE\r\nContent-Range: bytes */{}\r\n\r\n",
+                file_length
+            );
+            stream.write_all(out.as_bytes()).unwrap();
+            return Connection::Close;
+        }
+    };
+
+    // Manifests are parsed for the preload links. Other compressed responses are
+    // compressed as they're sent in chunks, except to HTTP/1.0 clients that
+    // need the length up front. Everything else is streamed from the file.
+    let manifest = relative_path.ends_with(".mpd") && precompressed == Encoding::Identity;
+    let chunked_body = compress && !manifest && request.version != "HTTP/1.0";
+    let mut file_data = None;
+    if manifest || (compress && !chunked_body) {
+        let data = match (&cached, &mut file) {
+            (Some(cached), _) => Ok(cached[start as usize..(start + length) as usize].to_vec()),
+            (None, Some(file)) => range::read_range(file, start, length),
+            (None, None) => Err(io::ErrorKind::NotFound.into()),
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(_) => {
+                response_404(stream, config, is_head);
+                return connection;
+            }
+        };
+        file_data = Some(data);
+    }
+
+    let mut links = String::new();
+    // Preloading only makes sense when the client gets the whole manifest
+    if let (Some(data), RangeRequest::Full) = (&file_data, &range) {
+        if relative_path.ends_with(".mpd") {
+            if let (true, Some(modified)) = (config.logging.missing_segments, modified) {
+                let xml = String::from_utf8_lossy(data);
+                state
+                    .missing_segments
+                    .check(root, relative_path, modified, &xml);
+            }
+            let mode = settings.preload_init_segments;
+            let segments = preload::init_segments(&String::from_utf8_lossy(data), mode);
+            // Links are relative to the url the client used, not the file on disk
+            let request_path = request.target.split('?').next().unwrap_or("");
+            links = preload::link_headers(request_path, &segments);
+        }
+    }
+
+    if let (Some(data), true) = (&file_data, compress) {
+        match compression::compress(data, encoding) {
+            Ok(compressed) => file_data = Some(compressed),
+            Err(e) => {
+                error!("Failed to compress {}: {}", file_path, e);
+                response_500(stream, config, is_head);
+                return Connection::Close;
+            }
+        }
+    }
+    let content_length = match &file_data {
+        Some(data) => data.len() as u64,
+        None => length,
     };
 
+    // HTTP/1.0 clients don't know what to do with informational responses
+    if settings.early_hints && !links.is_empty() && request.version != "HTTP/1.0" && !is_head {
+        let hints = format!("HTTP/1.1 103 Early Hints\r\n{}\r\n", links);
+        if stream.write_all(hints.as_bytes()).is_err() || stream.flush().is_err() {
+            return Connection::Close;
+        }
+    }
+
     // TODO: handle Err
     // TODO: should all the responses contain information about the server? version number etc?
-    let access_origin = &config.network.allow_origin[..];
-    let out = format!("HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: {}\r\nContent-type: {}\r\nContent-Length: {}\r\n\r\n", access_origin, file_type, file_data.len());
-    stream.write(out.as_bytes()).unwrap();
-    stream.write_all(&file_data[..]).unwrap();
-    stream.flush().unwrap();
+    let status = match range {
+        RangeRequest::Partial { .. } => "206 PARTIAL CONTENT",
+        _ => "200 OK",
+    };
+    let mut out = format!("HTTP/1.1 {}\r\nContent-type: {}\r\n", status, file_type);
+    if chunked_body {
+        out.push_str("Transfer-Encoding: chunked\r\n");
+    } else {
+        out.push_str(&format!("Content-Length: {}\r\n", content_length));
+    }
+    out.push_str("Accept-Ranges: bytes\r\n");
+    out.push_str(&cors_headers);
+    if let RangeRequest::Partial { start, end } = range {
+        out.push_str(&format!(
+            "Content-Range: bytes {}-{}/{}\r\n",
+            start, end, file_length
+        ));
+    }
+    if let Some(modified) = modified {
+        let date = conditional::last_modified(modified);
+        out.push_str(&format!("Last-Modified: {}\r\n", date));
+        let etag = compression::encoded_etag(&conditional::etag(modified, file_length), encoding);
+        out.push_str(&format!("ETag: {}\r\n", etag));
+    }
+    if encoding != Encoding::Identity {
+        out.push_str(&format!("Content-Encoding: {}\r\n", encoding.name()));
+    }
+    out.push_str(vary);
+    out.push_str(connection_header(keep_alive, &request));
+    out.push_str(&cache_headers(
+        &settings.cache_headers,
+        immutable,
+        &custom_headers,
+    ));
+    out.push_str(&custom_header_lines);
+    out.push_str(&links);
+    // The digest is of the whole file. It isn't computed for ranges
+    // so that they don't have to read the whole file. Chunked responses are
+    // compressed as they're sent so their digest isn't known beforehand
+    if settings.content_digest && range == RangeRequest::Full && !chunked_body {
+        // Without the modification time the digest can't be cached
+        if let Some(modified) = modified {
+            // The digest is of the data that is sent so the encodings are cached separately
+            let key = if compress {
+                format!("{}:{}", served_path, encoding.name())
+            } else {
+                served_path.clone()
+            };
+            let digest = match (&file_data, &cached) {
+                // Generated bodies, like the HLS media playlists or the WebVTT
+                // segments, differ by the query of the same file
+                (Some(data), _) if generated.is_some() => digest_cache::hash(&data[..]),
+                (None, Some(data)) if generated.is_some() => digest_cache::hash(&data[..]),
+                (Some(data), _) => state
+                    .digests
+                    .digest(&key, modified, content_length, &data[..]),
+                (None, Some(data)) => {
+                    state
+                        .digests
+                        .digest(&key, modified, content_length, &data[..])
+                }
+                (None, None) => match &mut file {
+                    Some(file) => file
+                        .seek(SeekFrom::Start(0))
+                        .and_then(|_| state.digests.digest(&key, modified, content_length, &*file)),
+                    None => Err(io::ErrorKind::NotFound.into()),
+                },
+            };
+            // Without the digest the response is still valid
+            if let Ok(digest) = digest {
+                out.push_str(&digest_cache::digest_headers(&digest));
+            }
+        }
+    }
+    out.push_str("\r\n");
+    if stream.write_all(out.as_bytes()).is_err() {
+        return Connection::Close;
+    }
+    if is_head {
+        let _ = stream.flush();
+        return connection;
+    }
+    let is_gone = |stream: &S| transfer::peer_gone(stream.tcp());
+    let rate = config.performance.max_bytes_per_second_per_connection;
+    let written = match (&file_data, &cached) {
+        _ if chunked_body => {
+            let mut body = ChunkedWriter::new(&mut *stream);
+            let sent = match (&cached, file.as_ref()) {
+                (Some(cached), _) => compression::compress_to(&cached[..], &mut body, encoding),
+                (None, Some(mut file)) => file
+                    .seek(SeekFrom::Start(0))
+                    .and_then(|_| compression::compress_to(file, &mut body, encoding)),
+                (None, None) => Err(io::ErrorKind::NotFound.into()),
+            }
+            .map(|_| ());
+            let bytes = body.written();
+            match sent.and_then(|_| body.finish()) {
+                Ok(_) => Ok(bytes),
+                Err(_) => Err(bytes),
+            }
+        }
+        (Some(data), _) => transfer::write_body(stream, &data[..], content_length, rate, is_gone),
+        (None, Some(cached)) => {
+            let data = &cached[start as usize..];
+            transfer::write_body(stream, data, content_length, rate, is_gone)
+        }
+        (None, None) => match file.as_ref() {
+            Some(mut file) => match file.seek(SeekFrom::Start(start)) {
+                Ok(_) => transfer::write_body(stream, file, content_length, rate, is_gone),
+                Err(_) => Err(0),
+            },
+            None => Err(0),
+        },
+    };
+
+    if let Some(tenant) = tenant {
+        let bytes = match written {
+            Ok(bytes) | Err(bytes) => bytes,
+        };
+        state.quotas.add_bytes(&tenant.name, bytes);
+    }
+
+    if let Err(bytes) = written {
+        info!(
+            "{} went away after {} of {} bytes of {}",
+            peer(stream),
+            bytes,
+            content_length,
+            file_path
+        );
+        return Connection::Close;
+    }
+
+    state.prefetcher.prefetch(client, &file_path);
     // TODO: this should happen on every error.
     //       create struct out of the stream that implements drop
     // TODO:: actully do we even need this because of write_all?
     //stream.shutdown().unwrap();
+    connection
+}
+
+/// Socket the content is served from
+struct ClientListener {
+    listener: TcpListener,
+    tls: bool,
+    /// Set TCP_NODELAY on the accepted connections
+    no_delay: bool,
+}
+
+/// Client and redirect listeners from network.address, network.port,
+/// network.listeners and network.redirectPort
+fn bind_configured(config: &Config) -> (Vec<ClientListener>, Vec<TcpListener>) {
+    let network = &config.network;
+    // TODO: pass down the error
+    let bind = |address: &str, port: &str, options| {
+        listen::bind(address, port, network.dual_stack, options).unwrap()
+    };
+    let mut addresses = vec![(
+        &network.address,
+        &network.port,
+        config.security.https,
+        &network.socket_options,
+    )];
+    for listener in &network.listeners {
+        let tls = listener.tls.unwrap_or(config.security.https);
+        let options = listener
+            .socket_options
+            .as_ref()
+            .unwrap_or(&network.socket_options);
+        addresses.push((&listener.address, &listener.port, tls, options));
+    }
+    let mut listeners = vec![];
+    for (address, port, tls, options) in addresses {
+        for listener in bind(address, port, options) {
+            listeners.push(ClientListener {
+                listener,
+                tls,
+                no_delay: options.tcp_no_delay,
+            });
+        }
+    }
+    let redirect_listeners = match (config.security.https, &network.redirect_port) {
+        (true, Some(port)) => bind(&network.address, port, &network.socket_options),
+        _ => vec![],
+    };
+    (listeners, redirect_listeners)
+}
+
+/// Client and redirect listeners from the sockets systemd passed. They are
+/// told apart by the FileDescriptorName of the socket: "https" and "http"
+/// sockets use and don't use TLS, "redirect" sockets redirect to https
+/// and others follow security.https. They get network.socketOptions
+/// except for the backlog.
+fn systemd_listeners(
+    inherited: Vec<listen::Inherited>,
+    config: &Config,
+) -> (Vec<ClientListener>, Vec<TcpListener>) {
+    let options = &config.network.socket_options;
+    let mut listeners = vec![];
+    let mut redirect_listeners = vec![];
+    for socket in inherited {
+        let listener = socket.listener;
+        if let Err(e) = listen::set_buffer_sizes(SockRef::from(&listener), options) {
+            warn!("Cannot set the socket buffer sizes: {}", e);
+        }
+        let tls = match socket.name.as_deref() {
+            Some("redirect") => {
+                redirect_listeners.push(listener);
+                continue;
+            }
+            Some("https") => true,
+            Some("http") => false,
+            _ => config.security.https,
+        };
+        listeners.push(ClientListener {
+            listener,
+            tls,
+            no_delay: options.tcp_no_delay,
+        });
+    }
+    (listeners, redirect_listeners)
 }
 
 pub struct DashServer {
-    acceptor: Arc<SslAcceptor>,
-    listener: std::net::TcpListener,
+    /// None when no listener uses TLS
+    acceptor: Option<TlsAcceptor>,
+    listeners: Vec<ClientListener>,
+    /// Plain http listeners that redirect to https
+    redirect_listeners: Vec<TcpListener>,
+    admin_listeners: Vec<TcpListener>,
+    /// Admin connections being served
+    admin_connections: AtomicUsize,
     thread_pool: ThreadPool,
+    state: Arc<ServerState>,
+    config: SharedConfig,
+    /// None when the content isn't watched
+    watcher: Option<Mutex<Watcher>>,
+    /// performance.drainTimeout of the config the server was started with
+    drain_timeout: Duration,
+}
+
+/// Is `dir` under one of the live stream directories that are pruned.
+/// Compared without symlinks since the same directory can be watched
+/// through the document root.
+fn is_pruned(watch: &config::Watch, dir: &Path) -> bool {
+    let dir = match fs::canonicalize(dir) {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+    watch
+        .prune_directories
+        .iter()
+        .filter_map(|pruned| fs::canonicalize(pruned).ok())
+        .any(|pruned| dir.starts_with(pruned))
+}
+
+/// Directories watched for changes: the document roots and the live
+/// streams that are pruned
+fn watched_roots(config: &Config, watch: &config::Watch) -> Vec<String> {
+    let mut roots = vec![config.network.document_root.clone()];
+    roots.extend(config.tenants.iter().map(|tenant| tenant.root.clone()));
+    roots.extend(watch.prune_directories.iter().cloned());
+    roots
+}
+
+/// Certificates of the tenants with their own certificate for their host
+fn sni_certificates(config: &Config) -> Vec<SniCertificate> {
+    config
+        .tenants
+        .iter()
+        .filter_map(|tenant| {
+            Some(SniCertificate {
+                host: tenant.host.clone()?,
+                certificate_file: tenant.certificate_file.clone()?,
+                private_key_file: tenant.private_key_file.clone()?,
+            })
+        })
+        .collect()
 }
 
 impl DashServer {
-    pub fn new() -> DashServer {
-        let config = config::GlobalConfig::config();
+    /// Settings that are only read here, like the address and the cache
+    /// sizes, don't change when `config` is replaced later
+    pub fn new(config: SharedConfig) -> DashServer {
+        let shared_config = config;
+        let config = shared_config.get();
 
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        let (listeners, redirect_listeners) = match listen::systemd_sockets() {
+            inherited if inherited.is_empty() => bind_configured(&config),
+            inherited => systemd_listeners(inherited, &config),
+        };
 
-        // TODO: pass down the error
-        acceptor
-            .set_private_key_file(&config.security.private_key_file[..], SslFiletype::PEM)
-            .unwrap();
-        acceptor
-            .set_certificate_file(&config.security.certificate_file[..], SslFiletype::PEM)
+        let acceptor = if listeners.iter().any(|listener| listener.tls) {
+            // TODO: pass down the error
+            let acceptor = TlsAcceptor::new(
+                &config.security.certificate_file,
+                &config.security.private_key_file,
+                config
+                    .security
+                    .client_auth
+                    .as_ref()
+                    .map(|client_auth| &client_auth.ca_file[..]),
+                sni_certificates(&config),
+                Duration::from_secs_f64(config.security.certificate_check_interval),
+            )
             .unwrap();
-        acceptor.check_private_key().unwrap();
-        let acceptor = Arc::new(acceptor.build());
+            Some(acceptor)
+        } else {
+            None
+        };
+
+        // TODO: pass down the error
+        let admin_listeners = match &config.admin {
+            Some(admin) => listen::bind(
+                &admin.address,
+                &admin.port,
+                false,
+                &config.network.socket_options,
+            )
+            .unwrap(),
+            None => vec![],
+        };
 
-        let address = format!("{}:{}", config.network.address, config.network.port);
-        let listener = TcpListener::bind(address).unwrap();
         // TODO: would we benefit from M:N model?
-        let pool = ThreadPool::new(config.performance.thread_pool_size);
+        let performance = &config.performance;
+        let max_size = performance
+            .max_thread_pool_size
+            .max(performance.thread_pool_size);
+        let pool = ThreadPool::scaling(performance.thread_pool_size, max_size);
+        // Validated with the rest of the config
+        let drain_timeout = Duration::from_secs_f64(performance.drain_timeout);
+
+        let state = Arc::new(ServerState {
+            not_found: NotFoundCache::new(Duration::from_secs_f64(
+                config.performance.not_found_cache_ttl,
+            )),
+            digests: DigestCache::new(),
+            files: FileCache::new(
+                config.performance.file_cache_size,
+                config.performance.file_cache_max_entry_size,
+                Duration::from_secs_f64(config.performance.file_cache_ttl),
+            ),
+            prefetcher: Prefetcher::new(config.performance.prefetch_segments),
+            sidecars: Sidecars::new(),
+            quotas: Quotas::new(),
+            shutdown: Arc::new(Shutdown::new()),
+            // TODO: pass down the error
+            access_log: config
+                .logging
+                .access_log
+                .as_ref()
+                .map(|path| AccessLog::open(path).unwrap()),
+            stats: Stats::new(),
+            rate_limiter: RateLimiter::new(),
+            jwt_keys: JwtKeys::new(),
+            credentials: Credentials::new(),
+            clear_keys: ClearKeys::new(),
+            rewriter: Rewriter::new(),
+            generator: Generator::new(),
+            missing_segments: MissingSegments::new(),
+            addresses: Arc::new(AddressConnections::new()),
+        });
+
+        let watcher = config.watch.as_ref().and_then(|watch| {
+            match Watcher::new(&watched_roots(&config, watch)) {
+                Ok(watcher) => Some(Mutex::new(watcher)),
+                Err(e) => {
+                    error!("Cannot watch the content for changes: {}", e);
+                    None
+                }
+            }
+        });
 
         DashServer {
-            acceptor: acceptor,
-            listener: listener,
+            acceptor,
+            listeners,
+            redirect_listeners,
+            admin_listeners,
+            admin_connections: AtomicUsize::new(0),
             thread_pool: pool,
+            state,
+            config: shared_config,
+            watcher,
+            drain_timeout,
         }
     }
 
-    // TODO: support for regular http
-    pub fn start_server(&self) {
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let acceptor = self.acceptor.clone();
-                    self.thread_pool.execute(move || {
-                        // Ignore streams with tls handshake errors
-                        if let Ok(stream) = acceptor.accept(stream) {
-                            handle_client(stream);
+    /// Serve until the server is stopped with its `Stopper`. Returns false
+    /// if some connections were still open after the drain timeout.
+    pub fn start_server(&mut self) -> bool {
+        let stopper = self.stopper();
+        // The accept loop shares the server, the thread pool is stopped after it
+        let server = &*self;
+        thread::scope(|scope| server.accept_loop(scope, &stopper));
+
+        let timeout = self.drain_timeout;
+        let start = Instant::now();
+        if !self.state.shutdown.drain(timeout) {
+            return false;
+        }
+        // Redirects aren't tracked as connections but they may still be in the queue
+        self.thread_pool
+            .shutdown(timeout.saturating_sub(start.elapsed()))
+    }
+
+    /// Handle for stopping the server from another thread
+    pub fn stopper(&self) -> Stopper {
+        Stopper::new(self.state.shutdown.clone())
+    }
+
+    /// Handle for reopening the access log from another thread
+    #[allow(dead_code)] // The tests include this module and don't rotate the logs
+    pub fn access_log(&self) -> Option<AccessLog> {
+        self.state.access_log.clone()
+    }
+
+    /// Wait for connections on every listener with a single thread until
+    /// the server is stopped
+    fn accept_loop<'scope>(
+        &'scope self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        stopper: &'scope Stopper,
+    ) {
+        let mut fds = vec![self.state.shutdown.wake_fd()];
+        let mut sources = vec![Source::Stop];
+        for (i, listener) in self.listeners.iter().enumerate() {
+            fds.push(listener.listener.as_raw_fd());
+            sources.push(Source::Client(i));
+        }
+        for (i, listener) in self.redirect_listeners.iter().enumerate() {
+            fds.push(listener.as_raw_fd());
+            sources.push(Source::Redirect(i));
+        }
+        for (i, listener) in self.admin_listeners.iter().enumerate() {
+            fds.push(listener.as_raw_fd());
+            sources.push(Source::Admin(i));
+        }
+        if let Some(watcher) = &self.watcher {
+            fds.push(watcher.lock().unwrap().fd());
+            sources.push(Source::Watch);
+        }
+        for listener in self.all_listeners() {
+            // TODO: pass down the error
+            listener.set_nonblocking(true).unwrap();
+        }
+
+        loop {
+            let ready = match poll::readable(&fds) {
+                Ok(ready) => ready,
+                Err(e) => {
+                    error!("Cannot wait for connections: {}", e);
+                    return;
+                }
+            };
+            for i in ready {
+                match sources[i] {
+                    Source::Stop => return,
+                    Source::Client(i) => self.accept_client(&self.listeners[i]),
+                    Source::Redirect(i) => self.accept_redirect(&self.redirect_listeners[i]),
+                    Source::Watch => self.content_changed(),
+                    Source::Admin(i) => {
+                        if let Some(stream) = accept(&self.admin_listeners[i]) {
+                            let guard = match admin::try_open(&self.admin_connections) {
+                                Some(guard) => guard,
+                                None => {
+                                    admin::reject(stream);
+                                    continue;
+                                }
+                            };
+                            // A thread of its own so the API keeps working
+                            // when every worker is busy, without holding up
+                            // the other listeners
+                            scope.spawn(move || {
+                                admin::handle(stream, &self.state, &self.config.get(), stopper);
+                                drop(guard);
+                            });
                         }
-                    });
+                    }
                 }
-                Err(e) => {
-                    println!("Error: {:?}", e);
+            }
+        }
+    }
+
+    /// Forget what the caches know about the changed files and prune the
+    /// old segments of the live streams
+    fn content_changed(&self) {
+        let watcher = match &self.watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+        let config = self.config.get();
+        for change in watcher.lock().unwrap().changes() {
+            let (path, written) = match change {
+                Change::Written(path) => (path, true),
+                Change::Removed(path) => (path, false),
+                Change::Unknown => {
+                    warn!("Content changes were lost, flushing the caches");
+                    self.state.files.clear();
+                    self.state.not_found.clear();
+                    self.state.generator.clear();
+                    continue;
+                }
+            };
+            debug!("{} changed", path);
+            self.state.files.remove(&path);
+            self.state.not_found.remove(&path);
+            self.state.generator.invalidate(Path::new(&path));
+
+            // Removing the pruned segments causes changes of their own
+            if !written || !path.ends_with(generate::SEGMENT_EXTENSION) {
+                continue;
+            }
+            // The config may have been reloaded without the watch section
+            if let (Some(watch), Some(dir)) = (&config.watch, Path::new(&path).parent()) {
+                if is_pruned(watch, dir) {
+                    let age = Duration::from_secs_f64(watch.prune_after);
+                    for pruned in watch::prune(dir, age) {
+                        debug!("Pruned {}", pruned.display());
+                    }
                 }
             }
         }
     }
 
-    /// Graefully stop the server
-    #[allow(dead_code)]
-    pub fn stop_server(&self) {
-        drop(&self.listener);
-        drop(&self.thread_pool);
+    fn all_listeners(&self) -> impl Iterator<Item = &TcpListener> {
+        self.listeners
+            .iter()
+            .map(|listener| &listener.listener)
+            .chain(&self.redirect_listeners)
+            .chain(&self.admin_listeners)
+    }
+
+    fn accept_client(&self, listener: &ClientListener) {
+        let stream = match accept(&listener.listener) {
+            Some(stream) => stream,
+            None => return,
+        };
+        let config = self.config.get();
+        // The socket's address is the proxy's, the client's address is
+        // checked after the PROXY header is read
+        let proxy_protocol = config.network.proxy_protocol;
+        if !proxy_protocol && !self.is_allowed(&stream, &config) {
+            return;
+        }
+        let max_queued = config.performance.max_queued_connections;
+        if max_queued > 0 && self.thread_pool.queued() >= max_queued {
+            reject(&self.state, stream, listener.tls, Rejection::Queue);
+            return;
+        }
+        // Queued connections count too, they are served before the server stops
+        let max_connections = config.performance.max_connections;
+        let guard = match self.state.shutdown.try_track(max_connections) {
+            Some(guard) => guard,
+            None => {
+                reject(&self.state, stream, listener.tls, Rejection::Server);
+                return;
+            }
+        };
+        // Counted before the handshake, which is the expensive part of a TLS connection
+        let per_address = config.performance.max_connections_per_address;
+        let address = match stream.peer_addr() {
+            Ok(address) => address.ip(),
+            // Already disconnected
+            Err(_) => return,
+        };
+        let address_guard = if proxy_protocol {
+            None
+        } else {
+            match self.state.addresses.try_open(address, per_address) {
+                Some(guard) => Some(guard),
+                None => {
+                    reject(&self.state, stream, listener.tls, Rejection::Address);
+                    return;
+                }
+            }
+        };
+        if listener.no_delay {
+            // Only fails if the client is already gone
+            let _ = stream.set_nodelay(true);
+        }
+        let acceptor = match (&self.acceptor, listener.tls) {
+            (Some(tls), true) => Some(tls.acceptor()),
+            _ => None,
+        };
+        let state = self.state.clone();
+        let tls = listener.tls;
+        self.thread_pool.execute(move || {
+            let mut stream = stream;
+            let mut address = address;
+            let mut address_guard = address_guard;
+            if proxy_protocol {
+                address = match accept_proxied(&mut stream, &state, &config) {
+                    Some(address) => address,
+                    None => return,
+                };
+                match state.addresses.try_open(address, per_address) {
+                    Some(guard) => address_guard = Some(guard),
+                    None => {
+                        reject(&state, stream, tls, Rejection::Address);
+                        return;
+                    }
+                }
+            }
+            match acceptor {
+                Some(acceptor) => match acceptor.accept(stream) {
+                    Ok(stream) => handle_client(stream, address, &state, &config),
+                    Err(e) => warn!("TLS handshake with {} failed: {}", address, e),
+                },
+                None => handle_client(stream, address, &state, &config),
+            }
+            drop(address_guard);
+            drop(guard);
+        });
+    }
+
+    /// Check the address of a new connection against security.allowCidrs and
+    /// denyCidrs. Denied connections are counted and closed without a response.
+    fn is_allowed(&self, stream: &TcpStream, config: &Config) -> bool {
+        match stream.peer_addr() {
+            Ok(address) => is_allowed(address.ip(), &self.state, config),
+            Err(_) => false,
+        }
+    }
+
+    fn accept_redirect(&self, listener: &TcpListener) {
+        if let Some(stream) = accept(listener) {
+            let config = self.config.get();
+            let proxy_protocol = config.network.proxy_protocol;
+            if !proxy_protocol && !self.is_allowed(&stream, &config) {
+                return;
+            }
+            let state = self.state.clone();
+            self.thread_pool.execute(move || {
+                let mut stream = stream;
+                if proxy_protocol && accept_proxied(&mut stream, &state, &config).is_none() {
+                    return;
+                }
+                handle_redirect(stream, &config)
+            });
+        }
+    }
+}
+
+/// Read the PROXY protocol header of a new connection and check the client's
+/// address in it like accept_client checks the address of the socket.
+/// None closes the connection.
+fn accept_proxied(stream: &mut TcpStream, state: &ServerState, config: &Config) -> Option<IpAddr> {
+    let timeout = Duration::from_secs_f64(config.performance.header_timeout);
+    // Only fails if the client is already gone
+    let _ = stream.set_read_timeout(Some(timeout));
+    let address = match proxy::read_header(stream) {
+        Ok(Some(source)) => source.ip(),
+        // The proxy's own connection
+        Ok(None) => stream.peer_addr().ok()?.ip(),
+        Err(e) => {
+            debug!("No valid PROXY header from {}: {}", peer(stream), e);
+            return None;
+        }
+    };
+    if is_allowed(address, state, config) {
+        Some(address)
+    } else {
+        None
+    }
+}
+
+/// Check `address` against security.allowCidrs and denyCidrs. Denied
+/// connections are counted and closed without a response.
+fn is_allowed(address: IpAddr, state: &ServerState, config: &Config) -> bool {
+    let security = &config.security;
+    if security.allow_cidrs.is_empty() && security.deny_cidrs.is_empty() {
+        return true;
+    }
+    let allowed = ip_filter::is_allowed(address, &security.allow_cidrs, &security.deny_cidrs);
+    if !allowed {
+        debug!("{} is not allowed to connect", address);
+        state
+            .stats
+            .denied_connections
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    allowed
+}
+
+/// Turn away a connection over one of the connection limits. This runs on
+/// the accept thread, or before the handshake with the PROXY protocol, so TLS
+/// clients are closed without a handshake.
+fn reject(state: &ServerState, mut stream: TcpStream, tls: bool, rejection: Rejection) {
+    state
+        .stats
+        .rejected_connections
+        .fetch_add(1, Ordering::Relaxed);
+    let response: &[u8] = match rejection {
+        Rejection::Server => {
+            debug!("Too many connections, rejected {}", peer(&stream));
+            b"HTTP/1.1 503 SERVICE UNAVAILABLE\r\nRetry-After: 1\r\n\r\n"
+        }
+        Rejection::Queue => {
+            debug!("Connection queue is full, rejected {}", peer(&stream));
+            b"HTTP/1.1 503 SERVICE UNAVAILABLE\r\nRetry-After: 1\r\n\r\n"
+        }
+        Rejection::Address => {
+            debug!("Too many connections from {}, rejected", peer(&stream));
+            b"HTTP/1.1 429 TOO MANY REQUESTS\r\nRetry-After: 1\r\n\r\n"
+        }
+    };
+    if !tls {
+        // A client that doesn't read can't hold up the accept loop for long
+        let _ = stream.set_write_timeout(Some(REJECT_WRITE_TIMEOUT));
+        // Errors must not stop the accept loop
+        let _ = stream.write_all(response);
     }
 }
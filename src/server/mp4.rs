@@ -0,0 +1,480 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// What the init segment of a fragmented MP4 tells about its first track
+#[derive(Debug, PartialEq, Clone)]
+pub struct Track {
+    /// Handler type, e.g. "vide", "soun" or "subt"
+    pub handler: String,
+    /// Units per second of the sample durations
+    pub timescale: u32,
+    /// ISO 639-2/T code, None if undetermined
+    pub language: Option<String>,
+    /// RFC 6381 codecs parameter, e.g. "avc1.64001F" or "mp4a.40.2"
+    pub codecs: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_rate: Option<u32>,
+    /// Sample duration for the fragments that don't have their own
+    pub default_sample_duration: Option<u32>,
+}
+
+/// The boxes in `data` as (type, payload) pairs. Stops at the first box
+/// whose size doesn't fit.
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(0..4)?.try_into().unwrap()) as u64;
+        let box_type = data.get(4..8)?;
+        let (header, size) = match size {
+            // The size of the last box can be left out
+            0 => (8, data.len() as u64),
+            1 => (16, u64::from_be_bytes(data.get(8..16)?.try_into().unwrap())),
+            _ => (8, size),
+        };
+        if size < header || size > data.len() as u64 {
+            return None;
+        }
+        let payload = &data[header as usize..size as usize];
+        data = &data[size as usize..];
+        Some((box_type, payload))
+    })
+}
+
+/// Payload of the first box of type `box_type` in `data`
+fn child<'a>(data: &'a [u8], box_type: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).find(|(t, _)| *t == box_type).map(|(_, p)| p)
+}
+
+/// Payload of the box at `path`, e.g. [b"moov", b"trak"]
+fn find<'a>(data: &'a [u8], path: &[&[u8]]) -> Option<&'a [u8]> {
+    path.iter()
+        .try_fold(data, |data, box_type| child(data, box_type))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Timescale and language from a mdhd payload
+fn media_header(mdhd: &[u8]) -> Option<(u32, Option<String>)> {
+    // The times are 64 bits in version 1
+    let offset = if *mdhd.first()? == 1 { 20 } else { 12 };
+    let timescale = u32_at(mdhd, offset)?;
+    let duration_size = if offset == 20 { 8 } else { 4 };
+    // Three 5 bit letters, each stored as the letter - 0x60
+    let packed = u16_at(mdhd, offset + 4 + duration_size)?;
+    let language: String = [10, 5, 0]
+        .iter()
+        .map(|shift| (((packed >> shift) & 0x1f) as u8 + 0x60) as char)
+        .collect();
+    let language = if language.chars().all(|c| c.is_ascii_lowercase()) && language != "und" {
+        Some(language)
+    } else {
+        None
+    };
+    Some((timescale, language))
+}
+
+/// Codecs parameter of an AVC sample entry from its avcC box
+fn avc_codecs(entry_type: &str, avcc: &[u8]) -> Option<String> {
+    let profile = avcc.get(1..4)?;
+    Some(format!(
+        "{}.{:02X}{:02X}{:02X}",
+        entry_type, profile[0], profile[1], profile[2]
+    ))
+}
+
+/// Codecs parameter of a HEVC sample entry from its hvcC box (ISO 14496-15 E.3)
+fn hevc_codecs(entry_type: &str, hvcc: &[u8]) -> Option<String> {
+    let profile = *hvcc.get(1)?;
+    let space = ["", "A", "B", "C"][(profile >> 6) as usize];
+    let tier = if profile & 0x20 != 0 { 'H' } else { 'L' };
+    // The compatibility flags are written in reverse bit order
+    let compatibility = u32_at(hvcc, 2)?.reverse_bits();
+    let constraints = hvcc.get(6..12)?;
+    let level = *hvcc.get(12)?;
+    let mut codecs = format!(
+        "{}.{}{}.{:X}.{}{}",
+        entry_type,
+        space,
+        profile & 0x1f,
+        compatibility,
+        tier,
+        level
+    );
+    // Trailing zero bytes of the constraints are left out
+    let used = constraints
+        .iter()
+        .rposition(|b| *b != 0)
+        .map_or(0, |i| i + 1);
+    for byte in &constraints[..used] {
+        codecs.push_str(&format!(".{:X}", byte));
+    }
+    Some(codecs)
+}
+
+/// Size of an descriptor in an esds box. Returns the size and how many bytes it took.
+fn descriptor_size(data: &[u8]) -> Option<(usize, usize)> {
+    let mut size = 0;
+    for (i, byte) in data.iter().take(4).enumerate() {
+        size = (size << 7) | (byte & 0x7f) as usize;
+        if byte & 0x80 == 0 {
+            return Some((size, i + 1));
+        }
+    }
+    None
+}
+
+/// The payload of the first descriptor with `tag`, searching from the
+/// start of `data`
+fn descriptor(data: &[u8], tag: u8) -> Option<&[u8]> {
+    if *data.first()? != tag {
+        return None;
+    }
+    let (size, length) = descriptor_size(&data[1..])?;
+    data.get(1 + length..1 + length + size)
+}
+
+/// Codecs parameter of a MPEG-4 audio sample entry from its esds box
+fn mp4a_codecs(esds: &[u8]) -> Option<String> {
+    let es = descriptor(esds.get(4..)?, 0x03)?;
+    let flags = *es.get(2)?;
+    let mut offset = 3;
+    if flags & 0x80 != 0 {
+        offset += 2;
+    }
+    if flags & 0x40 != 0 {
+        offset += 1 + *es.get(offset)? as usize;
+    }
+    if flags & 0x20 != 0 {
+        offset += 2;
+    }
+    let config = descriptor(es.get(offset..)?, 0x04)?;
+    let object_type = *config.first()?;
+    // Only MPEG-4 audio has the audio object type after it
+    if object_type != 0x40 {
+        return Some(format!("mp4a.{:x}", object_type));
+    }
+    let specific = descriptor(config.get(13..)?, 0x05)?;
+    let mut audio_type = specific.first()? >> 3;
+    if audio_type == 31 {
+        audio_type = 32 + ((specific.first()? & 0x07) << 3 | specific.get(1)? >> 5);
+    }
+    Some(format!("mp4a.40.{}", audio_type))
+}
+
+/// Codecs parameter of a XML subtitle sample entry. IMSC1 text and image
+/// profiles are told apart by the profile namespaces of the entry
+fn stpp_codecs(entry: &[u8]) -> Option<String> {
+    // Namespace, schema location and auxiliary MIME types as C strings
+    let namespaces = entry.get(8..)?.split(|byte| *byte == 0).next()?;
+    let namespaces = String::from_utf8_lossy(namespaces);
+    let profile = if namespaces.contains("/profile/imsc1/text") {
+        ".ttml.im1t"
+    } else if namespaces.contains("/profile/imsc1/image") {
+        ".ttml.im1i"
+    } else {
+        ""
+    };
+    Some(format!("stpp{}", profile))
+}
+
+/// Codecs and the picture size or the audio channels and sample rate from a
+/// sample description entry
+fn sample_entry(track: &mut Track, entry_type: &[u8], entry: &[u8]) -> Option<()> {
+    let mut name = String::from_utf8_lossy(entry_type).into_owned();
+    let (children, visual) = match &track.handler[..] {
+        "vide" => (entry.get(78..)?, true),
+        "soun" => (entry.get(28..)?, false),
+        _ => (entry, false),
+    };
+    // Encrypted entries have the original type in sinf/frma
+    if name == "encv" || name == "enca" {
+        name =
+            String::from_utf8_lossy(find(children, &[b"sinf", b"frma"])?.get(0..4)?).into_owned();
+    }
+    if visual {
+        track.width = Some(u16_at(entry, 24)? as u32);
+        track.height = Some(u16_at(entry, 26)? as u32);
+    } else if track.handler == "soun" {
+        track.channels = Some(u16_at(entry, 16)?);
+        track.sample_rate = Some(u32_at(entry, 24)? >> 16);
+    }
+    track.codecs = match &name[..] {
+        "avc1" | "avc3" => avc_codecs(&name, child(children, b"avcC")?)?,
+        "hvc1" | "hev1" => hevc_codecs(&name, child(children, b"hvcC")?)?,
+        "mp4a" => mp4a_codecs(child(children, b"esds")?)?,
+        "Opus" => "opus".to_string(),
+        "fLaC" => "flac".to_string(),
+        "stpp" => stpp_codecs(entry)?,
+        _ => name.trim().to_string(),
+    };
+    Some(())
+}
+
+/// Read the first track of the init segment in `data`. None if it isn't a
+/// MP4 init segment.
+pub fn parse_init(data: &[u8]) -> Option<Track> {
+    let moov = child(data, b"moov")?;
+    let trak = child(moov, b"trak")?;
+    let mdia = child(trak, b"mdia")?;
+    let (timescale, language) = media_header(child(mdia, b"mdhd")?)?;
+    let handler = String::from_utf8_lossy(child(mdia, b"hdlr")?.get(8..12)?).into_owned();
+    let mut track = Track {
+        handler,
+        timescale,
+        language,
+        codecs: String::new(),
+        width: None,
+        height: None,
+        channels: None,
+        sample_rate: None,
+        default_sample_duration: find(moov, &[b"mvex", b"trex"]).and_then(|t| u32_at(t, 12)),
+    };
+    let stsd = find(mdia, &[b"minf", b"stbl", b"stsd"])?;
+    let (entry_type, entry) = boxes(stsd.get(8..)?).next()?;
+    sample_entry(&mut track, entry_type, entry)?;
+    Some(track)
+}
+
+/// Duration of a track fragment from its tfhd and trun boxes
+fn fragment_duration(traf: &[u8], default_duration: Option<u32>) -> Option<u64> {
+    let tfhd = child(traf, b"tfhd")?;
+    let flags = u32_at(tfhd, 0)? & 0xffffff;
+    let mut default_duration = default_duration;
+    if flags & 0x08 != 0 {
+        // After the track id and the optional data offset and description index
+        let mut offset = 8;
+        if flags & 0x01 != 0 {
+            offset += 8;
+        }
+        if flags & 0x02 != 0 {
+            offset += 4;
+        }
+        default_duration = Some(u32_at(tfhd, offset)?);
+    }
+
+    let mut duration = 0;
+    for (box_type, trun) in boxes(traf) {
+        if box_type != b"trun" {
+            continue;
+        }
+        let flags = u32_at(trun, 0)? & 0xffffff;
+        let count = u32_at(trun, 4)? as usize;
+        if flags & 0x100 == 0 {
+            duration += default_duration? as u64 * count as u64;
+            continue;
+        }
+        let mut offset = 8;
+        if flags & 0x01 != 0 {
+            offset += 4;
+        }
+        if flags & 0x04 != 0 {
+            offset += 4;
+        }
+        // Each sample has the fields its flags tell, the duration first
+        let sample_size = [0x100, 0x200, 0x400, 0x800]
+            .iter()
+            .filter(|flag| flags & *flag != 0)
+            .count()
+            * 4;
+        for sample in 0..count {
+            duration += u32_at(trun, offset + sample * sample_size)? as u64;
+        }
+    }
+    Some(duration)
+}
+
+/// Duration of the media segment in `data` in the units of the track
+/// timescale. A segment can have many fragments, e.g. the chunks of a low
+/// latency segment. None if it has no fragments or they can't be read.
+pub fn segment_duration(data: &[u8], track: &Track) -> Option<u64> {
+    let mut duration = None;
+    for (_, moof) in boxes(data).filter(|(t, _)| *t == b"moof") {
+        let traf = child(moof, b"traf")?;
+        let fragment = fragment_duration(traf, track.default_sample_duration)?;
+        duration = Some(duration.unwrap_or(0) + fragment);
+    }
+    duration
+}
+
+/// Decode time of the first sample of the media segment in `data`
+pub fn decode_time(data: &[u8]) -> Option<u64> {
+    let moof = child(data, b"moof")?;
+    let tfdt = find(moof, &[b"traf", b"tfdt"])?;
+    // The time is 64 bits in version 1
+    if *tfdt.first()? == 1 {
+        u64_at(tfdt, 4)
+    } else {
+        u32_at(tfdt, 4).map(u64::from)
+    }
+}
+
+/// The moof boxes of the segment in `file` with their headers. The media
+/// data between them is skipped so that big segments aren't read.
+pub fn read_fragments(file: &mut (impl Read + Seek)) -> io::Result<Vec<u8>> {
+    let mut fragments = vec![];
+    let mut header = [0; 16];
+    loop {
+        match file.read_exact(&mut header[..8]) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(fragments),
+            Err(e) => return Err(e),
+        }
+        let mut header_size = 8;
+        let size = match u32_at(&header, 0).unwrap() {
+            // The last box goes to the end of the file
+            0 => return Ok(fragments),
+            1 => {
+                file.read_exact(&mut header[8..16])?;
+                header_size = 16;
+                u64_at(&header, 8).unwrap()
+            }
+            size => size as u64,
+        };
+        if size < header_size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid box size",
+            ));
+        }
+        if &header[4..8] == b"moof" {
+            let start = fragments.len();
+            fragments.extend_from_slice(&header[..header_size]);
+            fragments.resize(start + size as usize, 0);
+            file.read_exact(&mut fragments[start + header_size..])?;
+        } else {
+            file.seek(SeekFrom::Current((size - header_size as u64) as i64))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod mp4_tests {
+    use super::*;
+    use std::fs;
+
+    const VOD: &str = "test_data/generated/vod";
+
+    fn read(path: &str) -> Vec<u8> {
+        fs::read(format!("{}/{}", VOD, path)).unwrap()
+    }
+
+    #[test]
+    fn video_init_segment() {
+        let track = parse_init(&read("video-720/init.mp4")).unwrap();
+        assert_eq!(track.handler, "vide");
+        assert_eq!(track.timescale, 90000);
+        assert_eq!(track.language, None);
+        assert_eq!(track.codecs, "avc1.64001F");
+        assert_eq!((track.width, track.height), (Some(1280), Some(720)));
+        assert_eq!(track.channels, None);
+    }
+
+    #[test]
+    fn audio_init_segment() {
+        let track = parse_init(&read("audio-en/init.mp4")).unwrap();
+        assert_eq!(track.handler, "soun");
+        assert_eq!(track.timescale, 48000);
+        assert_eq!(track.language.as_deref(), Some("eng"));
+        assert_eq!(track.codecs, "mp4a.40.2");
+        assert_eq!(track.channels, Some(2));
+        assert_eq!(track.sample_rate, Some(48000));
+        assert_eq!(track.width, None);
+    }
+
+    #[test]
+    fn hevc_codecs_parameter() {
+        // Main profile, main tier, level 3.1, progressive source
+        let mut hvcc = vec![1, 0x01, 0x60, 0, 0, 0, 0x90, 0, 0, 0, 0, 0, 93];
+        assert_eq!(
+            hevc_codecs("hvc1", &hvcc).as_deref(),
+            Some("hvc1.1.6.L93.90")
+        );
+        hvcc[1] = 0x22;
+        assert_eq!(
+            hevc_codecs("hev1", &hvcc).as_deref(),
+            Some("hev1.2.6.H93.90")
+        );
+    }
+
+    #[test]
+    fn subtitle_codecs_parameter() {
+        let entry = |namespaces: &str| {
+            let mut entry = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            entry.extend_from_slice(namespaces.as_bytes());
+            entry.extend_from_slice(b"\0\0\0");
+            entry
+        };
+        let text = "http://www.w3.org/ns/ttml http://www.w3.org/ns/ttml/profile/imsc1/text";
+        assert_eq!(stpp_codecs(&entry(text)).as_deref(), Some("stpp.ttml.im1t"));
+        let image = "http://www.w3.org/ns/ttml/profile/imsc1/image";
+        assert_eq!(
+            stpp_codecs(&entry(image)).as_deref(),
+            Some("stpp.ttml.im1i")
+        );
+        assert_eq!(
+            stpp_codecs(&entry("http://www.w3.org/ns/ttml")).as_deref(),
+            Some("stpp")
+        );
+        assert_eq!(stpp_codecs(&[0, 0, 0]), None);
+    }
+
+    #[test]
+    fn segment_durations() {
+        let video = parse_init(&read("video-360/init.mp4")).unwrap();
+        // Durations in the samples of the trun
+        assert_eq!(
+            segment_duration(&read("video-360/seg-1.m4s"), &video),
+            Some(180000)
+        );
+        assert_eq!(
+            segment_duration(&read("video-360/seg-3.m4s"), &video),
+            Some(90000)
+        );
+        // Durations from the tfhd default
+        let audio = parse_init(&read("audio-en/init.mp4")).unwrap();
+        assert_eq!(
+            segment_duration(&read("audio-en/seg-1.m4s"), &audio),
+            Some(94 * 1024)
+        );
+        assert_eq!(segment_duration(&read("audio-en/init.mp4"), &audio), None);
+        assert_eq!(parse_init(&read("audio-en/seg-1.m4s")), None);
+    }
+
+    #[test]
+    fn fragments_without_media_data() {
+        let path = format!("{}/video-720/seg-2.m4s", VOD);
+        let fragments = read_fragments(&mut fs::File::open(path).unwrap()).unwrap();
+        // Without the styp box and the 1800 bytes of media data
+        assert_eq!(fragments.len(), 2304 - 24 - 1808);
+        assert_eq!(boxes(&fragments).count(), 1);
+        let video = parse_init(&read("video-720/init.mp4")).unwrap();
+        assert_eq!(segment_duration(&fragments, &video), Some(180000));
+        assert_eq!(decode_time(&fragments), Some(180000));
+        assert_eq!(decode_time(&read("video-720/seg-1.m4s")), Some(0));
+    }
+
+    #[test]
+    fn truncated_boxes() {
+        let data = read("video-720/init.mp4");
+        assert_eq!(parse_init(&data[..data.len() - 10]), None);
+        assert_eq!(parse_init(&[]), None);
+        assert_eq!(boxes(&[0, 0, 0, 4, b'f', b'r', b'e', b'e']).count(), 0);
+    }
+}
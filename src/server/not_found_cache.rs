@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Upper limit for remembered paths so random 404 urls can't use up all the memory
+const MAX_ENTRIES: usize = 10_000;
+
+/// Remembers paths that recently didn't exist so players retrying a missing
+/// live segment don't cause a filesystem lookup on every request.
+/// Entries expire after `ttl` so newly published files show up shortly after.
+pub struct NotFoundCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Instant>>,
+}
+
+impl NotFoundCache {
+    /// Zero `ttl` disables the cache
+    pub fn new(ttl: Duration) -> NotFoundCache {
+        NotFoundCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Was `path` missing during the last `ttl`
+    pub fn is_missing(&self, path: &str) -> bool {
+        if self.ttl.is_zero() {
+            return false;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(path) {
+            Some(added) if added.elapsed() < self.ttl => true,
+            Some(_) => {
+                entries.remove(path);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Remember that `path` doesn't exist
+    pub fn insert(&self, path: &str) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            let ttl = self.ttl;
+            entries.retain(|_, added| added.elapsed() < ttl);
+            // Still full of fresh entries. Not caching this one is fine
+            if entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+        entries.insert(path.to_string(), Instant::now());
+    }
+
+    /// Forget that `path` was missing, e.g. when it was just completed
+    pub fn remove(&self, path: &str) {
+        self.entries.lock().unwrap().remove(path);
+    }
+
+    /// Forget the missing paths that `matches`
+    pub fn remove_matching(&self, matches: impl Fn(&str) -> bool) {
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|path, _| !matches(path));
+    }
+
+    /// Forget the missing paths, e.g. after files were published by hand
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod not_found_cache_tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn remembers_missing_path() {
+        let cache = NotFoundCache::new(Duration::from_secs(60));
+        assert!(!cache.is_missing("a/seg-1.m4s"));
+        cache.insert("a/seg-1.m4s");
+        assert!(cache.is_missing("a/seg-1.m4s"));
+        assert!(!cache.is_missing("a/seg-2.m4s"));
+        cache.remove("a/seg-1.m4s");
+        assert!(!cache.is_missing("a/seg-1.m4s"));
+
+        cache.insert("a/seg-1.m4s");
+        cache.insert("b/seg-1.m4s");
+        cache.remove_matching(|path| path.starts_with("a/"));
+        assert!(!cache.is_missing("a/seg-1.m4s"));
+        assert!(cache.is_missing("b/seg-1.m4s"));
+    }
+
+    #[test]
+    fn entries_expire() {
+        let cache = NotFoundCache::new(Duration::from_millis(50));
+        cache.insert("a/seg-1.m4s");
+        thread::sleep(Duration::from_millis(100));
+        assert!(!cache.is_missing("a/seg-1.m4s"));
+    }
+
+    #[test]
+    fn zero_ttl_disables_cache() {
+        let cache = NotFoundCache::new(Duration::from_secs(0));
+        cache.insert("a/seg-1.m4s");
+        assert!(!cache.is_missing("a/seg-1.m4s"));
+    }
+}
@@ -0,0 +1,56 @@
+use std::convert::TryFrom;
+use std::io;
+use std::os::unix::io::RawFd;
+
+/// Wait until some of `fds` have something to read, e.g. a connection to
+/// accept. Returns their indexes in `fds`. A wait interrupted by a signal
+/// returns an empty list.
+pub fn readable(fds: &[RawFd]) -> io::Result<Vec<usize>> {
+    let mut poll_fds: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let count = libc::nfds_t::try_from(poll_fds.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Too many sockets to poll"))?;
+    // Blocks without a timeout, the shutdown is one of the fds
+    let ready = unsafe { libc::poll(poll_fds.as_mut_ptr(), count, -1) };
+    if ready < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::Interrupted {
+            return Ok(vec![]);
+        }
+        return Err(error);
+    }
+    Ok(poll_fds
+        .iter()
+        .enumerate()
+        .filter(|(_, poll_fd)| poll_fd.revents != 0)
+        .map(|(i, _)| i)
+        .collect())
+}
+
+#[cfg(test)]
+mod poll_tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn ready_sockets() {
+        let quiet = TcpListener::bind("127.0.0.1:0").unwrap();
+        let busy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(busy.local_addr().unwrap()).unwrap();
+        let fds = [quiet.as_raw_fd(), busy.as_raw_fd()];
+        assert_eq!(readable(&fds).unwrap(), vec![1]);
+
+        let (mut writer, reader) = std::os::unix::net::UnixStream::pair().unwrap();
+        writer.write_all(b"x").unwrap();
+        let fds = [quiet.as_raw_fd(), reader.as_raw_fd(), busy.as_raw_fd()];
+        assert_eq!(readable(&fds).unwrap(), vec![1, 2]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use mpeg_dash::ThreadPool;
+
+/// Upper limit for tracked playback sessions
+const MAX_SESSIONS: usize = 10_000;
+/// Session is forgotten if it hasn't requested anything for this long
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Predictions are dropped while this many are waiting to be warmed
+const MAX_QUEUED: usize = 1000;
+
+/// Segment number of the last request in a session
+struct Session {
+    last_number: u64,
+    last_seen: Instant,
+}
+
+/// Segment path split around the segment number, e.g. "video/seg-", 12, "-x.m4s"
+#[derive(Debug, PartialEq)]
+struct NumberedPath<'a> {
+    prefix: &'a str,
+    number: u64,
+    /// Length of the number in the path. Used to keep the zero padding
+    width: usize,
+    suffix: &'a str,
+}
+
+impl NumberedPath<'_> {
+    /// Split `path` around the last number in the file name.
+    /// The extension is skipped so "mp4" or "m4s" isn't mistaken for a number.
+    fn parse(path: &str) -> Option<NumberedPath<'_>> {
+        let name_start = path.rfind('/').map(|pos| pos + 1).unwrap_or(0);
+        let name = &path[name_start..];
+        let stem_len = name.rfind('.').unwrap_or(name.len());
+        let end = name[..stem_len].rfind(|c: char| c.is_ascii_digit())? + 1;
+        let start = name[..end]
+            .rfind(|c: char| !c.is_ascii_digit())
+            .map(|pos| pos + 1)
+            .unwrap_or(0);
+
+        Some(NumberedPath {
+            prefix: &path[..name_start + start],
+            number: name[start..end].parse().ok()?,
+            width: end - start,
+            suffix: &name[end..],
+        })
+    }
+
+    fn with_number(&self, number: u64) -> String {
+        format!(
+            "{}{:0width$}{}",
+            self.prefix,
+            number,
+            self.suffix,
+            width = self.width
+        )
+    }
+}
+
+/// Follows the segment requests of every client and predicts which segments
+/// will be requested next. A session is a client address and a segment
+/// naming pattern, e.g. "video/avc1/seg-$Number$.m4s".
+pub struct Prefetcher {
+    /// How many segments ahead are predicted. Zero disables the prediction
+    count: usize,
+    sessions: Mutex<HashMap<(IpAddr, String), Session>>,
+    /// Warms the predicted segments so the workers serving the requests
+    /// don't wait for it. None when the prediction is disabled
+    pool: Option<ThreadPool>,
+}
+
+impl Prefetcher {
+    pub fn new(count: usize) -> Prefetcher {
+        Prefetcher {
+            count,
+            sessions: Mutex::new(HashMap::new()),
+            pool: (count > 0).then(|| ThreadPool::new(1)),
+        }
+    }
+
+    /// Record that `peer` was served `path` and warm the segments it's
+    /// predicted to request next in the background
+    pub fn prefetch(&self, peer: IpAddr, path: &str) {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return,
+        };
+        let next_segments = self.record(peer, path);
+        if next_segments.is_empty() || pool.queued() >= MAX_QUEUED {
+            return;
+        }
+        pool.execute(move || {
+            for next_segment in &next_segments {
+                warm(next_segment);
+            }
+        });
+    }
+
+    /// Record that `peer` was served `path` and return the paths that should be
+    /// requested next. Nothing is predicted until the client has requested two
+    /// consecutive segments so random access and seeking don't warm anything.
+    pub fn record(&self, peer: IpAddr, path: &str) -> Vec<String> {
+        if self.count == 0 {
+            return vec![];
+        }
+
+        let numbered = match NumberedPath::parse(path) {
+            Some(numbered) => numbered,
+            None => return vec![],
+        };
+        let pattern = format!("{}$Number${}", numbered.prefix, numbered.suffix);
+
+        let key = (peer, pattern);
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= MAX_SESSIONS && !sessions.contains_key(&key) {
+            sessions.retain(|_, session| session.last_seen.elapsed() < SESSION_TIMEOUT);
+            // Still full of active sessions. Not following this one is fine
+            if sessions.len() >= MAX_SESSIONS {
+                return vec![];
+            }
+        }
+
+        let session = sessions.entry(key).or_insert(Session {
+            last_number: u64::MAX,
+            last_seen: Instant::now(),
+        });
+        let sequential = session.last_number.checked_add(1) == Some(numbered.number)
+            && session.last_seen.elapsed() < SESSION_TIMEOUT;
+        session.last_number = numbered.number;
+        session.last_seen = Instant::now();
+
+        if !sequential {
+            return vec![];
+        }
+
+        (1..=self.count as u64)
+            .map(|ahead| numbered.with_number(numbered.number + ahead))
+            .collect()
+    }
+}
+
+/// Ask the kernel to start reading the file into the page cache in the
+/// background so it's ready when the client requests it.
+/// Files that don't exist yet (live edge) are ignored.
+fn warm(path: &str) {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return,
+    };
+    advise_will_need(&file);
+}
+
+#[cfg(target_os = "linux")]
+fn advise_will_need(file: &File) {
+    use std::os::unix::io::AsRawFd;
+    // Only a hint, failing is fine
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_WILLNEED);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_will_need(_file: &File) {}
+
+#[cfg(test)]
+mod prefetch_tests {
+    use super::*;
+
+    #[test]
+    fn parse_numbered_path() {
+        assert_eq!(
+            NumberedPath::parse("v1/seg-0012.m4s"),
+            Some(NumberedPath {
+                prefix: "v1/seg-",
+                number: 12,
+                width: 4,
+                suffix: ".m4s"
+            })
+        );
+        assert_eq!(NumberedPath::parse("v1/seg-5.mp4").unwrap().number, 5);
+        assert_eq!(NumberedPath::parse("v1/init.mp4"), None);
+        assert_eq!(
+            NumberedPath::parse("v1/00"),
+            Some(NumberedPath {
+                prefix: "v1/",
+                number: 0,
+                width: 2,
+                suffix: ""
+            })
+        );
+    }
+
+    #[test]
+    fn predicts_after_sequential_requests() {
+        let prefetcher = Prefetcher::new(2);
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(prefetcher.record(peer, "v1/seg-0009.m4s").is_empty());
+        assert_eq!(
+            prefetcher.record(peer, "v1/seg-0010.m4s"),
+            vec!["v1/seg-0011.m4s", "v1/seg-0012.m4s"]
+        );
+
+        // Seeking breaks the sequence
+        assert!(prefetcher.record(peer, "v1/seg-0100.m4s").is_empty());
+        // Other clients and representations are separate sessions
+        let other: IpAddr = "127.0.0.2".parse().unwrap();
+        assert!(prefetcher.record(other, "v1/seg-0101.m4s").is_empty());
+        assert!(prefetcher.record(peer, "v2/seg-0101.m4s").is_empty());
+    }
+
+    #[test]
+    fn sessions_are_limited() {
+        let prefetcher = Prefetcher::new(1);
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        for i in 0..MAX_SESSIONS {
+            prefetcher.record(peer, &format!("v{}/seg-1.m4s", i));
+        }
+        // The active sessions are kept and a new one isn't followed
+        prefetcher.record(peer, "new/seg-1.m4s");
+        assert!(prefetcher.record(peer, "new/seg-2.m4s").is_empty());
+        assert_eq!(prefetcher.sessions.lock().unwrap().len(), MAX_SESSIONS);
+        assert_eq!(
+            prefetcher.record(peer, "v0/seg-2.m4s"),
+            vec!["v0/seg-3.m4s"]
+        );
+    }
+
+    #[test]
+    fn zero_count_disables_prediction() {
+        let prefetcher = Prefetcher::new(0);
+        let peer: IpAddr = "127.0.0.1".parse().unwrap();
+        prefetcher.record(peer, "v1/seg-1.m4s");
+        assert!(prefetcher.record(peer, "v1/seg-2.m4s").is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::config::PreloadMode;
+
+struct Representation {
+    id: String,
+    bandwidth: u64,
+    initialization: Option<String>,
+}
+
+/// Value of the attribute `name` in the element
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.local_name().as_ref() == name.as_bytes())
+        .and_then(|attr| attr.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// Fill the identifiers that are allowed in the initialization template (ISO 23009-1 5.3.9.4.4)
+fn substitute(template: &str, representation: &Representation) -> String {
+    let mut out = String::new();
+    let mut parts = template.split('$');
+    // Text before the first '$' is never an identifier
+    out.push_str(parts.next().unwrap_or(""));
+
+    let mut is_identifier = true;
+    for part in parts {
+        if !is_identifier {
+            out.push_str(part);
+        } else if part.is_empty() {
+            // "$$" is an escaped '$'
+            out.push('$');
+        } else if part == "RepresentationID" {
+            out.push_str(&representation.id);
+        } else if part == "Bandwidth" {
+            out.push_str(&representation.bandwidth.to_string());
+        } else if let Some(width) = part
+            .strip_prefix("Bandwidth%0")
+            .and_then(|format| format.strip_suffix('d'))
+            .and_then(|width| width.parse::<usize>().ok())
+        {
+            out.push_str(&format!(
+                "{:0width$}",
+                representation.bandwidth,
+                width = width
+            ));
+        } else {
+            // Not something we know how to fill. Leave it as it was
+            out.push('$');
+            out.push_str(part);
+            out.push('$');
+        }
+        is_identifier = !is_identifier;
+    }
+    out
+}
+
+/// Initialization urls of the representations in the manifest `mpd`.
+/// The urls are relative to the manifest unless they were absolute in the manifest.
+/// With PreloadMode::Lowest only the lowest bandwidth representation of every
+/// adaptation set is used since that is what players usually start from.
+pub fn init_segments(mpd: &str, mode: PreloadMode) -> Vec<String> {
+    let mut segments: Vec<String> = vec![];
+    if mode == PreloadMode::Off {
+        return segments;
+    }
+
+    let mut reader = Reader::from_str(mpd);
+    // Names of the currently open elements
+    let mut stack: Vec<String> = vec![];
+    let mut period_init: Option<String> = None;
+    let mut set_init: Option<String> = None;
+    let mut representations: Vec<Representation> = vec![];
+
+    loop {
+        let (element, is_empty) = match reader.read_event() {
+            Ok(Event::Start(element)) => (element, false),
+            Ok(Event::Empty(element)) => (element, true),
+            Ok(Event::End(element)) => {
+                if element.local_name().as_ref() == b"AdaptationSet" {
+                    let mut selected: Vec<&Representation> = representations.iter().collect();
+                    if mode == PreloadMode::Lowest {
+                        selected.sort_by_key(|representation| representation.bandwidth);
+                        selected.truncate(1);
+                    }
+
+                    for representation in selected {
+                        let template = representation
+                            .initialization
+                            .as_ref()
+                            .or(set_init.as_ref())
+                            .or(period_init.as_ref());
+                        if let Some(template) = template {
+                            let segment = substitute(template, representation);
+                            if !segments.contains(&segment) {
+                                segments.push(segment);
+                            }
+                        }
+                    }
+
+                    representations.clear();
+                    set_init = None;
+                } else if element.local_name().as_ref() == b"Period" {
+                    period_init = None;
+                }
+                stack.pop();
+                continue;
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            Ok(_) => continue,
+        };
+
+        let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+        let parent = stack.last().map(|parent| &parent[..]);
+        // SegmentBase can only have its Initialization in a separate file with sourceURL.
+        // Otherwise the init data is a byte range of the media file itself.
+        let initialization = match (&name[..], parent) {
+            ("SegmentTemplate", _) => attribute(&element, "initialization"),
+            ("Initialization", Some("SegmentBase")) => attribute(&element, "sourceURL"),
+            _ => None,
+        };
+
+        if name == "Representation" {
+            representations.push(Representation {
+                id: attribute(&element, "id").unwrap_or_default(),
+                bandwidth: attribute(&element, "bandwidth")
+                    .and_then(|bandwidth| bandwidth.parse().ok())
+                    .unwrap_or(0),
+                initialization: None,
+            });
+        } else if let Some(initialization) = initialization {
+            // Initialization is inside SegmentBase so the owner is one level higher
+            let owner_level = if name == "Initialization" { 2 } else { 1 };
+            let owner = stack
+                .len()
+                .checked_sub(owner_level)
+                .map(|level| &stack[level][..]);
+            match owner {
+                Some("Period") => period_init = Some(initialization),
+                Some("AdaptationSet") => set_init = Some(initialization),
+                Some("Representation") => {
+                    if let Some(representation) = representations.last_mut() {
+                        representation.initialization = Some(initialization);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !is_empty {
+            stack.push(name);
+        }
+    }
+
+    segments
+}
+
+/// "Link" header lines preloading the `segments` of the manifest in `mpd_path`.
+/// Urls that could end the header line or the <> around them are left out
+/// since the manifest may come from an encoder.
+pub fn link_headers(mpd_path: &str, segments: &[String]) -> String {
+    let base = &mpd_path[..mpd_path.rfind('/').map(|pos| pos + 1).unwrap_or(0)];
+    let mut out = String::new();
+    let is_safe = |url: &str| !url.contains(|c: char| c.is_control() || c == '<' || c == '>');
+    for segment in segments.iter().filter(|segment| is_safe(segment)) {
+        let absolute = segment.starts_with('/')
+            || segment.starts_with("http://")
+            || segment.starts_with("https://");
+        let url = if absolute {
+            segment.clone()
+        } else {
+            format!("{}{}", base, segment)
+        };
+        // Players fetch segments with XHR/fetch and the response is
+        // used cross-origin so the preload has to match that
+        out.push_str(&format!(
+            "Link: <{}>; rel=preload; as=fetch; crossorigin\r\n",
+            url
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod preload_tests {
+    use super::*;
+
+    const MPD: &str = r#"<?xml version="1.0"?>
+<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static">
+  <Period>
+    <AdaptationSet mimeType="video/mp4">
+      <SegmentTemplate initialization="$RepresentationID$/init-$Bandwidth%08d$.mp4" media="$RepresentationID$/$Number$.m4s"/>
+      <Representation id="v1" bandwidth="2000000"/>
+      <Representation id="v0" bandwidth="500000"/>
+    </AdaptationSet>
+    <AdaptationSet mimeType="audio/mp4">
+      <Representation id="a0" bandwidth="128000">
+        <SegmentTemplate initialization="audio/init.mp4" media="audio/$Number$.m4s"/>
+      </Representation>
+    </AdaptationSet>
+    <AdaptationSet mimeType="text/vtt">
+      <Representation id="t0" bandwidth="100">
+        <SegmentBase><Initialization range="0-100"/></SegmentBase>
+      </Representation>
+      <Representation id="t1" bandwidth="100">
+        <SegmentBase><Initialization sourceURL="text/init.mp4"/></SegmentBase>
+      </Representation>
+    </AdaptationSet>
+  </Period>
+</MPD>"#;
+
+    #[test]
+    fn all_init_segments() {
+        assert_eq!(
+            init_segments(MPD, PreloadMode::All),
+            vec![
+                "v1/init-02000000.mp4",
+                "v0/init-00500000.mp4",
+                "audio/init.mp4",
+                "text/init.mp4"
+            ]
+        );
+    }
+
+    #[test]
+    fn lowest_init_segments() {
+        assert_eq!(
+            init_segments(MPD, PreloadMode::Lowest),
+            vec!["v0/init-00500000.mp4", "audio/init.mp4"]
+        );
+        assert!(init_segments(MPD, PreloadMode::Off).is_empty());
+    }
+
+    #[test]
+    fn template_substitution() {
+        let representation = Representation {
+            id: "v1".to_string(),
+            bandwidth: 42,
+            initialization: None,
+        };
+        assert_eq!(
+            substitute("$$$RepresentationID$$$", &representation),
+            "$v1$"
+        );
+        assert_eq!(
+            substitute("a$Bandwidth$/$Time$", &representation),
+            "a42/$Time$"
+        );
+    }
+
+    #[test]
+    fn link_header_urls() {
+        let segments = vec!["v0/init.mp4".to_string(), "/abs/init.mp4".to_string()];
+        assert_eq!(
+            link_headers("/movies/a/stream.mpd", &segments),
+            "Link: </movies/a/v0/init.mp4>; rel=preload; as=fetch; crossorigin\r\n\
+             Link: </abs/init.mp4>; rel=preload; as=fetch; crossorigin\r\n"
+        );
+    }
+
+    #[test]
+    fn unsafe_link_header_urls() {
+        let mpd = MPD.replace("audio/init.mp4", "init.mp4&#13;&#10;Set-Cookie: injected=1");
+        let segments = init_segments(&mpd, PreloadMode::All);
+        assert_eq!(segments[2], "init.mp4\r\nSet-Cookie: injected=1");
+        let mut segments = segments[2..3].to_vec();
+        segments.push("a>; rel=next, <b".to_string());
+        segments.push("v0/init\u{7f}.mp4".to_string());
+        assert_eq!(link_headers("/movies/a/stream.mpd", &segments), "");
+    }
+}
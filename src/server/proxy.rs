@@ -0,0 +1,323 @@
+use std::convert::TryInto;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use super::ip_filter::in_range;
+use super::request::Request;
+
+/// Start of the binary version 2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest version 1 header with the line break
+const V1_MAX_LENGTH: usize = 107;
+
+fn invalid(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Source address of a "PROXY TCP4 <src> <dst> <src port> <dst port>" line
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let mut parts = line.split(' ');
+    if parts.next() != Some("PROXY") {
+        return Err(invalid("Not a PROXY header"));
+    }
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        // The proxy's own connections, e.g. health checks
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid("Unknown PROXY protocol")),
+    }
+    let parts: Vec<&str> = parts.collect();
+    if parts.len() != 4 {
+        return Err(invalid("Invalid PROXY header"));
+    }
+    let address = parts[0]
+        .parse::<IpAddr>()
+        .map_err(|_| invalid("Invalid PROXY source address"))?;
+    let port = parts[2]
+        .parse::<u16>()
+        .map_err(|_| invalid("Invalid PROXY source port"))?;
+    Ok(Some(SocketAddr::new(address, port)))
+}
+
+/// Source address of the version 2 header after the signature and the
+/// fixed part, which are `command`, `family` and the length of `addresses`
+fn parse_v2(command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+    match command & 0x0f {
+        // LOCAL, the proxy's own connection
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("Unknown PROXY command")),
+    }
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+    // Addresses of both ends followed by the ports. The low bits tell if
+    // it's a stream or datagram which doesn't matter here.
+    match family >> 4 {
+        1 if addresses.len() >= 12 => {
+            let octets: [u8; 4] = addresses[..4].try_into().unwrap();
+            Ok(Some(SocketAddr::new(
+                Ipv4Addr::from(octets).into(),
+                port(8),
+            )))
+        }
+        2 if addresses.len() >= 36 => {
+            let octets: [u8; 16] = addresses[..16].try_into().unwrap();
+            Ok(Some(SocketAddr::new(
+                Ipv6Addr::from(octets).into(),
+                port(32),
+            )))
+        }
+        // Unix sockets and unspecified addresses
+        0 | 3 => Ok(None),
+        _ => Err(invalid("Invalid PROXY addresses")),
+    }
+}
+
+/// Read the PROXY protocol header, version 1 or 2, from the start of a
+/// connection. Nothing after the header is read. None is the proxy's own
+/// connection, which keeps the address of the socket.
+pub fn read_header(stream: &mut impl Read) -> io::Result<Option<SocketAddr>> {
+    // The shortest header, "PROXY UNKNOWN\r\n", is longer than this
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start)?;
+    if &start == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed)?;
+        let mut addresses = vec![0u8; usize::from(u16::from_be_bytes([fixed[2], fixed[3]]))];
+        stream.read_exact(&mut addresses)?;
+        return parse_v2(fixed[0], fixed[1], &addresses);
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("No PROXY header"));
+    }
+    // A byte at a time so the request after the header stays in the socket
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid("PROXY header is too long"));
+        }
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("Invalid PROXY header"))?;
+    parse_v1(line)
+}
+
+/// Address of a "for" parameter or a X-Forwarded-For entry. Ports and the
+/// brackets and quotes around IPv6 addresses are left out.
+fn parse_forwarded_address(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+    if let Ok(address) = value.parse::<IpAddr>() {
+        return Some(address);
+    }
+    if let Ok(address) = value.parse::<SocketAddr>() {
+        return Some(address.ip());
+    }
+    value
+        .strip_prefix('[')
+        .and_then(|value| value.strip_suffix(']'))
+        .and_then(|value| value.parse().ok())
+}
+
+/// Addresses the proxies added, the client first. Forwarded is used when
+/// the request has it, X-Forwarded-For otherwise. None is an address that
+/// couldn't be read, like "unknown" or an obfuscated one.
+fn forwarded_addresses(request: &Request) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<&str> {
+        request
+            .headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| value.split(','))
+            .collect()
+    };
+    let forwarded = values("Forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, value)| parse_forwarded_address(value))
+            })
+            .collect();
+    }
+    values("X-Forwarded-For")
+        .iter()
+        .map(|value| parse_forwarded_address(value))
+        .collect()
+}
+
+/// Address of the client that sent `request` over a connection from
+/// `peer`. The forwarding headers are only read when `peer` is one of the
+/// `trusted` proxies. They are read from the right, skipping the trusted
+/// proxies, so a client can't pick its address by sending the headers itself.
+pub fn client_address(peer: IpAddr, request: &Request, trusted: &[String]) -> IpAddr {
+    let is_trusted = |address: IpAddr| {
+        let address = address.to_canonical();
+        trusted.iter().any(|range| in_range(address, range))
+    };
+    let mut client = peer;
+    if !is_trusted(peer) {
+        return client;
+    }
+    for address in forwarded_addresses(request).into_iter().rev() {
+        match address {
+            Some(address) => {
+                client = address;
+                if !is_trusted(address) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod proxy_tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn v1_headers() {
+        let mut data: &[u8] = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(
+            read_header(&mut data).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        // The request is left alone
+        assert_eq!(data, b"GET / HTTP/1.1\r\n");
+
+        let mut data: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 443\r\n";
+        assert_eq!(
+            read_header(&mut data).unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+        let mut data: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut data).unwrap(), None);
+
+        for invalid in [
+            &b"GET / HTTP/1.1\r\n\r\n"[..],
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 example 198.51.100.1 56324 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1",
+        ] {
+            let mut data = invalid;
+            assert!(read_header(&mut data).is_err());
+        }
+        let long = format!("PROXY TCP4 {}\r\n", "1".repeat(200));
+        assert!(read_header(&mut long.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn v2_headers() {
+        let mut header = V2_SIGNATURE.to_vec();
+        // PROXY over TCP4, 12 bytes of addresses
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        header.extend_from_slice(b"GET");
+        let mut data = &header[..];
+        assert_eq!(
+            read_header(&mut data).unwrap(),
+            Some("192.0.2.1:56324".parse().unwrap())
+        );
+        assert_eq!(data, b"GET");
+
+        let mut header = V2_SIGNATURE.to_vec();
+        // PROXY over TCP6 with a TLV after the addresses
+        header.extend_from_slice(&[0x21, 0x21, 0, 40]);
+        header.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        header.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        header.extend_from_slice(&[0x0f, 0xa0, 0x01, 0xbb, 0x04, 0, 1, 0]);
+        assert_eq!(
+            read_header(&mut &header[..]).unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(read_header(&mut &local[..]).unwrap(), None);
+
+        let mut version_1 = V2_SIGNATURE.to_vec();
+        version_1.extend_from_slice(&[0x11, 0x11, 0, 0]);
+        assert!(read_header(&mut &version_1[..]).is_err());
+        let mut short = V2_SIGNATURE.to_vec();
+        short.extend_from_slice(&[0x21, 0x11, 0, 4, 192, 0, 2, 1]);
+        assert!(read_header(&mut &short[..]).is_err());
+    }
+
+    #[test]
+    fn forwarded_clients() {
+        let trusted = vec!["10.0.0.0/8".to_string(), "::1".to_string()];
+        let request = |headers: &str| {
+            Request::parse(&format!("GET / HTTP/1.1\r\nHost: a\r\n{}\r\n", headers)).unwrap()
+        };
+        let client =
+            |peer: &str, headers: &str| client_address(ip(peer), &request(headers), &trusted);
+        // Only trusted proxies can forward
+        assert_eq!(
+            client("192.0.2.9", "X-Forwarded-For: 192.0.2.1\r\n"),
+            ip("192.0.2.9")
+        );
+        assert_eq!(
+            client("10.0.0.1", "X-Forwarded-For: 192.0.2.1\r\n"),
+            ip("192.0.2.1")
+        );
+        assert_eq!(client("10.0.0.1", ""), ip("10.0.0.1"));
+        // The client can send its own header in front of the proxy's
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                "X-Forwarded-For: 1.1.1.1, 192.0.2.1, 10.0.0.2\r\n"
+            ),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                "X-Forwarded-For: 1.1.1.1\r\nX-Forwarded-For: 192.0.2.1:5000\r\n"
+            ),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client("10.0.0.1", "X-Forwarded-For: 10.0.0.3, 10.0.0.2\r\n"),
+            ip("10.0.0.3")
+        );
+        assert_eq!(
+            client("10.0.0.1", "X-Forwarded-For: 192.0.2.1, unknown\r\n"),
+            ip("10.0.0.1")
+        );
+        // Forwarded wins
+        assert_eq!(
+            client(
+                "::1",
+                "X-Forwarded-For: 192.0.2.1\r\nForwarded: for=192.0.2.60;proto=https, for=\"[2001:db8::1]:4711\"\r\n"
+            ),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            client(
+                "::ffff:10.0.0.1",
+                "Forwarded: proto=http;For=192.0.2.60\r\n"
+            ),
+            ip("192.0.2.60")
+        );
+        assert_eq!(
+            client("10.0.0.1", "Forwarded: for=_hidden\r\n"),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{Quota, QuotaPeriod};
+
+/// Usage of one tenant in the current period
+struct Usage {
+    period: u64,
+    requests: u64,
+    bytes: u64,
+}
+
+/// Number of the UTC month `days` days after 1970-01-01, counted from year 0
+fn month_number(days: u64) -> u64 {
+    // Howard Hinnant's civil_from_days with the era shifted to start in March
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    year * 12 + month - 1
+}
+
+/// Number of the period `now` is in. Only used to notice when the period changes
+fn period_number(period: QuotaPeriod, now: SystemTime) -> u64 {
+    let days = now
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() / 86_400)
+        .unwrap_or(0);
+    match period {
+        QuotaPeriod::Daily => days,
+        QuotaPeriod::Monthly => month_number(days),
+    }
+}
+
+/// Request and byte counts of every tenant with a quota.
+/// The counts live in memory so restarting the server resets them.
+pub struct Quotas {
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl Quotas {
+    pub fn new() -> Quotas {
+        Quotas {
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a request for `tenant`. Returns false if the tenant has already
+    /// used its quota for the period, in which case the request isn't counted.
+    pub fn try_request(&self, tenant: &str, quota: &Quota) -> bool {
+        let period = period_number(quota.period, SystemTime::now());
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(tenant.to_string()).or_insert(Usage {
+            period,
+            requests: 0,
+            bytes: 0,
+        });
+        if usage.period != period {
+            *usage = Usage {
+                period,
+                requests: 0,
+                bytes: 0,
+            };
+        }
+
+        let over_requests = quota.max_requests.is_some_and(|max| usage.requests >= max);
+        let over_bytes = quota.max_bytes.is_some_and(|max| usage.bytes >= max);
+        if over_requests || over_bytes {
+            return false;
+        }
+        usage.requests += 1;
+        true
+    }
+
+    /// Requests and bytes that `tenant` has used in the current period of `quota`
+    pub fn usage(&self, tenant: &str, quota: &Quota) -> (u64, u64) {
+        let period = period_number(quota.period, SystemTime::now());
+        match self.usage.lock().unwrap().get(tenant) {
+            Some(usage) if usage.period == period => (usage.requests, usage.bytes),
+            _ => (0, 0),
+        }
+    }
+
+    /// Count `bytes` of content served to `tenant`
+    pub fn add_bytes(&self, tenant: &str, bytes: u64) {
+        if let Some(usage) = self.usage.lock().unwrap().get_mut(tenant) {
+            usage.bytes += bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod quota_tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn month_numbers() {
+        // 1970-01-01
+        assert_eq!(month_number(0), 1970 * 12);
+        // 2024-02-29 and 2024-03-01
+        assert_eq!(month_number(19_782), 2024 * 12 + 1);
+        assert_eq!(month_number(19_783), 2024 * 12 + 2);
+        // 2023-12-31 and 2024-01-01
+        assert_eq!(month_number(19_722), 2023 * 12 + 11);
+        assert_eq!(month_number(19_723), 2024 * 12);
+    }
+
+    #[test]
+    fn daily_periods() {
+        let day = UNIX_EPOCH + Duration::from_secs(86_400 * 3 + 10);
+        assert_eq!(period_number(QuotaPeriod::Daily, day), 3);
+    }
+
+    #[test]
+    fn request_and_byte_limits() {
+        let quotas = Quotas::new();
+        let requests = Quota {
+            period: QuotaPeriod::Daily,
+            max_requests: Some(2),
+            max_bytes: None,
+        };
+        assert!(quotas.try_request("a", &requests));
+        assert!(quotas.try_request("a", &requests));
+        assert!(!quotas.try_request("a", &requests));
+        // Tenants are counted separately
+        assert!(quotas.try_request("b", &requests));
+
+        let bytes = Quota {
+            period: QuotaPeriod::Monthly,
+            max_requests: None,
+            max_bytes: Some(100),
+        };
+        assert!(quotas.try_request("c", &bytes));
+        quotas.add_bytes("c", 99);
+        assert!(quotas.try_request("c", &bytes));
+        quotas.add_bytes("c", 1);
+        assert!(!quotas.try_request("c", &bytes));
+
+        assert_eq!(quotas.usage("a", &requests), (2, 0));
+        assert_eq!(quotas.usage("c", &bytes), (2, 100));
+        assert_eq!(quotas.usage("d", &bytes), (0, 0));
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// What part of the file the client asked for with the Range header
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    /// No Range header or one we don't support. The whole file is sent
+    Full,
+    /// Bytes from `start` to `end`, both included
+    Partial { start: u64, end: u64 },
+    /// The range is outside of the file. Answered with 416
+    Unsatisfiable,
+}
+
+/// Parse the Range header for a file of `length` bytes (RFC 9110 14.2).
+/// Only a single byte range is supported. Other units and multiple ranges
+/// are ignored which is allowed, the client then gets the whole file.
+pub fn parse_range(header: Option<&str>, length: u64) -> RangeRequest {
+    let spec = match header.and_then(|header| header.trim().strip_prefix("bytes=")) {
+        Some(spec) => spec.trim(),
+        None => return RangeRequest::Full,
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let (first, last) = match spec.split_once('-') {
+        Some(range) => range,
+        None => return RangeRequest::Full,
+    };
+
+    let (start, end) = if first.is_empty() {
+        // "-500" is the last 500 bytes
+        let suffix: u64 = match last.parse() {
+            Ok(suffix) => suffix,
+            Err(_) => return RangeRequest::Full,
+        };
+        if suffix == 0 || length == 0 {
+            return RangeRequest::Unsatisfiable;
+        }
+        (length.saturating_sub(suffix), length - 1)
+    } else {
+        let start: u64 = match first.parse() {
+            Ok(start) => start,
+            Err(_) => return RangeRequest::Full,
+        };
+        let end = if last.is_empty() {
+            u64::MAX
+        } else {
+            match last.parse() {
+                Ok(end) => end,
+                Err(_) => return RangeRequest::Full,
+            }
+        };
+        if end < start {
+            return RangeRequest::Full;
+        }
+        if start >= length {
+            return RangeRequest::Unsatisfiable;
+        }
+        (start, end.min(length - 1))
+    };
+
+    RangeRequest::Partial { start, end }
+}
+
+/// Read `length` bytes from `start` without reading the rest of the file
+pub fn read_range(file: &mut File, start: u64, length: u64) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut data = vec![0u8; length as usize];
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod range_tests {
+    use super::*;
+
+    #[test]
+    fn byte_ranges() {
+        let range = |header: &str| parse_range(Some(header), 1000);
+        assert_eq!(
+            range("bytes=0-499"),
+            RangeRequest::Partial { start: 0, end: 499 }
+        );
+        assert_eq!(
+            range("bytes=500-"),
+            RangeRequest::Partial {
+                start: 500,
+                end: 999
+            }
+        );
+        assert_eq!(
+            range("bytes=-100"),
+            RangeRequest::Partial {
+                start: 900,
+                end: 999
+            }
+        );
+        assert_eq!(
+            range("bytes=900-5000"),
+            RangeRequest::Partial {
+                start: 900,
+                end: 999
+            }
+        );
+        assert_eq!(
+            range("bytes=-5000"),
+            RangeRequest::Partial { start: 0, end: 999 }
+        );
+    }
+
+    #[test]
+    fn unsupported_ranges() {
+        let range = |header: &str| parse_range(Some(header), 1000);
+        assert_eq!(parse_range(None, 1000), RangeRequest::Full);
+        assert_eq!(range("bytes=0-1,5-6"), RangeRequest::Full);
+        assert_eq!(range("items=0-1"), RangeRequest::Full);
+        assert_eq!(range("bytes=5-1"), RangeRequest::Full);
+        assert_eq!(range("bytes=a-b"), RangeRequest::Full);
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        let range = |header: &str| parse_range(Some(header), 1000);
+        assert_eq!(range("bytes=1000-"), RangeRequest::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), RangeRequest::Unsatisfiable);
+        assert_eq!(
+            parse_range(Some("bytes=0-"), 0),
+            RangeRequest::Unsatisfiable
+        );
+    }
+}
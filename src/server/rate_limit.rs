@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::ip_filter::in_range;
+use crate::config::RateLimit;
+
+/// Upper limit for tracked addresses so a botnet can't use up all the memory
+const MAX_ENTRIES: usize = 100_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Tokens in the bucket at `now`. It fills up at the request rate up to the burst
+    fn tokens_at(&self, limit: &RateLimit, now: Instant) -> f64 {
+        let refill = now.duration_since(self.updated).as_secs_f64() * limit.requests_per_second;
+        (self.tokens + refill).min(f64::from(limit.burst))
+    }
+}
+
+/// Token bucket of every client address that made requests recently
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for a request from `address`. Fails with the time until
+    /// the next token when the bucket is empty.
+    pub fn try_request(&self, address: IpAddr, limit: &RateLimit) -> Result<(), Duration> {
+        self.try_request_at(address, limit, Instant::now())
+    }
+
+    fn try_request_at(
+        &self,
+        address: IpAddr,
+        limit: &RateLimit,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        // IPv4 clients of a dual stack socket show up as ::ffff:a.b.c.d
+        let address = address.to_canonical();
+        if limit.exempt.iter().any(|range| in_range(address, range)) {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_ENTRIES && !buckets.contains_key(&address) {
+            // A full bucket is the same as not having one
+            let burst = f64::from(limit.burst);
+            buckets.retain(|_, bucket| bucket.tokens_at(limit, now) < burst);
+        }
+        let bucket = buckets.entry(address).or_insert(Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+        });
+        bucket.tokens = bucket.tokens_at(limit, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / limit.requests_per_second))
+        }
+    }
+}
+
+#[cfg(test)]
+mod rate_limit_tests {
+    use super::*;
+
+    fn limit(requests_per_second: f64, burst: u32) -> RateLimit {
+        RateLimit {
+            requests_per_second,
+            burst,
+            exempt: vec!["10.0.0.0/8".to_string(), "::1".to_string()],
+        }
+    }
+
+    #[test]
+    fn burst_then_rate() {
+        let limiter = RateLimiter::new();
+        let limit = limit(2.0, 3);
+        let client = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.try_request_at(client, &limit, start).is_ok());
+        }
+        assert_eq!(
+            limiter.try_request_at(client, &limit, start),
+            Err(Duration::from_millis(500))
+        );
+        // Other clients have buckets of their own
+        assert!(limiter
+            .try_request_at("192.0.2.2".parse().unwrap(), &limit, start)
+            .is_ok());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.try_request_at(client, &limit, later).is_ok());
+        assert!(limiter.try_request_at(client, &limit, later).is_err());
+    }
+
+    #[test]
+    fn exempt_addresses() {
+        let limiter = RateLimiter::new();
+        let limit = limit(1.0, 1);
+        let now = Instant::now();
+        for client in ["10.0.0.7", "::1", "::ffff:10.0.0.7"].iter() {
+            for _ in 0..5 {
+                assert!(limiter
+                    .try_request_at(client.parse().unwrap(), &limit, now)
+                    .is_ok());
+            }
+        }
+    }
+}
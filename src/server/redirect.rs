@@ -0,0 +1,55 @@
+use super::request::{host_name, Request};
+
+/// Location of the https version of the requested url. The client has to
+/// send the Host header so the url can be built, None if it didn't.
+pub fn https_location(request: &Request, https_port: &str) -> Option<String> {
+    let host = host_name(request.header("Host")?);
+    if host.is_empty() {
+        return None;
+    }
+    // Absolute form targets are only sent to proxies
+    let target = if request.target.starts_with('/') {
+        &request.target[..]
+    } else {
+        "/"
+    };
+    let port = if https_port == "443" {
+        String::new()
+    } else {
+        format!(":{}", https_port)
+    };
+    Some(format!("https://{}{}{}", host, port, target))
+}
+
+#[cfg(test)]
+mod redirect_tests {
+    use super::*;
+
+    fn location(head: &str, port: &str) -> Option<String> {
+        https_location(&Request::parse(head).unwrap(), port)
+    }
+
+    #[test]
+    fn location_on_https_port() {
+        assert_eq!(
+            location(
+                "GET /a.mpd?x=1 HTTP/1.1\r\nHost: example.com\r\n\r\n",
+                "443"
+            ),
+            Some("https://example.com/a.mpd?x=1".to_string())
+        );
+        assert_eq!(
+            location("GET /a.mpd HTTP/1.1\r\nHost: [::1]:80\r\n\r\n", "8443"),
+            Some("https://[::1]:8443/a.mpd".to_string())
+        );
+    }
+
+    #[test]
+    fn host_is_required() {
+        assert_eq!(location("GET /a.mpd HTTP/1.0\r\n\r\n", "443"), None);
+        assert_eq!(
+            location("GET /a.mpd HTTP/1.0\r\nHost:\r\n\r\n", "443"),
+            None
+        );
+    }
+}
@@ -0,0 +1,499 @@
+use super::body::{Framing, FramingError};
+
+/// Parsed HTTP request head (request line + headers)
+pub struct Request {
+    pub method: String,
+    /// Request target exactly as it was sent by the client
+    pub target: String,
+    /// Percent-decoded path of the target without the query
+    pub path: String,
+    /// Decoded name and value pairs of the query string in the order they were sent
+    pub query: Vec<(String, String)>,
+    pub version: String,
+    /// Header names are kept as sent. Use `header` for case-insensitive lookup
+    pub headers: Vec<(String, String)>,
+}
+
+/// What the client expects from the server before sending the request body
+#[derive(Debug, PartialEq)]
+pub enum Expectation {
+    /// No Expect header
+    None,
+    /// "Expect: 100-continue"
+    Continue,
+    /// Any other expectation. Has to be answered with 417 Expectation Failed
+    Unsupported,
+}
+
+/// Length of the request target in a (possibly incomplete) request head.
+/// Returns None if the target hasn't started yet.
+pub fn target_length(buffer: &[u8]) -> Option<usize> {
+    let line_end = buffer
+        .windows(2)
+        .position(|w| w == b"\r\n")
+        .unwrap_or(buffer.len());
+    let line = &buffer[..line_end];
+
+    let target_start = line.iter().position(|&c| c == b' ')? + 1;
+    let target = &line[target_start..];
+    Some(
+        target
+            .iter()
+            .position(|&c| c == b' ')
+            .unwrap_or(target.len()),
+    )
+}
+
+/// Sizes in a (possibly incomplete) request head for checking the limits
+/// before the whole head has arrived
+#[derive(Debug, PartialEq)]
+pub struct HeadSizes {
+    pub request_line: usize,
+    /// Header lines, the incomplete last one included
+    pub headers: usize,
+    pub longest_header: usize,
+}
+
+pub fn head_sizes(buffer: &[u8]) -> HeadSizes {
+    let mut lines = buffer
+        .split(|&c| c == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    let mut sizes = HeadSizes {
+        request_line: lines.next().map_or(0, <[u8]>::len),
+        headers: 0,
+        longest_header: 0,
+    };
+    // The empty line ends the head, anything after it is body
+    for line in lines.take_while(|line| !line.is_empty()) {
+        sizes.headers += 1;
+        sizes.longest_header = sizes.longest_header.max(line.len());
+    }
+    sizes
+}
+
+/// Host header value without the port
+pub fn host_name(host: &str) -> &str {
+    if host.starts_with('[') {
+        // IPv6 literal, e.g. "[::1]:8443"
+        match host.find(']') {
+            Some(end) => &host[..=end],
+            None => host,
+        }
+    } else {
+        host.split(':').next().unwrap_or(host)
+    }
+}
+
+/// Remove the content hash from a fingerprinted path "<prefix>/<hash>/<rest>".
+/// Returns the hash and "/<rest>" or None if the path isn't fingerprinted.
+pub fn strip_fingerprint<'a>(path: &'a str, prefix: &str) -> Option<(&'a str, &'a str)> {
+    let prefix = prefix.trim_end_matches('/');
+    let rest = path.strip_prefix(prefix)?.strip_prefix('/')?;
+    let hash_len = rest.find('/')?;
+    let hash = &rest[..hash_len];
+    if hash.len() < 8 || hash.len() > 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some((hash, &rest[hash_len..]))
+}
+
+/// Is the version of the form "HTTP/<digit>.<digit>" (RFC 9112 2.3)
+fn is_valid_version(version: &str) -> bool {
+    match version
+        .strip_prefix("HTTP/")
+        .map(|number| number.as_bytes())
+    {
+        Some([major, b'.', minor]) => major.is_ascii_digit() && minor.is_ascii_digit(),
+        _ => false,
+    }
+}
+
+/// Decode the "%XX" escapes of a path or query component (RFC 3986 2.1).
+/// `plus_as_space` decodes "+" to a space like browsers encode forms.
+/// Returns None for invalid escapes and if the result isn't UTF-8 or contains
+/// a NUL byte that would cut the path short in the filesystem.
+fn percent_decode(input: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = input.get(i + 1..i + 3)?;
+                if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return None;
+                }
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            byte => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    if out.contains(&0) {
+        return None;
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Split the request target to the decoded path and query parameters.
+/// The fragment is never sent by clients but it's dropped just in case.
+fn parse_target(target: &str) -> Option<(String, Vec<(String, String)>)> {
+    let target = target.split('#').next().unwrap_or("");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let mut params = vec![];
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        params.push((percent_decode(name, true)?, percent_decode(value, true)?));
+    }
+    Some((percent_decode(path, false)?, params))
+}
+
+impl Request {
+    /// Parse the request head. `head` should contain everything up to "\r\n\r\n".
+    /// Returns None if the request line or any of the header lines are malformed.
+    pub fn parse(head: &str) -> Option<Request> {
+        let mut lines = head.lines();
+
+        let mut request_parts = lines.next()?.split_whitespace();
+        let method = request_parts.next()?.to_string();
+        let target = request_parts.next()?.to_string();
+        let version = request_parts.next()?.to_string();
+        if request_parts.next().is_some() || !is_valid_version(&version) {
+            return None;
+        }
+        let (path, query) = parse_target(&target)?;
+
+        let mut headers = vec![];
+        for line in lines {
+            // Empty line is the end of the header
+            if line.is_empty() {
+                break;
+            }
+
+            let (name, value) = line.split_once(':')?;
+            // Whitespace between the name and the colon is not allowed (RFC 7230 3.2.4)
+            if name.is_empty() || name.ends_with(char::is_whitespace) {
+                return None;
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        }
+
+        Some(Request {
+            method,
+            target,
+            path,
+            query,
+            version,
+            headers,
+        })
+    }
+
+    /// Value of the first header with the `name`. Header names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// How many times the header with the `name` was sent
+    pub fn header_count(&self, name: &str) -> usize {
+        self.headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .count()
+    }
+
+    /// Can the request be answered. HTTP/1.0 and HTTP/1.1 are supported.
+    /// Later 1.x versions are compatible and answered like 1.1 (RFC 9110 6.2).
+    pub fn is_supported_version(&self) -> bool {
+        self.version.starts_with("HTTP/1.")
+    }
+
+    /// Value of the first query parameter with the `name`. Parameter names are case-sensitive
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Does the client want to keep the connection open after the response.
+    /// HTTP/1.1 connections are persistent unless the client sends
+    /// "Connection: close", HTTP/1.0 ones only with "Connection: keep-alive".
+    pub fn keep_alive(&self) -> bool {
+        let has_option = |option: &str| {
+            self.header("Connection")
+                .map(|value| {
+                    value
+                        .split(',')
+                        .any(|token| token.trim().eq_ignore_ascii_case(option))
+                })
+                .unwrap_or(false)
+        };
+        if self.version == "HTTP/1.0" {
+            has_option("keep-alive")
+        } else {
+            !has_option("close")
+        }
+    }
+
+    /// How the request body is framed according to Content-Length and Transfer-Encoding.
+    /// Requests with both of the headers are rejected since they are a common way
+    /// to smuggle requests through proxies.
+    pub fn body_framing(&self, max_body_size: u64) -> Result<Framing, FramingError> {
+        let transfer_encoding = self.header("Transfer-Encoding");
+        let content_length: Vec<&str> = self
+            .headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+            .map(|(_, value)| &value[..])
+            .collect();
+
+        if let Some(encoding) = transfer_encoding {
+            if !content_length.is_empty() {
+                return Err(FramingError::Invalid);
+            }
+
+            // Chunked has to be the last coding (RFC 7230 3.3.3) and
+            // it's also the only one we can decode
+            let codings: Vec<&str> = encoding.split(',').map(|coding| coding.trim()).collect();
+            return match codings.last() {
+                Some(last) if last.eq_ignore_ascii_case("chunked") => {
+                    if codings.len() == 1 {
+                        Ok(Framing::Chunked)
+                    } else {
+                        Err(FramingError::UnsupportedEncoding)
+                    }
+                }
+                _ => Err(FramingError::Invalid),
+            };
+        }
+
+        let length = match content_length.first() {
+            None => return Ok(Framing::None),
+            Some(length) => length,
+        };
+        if content_length.iter().any(|other| other != length) {
+            return Err(FramingError::Invalid);
+        }
+
+        let length = length.parse::<u64>().map_err(|_| FramingError::Invalid)?;
+        if length > max_body_size {
+            Err(FramingError::TooLarge)
+        } else if length == 0 {
+            Ok(Framing::None)
+        } else {
+            Ok(Framing::Length(length))
+        }
+    }
+
+    /// Read the Expect header.
+    /// Expect is ignored for HTTP/1.0 requests as they don't support 100 Continue
+    pub fn expectation(&self) -> Expectation {
+        if self.version == "HTTP/1.0" {
+            return Expectation::None;
+        }
+
+        match self.header("Expect") {
+            None => Expectation::None,
+            Some(value) if value.eq_ignore_ascii_case("100-continue") => Expectation::Continue,
+            Some(_) => Expectation::Unsupported,
+        }
+    }
+}
+
+#[cfg(test)]
+mod request_tests {
+    use super::*;
+
+    #[test]
+    fn parse_request_head() {
+        let req =
+            Request::parse("GET /a.mpd HTTP/1.1\r\nHost: localhost\r\nX-Test:  value \r\n\r\n")
+                .unwrap();
+        assert_eq!(req.method, "GET");
+        assert_eq!(req.target, "/a.mpd");
+        assert_eq!(req.version, "HTTP/1.1");
+        assert_eq!(req.header("host"), Some("localhost"));
+        assert_eq!(req.header("X-TEST"), Some("value"));
+        assert_eq!(req.header("Expect"), None);
+    }
+
+    #[test]
+    fn parse_invalid_request_head() {
+        assert!(Request::parse("").is_none());
+        assert!(Request::parse("GET /\r\n\r\n").is_none());
+        assert!(Request::parse("GET / FTP/1.0\r\n\r\n").is_none());
+        assert!(Request::parse("GET / HTTP/1\r\n\r\n").is_none());
+        assert!(Request::parse("GET / HTTP/1.10\r\n\r\n").is_none());
+        assert!(Request::parse("GET / HTTP/1.1\r\nNo colon\r\n\r\n").is_none());
+        assert!(Request::parse("GET / HTTP/1.1\r\nHost : localhost\r\n\r\n").is_none());
+        assert!(Request::parse("GET /a%2 HTTP/1.1\r\n\r\n").is_none());
+        assert!(Request::parse("GET /a%zz HTTP/1.1\r\n\r\n").is_none());
+        assert!(Request::parse("GET /a%00.mpd HTTP/1.1\r\n\r\n").is_none());
+        assert!(Request::parse("GET /a%ff HTTP/1.1\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn decode_target() {
+        let req =
+            Request::parse("GET /my%20movie/a+b.mpd?token=a%2Fb&name=x+y&flag HTTP/1.1\r\n\r\n")
+                .unwrap();
+        assert_eq!(req.path, "/my movie/a+b.mpd");
+        assert_eq!(req.query_param("token"), Some("a/b"));
+        assert_eq!(req.query_param("name"), Some("x y"));
+        assert_eq!(req.query_param("flag"), Some(""));
+        assert_eq!(req.query_param("missing"), None);
+
+        let req = Request::parse("GET /%C3%A4.mpd HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(req.path, "/\u{e4}.mpd");
+        assert!(req.query.is_empty());
+    }
+
+    #[test]
+    fn request_target_length() {
+        assert_eq!(target_length(b"GET"), None);
+        assert_eq!(target_length(b"GET "), Some(0));
+        assert_eq!(target_length(b"GET /abc"), Some(4));
+        assert_eq!(
+            target_length(b"GET /abc HTTP/1.1\r\nHost: a b\r\n"),
+            Some(4)
+        );
+    }
+
+    #[test]
+    fn request_head_sizes() {
+        assert_eq!(
+            head_sizes(b"GET /abc HT"),
+            HeadSizes {
+                request_line: 11,
+                headers: 0,
+                longest_header: 0
+            }
+        );
+        assert_eq!(
+            head_sizes(b"GET /abc HTTP/1.1\r\nHost: a\r\nAccept: text/pl"),
+            HeadSizes {
+                request_line: 17,
+                headers: 2,
+                longest_header: 15
+            }
+        );
+        // The body isn't part of the head
+        assert_eq!(
+            head_sizes(b"POST / HTTP/1.1\r\nA: b\r\n\r\nbody\r\nmore"),
+            HeadSizes {
+                request_line: 15,
+                headers: 1,
+                longest_header: 4
+            }
+        );
+    }
+
+    #[test]
+    fn supported_versions() {
+        let supported = |version: &str| {
+            let head = format!("GET / {}\r\n\r\n", version);
+            Request::parse(&head).unwrap().is_supported_version()
+        };
+        assert!(supported("HTTP/1.0"));
+        assert!(supported("HTTP/1.1"));
+        assert!(supported("HTTP/1.2"));
+        assert!(!supported("HTTP/0.9"));
+        assert!(!supported("HTTP/2.0"));
+    }
+
+    #[test]
+    fn keep_alive() {
+        let keep_alive = |head: &str| Request::parse(head).unwrap().keep_alive();
+        assert!(keep_alive("GET / HTTP/1.1\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive("GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            "GET / HTTP/1.0\r\nConnection: TE, keep-alive\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn body_framing() {
+        let framing = |head: &str| Request::parse(head).unwrap().body_framing(100);
+        assert_eq!(framing("POST / HTTP/1.1\r\n\r\n"), Ok(Framing::None));
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n"),
+            Ok(Framing::Length(10))
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"),
+            Ok(Framing::Chunked)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 101\r\n\r\n"),
+            Err(FramingError::TooLarge)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            Err(FramingError::Invalid)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"),
+            Err(FramingError::Invalid)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 1\r\n\r\n"),
+            Err(FramingError::Invalid)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+            Err(FramingError::UnsupportedEncoding)
+        );
+        assert_eq!(
+            framing("POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n"),
+            Err(FramingError::Invalid)
+        );
+    }
+
+    #[test]
+    fn host_without_port() {
+        assert_eq!(host_name("example.com:443"), "example.com");
+        assert_eq!(host_name("example.com"), "example.com");
+        assert_eq!(host_name("[::1]:443"), "[::1]");
+    }
+
+    #[test]
+    fn fingerprinted_paths() {
+        assert_eq!(
+            strip_fingerprint("/vod/0123abcd/movie/stream.mpd", "/vod"),
+            Some(("0123abcd", "/movie/stream.mpd"))
+        );
+        assert_eq!(
+            strip_fingerprint("/vod/0123abcd/movie/stream.mpd", "/vod/"),
+            Some(("0123abcd", "/movie/stream.mpd"))
+        );
+        assert_eq!(strip_fingerprint("/vod/movie/stream.mpd", "/vod"), None);
+        assert_eq!(strip_fingerprint("/vod/0123abcdx/stream.mpd", "/vod"), None);
+        assert_eq!(
+            strip_fingerprint("/vodka/0123abcd/stream.mpd", "/vod"),
+            None
+        );
+        assert_eq!(strip_fingerprint("/vod/0123abcd", "/vod"), None);
+    }
+
+    #[test]
+    fn expect_header() {
+        let req = Request::parse("PUT /a HTTP/1.1\r\nExpect: 100-Continue\r\n\r\n").unwrap();
+        assert_eq!(req.expectation(), Expectation::Continue);
+        let req = Request::parse("PUT /a HTTP/1.1\r\nExpect: something\r\n\r\n").unwrap();
+        assert_eq!(req.expectation(), Expectation::Unsupported);
+        let req = Request::parse("PUT /a HTTP/1.0\r\nExpect: something\r\n\r\n").unwrap();
+        assert_eq!(req.expectation(), Expectation::None);
+    }
+}
@@ -0,0 +1,72 @@
+use openssl::rand;
+
+use super::request::Request;
+
+/// Longest X-Request-Id that is taken from the client
+const MAX_LENGTH: usize = 128;
+
+/// Random bytes in a generated id
+const GENERATED_BYTES: usize = 16;
+
+/// Ids from the client end up in the logs and in the response so only
+/// printable ascii without spaces is accepted
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_LENGTH && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn generate() -> String {
+    let mut bytes = [0u8; GENERATED_BYTES];
+    // Only fails if the system has no entropy to give. A fixed id is still
+    // better than no response
+    if rand::rand_bytes(&mut bytes).is_err() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Id of the request for correlating the logs along a CDN chain. The
+/// X-Request-Id from the client or a CDN in front is used if there is one,
+/// otherwise a new one is generated.
+pub fn request_id(request: Option<&Request>) -> String {
+    match request.and_then(|request| request.header("X-Request-Id")) {
+        Some(id) if is_valid(id) => id.to_string(),
+        _ => generate(),
+    }
+}
+
+#[cfg(test)]
+mod request_id_tests {
+    use super::*;
+
+    fn id_for(head: &str) -> String {
+        request_id(Request::parse(head).as_ref())
+    }
+
+    #[test]
+    fn incoming_id_is_kept() {
+        assert_eq!(
+            id_for("GET / HTTP/1.1\r\nX-Request-Id: cdn-42\r\n\r\n"),
+            "cdn-42"
+        );
+    }
+
+    #[test]
+    fn generated_ids() {
+        let first = id_for("GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(first.len(), 2 * GENERATED_BYTES);
+        assert!(first.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(first, id_for("GET / HTTP/1.1\r\n\r\n"));
+        assert_eq!(request_id(None).len(), 2 * GENERATED_BYTES);
+    }
+
+    #[test]
+    fn invalid_incoming_ids_are_replaced() {
+        let long = format!(
+            "GET / HTTP/1.1\r\nX-Request-Id: {}\r\n\r\n",
+            "a".repeat(129)
+        );
+        assert_eq!(id_for(&long).len(), 2 * GENERATED_BYTES);
+        let spaces = "GET / HTTP/1.1\r\nX-Request-Id: a b\r\n\r\n";
+        assert_eq!(id_for(spaces).len(), 2 * GENERATED_BYTES);
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use log::warn;
+use regex::Regex;
+
+use crate::config::Rewrite;
+
+/// Applies the rewrite rules. Patterns are compiled once and kept, since
+/// the config can be replaced while the server runs.
+pub struct Rewriter {
+    compiled: Mutex<HashMap<String, Regex>>,
+}
+
+impl Rewriter {
+    pub fn new() -> Rewriter {
+        Rewriter {
+            compiled: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn regex(&self, pattern: &str) -> Option<Regex> {
+        let mut compiled = self.compiled.lock().unwrap();
+        if let Some(regex) = compiled.get(pattern) {
+            return Some(regex.clone());
+        }
+        match Regex::new(pattern) {
+            Ok(regex) => {
+                compiled.insert(pattern.to_string(), regex.clone());
+                Some(regex)
+            }
+            Err(e) => {
+                warn!("Invalid rewrite pattern {}: {}", pattern, e);
+                None
+            }
+        }
+    }
+
+    /// `path` rewritten by the first matching rule. None when no rule matches.
+    pub fn apply(&self, rules: &[Rewrite], path: &str) -> Option<String> {
+        for rule in rules {
+            if let Some(prefix) = &rule.prefix {
+                if let Some(rest) = path.strip_prefix(&prefix[..]) {
+                    return Some(format!("{}{}", rule.to, rest));
+                }
+            } else if let Some(regex) = rule.pattern.as_deref().and_then(|p| self.regex(p)) {
+                if regex.is_match(path) {
+                    return Some(regex.replace(path, &rule.to[..]).into_owned());
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod rewrite_tests {
+    use super::*;
+
+    fn prefix(prefix: &str, to: &str) -> Rewrite {
+        Rewrite {
+            prefix: Some(prefix.to_string()),
+            pattern: None,
+            to: to.to_string(),
+        }
+    }
+
+    fn pattern(pattern: &str, to: &str) -> Rewrite {
+        Rewrite {
+            prefix: None,
+            pattern: Some(pattern.to_string()),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn rewritten_paths() {
+        let rules = vec![
+            prefix("/live/chan1/", "/channels/1/live/"),
+            pattern("^/v[0-9]+/(.*)$", "/$1"),
+            pattern("^/(?P<title>[a-z]+)/hd/", "/${title}/1080p/"),
+            pattern("[", "/broken/"),
+        ];
+        let rewriter = Rewriter::new();
+        let rewrite = |path: &str| rewriter.apply(&rules, path);
+        assert_eq!(
+            rewrite("/live/chan1/stream.mpd").as_deref(),
+            Some("/channels/1/live/stream.mpd")
+        );
+        assert_eq!(rewrite("/v12/vod/a.mpd").as_deref(), Some("/vod/a.mpd"));
+        // Cached pattern gives the same result
+        assert_eq!(rewrite("/v3/b.mpd").as_deref(), Some("/b.mpd"));
+        assert_eq!(
+            rewrite("/movie/hd/seg-1.m4s").as_deref(),
+            Some("/movie/1080p/seg-1.m4s")
+        );
+        // Only the first matching rule is applied
+        assert_eq!(
+            rewrite("/v1/live/chan1/a.mpd").as_deref(),
+            Some("/live/chan1/a.mpd")
+        );
+        assert_eq!(rewrite("/live/chan2/stream.mpd"), None);
+        assert_eq!(rewrite("/vod/a.mpd"), None);
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::{Component, Path};
+
+/// Does the path try to climb out of the root with ".."
+pub fn has_parent_segments(relative_path: &str) -> bool {
+    relative_path.split('/').any(|segment| segment == "..")
+}
+
+/// Is the url `path` under `prefix` when both are split into segments.
+/// "/health" covers "/health" and "/health/live" but not "/healthz".
+pub fn has_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'),
+        None => false,
+    }
+}
+
+/// Is the existing file in `path` inside the `root` directory once all the
+/// symlinks are resolved. False if either of them doesn't exist.
+pub fn is_inside(root: &str, path: &str) -> bool {
+    let root = match fs::canonicalize(root) {
+        Ok(root) => root,
+        Err(_) => return false,
+    };
+    match fs::canonicalize(Path::new(path)) {
+        Ok(path) => path.starts_with(root),
+        Err(_) => false,
+    }
+}
+
+/// Do `a` and `b` name the same file without looking at the disk, e.g.
+/// "./live/seg-1.m4s" and "live//seg-1.m4s"
+pub fn same_path(a: &Path, b: &Path) -> bool {
+    let a = a.components().filter(|c| *c != Component::CurDir);
+    let b = b.components().filter(|c| *c != Component::CurDir);
+    a.eq(b)
+}
+
+#[cfg(test)]
+mod root_tests {
+    use super::*;
+
+    #[test]
+    fn parent_segments() {
+        assert!(has_parent_segments("../secret"));
+        assert!(has_parent_segments("a/../../secret"));
+        assert!(!has_parent_segments("a/..b/c"));
+    }
+
+    #[test]
+    fn url_prefixes() {
+        assert!(has_prefix("/health", "/health"));
+        assert!(has_prefix("/health/live", "/health"));
+        assert!(has_prefix("/health/live", "/health/"));
+        assert!(!has_prefix("/healthz", "/health"));
+        assert!(!has_prefix("/health", "/health/"));
+        assert!(has_prefix("/anything", "/"));
+    }
+
+    #[test]
+    fn same_paths() {
+        let same = |a: &str, b: &str| same_path(Path::new(a), Path::new(b));
+        assert!(same("./live/seg-1.m4s", "live/seg-1.m4s"));
+        assert!(same("live//seg-1.m4s", "live/./seg-1.m4s"));
+        assert!(!same("live/seg-1.m4s", "live/seg-10.m4s"));
+        assert!(!same("/live/seg-1.m4s", "live/seg-1.m4s"));
+    }
+
+    #[test]
+    fn inside_root() {
+        assert!(is_inside("test_data", "test_data/sidecar/stream.mpd"));
+        assert!(is_inside(".", "test_data/sidecar/stream.mpd"));
+        assert!(!is_inside(
+            "test_data/sidecar",
+            "test_data/unit_test_config.json"
+        ));
+        assert!(!is_inside(
+            "test_data/sidecar",
+            "test_data/sidecar/../unit_test_config.json"
+        ));
+        assert!(!is_inside("test_data", "test_data/missing.mpd"));
+    }
+}
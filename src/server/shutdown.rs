@@ -0,0 +1,179 @@
+use std::io::Write;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the connections are checked while draining
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shutdown state shared by the accept loop and the connections
+pub struct Shutdown {
+    requested: AtomicBool,
+    /// Connections that are accepted and not closed yet
+    active: AtomicUsize,
+    /// Written to when a stop is requested
+    waker: UnixStream,
+    /// Becomes readable when a stop is requested. Never read so it stays readable
+    woken: UnixStream,
+}
+
+/// Counts a connection as active until it's dropped
+pub struct ConnectionGuard {
+    shutdown: Arc<Shutdown>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.shutdown.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        // Only fails when the process is out of file descriptors
+        let (waker, woken) = UnixStream::pair().unwrap();
+        waker.set_nonblocking(true).unwrap();
+        Shutdown {
+            requested: AtomicBool::new(false),
+            active: AtomicUsize::new(0),
+            waker,
+            woken,
+        }
+    }
+
+    /// Has the server been asked to stop. Connections are closed after the
+    /// response that is being sent instead of waiting for the next request.
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Count a new connection until the returned guard is dropped
+    pub fn track(self: &Arc<Self>) -> ConnectionGuard {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            shutdown: self.clone(),
+        }
+    }
+
+    /// Count a new connection like `track` unless there are already `limit`
+    /// active connections. Zero `limit` means no limit.
+    pub fn try_track(self: &Arc<Self>, limit: usize) -> Option<ConnectionGuard> {
+        if limit == 0 {
+            return Some(self.track());
+        }
+        self.active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+                if active < limit {
+                    Some(active + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+        Some(ConnectionGuard {
+            shutdown: self.clone(),
+        })
+    }
+
+    /// Socket that becomes readable once the server is asked to stop, for
+    /// waking up the accept loop
+    pub fn wake_fd(&self) -> RawFd {
+        self.woken.as_raw_fd()
+    }
+
+    /// Connections that are being served or waiting in the queue
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the active connections to close for at most `timeout`.
+    /// Returns false if some of them were still open.
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        while self.active.load(Ordering::SeqCst) > 0 {
+            if start.elapsed() >= timeout {
+                return false;
+            }
+            thread::sleep(DRAIN_POLL_INTERVAL);
+        }
+        true
+    }
+}
+
+/// Stops a running server from another thread, e.g. the one waiting for signals
+#[derive(Clone)]
+pub struct Stopper {
+    shutdown: Arc<Shutdown>,
+}
+
+impl Stopper {
+    pub fn new(shutdown: Arc<Shutdown>) -> Stopper {
+        Stopper { shutdown }
+    }
+
+    /// Stop accepting connections. The accept loop wakes up and returns,
+    /// and the server starts draining.
+    pub fn stop(&self) {
+        self.shutdown.requested.store(true, Ordering::SeqCst);
+        // A full socket buffer means an earlier stop already woke the loop
+        let _ = (&self.shutdown.waker).write(&[1]);
+    }
+}
+
+#[cfg(test)]
+mod shutdown_tests {
+    use super::super::poll::readable;
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn drain_waits_for_connections() {
+        let shutdown = Arc::new(Shutdown::new());
+        assert!(shutdown.drain(Duration::ZERO));
+
+        let guard = shutdown.track();
+        assert!(!shutdown.drain(Duration::from_millis(10)));
+        let closer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+        });
+        assert!(shutdown.drain(Duration::from_secs(5)));
+        closer.join().unwrap();
+    }
+
+    #[test]
+    fn connection_limit() {
+        let shutdown = Arc::new(Shutdown::new());
+        let first = shutdown.try_track(2).unwrap();
+        let _second = shutdown.try_track(2).unwrap();
+        assert!(shutdown.try_track(2).is_none());
+        assert_eq!(shutdown.active(), 2);
+        drop(first);
+        assert!(shutdown.try_track(2).is_some());
+        // No limit
+        let _guards: Vec<_> = (0..5).map(|_| shutdown.try_track(0).unwrap()).collect();
+        assert_eq!(shutdown.active(), 6);
+    }
+
+    #[test]
+    fn stop_wakes_the_accept_loop() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let shutdown = Arc::new(Shutdown::new());
+        let stopper = Stopper::new(shutdown.clone());
+        let waker = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stopper.stop();
+            // Stopping again doesn't block
+            stopper.stop();
+        });
+        let fds = [listener.as_raw_fd(), shutdown.wake_fd()];
+        assert_eq!(readable(&fds).unwrap(), vec![1]);
+        assert!(shutdown.is_requested());
+        waker.join().unwrap();
+        // Stays readable for every later wait
+        assert_eq!(readable(&fds).unwrap(), vec![1]);
+    }
+}
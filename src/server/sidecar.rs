@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::config::{
+    CacheHeaders, Config, Cors, ManifestFilter, PreloadMode, Tenant, TitleOverrides,
+};
+use log::warn;
+
+/// File name ending of the per-title sidecar files
+pub const SIDECAR_EXTENSION: &str = ".dash.json";
+
+/// Sidecars found in a directory. Scanned again when the directory changes
+struct CachedDir {
+    modified: SystemTime,
+    /// File name without SIDECAR_EXTENSION and the parsed content
+    sidecars: Vec<(String, Arc<TitleOverrides>)>,
+}
+
+/// Finds the "<stream>.dash.json" sidecar of the requested file.
+/// A sidecar applies to the files in its own directory and all the
+/// directories under it. The closest one is used. If a directory has many
+/// sidecars, a manifest uses the one with the same name, e.g. "stream.mpd"
+/// uses "stream.dash.json", and the other files use the first one by name.
+pub struct Sidecars {
+    dirs: Mutex<HashMap<PathBuf, CachedDir>>,
+}
+
+fn scan_dir(dir: &Path) -> Vec<(String, Arc<TitleOverrides>)> {
+    let mut sidecars = vec![];
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return sidecars,
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let stem = match name.strip_suffix(SIDECAR_EXTENSION) {
+            Some(stem) => stem.to_string(),
+            None => continue,
+        };
+
+        let parsed = fs::read_to_string(entry.path())
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(overrides) => sidecars.push((stem, Arc::new(overrides))),
+            Err(e) => warn!("Ignoring invalid sidecar {:?}: {}", entry.path(), e),
+        }
+    }
+
+    sidecars.sort_by(|a, b| a.0.cmp(&b.0));
+    sidecars
+}
+
+impl Sidecars {
+    pub fn new() -> Sidecars {
+        Sidecars {
+            dirs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sidecars in `dir`, from the cache if the directory hasn't changed
+    fn dir_sidecars(&self, dir: &Path) -> Vec<(String, Arc<TitleOverrides>)> {
+        let modified = match fs::metadata(dir).and_then(|meta| meta.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return vec![],
+        };
+
+        if let Some(cached) = self.dirs.lock().unwrap().get(dir) {
+            if cached.modified == modified {
+                return cached.sidecars.clone();
+            }
+        }
+
+        let sidecars = scan_dir(dir);
+        self.dirs.lock().unwrap().insert(
+            dir.to_path_buf(),
+            CachedDir {
+                modified,
+                sidecars: sidecars.clone(),
+            },
+        );
+        sidecars
+    }
+
+    /// Overrides for the file in `relative_path` under `root`.
+    /// Sidecars above the root are not used.
+    pub fn find(&self, root: &str, relative_path: &str) -> Option<Arc<TitleOverrides>> {
+        let root = Path::new(root);
+        let path = Path::new(relative_path);
+        let manifest_stem = relative_path
+            .strip_suffix(".mpd")
+            .and_then(|_| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned());
+
+        for dir in path.ancestors().skip(1) {
+            let sidecars = self.dir_sidecars(&root.join(dir));
+            let same_name = sidecars
+                .iter()
+                .find(|(stem, _)| Some(stem) == manifest_stem.as_ref());
+            if let Some((_, overrides)) = same_name.or_else(|| sidecars.first()) {
+                return Some(overrides.clone());
+            }
+        }
+
+        None
+    }
+}
+
+/// Settings for serving one file: the global config with the tenant's
+/// settings and then the title's overrides applied
+pub struct TitleSettings<'a> {
+    /// The cors section, or the tenant's own
+    pub cors: &'a Cors,
+    /// Origins for Access-Control-Allow-Origin
+    pub allowed_origins: Vec<&'a str>,
+    pub cache_headers: CacheHeaders,
+    pub preload_init_segments: PreloadMode,
+    pub early_hints: bool,
+    pub content_digest: bool,
+    /// Whether the manifests get the ContentProtection elements of contentProtection
+    pub content_protection: bool,
+    pub manifest_filter: Option<&'a ManifestFilter>,
+}
+
+impl<'a> TitleSettings<'a> {
+    pub fn new(
+        config: &'a Config,
+        tenant: Option<&'a Tenant>,
+        overrides: Option<&'a TitleOverrides>,
+        relative_path: &str,
+    ) -> TitleSettings<'a> {
+        let cors = tenant
+            .and_then(|tenant| tenant.cors.as_ref())
+            .unwrap_or(&config.cors);
+        let mut settings = TitleSettings {
+            cors,
+            allowed_origins: if cors.allowed_origins.is_empty() {
+                vec![&config.network.allow_origin]
+            } else {
+                cors.allowed_origins
+                    .iter()
+                    .map(|origin| &origin[..])
+                    .collect()
+            },
+            cache_headers: config.cache_control.for_path(relative_path).clone(),
+            preload_init_segments: config.network.preload_init_segments,
+            early_hints: config.network.early_hints,
+            content_digest: config.security.content_digest,
+            content_protection: true,
+            manifest_filter: None,
+        };
+
+        if let Some(allow_origin) = tenant.and_then(|tenant| tenant.allow_origin.as_ref()) {
+            settings.allowed_origins = vec![allow_origin];
+        }
+
+        let overrides = match overrides {
+            Some(overrides) => overrides,
+            None => return settings,
+        };
+
+        if let Some(allow_origin) = &overrides.allow_origin {
+            settings.allowed_origins = vec![allow_origin];
+        }
+        if let Some(cache_control) = &overrides.cache_control {
+            let headers = if relative_path.ends_with(".mpd") {
+                &cache_control.manifest
+            } else {
+                &cache_control.segment
+            };
+            if let Some(headers) = headers {
+                let merged = &mut settings.cache_headers;
+                if headers.cache_control.is_some() {
+                    merged.cache_control = headers.cache_control.clone();
+                }
+                if headers.surrogate_control.is_some() {
+                    merged.surrogate_control = headers.surrogate_control.clone();
+                }
+                if headers.cdn_cache_control.is_some() {
+                    merged.cdn_cache_control = headers.cdn_cache_control.clone();
+                }
+            }
+        }
+        if let Some(preload) = overrides.preload_init_segments {
+            settings.preload_init_segments = preload;
+        }
+        if let Some(early_hints) = overrides.early_hints {
+            settings.early_hints = early_hints;
+        }
+        if let Some(content_digest) = overrides.content_digest {
+            settings.content_digest = content_digest;
+        }
+        if let Some(content_protection) = overrides.content_protection {
+            settings.content_protection = content_protection;
+        }
+        if overrides.manifest_filter.is_some() {
+            settings.manifest_filter = overrides.manifest_filter.as_ref();
+        }
+        settings
+    }
+}
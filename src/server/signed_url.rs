@@ -0,0 +1,110 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// Why a url was not accepted
+#[derive(Debug, PartialEq)]
+pub enum Denied {
+    /// The token or the expiry time is missing
+    Missing,
+    Expired,
+    Invalid,
+}
+
+/// Hex encoded HMAC-SHA256 of the `path` and `expires`. This is what the
+/// service handing out the urls has to compute as well.
+pub fn sign(secret: &str, path: &str, expires: u64) -> String {
+    let message = format!("{}\n{}", path, expires);
+    // HMAC takes keys of any length so only an out of memory fails here
+    let key = PKey::hmac(secret.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(message.as_bytes()).unwrap();
+    let signature = signer.sign_to_vec().unwrap();
+    signature.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Check the `token` and `expires` query parameters of a request to `path`
+pub fn verify(
+    secret: &str,
+    path: &str,
+    token: Option<&str>,
+    expires: Option<&str>,
+) -> Result<(), Denied> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    verify_at(secret, path, token, expires, now)
+}
+
+fn verify_at(
+    secret: &str,
+    path: &str,
+    token: Option<&str>,
+    expires: Option<&str>,
+    now: u64,
+) -> Result<(), Denied> {
+    let (token, expires) = match (token, expires) {
+        (Some(token), Some(expires)) => (token, expires),
+        _ => return Err(Denied::Missing),
+    };
+    let expires: u64 = expires.parse().map_err(|_| Denied::Invalid)?;
+    let expected = sign(secret, path, expires);
+    // Constant time so the response time doesn't tell how much was right
+    let token = token.to_ascii_lowercase();
+    if token.len() != expected.len() || !memcmp::eq(token.as_bytes(), expected.as_bytes()) {
+        return Err(Denied::Invalid);
+    }
+    // Checked after the signature so a forged expiry time is never reported
+    // as merely expired
+    if expires < now {
+        return Err(Denied::Expired);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod signed_url_tests {
+    use super::*;
+
+    #[test]
+    fn signatures() {
+        let token = sign("secret", "/live/stream.mpd", 1000);
+        assert_eq!(token.len(), 64);
+        assert_eq!(token, sign("secret", "/live/stream.mpd", 1000));
+        assert_ne!(token, sign("secret", "/live/stream.mpd", 1001));
+        assert_ne!(token, sign("secret", "/live/other.mpd", 1000));
+        assert_ne!(token, sign("other", "/live/stream.mpd", 1000));
+    }
+
+    #[test]
+    fn verified_urls() {
+        let path = "/live/stream.mpd";
+        let token = sign("secret", path, 1000);
+        let check = |token: Option<&str>, expires: Option<&str>, now: u64| {
+            verify_at("secret", path, token, expires, now)
+        };
+        assert_eq!(check(Some(&token), Some("1000"), 999), Ok(()));
+        assert_eq!(check(Some(&token), Some("1000"), 1000), Ok(()));
+        assert_eq!(
+            check(Some(&token.to_uppercase()), Some("1000"), 1000),
+            Ok(())
+        );
+        assert_eq!(
+            check(Some(&token), Some("1000"), 1001),
+            Err(Denied::Expired)
+        );
+        assert_eq!(check(Some(&token), Some("2000"), 999), Err(Denied::Invalid));
+        assert_eq!(check(Some(&token), Some("soon"), 999), Err(Denied::Invalid));
+        assert_eq!(check(Some("abc"), Some("1000"), 999), Err(Denied::Invalid));
+        assert_eq!(check(None, Some("1000"), 999), Err(Denied::Missing));
+        assert_eq!(check(Some(&token), None, 999), Err(Denied::Missing));
+        assert_eq!(
+            verify_at("other", path, Some(&token), Some("1000"), 999),
+            Err(Denied::Invalid)
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Extensions of media segments, init segments included
+const SEGMENT_EXTENSIONS: &[&str] = &["m4s", "mp4", "m4v", "m4a", "cmfv", "cmfa", "webm", "ts"];
+
+/// What was requested, for timing manifests and segments separately.
+/// Manifest latency decides how fast a live stream starts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentKind {
+    Manifest,
+    Segment,
+    Other,
+}
+
+impl ContentKind {
+    pub fn of(path: &str) -> ContentKind {
+        let extension = path
+            .rsplit('/')
+            .next()
+            .and_then(|name| name.rsplit_once('.'))
+            .map(|(_, extension)| extension.to_ascii_lowercase())
+            .unwrap_or_default();
+        if extension == "mpd" || extension == "m3u8" {
+            ContentKind::Manifest
+        } else if SEGMENT_EXTENSIONS.contains(&&extension[..]) {
+            ContentKind::Segment
+        } else {
+            ContentKind::Other
+        }
+    }
+}
+
+/// How long the responses of one content kind took
+#[derive(Default)]
+struct Latency {
+    responses: AtomicU64,
+    body_bytes: AtomicU64,
+    /// From the start of the request to the first byte of the response
+    first_byte_micros: AtomicU64,
+    max_first_byte_micros: AtomicU64,
+    /// From the first byte to the last one
+    transfer_micros: AtomicU64,
+}
+
+impl Latency {
+    fn record(&self, body_bytes: u64, first_byte: Duration, transfer: Duration) {
+        let first_byte = first_byte.as_micros() as u64;
+        self.responses.fetch_add(1, Ordering::Relaxed);
+        self.body_bytes.fetch_add(body_bytes, Ordering::Relaxed);
+        self.first_byte_micros
+            .fetch_add(first_byte, Ordering::Relaxed);
+        self.max_first_byte_micros
+            .fetch_max(first_byte, Ordering::Relaxed);
+        self.transfer_micros
+            .fetch_add(transfer.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Averages over every response since the start. Throughput is zero
+    /// until something took measurable time to send.
+    fn to_json(&self) -> Value {
+        let responses = self.responses.load(Ordering::Relaxed);
+        let body_bytes = self.body_bytes.load(Ordering::Relaxed);
+        let transfer_micros = self.transfer_micros.load(Ordering::Relaxed);
+        let average = |total: u64| total.checked_div(responses).unwrap_or(0);
+        // Bytes times a million doesn't fit in u64 after some terabytes
+        let bytes_per_second = (u128::from(body_bytes) * 1_000_000)
+            .checked_div(u128::from(transfer_micros))
+            .unwrap_or(0) as u64;
+        json!({
+            "responses": responses,
+            "avgTimeToFirstByteMicros": average(self.first_byte_micros.load(Ordering::Relaxed)),
+            "maxTimeToFirstByteMicros": self.max_first_byte_micros.load(Ordering::Relaxed),
+            "avgTransferMicros": average(transfer_micros),
+            "bytesPerSecond": bytes_per_second,
+        })
+    }
+}
+
+/// Counters of the served requests since the server started
+pub struct Stats {
+    started: Instant,
+    requests: AtomicU64,
+    /// Response bodies without the heads
+    body_bytes: AtomicU64,
+    /// Responses by the first digit of the status, 1xx to 5xx
+    responses: [AtomicU64; 5],
+    /// Connections closed right away because of performance.maxConnections,
+    /// maxQueuedConnections or maxConnectionsPerAddress
+    pub rejected_connections: AtomicU64,
+    /// Connections closed because security.allowCidrs or denyCidrs don't let
+    /// the address in
+    pub denied_connections: AtomicU64,
+    manifests: Latency,
+    segments: Latency,
+    others: Latency,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats {
+            started: Instant::now(),
+            requests: AtomicU64::new(0),
+            body_bytes: AtomicU64::new(0),
+            responses: Default::default(),
+            rejected_connections: AtomicU64::new(0),
+            denied_connections: AtomicU64::new(0),
+            manifests: Latency::default(),
+            segments: Latency::default(),
+            others: Latency::default(),
+        }
+    }
+
+    /// Count a request that was answered with `status`. Zero status means
+    /// the connection was closed without a response. `timing` is the time
+    /// to the first byte and the time it took to send the rest, if anything was sent.
+    pub fn record(
+        &self,
+        kind: ContentKind,
+        status: u16,
+        body_bytes: u64,
+        timing: Option<(Duration, Duration)>,
+    ) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.body_bytes.fetch_add(body_bytes, Ordering::Relaxed);
+        let class = (status / 100) as usize;
+        if let Some(count) = class.checked_sub(1).and_then(|i| self.responses.get(i)) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some((first_byte, transfer)) = timing {
+            let latency = match kind {
+                ContentKind::Manifest => &self.manifests,
+                ContentKind::Segment => &self.segments,
+                ContentKind::Other => &self.others,
+            };
+            latency.record(body_bytes, first_byte, transfer);
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let responses: serde_json::Map<String, Value> = self
+            .responses
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let count = count.load(Ordering::Relaxed);
+                (format!("{}xx", i + 1), count.into())
+            })
+            .collect();
+        json!({
+            "uptimeSeconds": self.started.elapsed().as_secs(),
+            "requests": self.requests.load(Ordering::Relaxed),
+            "bodyBytes": self.body_bytes.load(Ordering::Relaxed),
+            "responses": responses,
+            "rejectedConnections": self.rejected_connections.load(Ordering::Relaxed),
+            "deniedConnections": self.denied_connections.load(Ordering::Relaxed),
+            "latency": {
+                "manifest": self.manifests.to_json(),
+                "segment": self.segments.to_json(),
+                "other": self.others.to_json(),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn content_kinds() {
+        assert_eq!(ContentKind::of("/live/stream.mpd"), ContentKind::Manifest);
+        assert_eq!(ContentKind::of("/hls/index.M3U8"), ContentKind::Manifest);
+        assert_eq!(ContentKind::of("/live/v/seg-1.m4s"), ContentKind::Segment);
+        assert_eq!(ContentKind::of("/live/v/init.mp4"), ContentKind::Segment);
+        assert_eq!(ContentKind::of("/subs/en.vtt"), ContentKind::Other);
+        assert_eq!(ContentKind::of("/live.d/stream"), ContentKind::Other);
+    }
+
+    #[test]
+    fn counts_by_status_class() {
+        let stats = Stats::new();
+        stats.record(ContentKind::Segment, 200, 100, None);
+        stats.record(ContentKind::Segment, 206, 50, None);
+        stats.record(ContentKind::Other, 404, 0, None);
+        // Closed without a response
+        stats.record(ContentKind::Other, 0, 0, None);
+        let json = stats.to_json();
+        assert_eq!(json["requests"], 4);
+        assert_eq!(json["bodyBytes"], 150);
+        assert_eq!(
+            json["responses"],
+            json!({"1xx": 0, "2xx": 2, "3xx": 0, "4xx": 1, "5xx": 0})
+        );
+    }
+
+    #[test]
+    fn latency_by_content_kind() {
+        let stats = Stats::new();
+        let ms = Duration::from_millis;
+        stats.record(ContentKind::Manifest, 200, 1000, Some((ms(2), ms(1))));
+        stats.record(ContentKind::Manifest, 200, 1000, Some((ms(4), ms(1))));
+        stats.record(ContentKind::Segment, 200, 500_000, Some((ms(1), ms(500))));
+        let latency = &stats.to_json()["latency"];
+        assert_eq!(
+            latency["manifest"],
+            json!({
+                "responses": 2,
+                "avgTimeToFirstByteMicros": 3000,
+                "maxTimeToFirstByteMicros": 4000,
+                "avgTransferMicros": 1000,
+                "bytesPerSecond": 1_000_000,
+            })
+        );
+        assert_eq!(latency["segment"]["bytesPerSecond"], 1_000_000);
+        assert_eq!(latency["other"]["responses"], 0);
+        assert_eq!(latency["other"]["bytesPerSecond"], 0);
+    }
+}
@@ -0,0 +1,35 @@
+use openssl::ssl::SslStream;
+use openssl::x509::X509VerifyResult;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+/// Connection to a client, either plain TCP or TLS over TCP
+pub trait ClientStream: Read + Write {
+    /// The TCP connection under the stream. Used for the socket options
+    /// and the peer address which the TLS layer doesn't have.
+    fn tcp(&self) -> &TcpStream;
+
+    /// Did the client send a certificate that was verified in the TLS handshake
+    fn has_client_certificate(&self) -> bool;
+}
+
+impl ClientStream for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+
+    fn has_client_certificate(&self) -> bool {
+        false
+    }
+}
+
+impl ClientStream for SslStream<TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        self.get_ref()
+    }
+
+    fn has_client_certificate(&self) -> bool {
+        self.ssl().peer_certificate().is_some()
+            && self.ssl().verify_result() == X509VerifyResult::OK
+    }
+}
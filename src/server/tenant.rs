@@ -0,0 +1,69 @@
+use super::request::host_name;
+use crate::config::Tenant;
+
+/// Find the tenant for the request. Tenants are matched by the Host header
+/// first and then by the path prefix. Returns the tenant and the path
+/// relative to the tenant, e.g. "/acme/live/stream.mpd" -> "/live/stream.mpd".
+pub fn route<'a, 'b>(
+    tenants: &'a [Tenant],
+    host: Option<&str>,
+    path: &'b str,
+) -> Option<(&'a Tenant, &'b str)> {
+    if let Some(host) = host.map(host_name) {
+        let by_host = tenants.iter().find(|tenant| match &tenant.host {
+            Some(tenant_host) => tenant_host.eq_ignore_ascii_case(host),
+            None => false,
+        });
+        if let Some(tenant) = by_host {
+            return Some((tenant, path));
+        }
+    }
+
+    tenants.iter().find_map(|tenant| {
+        let prefix = tenant.prefix.as_ref()?.trim_end_matches('/');
+        let rest = path.strip_prefix(prefix)?;
+        if rest.starts_with('/') {
+            Some((tenant, rest))
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tenant_tests {
+    use super::*;
+
+    fn tenant(name: &str, host: Option<&str>, prefix: Option<&str>) -> Tenant {
+        Tenant {
+            name: name.to_string(),
+            host: host.map(|host| host.to_string()),
+            prefix: prefix.map(|prefix| prefix.to_string()),
+            root: format!("/srv/{}", name),
+            allow_origin: None,
+            cors: None,
+            certificate_file: None,
+            private_key_file: None,
+            quota: None,
+        }
+    }
+
+    #[test]
+    fn route_by_host_and_prefix() {
+        let tenants = vec![
+            tenant("acme", Some("acme.example.com"), None),
+            tenant("globex", None, Some("/globex/")),
+        ];
+
+        let (acme, path) = route(&tenants, Some("ACME.example.com:8443"), "/a.mpd").unwrap();
+        assert_eq!(acme.name, "acme");
+        assert_eq!(path, "/a.mpd");
+
+        let (globex, path) = route(&tenants, Some("localhost"), "/globex/a.mpd").unwrap();
+        assert_eq!(globex.name, "globex");
+        assert_eq!(path, "/a.mpd");
+
+        assert!(route(&tenants, Some("localhost"), "/globexx/a.mpd").is_none());
+        assert!(route(&tenants, None, "/a.mpd").is_none());
+    }
+}
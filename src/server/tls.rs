@@ -0,0 +1,212 @@
+use log::error;
+use openssl::error::ErrorStack;
+use openssl::ssl::{
+    NameType, SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype, SslMethod,
+    SslVerifyMode,
+};
+use openssl::x509::X509Name;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Certificate for the clients that ask for `host` with SNI in the handshake
+pub struct SniCertificate {
+    pub host: String,
+    pub certificate_file: String,
+    pub private_key_file: String,
+}
+
+struct Current {
+    acceptor: Arc<SslAcceptor>,
+    /// Modification times of the certificates and the private keys
+    modified: Vec<Option<SystemTime>>,
+    checked: Instant,
+}
+
+/// TLS acceptor that is built again when the certificate or the private key
+/// file changes, e.g. after certbot renewed the certificate. Connections that
+/// were accepted before keep using the old certificate.
+pub struct TlsAcceptor {
+    certificate_file: String,
+    private_key_file: String,
+    client_ca_file: Option<String>,
+    sni: Vec<SniCertificate>,
+    check_interval: Duration,
+    current: Mutex<Current>,
+}
+
+fn builder(
+    certificate_file: &str,
+    private_key_file: &str,
+    client_ca_file: Option<&str>,
+) -> Result<SslAcceptorBuilder, ErrorStack> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    acceptor.set_private_key_file(private_key_file, SslFiletype::PEM)?;
+    acceptor.set_certificate_file(certificate_file, SslFiletype::PEM)?;
+    acceptor.check_private_key()?;
+    if let Some(client_ca_file) = client_ca_file {
+        // The certificate is asked but not required in the handshake
+        // since some paths can be requested without it
+        acceptor.set_ca_file(client_ca_file)?;
+        acceptor.set_client_ca_list(X509Name::load_client_ca_file(client_ca_file)?);
+        acceptor.set_verify(SslVerifyMode::PEER);
+    }
+    Ok(acceptor)
+}
+
+fn build(
+    certificate_file: &str,
+    private_key_file: &str,
+    client_ca_file: Option<&str>,
+    sni: &[SniCertificate],
+) -> Result<SslAcceptor, ErrorStack> {
+    let mut contexts = HashMap::new();
+    for certificate in sni {
+        let context = builder(
+            &certificate.certificate_file,
+            &certificate.private_key_file,
+            client_ca_file,
+        )?
+        .build()
+        .into_context();
+        contexts.insert(certificate.host.to_ascii_lowercase(), context);
+    }
+    let mut acceptor = builder(certificate_file, private_key_file, client_ca_file)?;
+    if !contexts.is_empty() {
+        let contexts: HashMap<String, SslContext> = contexts;
+        // Clients without SNI or with other names get the default certificate
+        acceptor.set_servername_callback(move |ssl, _| {
+            let host = ssl
+                .servername(NameType::HOST_NAME)
+                .map(|host| host.to_ascii_lowercase());
+            if let Some(context) = host.and_then(|host| contexts.get(&host)) {
+                ssl.set_ssl_context(context)
+                    .map_err(|_| SniError::ALERT_FATAL)?;
+            }
+            Ok(())
+        });
+    }
+    Ok(acceptor.build())
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Modification times of every certificate and private key file
+fn files_modified(
+    certificate_file: &str,
+    private_key_file: &str,
+    sni: &[SniCertificate],
+) -> Vec<Option<SystemTime>> {
+    let mut files = vec![certificate_file, private_key_file];
+    for certificate in sni {
+        files.push(&certificate.certificate_file);
+        files.push(&certificate.private_key_file);
+    }
+    files.into_iter().map(modified).collect()
+}
+
+impl TlsAcceptor {
+    /// Clients are asked for a certificate signed by the CA in `client_ca_file`.
+    /// The `sni` certificates are used for their hosts. Zero `check_interval`
+    /// never checks the files again
+    pub fn new(
+        certificate_file: &str,
+        private_key_file: &str,
+        client_ca_file: Option<&str>,
+        sni: Vec<SniCertificate>,
+        check_interval: Duration,
+    ) -> Result<TlsAcceptor, ErrorStack> {
+        let current = Current {
+            acceptor: Arc::new(build(
+                certificate_file,
+                private_key_file,
+                client_ca_file,
+                &sni,
+            )?),
+            modified: files_modified(certificate_file, private_key_file, &sni),
+            checked: Instant::now(),
+        };
+        Ok(TlsAcceptor {
+            certificate_file: certificate_file.to_string(),
+            private_key_file: private_key_file.to_string(),
+            client_ca_file: client_ca_file.map(|file| file.to_string()),
+            sni,
+            check_interval,
+            current: Mutex::new(current),
+        })
+    }
+
+    /// Acceptor for a new connection. The files are checked at most once in
+    /// `check_interval` and the acceptor is built again if they changed.
+    pub fn acceptor(&self) -> Arc<SslAcceptor> {
+        let mut current = self.current.lock().unwrap();
+        if self.check_interval.is_zero() || current.checked.elapsed() < self.check_interval {
+            return current.acceptor.clone();
+        }
+        current.checked = Instant::now();
+
+        let files_modified =
+            files_modified(&self.certificate_file, &self.private_key_file, &self.sni);
+        if files_modified != current.modified {
+            // The files may be in the middle of being replaced. If they don't
+            // work yet the old ones are used until the next check.
+            let client_ca_file = self.client_ca_file.as_deref();
+            match build(
+                &self.certificate_file,
+                &self.private_key_file,
+                client_ca_file,
+                &self.sni,
+            ) {
+                Ok(acceptor) => {
+                    current.acceptor = Arc::new(acceptor);
+                    current.modified = files_modified;
+                }
+                Err(e) => error!("Failed to reload the certificate: {}", e),
+            }
+        }
+        current.acceptor.clone()
+    }
+}
+
+#[cfg(test)]
+mod tls_tests {
+    use super::*;
+    use std::fs::File;
+    use std::thread;
+
+    #[test]
+    fn reload_after_change() {
+        let dir = std::env::temp_dir().join(format!("mpeg-dash-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem").to_string_lossy().into_owned();
+        let key = dir.join("private.pem").to_string_lossy().into_owned();
+        fs::copy("cert.pem", &cert).unwrap();
+        fs::copy("private.pem", &key).unwrap();
+
+        let tls = TlsAcceptor::new(&cert, &key, None, vec![], Duration::from_millis(10)).unwrap();
+        let first = tls.acceptor();
+        thread::sleep(Duration::from_millis(20));
+        // Nothing changed
+        assert!(Arc::ptr_eq(&first, &tls.acceptor()));
+
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&cert)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        let reloaded = tls.acceptor();
+        assert!(!Arc::ptr_eq(&first, &reloaded));
+
+        // Broken files keep the old acceptor
+        fs::write(&key, "broken").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        assert!(Arc::ptr_eq(&reloaded, &tls.acceptor()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Size of the pieces the response body is written in.
+/// The client is checked between the pieces.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Paced bodies are written in pieces of this fraction of a second's worth
+/// of bytes so the rate stays even
+const PACING_PIECES_PER_SECOND: u64 = 10;
+
+/// Keeps the writes at `bytes_per_second` by sleeping before each piece
+struct Pacer {
+    bytes_per_second: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl Pacer {
+    /// Wait until the bytes sent so far fit in the rate, then count `bytes` more
+    fn pace(&mut self, bytes: u64) {
+        if self.bytes_per_second > 0 {
+            let due = Duration::from_secs_f64(self.sent as f64 / self.bytes_per_second as f64);
+            if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        self.sent += bytes;
+    }
+}
+
+/// Has the client closed or reset its side of the connection.
+/// Data waiting to be read (e.g. a pipelined request) doesn't count.
+pub fn peer_gone(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let mut byte = [0u8; 1];
+    let gone = match stream.peek(&mut byte) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != io::ErrorKind::WouldBlock,
+    };
+    // If this fails the next write fails too
+    let _ = stream.set_nonblocking(false);
+    gone
+}
+
+/// Copy `length` bytes from `source` in pieces and stop as soon as the client has
+/// gone away instead of pushing the rest of the file into a dead connection.
+/// Only one piece is in memory at a time so files of any size can be sent.
+/// Non-zero `bytes_per_second` limits the rate the body is sent at.
+/// Returns the number of bytes written, as an error if the transfer was aborted
+/// or the source ended early.
+pub fn write_body<W: Write, R: Read>(
+    writer: &mut W,
+    mut source: R,
+    length: u64,
+    bytes_per_second: u64,
+    is_gone: impl Fn(&W) -> bool,
+) -> Result<u64, u64> {
+    let mut chunk_size = CHUNK_SIZE;
+    if bytes_per_second > 0 {
+        let piece = (bytes_per_second / PACING_PIECES_PER_SECOND).max(1);
+        chunk_size = chunk_size.min(piece as usize);
+    }
+    let mut pacer = Pacer {
+        bytes_per_second,
+        started: Instant::now(),
+        sent: 0,
+    };
+    let mut buffer = vec![0u8; chunk_size.min(length as usize)];
+    let mut written = 0;
+    while written < length {
+        let wanted = buffer.len().min((length - written) as usize);
+        let read = match source.read(&mut buffer[..wanted]) {
+            Ok(0) | Err(_) => return Err(written),
+            Ok(read) => read,
+        };
+        pacer.pace(read as u64);
+        if is_gone(writer) || writer.write_all(&buffer[..read]).is_err() {
+            return Err(written);
+        }
+        written += read as u64;
+    }
+    match writer.flush() {
+        Ok(()) => Ok(written),
+        Err(_) => Err(written),
+    }
+}
+
+#[cfg(test)]
+mod transfer_tests {
+    use super::*;
+
+    /// Writer that accepts `limit` bytes and then fails like a reset connection
+    struct Closing {
+        written: Vec<u8>,
+        limit: usize,
+    }
+
+    impl Write for Closing {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written.len() >= self.limit {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            let len = buf.len().min(self.limit - self.written.len());
+            self.written.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn complete_transfer() {
+        let data = vec![7u8; CHUNK_SIZE * 2 + 1];
+        let mut writer = Closing {
+            written: vec![],
+            limit: usize::MAX,
+        };
+        assert_eq!(
+            write_body(&mut writer, &data[..], data.len() as u64, 0, |_| false),
+            Ok(data.len() as u64)
+        );
+        assert_eq!(writer.written, data);
+    }
+
+    #[test]
+    fn write_error_aborts() {
+        let data = vec![7u8; CHUNK_SIZE * 4];
+        let mut writer = Closing {
+            written: vec![],
+            limit: CHUNK_SIZE + 10,
+        };
+        assert_eq!(
+            write_body(&mut writer, &data[..], data.len() as u64, 0, |_| false),
+            Err(CHUNK_SIZE as u64)
+        );
+    }
+
+    #[test]
+    fn short_source_aborts() {
+        let data = [7u8; 10];
+        let mut writer = Closing {
+            written: vec![],
+            limit: usize::MAX,
+        };
+        assert_eq!(
+            write_body(&mut writer, &data[..], 20, 0, |_| false),
+            Err(10)
+        );
+        // Only the requested length is copied
+        assert_eq!(write_body(&mut writer, &data[..], 4, 0, |_| false), Ok(4));
+    }
+
+    #[test]
+    fn closed_peer_aborts() {
+        let data = vec![7u8; CHUNK_SIZE * 4];
+        let mut writer = Closing {
+            written: vec![],
+            limit: usize::MAX,
+        };
+        // Gone after the first chunk
+        let result = write_body(&mut writer, &data[..], data.len() as u64, 0, |writer| {
+            !writer.written.is_empty()
+        });
+        assert_eq!(result, Err(CHUNK_SIZE as u64));
+    }
+
+    #[test]
+    fn paced_transfer() {
+        let data = vec![7u8; 30_000];
+        let mut writer = Closing {
+            written: vec![],
+            limit: usize::MAX,
+        };
+        let started = Instant::now();
+        // Three pieces of 10 000 bytes, the last one can go out 200 ms after the first
+        let result = write_body(&mut writer, &data[..], data.len() as u64, 100_000, |_| {
+            false
+        });
+        assert_eq!(result, Ok(data.len() as u64));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,271 @@
+use log::warn;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::generate::SEGMENT_EXTENSION;
+
+/// Events of the files and directories in a watched directory
+const WATCHED_EVENTS: u32 = libc::IN_CREATE
+    | libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_DELETE;
+
+/// Size of struct inotify_event without the name that follows it
+const EVENT_HEADER_SIZE: usize = 16;
+
+/// What happened in a watched directory
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    /// The file or directory was created, written or moved in
+    Written(String),
+    /// The file or directory was deleted or moved away
+    Removed(String),
+    /// The kernel's event queue overflowed. Anything may have changed
+    Unknown,
+}
+
+/// Watches directory trees for changes with inotify. The paths of the
+/// changes are the root as given followed by the path under it, so they
+/// are the same as the paths the files are served from.
+pub struct Watcher {
+    inotify: File,
+    /// Watched directories by watch descriptor
+    dirs: HashMap<i32, String>,
+}
+
+impl Watcher {
+    /// Watch the `roots` and all their subdirectories. Subdirectories that
+    /// appear later are watched too.
+    pub fn new(roots: &[String]) -> io::Result<Watcher> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut watcher = Watcher {
+            inotify: unsafe { File::from_raw_fd(fd) },
+            dirs: HashMap::new(),
+        };
+        for root in roots {
+            watcher.watch_tree(root.trim_end_matches('/'))?;
+        }
+        Ok(watcher)
+    }
+
+    /// Becomes readable when there are changes
+    pub fn fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+
+    /// Watch `dir` and its subdirectories. Symlinks under it aren't followed
+    fn watch_tree(&mut self, dir: &str) -> io::Result<()> {
+        let path = CString::new(dir)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Path has a NUL byte"))?;
+        let mask = WATCHED_EVENTS | libc::IN_ONLYDIR;
+        let wd = unsafe { libc::inotify_add_watch(self.inotify.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Already watched through another root, e.g. a tenant under the document root
+        if self.dirs.contains_key(&wd) {
+            return Ok(());
+        }
+        self.dirs.insert(wd, dir.to_string());
+
+        for entry in fs::read_dir(dir)?.flatten() {
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                let subdir = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+                // The rest of the tree is still watched
+                if let Err(e) = self.watch_tree(&subdir) {
+                    warn!("Cannot watch {}: {}", subdir, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop watching `dir` and its subdirectories after it was moved away
+    fn unwatch_tree(&mut self, dir: &str) {
+        let subdir_prefix = format!("{}/", dir);
+        let moved: Vec<i32> = self
+            .dirs
+            .iter()
+            .filter(|(_, path)| *path == dir || path.starts_with(&subdir_prefix))
+            .map(|(wd, _)| *wd)
+            .collect();
+        for wd in moved {
+            unsafe { libc::inotify_rm_watch(self.inotify.as_raw_fd(), wd) };
+            self.dirs.remove(&wd);
+        }
+    }
+
+    /// Changes since the last call. Doesn't wait for more.
+    pub fn changes(&mut self) -> Vec<Change> {
+        let mut changes = vec![];
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = match self.inotify.read(&mut buffer) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // WouldBlock once everything has been read
+                Err(_) => break,
+            };
+            let mut offset = 0;
+            while offset + EVENT_HEADER_SIZE <= read {
+                let field = |at: usize| {
+                    let bytes = buffer[offset + at..offset + at + 4].try_into().unwrap();
+                    u32::from_ne_bytes(bytes)
+                };
+                let wd = field(0) as i32;
+                let mask = field(4);
+                let name_length = field(12) as usize;
+                // The name is padded with NULs
+                let name = &buffer[offset + EVENT_HEADER_SIZE..][..name_length];
+                let name = String::from_utf8_lossy(name);
+                let name = name.trim_end_matches('\0');
+                offset += EVENT_HEADER_SIZE + name_length;
+
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    changes.push(Change::Unknown);
+                    continue;
+                }
+                if mask & libc::IN_IGNORED != 0 {
+                    self.dirs.remove(&wd);
+                    continue;
+                }
+                let path = match self.dirs.get(&wd) {
+                    Some(dir) if !name.is_empty() => format!("{}/{}", dir, name),
+                    _ => continue,
+                };
+                let is_dir = mask & libc::IN_ISDIR != 0;
+                if mask & (libc::IN_DELETE | libc::IN_MOVED_FROM) != 0 {
+                    if is_dir {
+                        self.unwatch_tree(&path);
+                    }
+                    changes.push(Change::Removed(path));
+                } else {
+                    if is_dir {
+                        if let Err(e) = self.watch_tree(&path) {
+                            warn!("Cannot watch {}: {}", path, e);
+                        }
+                    }
+                    changes.push(Change::Written(path));
+                }
+            }
+        }
+        changes
+    }
+}
+
+/// Delete the media segments in `dir` that haven't been written for `age`.
+/// Returns the deleted files.
+pub fn prune(dir: &Path, age: Duration) -> Vec<PathBuf> {
+    let oldest_kept = match SystemTime::now().checked_sub(age) {
+        Some(time) => time,
+        None => return vec![],
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut pruned = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_segment = path.to_string_lossy().ends_with(SEGMENT_EXTENSION);
+        let modified = entry.metadata().and_then(|metadata| metadata.modified());
+        if let (true, Ok(modified)) = (is_segment, modified) {
+            if modified < oldest_kept && fs::remove_file(&path).is_ok() {
+                pruned.push(path);
+            }
+        }
+    }
+    pruned.sort();
+    pruned
+}
+
+#[cfg(test)]
+mod watch_tests {
+    use super::*;
+    use std::thread;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("dash-watch-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn changes_in_tree() {
+        let root = temp_dir("changes");
+        fs::create_dir(format!("{}/video", root)).unwrap();
+        let mut watcher = Watcher::new(&[format!("{}/", root)]).unwrap();
+        assert_eq!(watcher.changes(), vec![]);
+
+        fs::write(format!("{}/video/seg-1.m4s", root), b"moof").unwrap();
+        let segment = format!("{}/video/seg-1.m4s", root);
+        // Created and then closed after writing
+        assert_eq!(
+            watcher.changes(),
+            vec![
+                Change::Written(segment.clone()),
+                Change::Written(segment.clone())
+            ]
+        );
+
+        // New directories are watched too
+        fs::create_dir(format!("{}/audio", root)).unwrap();
+        assert_eq!(
+            watcher.changes(),
+            vec![Change::Written(format!("{}/audio", root))]
+        );
+        fs::rename(
+            format!("{}/video/seg-1.m4s", root),
+            format!("{}/audio/seg-1.m4s", root),
+        )
+        .unwrap();
+        assert_eq!(
+            watcher.changes(),
+            vec![
+                Change::Removed(segment),
+                Change::Written(format!("{}/audio/seg-1.m4s", root))
+            ]
+        );
+
+        // A directory moved away isn't watched anymore
+        fs::rename(format!("{}/audio", root), format!("{}.audio", root)).unwrap();
+        assert_eq!(
+            watcher.changes(),
+            vec![Change::Removed(format!("{}/audio", root))]
+        );
+        fs::write(format!("{}.audio/seg-2.m4s", root), b"moof").unwrap();
+        assert_eq!(watcher.changes(), vec![]);
+        fs::remove_dir_all(format!("{}.audio", root)).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn old_segments_are_pruned() {
+        let root = temp_dir("prune");
+        let dir = Path::new(&root);
+        fs::write(dir.join("init.mp4"), b"moov").unwrap();
+        fs::write(dir.join("seg-1.m4s"), b"moof").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        fs::write(dir.join("seg-2.m4s"), b"moof").unwrap();
+
+        assert_eq!(prune(dir, Duration::from_secs(60)), Vec::<PathBuf>::new());
+        assert_eq!(
+            prune(dir, Duration::from_millis(100)),
+            vec![dir.join("seg-1.m4s")]
+        );
+        assert!(dir.join("init.mp4").exists());
+        assert!(dir.join("seg-2.m4s").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+/// Query parameter of the segment urls of a split WebVTT file, e.g.
+/// "subtitles.en.vtt?segment=3"
+pub const SEGMENT_PARAM: &str = "segment";
+
+/// A cue block with its identifier, timings, settings and text
+struct Cue<'a> {
+    /// Seconds
+    start: f64,
+    end: f64,
+    block: &'a str,
+}
+
+/// A WebVTT file split into its header and its cues
+struct Parsed<'a> {
+    /// The "WEBVTT" line with the header and the STYLE and REGION blocks
+    header: Vec<&'a str>,
+    cues: Vec<Cue<'a>>,
+}
+
+/// Seconds of a "[hh:]mm:ss.ttt" timestamp
+fn parse_timestamp(value: &str) -> Option<f64> {
+    let (clock, millis) = value.split_once('.')?;
+    if millis.len() != 3 {
+        return None;
+    }
+    let mut seconds = 0.0;
+    let fields: Vec<&str> = clock.split(':').collect();
+    if fields.len() < 2 || fields.len() > 3 {
+        return None;
+    }
+    for field in &fields {
+        if field.len() < 2 || !field.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        seconds = seconds * 60.0 + field.parse::<f64>().ok()?;
+    }
+    let millis: u32 = millis.parse().ok()?;
+    Some(seconds + millis as f64 / 1000.0)
+}
+
+/// Start and end of a "<start> --> <end> [settings]" line
+fn parse_timings(line: &str) -> Option<(f64, f64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+}
+
+/// None if `data` isn't a WebVTT file. `data` has "\n" line endings.
+/// Comments and broken blocks are left out.
+fn parse(data: &str) -> Option<Parsed<'_>> {
+    let data = data.strip_prefix('\u{feff}').unwrap_or(data);
+    let first_line = data.lines().next()?;
+    let is_webvtt = first_line
+        .strip_prefix("WEBVTT")
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(' ') || rest.starts_with('\t'));
+    if !is_webvtt {
+        return None;
+    }
+
+    let mut parsed = Parsed {
+        header: vec![],
+        cues: vec![],
+    };
+    // Blocks are separated by blank lines
+    let blocks = data
+        .split("\n\n")
+        .map(|block| block.trim_matches('\n'))
+        .filter(|block| !block.is_empty());
+    for (i, block) in blocks.enumerate() {
+        let mut lines = block.lines();
+        let first = lines.next().unwrap_or("");
+        if i == 0 {
+            parsed.header.push(block);
+            continue;
+        }
+        // The identifier line is optional
+        let timings = match parse_timings(first) {
+            Some(timings) => Some(timings),
+            None if !first.contains("-->") => lines.next().and_then(parse_timings),
+            None => None,
+        };
+        match timings {
+            Some((start, end)) => parsed.cues.push(Cue { start, end, block }),
+            // Styles and regions only come before the cues
+            None if parsed.cues.is_empty()
+                && (first.starts_with("STYLE") || first.starts_with("REGION")) =>
+            {
+                parsed.header.push(block)
+            }
+            None => {}
+        }
+    }
+    Some(parsed)
+}
+
+/// "\r\n" and "\r" line endings as "\n"
+fn normalize_line_endings(data: &str) -> String {
+    data.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Seconds to the end of the last cue. None if `data` isn't a WebVTT file
+pub fn duration(data: &str) -> Option<f64> {
+    let data = normalize_line_endings(data);
+    let parsed = parse(&data)?;
+    Some(parsed.cues.iter().map(|cue| cue.end).fold(0.0, f64::max))
+}
+
+/// Number of `segment_duration` long segments the file is split into
+pub fn segment_count(duration: f64, segment_duration: f64) -> u64 {
+    (duration / segment_duration).ceil().max(1.0) as u64
+}
+
+/// Segment `number`, counting from 1, of the file split into
+/// `segment_duration` long segments. It has the header of the file and the
+/// cues shown during the segment, so a cue that crosses the end of a segment
+/// is in the next one too. The timestamps aren't changed since they are
+/// from the start of the presentation. None if there is no such segment.
+pub fn segment(data: &str, number: u64, segment_duration: f64) -> Option<String> {
+    let data = normalize_line_endings(data);
+    let parsed = parse(&data)?;
+    let duration = parsed.cues.iter().map(|cue| cue.end).fold(0.0, f64::max);
+    if number == 0 || number > segment_count(duration, segment_duration) {
+        return None;
+    }
+    let start = (number - 1) as f64 * segment_duration;
+    let end = start + segment_duration;
+    let blocks: Vec<&str> = parsed
+        .header
+        .iter()
+        .copied()
+        .chain(
+            parsed
+                .cues
+                .iter()
+                .filter(|cue| cue.start < end && cue.end > start)
+                .map(|cue| cue.block),
+        )
+        .collect();
+    Some(format!("{}\n", blocks.join("\n\n")))
+}
+
+#[cfg(test)]
+mod webvtt_tests {
+    use super::*;
+
+    const SUBTITLES: &str = "WEBVTT - English\r\n\r\nSTYLE\r\n::cue { color: yellow }\r\n\r\n\
+        NOTE made by hand\r\n\r\n1\r\n00:00.500 --> 00:01.500\r\nHello\r\n\r\n\
+        00:01.800 --> 00:02.500 align:start\r\nWorld\r\nagain\r\n\r\n\
+        00:00:04.000 --> 00:00:04.250\r\nBye\r\n";
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("00:01.500"), Some(1.5));
+        assert_eq!(parse_timestamp("01:00:01.001"), Some(3601.001));
+        assert_eq!(parse_timestamp("0:01.500"), None);
+        assert_eq!(parse_timestamp("00:01.5"), None);
+        assert_eq!(parse_timestamp("00:01"), None);
+        assert_eq!(
+            parse_timings("00:01.800 --> 00:02.500 align:start"),
+            Some((1.8, 2.5))
+        );
+    }
+
+    #[test]
+    fn cues() {
+        let data = normalize_line_endings(SUBTITLES);
+        let parsed = parse(&data).unwrap();
+        assert_eq!(
+            parsed.header,
+            ["WEBVTT - English", "STYLE\n::cue { color: yellow }"]
+        );
+        let cues: Vec<(f64, f64)> = parsed.cues.iter().map(|c| (c.start, c.end)).collect();
+        assert_eq!(cues, [(0.5, 1.5), (1.8, 2.5), (4.0, 4.25)]);
+        assert_eq!(parsed.cues[0].block, "1\n00:00.500 --> 00:01.500\nHello");
+        assert_eq!(duration(SUBTITLES), Some(4.25));
+        assert_eq!(duration("\u{feff}WEBVTT\n"), Some(0.0));
+        assert!(parse("WEBVTTX\n").is_none());
+        assert!(parse("1\n00:00.500 --> 00:01.500\nHello\n").is_none());
+    }
+
+    #[test]
+    fn segments() {
+        assert_eq!(segment_count(4.25, 2.0), 3);
+        assert_eq!(segment_count(4.0, 2.0), 2);
+        assert_eq!(segment_count(0.0, 2.0), 1);
+
+        let header = "WEBVTT - English\n\nSTYLE\n::cue { color: yellow }";
+        // The second cue is shown in both of the first segments
+        assert_eq!(
+            segment(SUBTITLES, 1, 2.0).unwrap(),
+            format!(
+                "{}\n\n1\n00:00.500 --> 00:01.500\nHello\n\n\
+                 00:01.800 --> 00:02.500 align:start\nWorld\nagain\n",
+                header
+            )
+        );
+        assert_eq!(
+            segment(SUBTITLES, 2, 2.0).unwrap(),
+            format!(
+                "{}\n\n00:01.800 --> 00:02.500 align:start\nWorld\nagain\n",
+                header
+            )
+        );
+        assert!(segment(SUBTITLES, 3, 2.0).unwrap().ends_with("\nBye\n"));
+        assert_eq!(segment(SUBTITLES, 0, 2.0), None);
+        assert_eq!(segment(SUBTITLES, 4, 2.0), None);
+    }
+}
@@ -0,0 +1,94 @@
+use openssl::ssl::{SslConnector, SslFiletype, SslMethod, SslVerifyMode};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Once;
+use std::{thread, time};
+
+#[cfg(test)]
+#[path = "../src/config.rs"]
+mod config;
+
+#[cfg(test)]
+#[path = "../src/mpd.rs"]
+mod mpd;
+
+#[cfg(test)]
+#[path = "../src/server/mod.rs"]
+mod server;
+
+static SERVER_INIT: Once = Once::new();
+
+const DASH_DOCUMENT: &str = "/test_data/unit_test_dash_document.mpd";
+
+/// Server that asks for client certificates signed by test_data/client_auth/ca.pem
+fn start_server() {
+    SERVER_INIT.call_once(|| {
+        let config = config::Config::load("test_data/client_auth/config.json").unwrap();
+        thread::spawn(move || {
+            let mut server = server::DashServer::new(config::SharedConfig::new(config));
+            server.start_server();
+        });
+        thread::sleep(time::Duration::from_secs(1));
+    });
+}
+
+/// Send the request with the client certificate in `certificate`, if any
+fn get_all(buf: &[u8], certificate: Option<(&str, &str)>) -> String {
+    start_server();
+    let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+    connector.set_verify_callback(SslVerifyMode::NONE, |_, _| true);
+    if let Some((certificate, key)) = certificate {
+        connector
+            .set_certificate_file(certificate, SslFiletype::PEM)
+            .unwrap();
+        connector
+            .set_private_key_file(key, SslFiletype::PEM)
+            .unwrap();
+    }
+    let stream = TcpStream::connect("127.0.0.1:8444").unwrap();
+    // An invalid certificate ends the connection without a response.
+    // Depending on the TLS version it fails in the handshake or after it
+    let mut stream = match connector.build().connect("localhost", stream) {
+        Ok(stream) => stream,
+        Err(_) => return String::new(),
+    };
+    let mut res = vec![];
+    if stream.write_all(buf).is_ok() {
+        let _ = stream.read_to_end(&mut res);
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+#[cfg(test)]
+mod client_auth_tests {
+    use super::*;
+
+    const CLIENT: (&str, &str) = (
+        "test_data/client_auth/client.pem",
+        "test_data/client_auth/client_key.pem",
+    );
+
+    #[test]
+    fn certificate_required() {
+        let request = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = get_all(request.as_bytes(), None);
+        assert!(resp.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"));
+
+        let resp = get_all(request.as_bytes(), Some(CLIENT));
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn exempt_path() {
+        let resp = get_all(b"GET /test_data/media/sub.vtt HTTP/1.0\r\n\r\n", None);
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn certificate_from_another_ca() {
+        // The server's own self signed certificate isn't signed by the CA
+        let request = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = get_all(request.as_bytes(), Some(("cert.pem", "private.pem")));
+        assert!(resp.is_empty());
+    }
+}
@@ -1,14 +1,19 @@
 use openssl::ssl::{HandshakeError, SslConnector, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::X509;
 use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::result::Result;
 
-use std::{thread, time};
+use std::{fs, thread, time};
 
 #[cfg(test)]
 #[path = "../src/config.rs"]
 mod config;
 
+#[cfg(test)]
+#[path = "../src/mpd.rs"]
+mod mpd;
+
 #[cfg(test)]
 #[path = "../src/server/mod.rs"]
 mod server;
@@ -30,7 +35,7 @@ impl TestServer {
     }
 
     pub fn write(&mut self, buf: &[u8]) {
-        self.connector.write(buf).unwrap();
+        self.connector.write_all(buf).unwrap();
     }
 
     /// Buf is data sent to the server
@@ -65,9 +70,9 @@ impl TestServer {
             IS_SERVER_INIT = true;
         }
 
-        let _ = config::GlobalConfig::init("test_data/unit_test_config.json");
-        thread::spawn(|| {
-            let server = server::DashServer::new();
+        let config = config::Config::load("test_data/unit_test_config.json").unwrap();
+        thread::spawn(move || {
+            let mut server = server::DashServer::new(config::SharedConfig::new(config));
             server.start_server();
         });
 
@@ -81,7 +86,7 @@ impl TestServer {
         connector.set_verify_callback(SslVerifyMode::NONE, |_, _| true);
         let connector = connector.build();
         let stream = TcpStream::connect("localhost:8443").unwrap();
-        return connector.connect("localhost", stream).unwrap();
+        connector.connect("localhost", stream).unwrap()
     }
 
     /// Like create_tcp_stream but verifies the cert and won't connect
@@ -90,7 +95,7 @@ impl TestServer {
         let connector = SslConnector::builder(SslMethod::tls()).unwrap();
         let connector = connector.build();
         let stream = TcpStream::connect("localhost:8443").unwrap();
-        return connector.connect("localhost", stream);
+        connector.connect("localhost", stream)
     }
 }
 
@@ -101,16 +106,32 @@ mod http_tests {
     #[test]
     fn http_long_message() {
         let mut server = TestServer::new();
-        let big_buff: [u8; 8192] = ['A' as u8; 8192];
+        let big_buff: [u8; 8192] = [b'A'; 8192];
         let result = server.first_response_line(&big_buff);
         assert_eq!(result, "HTTP/1.1 413 PAYLOAD TOO LARGE");
     }
 
+    #[test]
+    fn http_long_uri() {
+        let mut server = TestServer::new();
+        let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(3000));
+        let result = server.first_response_line(request.as_bytes());
+        assert_eq!(result, "HTTP/1.1 414 URI TOO LONG");
+    }
+
+    #[test]
+    fn http_too_many_headers() {
+        let mut server = TestServer::new();
+        let request = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(100));
+        let result = server.first_response_line(request.as_bytes());
+        assert_eq!(result, "HTTP/1.1 431 REQUEST HEADER FIELDS TOO LARGE");
+    }
+
     #[test]
     fn simple_http_connection() {
         let mut server = TestServer::new();
         let result = server.get_all(b"GET / HTTP/1.0\r\n\r\n");
-        assert!(result.len() > 0);
+        assert!(!result.is_empty());
     }
 
     #[test]
@@ -130,11 +151,36 @@ mod http_tests {
     }
 
     #[test]
-    fn http_only_allow_get_method() {
+    fn post_request_body_too_large() {
+        let mut server = TestServer::new();
+        let result = server.first_response_line(
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 99999999999\r\n\r\n",
+        );
+        assert_eq!(result, "HTTP/1.1 413 PAYLOAD TOO LARGE");
+    }
+
+    #[test]
+    fn post_request_invalid_framing() {
+        let mut server = TestServer::new();
+        let result = server.first_response_line(
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+        );
+        assert_eq!(result, "HTTP/1.1 400 BAD REQUEST");
+    }
+
+    #[test]
+    fn post_request_chunked_body() {
+        let mut server = TestServer::new();
+        let result = server.first_response_line(
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n4\r\ndata\r\n0\r\n\r\n",
+        );
+        assert_eq!(result, "HTTP/1.1 405 Method Not Allowed");
+    }
+
+    #[test]
+    fn http_only_allow_get_head_and_options_methods() {
         // Methods are from https://developer.mozilla.org/en-US/docs/Web/HTTP/Methods
-        let m_list = [
-            "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
-        ];
+        let m_list = ["POST", "PUT", "DELETE", "CONNECT", "TRACE", "PATCH"];
 
         for m in &m_list {
             // Server client can only handle one request
@@ -145,6 +191,47 @@ mod http_tests {
         }
     }
 
+    #[test]
+    fn unsupported_expectation() {
+        let mut server = TestServer::new();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nExpect: 200-ok\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        let resp = server.first_response_line(request.as_bytes());
+        assert_eq!(resp, "HTTP/1.1 417 EXPECTATION FAILED");
+    }
+
+    #[test]
+    fn unsupported_http_version() {
+        let mut server = TestServer::new();
+        let resp = server.first_response_line(b"GET / HTTP/2.0\r\n\r\n");
+        assert_eq!(resp, "HTTP/1.1 505 HTTP VERSION NOT SUPPORTED");
+    }
+
+    #[test]
+    fn host_required_for_http_1_1() {
+        let mut server = TestServer::new();
+        let request = format!("GET {} HTTP/1.1\r\n\r\n", DASH_DOCUMENT);
+        let resp = server.first_response_line(request.as_bytes());
+        assert_eq!(resp, "HTTP/1.1 400 BAD REQUEST");
+
+        let mut server = TestServer::new();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        let resp = server.first_response_line(request.as_bytes());
+        assert_eq!(resp, "HTTP/1.1 400 BAD REQUEST");
+    }
+
+    #[test]
+    fn malformed_request_line() {
+        let mut server = TestServer::new();
+        let resp = server.first_response_line(b"GET\r\n\r\n");
+        assert_eq!(resp, "HTTP/1.1 400 BAD REQUEST");
+    }
+
     #[test]
     fn connection_timeout() {
         let mut server = TestServer::new();
@@ -179,7 +266,7 @@ mod http_tests {
 
         // Needs to panic befor this
         let resp = server.get_response();
-        assert!(resp.len() > 0);
+        assert!(!resp.is_empty());
     }
 
     // Helper function to parsing response when requesting DASH_DOCUMENT
@@ -191,7 +278,7 @@ mod http_tests {
         let mut content_len: i32 = -1;
         let mut access_control = "";
         let mut content_type = "";
-        while let Some(line) = lines.next() {
+        for line in lines {
             if line.starts_with("Content-Length:") {
                 let tup: Vec<&str> = line.split_ascii_whitespace().collect();
                 content_len = tup[1].parse::<i32>().unwrap();
@@ -209,6 +296,500 @@ mod http_tests {
         assert_eq!(content_type, "application/dash+xml");
     }
 
+    #[test]
+    fn fingerprinted_document() {
+        let get = |path: &str| {
+            let mut server = TestServer::new();
+            let resp = server.get_all(format!("GET {} HTTP/1.0\r\n\r\n", path).as_bytes());
+            resp.lines()
+                .take_while(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+        };
+        const IMMUTABLE: &str = "Cache-Control: public, max-age=31536000, immutable";
+        // sha256sum test_data/unit_test_dash_document.mpd
+        let headers = get(&format!("/vod/c32c02eed222{}", DASH_DOCUMENT));
+        assert_eq!(headers[0], "HTTP/1.1 200 OK");
+        assert!(headers.iter().any(|h| h == IMMUTABLE));
+        assert!(headers.iter().any(|h| h == "Surrogate-Control: max-age=2"));
+        let headers = get("/vod/4ff06693/test_data/media/seg-1.m4s");
+        assert!(headers.iter().any(|h| h == IMMUTABLE));
+
+        // The hash has to match the content
+        let headers = get(&format!("/vod/0123abcd{}", DASH_DOCUMENT));
+        assert_eq!(headers[0], "HTTP/1.1 404 NOT FOUND");
+
+        // Dynamic manifests change behind the same url
+        let headers = get("/vod/cd7bbd38/test_data/dynamic_manifest.mpd");
+        assert_eq!(headers[0], "HTTP/1.1 200 OK");
+        assert!(!headers.iter().any(|h| h.contains("immutable")));
+    }
+
+    #[test]
+    fn content_digest_headers() {
+        let mut server = TestServer::new();
+        let msg = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = server.get_all(msg.as_bytes());
+        // openssl dgst -sha256 -binary test_data/unit_test_dash_document.mpd | base64
+        let digest = "wywC7tIiuOv4ildRh0hna+F/Iykg7s4WvkPBcQiQVvA=";
+        let headers: Vec<&str> = resp.lines().take_while(|line| !line.is_empty()).collect();
+        assert!(headers.contains(&format!("Repr-Digest: sha-256=:{}:", digest).as_str()));
+        assert!(headers.contains(&format!("Digest: SHA-256={}", digest).as_str()));
+    }
+
+    #[test]
+    fn preload_init_segments() {
+        let mut server = TestServer::new();
+        let msg = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = server.get_all(msg.as_bytes());
+        let links: Vec<&str> = resp
+            .lines()
+            .take_while(|line| !line.is_empty())
+            .filter(|line| line.starts_with("Link:"))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                "Link: </test_data/video/avc1/init.mp4>; rel=preload; as=fetch; crossorigin",
+                "Link: </test_data/audio/und/mp4a/init.mp4>; rel=preload; as=fetch; crossorigin"
+            ]
+        );
+    }
+
+    #[test]
+    fn early_hints() {
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        let resp = server.get_all(msg.as_bytes());
+        let mut responses = resp.split("\r\n\r\n");
+
+        let hints: Vec<&str> = responses.next().unwrap().lines().collect();
+        assert_eq!(hints[0], "HTTP/1.1 103 Early Hints");
+        assert!(hints[1].starts_with("X-Request-Id: "));
+        assert_eq!(
+            hints[2],
+            "Link: </test_data/video/avc1/init.mp4>; rel=preload; as=fetch; crossorigin"
+        );
+
+        let final_response = responses.next().unwrap();
+        assert!(final_response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(final_response.contains(hints[1]));
+        assert!(final_response.contains(hints[2]));
+    }
+
+    #[test]
+    fn sidecar_overrides() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"GET /test_data/sidecar/stream.mpd HTTP/1.0\r\n\r\n");
+        let headers: Vec<&str> = resp.lines().take_while(|line| !line.is_empty()).collect();
+        assert_eq!(headers[0], "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"Access-Control-Allow-Origin: https://player.example.com"));
+        assert!(headers.contains(&"Cache-Control: max-age=5"));
+        // Not overridden
+        assert!(headers.contains(&"Surrogate-Control: max-age=2"));
+        // Disabled by the sidecar
+        assert!(!headers.iter().any(|line| line.starts_with("Link:")));
+        assert!(!headers.iter().any(|line| line.starts_with("Digest:")));
+    }
+
+    #[test]
+    fn sidecar_not_served() {
+        let mut server = TestServer::new();
+        let resp =
+            server.first_response_line(b"GET /test_data/sidecar/stream.dash.json HTTP/1.0\r\n\r\n");
+        assert_eq!(resp, "HTTP/1.1 404 NOT FOUND");
+    }
+
+    #[test]
+    fn tenant_by_prefix() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"GET /acme/live/stream.mpd HTTP/1.0\r\n\r\n");
+        let headers: Vec<&str> = resp.lines().take_while(|line| !line.is_empty()).collect();
+        assert_eq!(headers[0], "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"Access-Control-Allow-Origin: https://acme.example.com"));
+    }
+
+    #[test]
+    fn tenant_by_host() {
+        let mut server = TestServer::new();
+        let resp = server.first_response_line(
+            b"GET /live/stream.mpd HTTP/1.0\r\nHost: acme.example.com:8443\r\n\r\n",
+        );
+        assert_eq!(resp, "HTTP/1.1 200 OK");
+    }
+
+    #[test]
+    fn tenant_cannot_escape_root() {
+        let mut server = TestServer::new();
+        let resp = server
+            .first_response_line(b"GET /acme/../unit_test_dash_document.mpd HTTP/1.0\r\n\r\n");
+        assert_eq!(resp, "HTTP/1.1 404 NOT FOUND");
+    }
+
+    #[test]
+    fn tenant_quota_exceeded() {
+        let mut server = TestServer::new();
+        let request = b"GET /limited/live/stream.mpd HTTP/1.0\r\n\r\n";
+        assert_eq!(server.first_response_line(request), "HTTP/1.1 200 OK");
+        let mut server = TestServer::new();
+        assert_eq!(server.first_response_line(request), "HTTP/1.1 200 OK");
+        let mut server = TestServer::new();
+        assert_eq!(
+            server.first_response_line(request),
+            "HTTP/1.1 429 TOO MANY REQUESTS"
+        );
+    }
+
+    #[test]
+    fn range_request() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(
+            b"GET /test_data/unit_test_dash_document.mpd HTTP/1.0\r\nRange: bytes=0-4\r\n\r\n",
+        );
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        let headers: Vec<&str> = head.lines().collect();
+        assert_eq!(headers[0], "HTTP/1.1 206 PARTIAL CONTENT");
+        assert!(headers.contains(&"Content-Length: 5"));
+        let length = fs::metadata("test_data/unit_test_dash_document.mpd")
+            .unwrap()
+            .len();
+        assert!(headers.contains(&format!("Content-Range: bytes 0-4/{}", length).as_str()));
+        assert_eq!(body, "<?xml");
+    }
+
+    #[test]
+    fn unsatisfiable_range() {
+        let mut server = TestServer::new();
+        let resp = server.first_response_line(
+            b"GET /test_data/unit_test_dash_document.mpd HTTP/1.0\r\nRange: bytes=99999999-\r\n\r\n",
+        );
+        assert_eq!(resp, "HTTP/1.1 416 RANGE NOT SATISFIABLE");
+    }
+
+    #[test]
+    fn head_request() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"HEAD /test_data/unit_test_dash_document.mpd HTTP/1.0\r\n\r\n");
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        let headers: Vec<&str> = head.lines().collect();
+        assert_eq!(headers[0], "HTTP/1.1 200 OK");
+        assert!(headers.contains(&"Content-type: application/dash+xml"));
+        let length = fs::metadata("test_data/unit_test_dash_document.mpd")
+            .unwrap()
+            .len();
+        assert!(headers.contains(&format!("Content-Length: {}", length).as_str()));
+        assert_eq!(body, "");
+    }
+
+    #[test]
+    fn persistent_connection() {
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {0} HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+             GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n\
+             GET {0} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        let resp = server.get_all(msg.as_bytes());
+        let statuses: Vec<&str> = resp
+            .lines()
+            .filter(|line| line.starts_with("HTTP/1.1 ") && !line.contains("103"))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                "HTTP/1.1 200 OK",
+                "HTTP/1.1 404 NOT FOUND",
+                "HTTP/1.1 200 OK"
+            ]
+        );
+        assert!(resp.contains("Connection: keep-alive\r\n"));
+        assert!(resp.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT).as_bytes());
+        let last_modified = resp
+            .lines()
+            .find_map(|line| line.strip_prefix("Last-Modified: "))
+            .unwrap();
+
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.0\r\nIf-Modified-Since: {}\r\n\r\n",
+            DASH_DOCUMENT, last_modified
+        );
+        let resp = server.get_all(msg.as_bytes());
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"));
+        assert!(head.contains(&format!("Last-Modified: {}", last_modified)));
+        assert_eq!(body, "");
+
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.0\r\nIf-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        assert_eq!(
+            server.first_response_line(msg.as_bytes()),
+            "HTTP/1.1 200 OK"
+        );
+    }
+
+    #[test]
+    fn if_none_match() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT).as_bytes());
+        let etag = resp
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap();
+
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.0\r\nIf-None-Match: \"stale\", {}\r\n\r\n",
+            DASH_DOCUMENT, etag
+        );
+        let resp = server.get_all(msg.as_bytes());
+        assert!(resp.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"));
+        assert!(resp.contains(&format!("ETag: {}\r\n", etag)));
+
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.0\r\nIf-None-Match: \"stale\"\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        assert_eq!(
+            server.first_response_line(msg.as_bytes()),
+            "HTTP/1.1 200 OK"
+        );
+    }
+
+    #[test]
+    fn compressed_manifest() {
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.0\r\nAccept-Encoding: gzip\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        server.write_all(msg.as_bytes());
+        let mut resp = vec![];
+        server.connector.read_to_end(&mut resp).unwrap();
+        let head_len = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&resp[..head_len]).into_owned();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains("Vary: Accept-Encoding\r\n"));
+
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(&resp[head_len..])
+            .read_to_string(&mut body)
+            .unwrap();
+        let document = fs::read_to_string(&DASH_DOCUMENT[1..]).unwrap();
+        assert_eq!(body, document);
+    }
+
+    #[test]
+    fn compressed_subtitles_are_chunked() {
+        let mut server = TestServer::new();
+        server.write_all(
+            b"GET /test_data/media/sub.vtt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\
+              Accept-Encoding: gzip\r\n\r\n",
+        );
+        let mut resp = vec![];
+        server.connector.read_to_end(&mut resp).unwrap();
+        let head_len = resp.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&resp[..head_len]).into_owned();
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!head.contains("Content-Length: "));
+
+        let mut chunks = &resp[head_len..];
+        let mut compressed = vec![];
+        loop {
+            let line_end = chunks.windows(2).position(|w| w == b"\r\n").unwrap();
+            let size = std::str::from_utf8(&chunks[..line_end]).unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            chunks = &chunks[line_end + 2..];
+            if size == 0 {
+                assert_eq!(chunks, b"\r\n");
+                break;
+            }
+            compressed.extend_from_slice(&chunks[..size]);
+            chunks = &chunks[size + 2..];
+        }
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut body)
+            .unwrap();
+        assert_eq!(body, fs::read_to_string("test_data/media/sub.vtt").unwrap());
+    }
+
+    #[test]
+    fn cors_preflight() {
+        let mut server = TestServer::new();
+        let msg = format!(
+            "OPTIONS {} HTTP/1.0\r\nOrigin: https://player.example.com\r\n\
+             Access-Control-Request-Method: GET\r\n\
+             Access-Control-Request-Headers: x-drm-token\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        let resp = server.get_all(msg.as_bytes());
+        let headers: Vec<&str> = resp.lines().take_while(|line| !line.is_empty()).collect();
+        assert_eq!(headers[0], "HTTP/1.1 204 NO CONTENT");
+        assert!(headers.contains(&"Access-Control-Allow-Origin: *"));
+        assert!(headers.contains(&"Access-Control-Allow-Methods: GET, HEAD, OPTIONS"));
+        assert!(headers.contains(&"Access-Control-Allow-Headers: x-drm-token"));
+        assert!(headers.contains(&"Access-Control-Max-Age: 86400"));
+    }
+
+    #[test]
+    fn cors_exposed_headers() {
+        let mut server = TestServer::new();
+        let msg = format!(
+            "GET {} HTTP/1.0\r\nOrigin: https://player.example.com\r\n\r\n",
+            DASH_DOCUMENT
+        );
+        let resp = server.get_all(msg.as_bytes());
+        let headers: Vec<&str> = resp.lines().take_while(|line| !line.is_empty()).collect();
+        assert!(headers.contains(&"Access-Control-Allow-Origin: *"));
+        assert!(headers.contains(&"Access-Control-Expose-Headers: Content-Length"));
+        assert!(!headers.contains(&"Vary: Origin"));
+    }
+
+    #[test]
+    fn path_outside_document_root() {
+        let mut server = TestServer::new();
+        let resp = server.first_response_line(b"GET /../../../../etc/passwd HTTP/1.0\r\n\r\n");
+        assert_eq!(resp, "HTTP/1.1 404 NOT FOUND");
+    }
+
+    #[test]
+    fn content_types() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"HEAD /test_data/media/sub.vtt HTTP/1.0\r\n\r\n");
+        assert!(resp.contains("Content-type: text/vtt\r\n"));
+
+        // Replaced in the config
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"HEAD /test_data/media/seg-1.m4s HTTP/1.0\r\n\r\n");
+        assert!(resp.contains("Content-type: video/mp4\r\n"));
+    }
+
+    #[test]
+    fn streamed_segment() {
+        let segment = fs::read_to_string("test_data/media/seg-1.m4s").unwrap();
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"GET /test_data/media/seg-1.m4s HTTP/1.0\r\n\r\n");
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains(&format!("Content-Length: {}\r\n", segment.len())));
+        assert_eq!(body, segment);
+
+        let mut server = TestServer::new();
+        let resp =
+            server.get_all(b"GET /test_data/media/seg-1.m4s HTTP/1.0\r\nRange: bytes=4-9\r\n\r\n");
+        let (head, body) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 206 PARTIAL CONTENT\r\n"));
+        assert_eq!(body, &segment[4..10]);
+    }
+
+    #[test]
+    fn cached_file_changes() {
+        let path = "test_data/media/cache_test.m4s";
+        let request = b"GET /test_data/media/cache_test.m4s HTTP/1.0\r\n\r\n";
+        fs::write(path, "first").unwrap();
+        let mut server = TestServer::new();
+        assert!(server.get_all(request).ends_with("\r\n\r\nfirst"));
+        let mut server = TestServer::new();
+        assert!(server.get_all(request).ends_with("\r\n\r\nfirst"));
+
+        fs::write(path, "second").unwrap();
+        let mut server = TestServer::new();
+        let resp = server.get_all(request);
+        fs::remove_file(path).unwrap();
+        assert!(resp.ends_with("\r\n\r\nsecond"));
+    }
+
+    #[test]
+    fn precompressed_variant() {
+        let compressed = fs::metadata("test_data/media/data.json.gz").unwrap();
+        let mut server = TestServer::new();
+        let resp = server.get_all(
+            b"GET /test_data/media/data.json HTTP/1.0\r\nAccept-Encoding: br, gzip\r\n\r\n",
+        );
+        assert!(resp.contains("Content-Encoding: gzip\r\n"));
+        assert!(resp.contains(&format!("Content-Length: {}\r\n", compressed.len())));
+        assert!(resp.contains("Vary: Accept-Encoding\r\n"));
+
+        let mut server = TestServer::new();
+        let resp = server
+            .get_all(b"GET /test_data/media/data.json HTTP/1.0\r\nAccept-Encoding: br\r\n\r\n");
+        assert!(!resp.contains("Content-Encoding"));
+        assert!(resp.ends_with("{\"title\": \"precompressed\"}\n"));
+    }
+
+    #[test]
+    fn percent_encoded_path_and_query() {
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"GET /test_data/media/sub%20title.vtt HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.ends_with("WEBVTT\n"));
+
+        let mut server = TestServer::new();
+        let resp =
+            server.get_all(b"GET /test_data/media/seg-1.m4s?token=abc%20def&x HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"GET /test_data/%2e%2e/Cargo.toml HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+
+        let mut server = TestServer::new();
+        let resp = server.get_all(b"GET /test_data/media/seg%zz.m4s HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 400"));
+    }
+
+    #[test]
+    fn redirect_to_https() {
+        TestServer::start_server();
+        let mut stream = TcpStream::connect("localhost:8081").unwrap();
+        stream
+            .write_all(b"GET /a.mpd?x=1 HTTP/1.1\r\nHost: localhost:8081\r\n\r\n")
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 301 MOVED PERMANENTLY\r\n"));
+        assert!(resp.contains("Location: https://localhost:8443/a.mpd?x=1\r\n"));
+    }
+
+    #[test]
+    fn plain_http_listener() {
+        TestServer::start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8082").unwrap();
+        let msg = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        stream.write_all(msg.as_bytes()).unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("<MPD"));
+    }
+
+    #[test]
+    fn surrogate_cache_headers() {
+        let mut server = TestServer::new();
+        let msg = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = server.get_all(msg.as_bytes());
+        let headers: Vec<&str> = resp.lines().take_while(|line| !line.is_empty()).collect();
+        assert!(headers.contains(&"Cache-Control: no-cache"));
+        assert!(headers.contains(&"Surrogate-Control: max-age=2"));
+        assert!(headers.contains(&"CDN-Cache-Control: max-age=1"));
+    }
+
     #[test]
     fn multi_part_msg() {
         let mut server = TestServer::new();
@@ -218,7 +799,7 @@ mod http_tests {
         server.write(b"\r\n\r\n");
 
         let resp = server.get_response();
-        assert!(resp.len() > 0);
+        assert!(!resp.is_empty());
         dash_document_succes(resp);
     }
 
@@ -235,7 +816,7 @@ mod http_tests {
         server.write(b"\r\n\r\n");
 
         let resp = server.get_response();
-        assert!(resp.len() > 0);
+        assert!(!resp.is_empty());
         dash_document_succes(resp);
     }
 
@@ -246,7 +827,7 @@ mod http_tests {
         server.write_all(msg.as_bytes());
 
         let resp = server.get_response();
-        assert!(resp.len() > 0);
+        assert!(!resp.is_empty());
         dash_document_succes(resp);
     }
 
@@ -260,4 +841,27 @@ mod http_tests {
             assert!(server.is_err());
         }
     }
+
+    #[test]
+    fn tenant_certificate_by_sni() {
+        TestServer::start_server();
+        let acme = X509::from_pem(&fs::read("test_data/sni/acme_cert.pem").unwrap()).unwrap();
+        let is_acme = |server_name: &str| {
+            let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+            connector.set_verify_callback(SslVerifyMode::NONE, |_, _| true);
+            let connector = connector.build();
+            let stream = TcpStream::connect("localhost:8443").unwrap();
+            let stream = connector
+                .configure()
+                .unwrap()
+                .verify_hostname(false)
+                .connect(server_name, stream)
+                .unwrap();
+            let certificate = stream.ssl().peer_certificate().unwrap();
+            certificate.to_der().unwrap() == acme.to_der().unwrap()
+        };
+        assert!(is_acme("acme.example.com"));
+        assert!(is_acme("ACME.example.com"));
+        assert!(!is_acme("localhost"));
+    }
 }
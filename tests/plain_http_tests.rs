@@ -0,0 +1,594 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Once;
+use std::{thread, time};
+
+#[cfg(test)]
+#[path = "../src/config.rs"]
+mod config;
+
+#[cfg(test)]
+#[path = "../src/mpd.rs"]
+mod mpd;
+
+#[cfg(test)]
+#[path = "../src/server/mod.rs"]
+mod server;
+
+static SERVER_INIT: Once = Once::new();
+
+const DASH_DOCUMENT: &str = "/test_data/unit_test_dash_document.mpd";
+
+/// Server without TLS. The certificate files in the config don't exist
+fn start_server() {
+    SERVER_INIT.call_once(|| {
+        let config = config::Config::load("test_data/plain_test_config.json").unwrap();
+        thread::spawn(move || {
+            let mut server = server::DashServer::new(config::SharedConfig::new(config));
+            server.start_server();
+        });
+        thread::sleep(time::Duration::from_secs(1));
+    });
+}
+
+fn get_all(buf: &[u8]) -> String {
+    start_server();
+    let mut stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+    stream.write_all(buf).unwrap();
+    let mut res = vec![];
+    stream.read_to_end(&mut res).unwrap();
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Request to the admin API with the token of the test config
+fn admin(method: &str, path: &str, body: &str) -> String {
+    start_server();
+    let mut stream = TcpStream::connect("127.0.0.1:8090").unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nAuthorization: Bearer test-token\r\nContent-Length: {}\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).unwrap();
+    res
+}
+
+#[cfg(test)]
+mod plain_http_tests {
+    use super::*;
+
+    #[test]
+    fn plain_http_request() {
+        let request = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = get_all(request.as_bytes());
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-type: application/dash+xml\r\n"));
+    }
+
+    #[test]
+    fn custom_response_headers() {
+        let request = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        let resp = get_all(request.as_bytes());
+        let head = resp.split("\r\n\r\n").next().unwrap();
+        assert!(head.contains("\r\nTiming-Allow-Origin: *"));
+        assert!(head.contains("\r\nCache-Control: no-store"));
+        assert_eq!(head.matches("Cache-Control").count(), 1);
+    }
+
+    #[test]
+    fn rewritten_paths() {
+        let document = std::fs::read("test_data/unit_test_dash_document.mpd").unwrap();
+        for path in [
+            "/docs/unit_test_dash_document.mpd",
+            "/v2/unit_test_dash_document.mpd",
+        ] {
+            let request = format!("GET {} HTTP/1.0\r\n\r\n", path);
+            let resp = get_all(request.as_bytes());
+            assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"), "{}", path);
+            assert!(resp.contains(&format!("Content-Length: {}\r\n", document.len())));
+        }
+        // Rewritten paths can't leave the document root either
+        let resp = get_all(b"GET /docs/../Cargo.toml HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+    }
+
+    #[test]
+    fn generated_manifest() {
+        let resp = get_all(b"GET /test_data/generated/vod/manifest.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-type: application/dash+xml\r\n"));
+        assert!(resp.contains("codecs=\"avc1.64001F\""));
+        assert!(resp.contains("media=\"$RepresentationID$/seg-$Number$.m4s\""));
+        let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+        assert!(body.starts_with("<?xml"));
+        assert!(resp.contains(&format!("Content-Length: {}\r\n", body.len())));
+
+        // The segment times are the validators
+        let etag_line = resp.lines().find(|l| l.starts_with("ETag: ")).unwrap();
+        let request = format!(
+            "GET /test_data/generated/vod/manifest.mpd HTTP/1.0\r\nIf-None-Match: {}\r\n\r\n",
+            &etag_line[6..]
+        );
+        let resp = get_all(request.as_bytes());
+        assert!(resp.starts_with("HTTP/1.1 304 NOT MODIFIED\r\n"));
+
+        // Only directories with representations get one
+        let resp = get_all(b"GET /test_data/sidecar/manifest.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        let resp = get_all(b"GET /test_data/generated/vod/other.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+    }
+
+    #[test]
+    fn subtitles() {
+        let resp = get_all(b"GET /test_data/generated/vod/manifest.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.contains("mimeType=\"text/vtt\" lang=\"en\""));
+        assert!(resp.contains("media=\"subtitles.en.vtt?segment=$Number$\""));
+        assert!(resp.contains("<BaseURL>subtitles.fi.ttml</BaseURL>"));
+
+        let resp = get_all(b"GET /test_data/generated/vod/subtitles.fi.ttml HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-type: application/ttml+xml\r\n"));
+
+        // The second segment has only the cue from 1.8 to 2.4 seconds
+        let resp =
+            get_all(b"GET /test_data/generated/vod/subtitles.en.vtt?segment=2 HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-type: text/vtt\r\n"));
+        let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+        assert!(body.starts_with("WEBVTT\n\nSTYLE\n"));
+        assert!(body.contains("\n\n2\n00:00:01.800 --> 00:00:02.400 line:90%\n"));
+        assert!(!body.contains("The end"));
+        assert!(resp.contains(&format!("Content-Length: {}\r\n", body.len())));
+
+        let resp =
+            get_all(b"GET /test_data/generated/vod/subtitles.en.vtt?segment=4 HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        let resp = get_all(b"GET /test_data/generated/vod/subtitles.en.vtt HTTP/1.0\r\n\r\n");
+        assert!(resp.contains("NOTE Cue times"));
+    }
+
+    #[test]
+    fn digests_of_generated_bodies() {
+        // The responses differ only by the query of the same file
+        let urls = [
+            "/test_data/generated/vod/manifest.m3u8?representation=video-360",
+            "/test_data/generated/vod/manifest.m3u8?representation=video-720",
+            "/test_data/generated/vod/subtitles.en.vtt?segment=1",
+            "/test_data/generated/vod/subtitles.en.vtt?segment=3",
+        ];
+        for url in urls.iter() {
+            let resp = get_all(format!("GET {} HTTP/1.0\r\n\r\n", url).as_bytes());
+            assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+            let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+            let digest = openssl::base64::encode_block(&openssl::sha::sha256(body.as_bytes()));
+            assert!(resp.contains(&format!("Repr-Digest: sha-256=:{}:\r\n", digest)));
+        }
+    }
+
+    #[test]
+    fn generated_live_manifest() {
+        // The live directory is a link to the on demand one
+        let resp = get_all(b"GET /test_data/generated/live/manifest.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("type=\"dynamic\""));
+        assert!(resp.contains("availabilityStartTime=\""));
+        assert!(resp.contains("minimumUpdatePeriod=\"PT2S\""));
+        assert!(!resp.contains("mediaPresentationDuration"));
+    }
+
+    #[test]
+    fn content_protection() {
+        let resp = get_all(b"GET /test_data/generated/live/manifest.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+        assert!(resp.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert!(body.contains(" xmlns:cenc=\"urn:mpeg:cenc:2013\""));
+        assert!(body.contains("<ContentProtection schemeIdUri=\"urn:mpeg:dash:mp4protection:2011\" value=\"cenc\" cenc:default_KID=\"10000000-1000-1000-1000-100000000001\"/>"));
+        assert!(body.contains("<ContentProtection schemeIdUri=\"urn:uuid:1077efec-c0b2-4d02-ace3-3c1e52e2fb4b\"><cenc:pssh>AAAAEnBzc2g=</cenc:pssh></ContentProtection>"));
+        // Only the video set is encrypted
+        assert_eq!(body.matches("<ContentProtection ").count(), 2);
+
+        // Manifest files get them too
+        let resp = get_all(b"GET /v3/unit_test_dash_document.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(
+            resp.matches("cenc:default_KID=\"20000000-2000-2000-2000-200000000002\"")
+                .count(),
+            2
+        );
+        assert!(resp.contains("<!-- Created with Bento4 mp4-dash.py"));
+        let resp = get_all(b"GET /test_data/generated/vod/manifest.mpd HTTP/1.0\r\n\r\n");
+        assert!(!resp.contains("ContentProtection"));
+    }
+
+    #[test]
+    fn clear_key_licenses() {
+        let post = |path: &str, body: &str| {
+            get_all(
+                format!(
+                    "POST {} HTTP/1.1\r\nHost: a\r\nOrigin: https://player.example.com\r\n\
+                     Content-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    path,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+        };
+        let resp = post(
+            "/license",
+            r#"{"kids":["EAAAABAAEAAQABAAAAAAAQ","IAAAACAAIAAgACAAAAAAAg"],"type":"temporary"}"#,
+        );
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-type: application/json\r\n"));
+        assert!(resp.contains("Cache-Control: no-store\r\n"));
+        assert!(resp.contains("Access-Control-Allow-Origin: *\r\n"));
+        assert!(resp.ends_with(concat!(
+            "\r\n\r\n{\"keys\":[",
+            r#"{"kty":"oct","kid":"EAAAABAAEAAQABAAAAAAAQ","k":"OiobaN0r2bLusl6ExHdmaA"},"#,
+            r#"{"kty":"oct","kid":"IAAAACAAIAAgACAAAAAAAg","k":"ABEiM0RVZneImaq7zN3u_w"}"#,
+            "],\"type\":\"temporary\"}"
+        )));
+
+        let resp = post("/license", r#"{"kids":["MAAAADAAMAAwAAAAAAAAAw"]}"#);
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        let resp = post("/license", "kids");
+        assert!(resp.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+        // Only the license server takes posts
+        let resp = post("/licenses", r#"{"kids":["EAAAABAAEAAQABAAAAAAAQ"]}"#);
+        assert!(resp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn hls_playlists() {
+        let resp = get_all(b"GET /test_data/generated/vod/manifest.m3u8 HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Content-type: application/vnd.apple.mpegurl\r\n"));
+        let (_, master) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(master.starts_with("#EXTM3U\n"));
+        assert!(master.contains("URI=\"manifest.m3u8?representation=audio-en\""));
+        assert!(master.contains(
+            "RESOLUTION=1280x720,AUDIO=\"audio\"\nmanifest.m3u8?representation=video-720\n"
+        ));
+
+        let resp = get_all(
+            b"GET /test_data/generated/vod/manifest.m3u8?representation=video-360 HTTP/1.0\r\n\r\n",
+        );
+        let (_, media) = resp.split_once("\r\n\r\n").unwrap();
+        assert!(media.contains("#EXT-X-MAP:URI=\"video-360/init.mp4\"\n"));
+        assert!(media.contains("#EXTINF:2.000,\nvideo-360/seg-1.m4s\n"));
+        assert!(media.ends_with("#EXT-X-ENDLIST\n"));
+
+        // From a manifest file
+        let resp = get_all(b"GET /test_data/unit_test_dash_document.m3u8 HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        let resp = get_all(
+            b"GET /test_data/generated/live/manifest.m3u8?representation=audio-en HTTP/1.0\r\n\r\n",
+        );
+        assert!(!resp.contains("#EXT-X-ENDLIST"));
+        let resp = get_all(
+            b"GET /test_data/generated/vod/manifest.m3u8?representation=missing HTTP/1.0\r\n\r\n",
+        );
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+    }
+
+    #[test]
+    fn in_progress_segment() {
+        let dir = "test_data/low_latency";
+        std::fs::create_dir_all(dir).unwrap();
+        let partial = format!("{}/seg-1.m4s.tmp", dir);
+        std::fs::write(&partial, "moof1").unwrap();
+        let encoder = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(200));
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&partial)
+                .unwrap();
+            file.write_all(b"mdat1").unwrap();
+            std::fs::rename(&partial, "test_data/low_latency/seg-1.m4s").unwrap();
+        });
+
+        let resp = get_all(b"GET /test_data/low_latency/seg-1.m4s HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n");
+        encoder.join().unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!resp.contains("Content-Length"));
+        assert!(resp.ends_with("\r\n\r\n5\r\nmoof1\r\n5\r\nmdat1\r\n0\r\n\r\n"));
+
+        // The complete segment is served as usual
+        let resp = get_all(b"GET /test_data/low_latency/seg-1.m4s HTTP/1.0\r\n\r\n");
+        assert!(resp.contains("Content-Length: 10\r\n"));
+        assert!(resp.ends_with("\r\n\r\nmoof1mdat1"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ingested_segments() {
+        let upload = |method: &str, token: &str, body: &str| {
+            get_all(
+                format!(
+                    "{} /ingest/chan1/video/seg-1.m4s HTTP/1.1\r\nHost: a\r\n\
+                     Authorization: Bearer {}\r\nContent-Length: {}\r\n\r\n{}",
+                    method,
+                    token,
+                    body.len(),
+                    body
+                )
+                .as_bytes(),
+            )
+        };
+        let segment = b"GET /test_data/ingest/chan1/video/seg-1.m4s HTTP/1.0\r\n\r\n";
+
+        let resp = upload("PUT", "wrong-token", "moof");
+        assert!(resp.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+        let resp = upload("GET", "encoder-token", "");
+        assert!(resp.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(resp.contains("Allow: PUT, POST, DELETE\r\n"));
+
+        let resp = upload("PUT", "encoder-token", "moof");
+        assert!(resp.starts_with("HTTP/1.1 201 CREATED\r\n"));
+        assert!(get_all(segment).ends_with("\r\n\r\nmoof"));
+
+        // Chunked upload that waits for 100 Continue
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+        stream
+            .write_all(
+                b"POST /ingest/chan1/video/seg-1.m4s HTTP/1.1\r\nHost: a\r\n\
+                  Authorization: Bearer encoder-token\r\nExpect: 100-continue\r\n\
+                  Transfer-Encoding: chunked\r\n\r\n",
+            )
+            .unwrap();
+        let mut interim = vec![];
+        while !interim.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).unwrap();
+            interim.push(byte[0]);
+        }
+        assert!(interim.starts_with(b"HTTP/1.1 100 Continue\r\n"));
+        stream
+            .write_all(b"4\r\nmoof\r\n4\r\nmdat\r\n0\r\n\r\n")
+            .unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 204 NO CONTENT\r\n"));
+        assert!(get_all(segment).ends_with("\r\n\r\nmoofmdat"));
+
+        let resp = upload("DELETE", "encoder-token", "");
+        assert!(resp.starts_with("HTTP/1.1 204 NO CONTENT\r\n"));
+        assert!(get_all(segment).starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        let resp = upload("DELETE", "encoder-token", "");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        std::fs::remove_dir_all("test_data/ingest").unwrap();
+    }
+
+    #[test]
+    fn plain_http_errors() {
+        let resp = get_all(b"GET /missing.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+        // Body from the configured error page
+        let page = std::fs::read_to_string("test_data/errors/404.html").unwrap();
+        assert!(resp.contains("Content-Type: text/html; charset=utf-8\r\n"));
+        assert!(resp.contains(&format!("Content-Length: {}\r\n", page.len())));
+        assert!(resp.ends_with(&format!("\r\n\r\n{}", page)));
+        let resp = get_all(b"HEAD /missing.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.ends_with("\r\n\r\n"));
+        let resp = get_all(b"GET\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 400 BAD REQUEST\r\n"));
+    }
+
+    #[test]
+    fn request_ids() {
+        let resp = get_all(b"GET /missing.mpd HTTP/1.0\r\nX-Request-Id: cdn-42\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\nX-Request-Id: cdn-42\r\n"));
+
+        let resp = get_all(b"GET /missing.mpd HTTP/1.0\r\n\r\n");
+        let id = resp.lines().nth(1).unwrap();
+        assert!(id.starts_with("X-Request-Id: "));
+        assert_eq!(id.len(), "X-Request-Id: ".len() + 32);
+    }
+
+    #[test]
+    fn slow_request_head() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8080").unwrap();
+        stream
+            .set_read_timeout(Some(time::Duration::from_millis(300)))
+            .unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        let start = time::Instant::now();
+        let mut res = [0u8; 64];
+        // Each byte comes well within the connection timeout but the whole
+        // head takes longer than the header timeout
+        let len = loop {
+            assert!(start.elapsed() < time::Duration::from_secs(4));
+            stream.write_all(b"X").unwrap();
+            if let Ok(len) = stream.read(&mut res) {
+                break len;
+            }
+        };
+        assert!(res[..len].starts_with(b"HTTP/1.1 408 REQUEST TIMEOUT\r\n"));
+    }
+
+    /// HS256 token with the secret of the test config
+    fn bearer_token(claims: &str) -> String {
+        let encode = |bytes: &[u8]| {
+            openssl::base64::encode_block(bytes)
+                .trim_end_matches('=')
+                .replace('+', "-")
+                .replace('/', "_")
+        };
+        let signed = format!(
+            "{}.{}",
+            encode(br#"{"alg":"HS256","typ":"JWT"}"#),
+            encode(claims.as_bytes())
+        );
+        let key = openssl::pkey::PKey::hmac(b"test-secret").unwrap();
+        let mut signer =
+            openssl::sign::Signer::new(openssl::hash::MessageDigest::sha256(), &key).unwrap();
+        signer.update(signed.as_bytes()).unwrap();
+        format!("{}.{}", signed, encode(&signer.sign_to_vec().unwrap()))
+    }
+
+    #[test]
+    fn bearer_tokens() {
+        let resp = get_all(b"GET /premium/missing.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+        assert!(resp.contains("WWW-Authenticate: Bearer\r\n"));
+
+        let request = |token: &str| {
+            format!(
+                "GET /premium/missing.mpd HTTP/1.0\r\nAuthorization: Bearer {}\r\n\r\n",
+                token
+            )
+        };
+        let resp = get_all(request("not.a.token").as_bytes());
+        assert!(resp.contains("WWW-Authenticate: Bearer error=\"invalid_token\"\r\n"));
+        let resp = get_all(request(&bearer_token(r#"{"scope":"hd"}"#)).as_bytes());
+        assert!(resp.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"));
+        // Past the authorization the missing file is just not found
+        let resp = get_all(request(&bearer_token(r#"{"scope":"hd premium"}"#)).as_bytes());
+        assert!(resp.starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+    }
+
+    #[test]
+    fn title_overrides() {
+        let resp = get_all(b"GET /v3/title_overrides/stream.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+
+        let request = |path: &str| {
+            let request = format!(
+                "GET {} HTTP/1.0\r\nAuthorization: Bearer {}\r\n\r\n",
+                path,
+                bearer_token("{}")
+            );
+            get_all(request.as_bytes())
+        };
+        let resp = request("/v3/title_overrides/stream.mpd");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        let body = &resp[resp.find("\r\n\r\n").unwrap() + 4..];
+        assert!(resp.contains(&format!("Content-Length: {}\r\n", body.len())));
+        // Left out of the title even though /v3/ is encrypted
+        assert!(!body.contains("ContentProtection"));
+        assert!(!body.contains("id=\"video/avc1\""));
+        assert!(body.contains("id=\"audio/und/mp4a\""));
+
+        let resp = request("/v3/title_overrides/stream.m3u8");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(resp.contains("audio"));
+        assert!(!resp.contains("avc1"));
+    }
+
+    #[test]
+    fn basic_auth() {
+        let resp = get_all(b"GET /staging/missing.mpd HTTP/1.0\r\n\r\n");
+        assert!(resp.starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+        assert!(resp.contains("WWW-Authenticate: Basic realm=\"Staging\", charset=\"UTF-8\"\r\n"));
+
+        let request = |credentials: &str| {
+            let request = format!(
+                "GET /staging/missing.mpd HTTP/1.0\r\nAuthorization: Basic {}\r\n\r\n",
+                openssl::base64::encode_block(credentials.as_bytes())
+            );
+            get_all(request.as_bytes())
+        };
+        assert!(request("bob:wrong").starts_with("HTTP/1.1 401 UNAUTHORIZED\r\n"));
+        // Past the authorization the missing file is just not found
+        assert!(request("bob:secret").starts_with("HTTP/1.1 404 NOT FOUND\r\n"));
+    }
+
+    #[test]
+    fn hotlinked_streams() {
+        let request = |header: &str| {
+            let request = format!("GET {} HTTP/1.0\r\n{}\r\n\r\n", DASH_DOCUMENT, header);
+            get_all(request.as_bytes())
+        };
+        let resp = request("Referer: https://player.example.com/watch");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+        let resp = request("Referer: https://evil.example/embed");
+        assert!(resp.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"));
+        let resp = request("Origin: https://evil.example");
+        assert!(resp.starts_with("HTTP/1.1 403 FORBIDDEN\r\n"));
+        // Requests without either header are allowed by default
+        let resp = request("Accept: */*");
+        assert!(resp.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn admin_needs_token() {
+        start_server();
+        let mut stream = TcpStream::connect("127.0.0.1:8090").unwrap();
+        stream
+            .write_all(b"GET /stats HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n")
+            .unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        assert!(res.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    }
+
+    #[test]
+    fn admin_endpoints() {
+        get_all(b"GET /missing.mpd HTTP/1.0\r\n\r\n");
+        let res = admin("GET", "/stats", "");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("Content-Type: application/json\r\n"));
+        assert!(res.contains("\"4xx\": "));
+        assert!(!res.contains("\"4xx\": 0"));
+
+        let request = format!("GET {} HTTP/1.0\r\n\r\n", DASH_DOCUMENT);
+        get_all(request.as_bytes());
+        let res = admin("GET", "/stats", "");
+        let body = res.split("\r\n\r\n").nth(1).unwrap();
+        let stats: serde_json::Value = serde_json::from_str(body).unwrap();
+        let manifest = &stats["latency"]["manifest"];
+        assert!(manifest["responses"].as_u64().unwrap() >= 1);
+        assert!(manifest["maxTimeToFirstByteMicros"].as_u64().unwrap() > 0);
+
+        // None of the segments of the test manifest exist
+        let res = admin("GET", "/segments/missing", "");
+        let body = res.split("\r\n\r\n").nth(1).unwrap();
+        let missing: serde_json::Value = serde_json::from_str(body).unwrap();
+        let manifest = &missing["manifests"]["./test_data/unit_test_dash_document.mpd"];
+        assert_eq!(manifest["missing"], 62);
+        assert_eq!(manifest["urls"][0], "test_data/video/avc1/init.mp4");
+        // Generated manifests only list existing segments
+        get_all(b"GET /test_data/generated/vod/manifest.mpd HTTP/1.0\r\n\r\n");
+        let res = admin("GET", "/segments/missing", "");
+        assert!(!res.contains("generated"));
+
+        // The test config has no tenants with quotas
+        let res = admin("GET", "/quotas", "");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("\"tenants\": {}"));
+
+        let res = admin("GET", "/config", "");
+        assert!(res.contains("\"port\": \"8090\""));
+        assert!(!res.contains("test-token"));
+
+        let res = admin("POST", "/cache/flush", "");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        let purge = "{\"paths\": [\"/missing.mpd\"], \"prefixes\": [\"/test_data/\"]}";
+        let res = admin("POST", "/cache/purge", purge);
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(res.contains("\"/missing.mpd\""));
+        let res = admin("POST", "/cache/purge", "{\"paths\": [\"missing.mpd\"]}");
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        let res = admin("GET", "/cache/purge", "");
+        assert!(res.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+        let res = admin("PUT", "/log-level", "{\"level\": \"debug\"}");
+        assert!(res.contains("\"level\": \"debug\""));
+        let res = admin("PUT", "/log-level", "{\"level\": \"loud\"}");
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let res = admin("DELETE", "/stats", "");
+        assert!(res.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        let res = admin("GET", "/nothing", "");
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}